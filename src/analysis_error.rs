@@ -0,0 +1,28 @@
+use std::error::Error;
+use std::fmt;
+
+// Failure of an analysis to produce a result from the data it was given
+#[derive(Debug)]
+pub(crate) enum AnalysisError {
+    // Nothing left to analyse: a header-only CSV, or a filter that removed every row
+    EmptyInput { context: String },
+    // Stopped on request (Ctrl-C) before finishing
+    Cancelled,
+}
+
+impl AnalysisError {
+    pub(crate) fn empty(context: impl Into<String>) -> Self {
+        AnalysisError::EmptyInput { context: context.into() }
+    }
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::EmptyInput { context } => write!(f, "{}: no rows or values to analyse", context),
+            AnalysisError::Cancelled => write!(f, "cancelled before finishing"),
+        }
+    }
+}
+
+impl Error for AnalysisError {}
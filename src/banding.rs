@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use csv::Writer;
+use serde::Serialize;
+use statrs::distribution::{ContinuousCDF, StudentsT};
+use crate::analysis_error::AnalysisError;
+use crate::eda_statistics::{assign_bins, quantile_bins, summary_statistics};
+use crate::fields::{self, LifeExpectancyRecord};
+use crate::format::format_value;
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::series::field_meta;
+
+// Cells with fewer values are flagged as too sparse to read much into
+pub(crate) const BAND_MIN_OBSERVATIONS: usize = 5;
+
+// Mean of the value feature for one (band, group) cell
+#[derive(Debug, Clone)]
+pub(crate) struct BandCell {
+    pub(crate) band: usize,
+    pub(crate) range: (f64, f64), // band edges of the band feature
+    pub(crate) group: String,
+    pub(crate) count: usize,
+    pub(crate) mean: f64,
+    pub(crate) ci: Option<(f64, f64)>, // 95% t interval; None below two values
+    pub(crate) sparse: bool,           // fewer than BAND_MIN_OBSERVATIONS values
+}
+
+// Whether the value feature moves with the band feature differently per group:
+// the band feature is cut into `n_bands` quantile bands over all records, and
+// each (band, group) cell gets the mean value with its count and 95% interval.
+// Cells come in band order, then group order; empty cells are left out.
+pub(crate) fn banded_interaction(
+    records: &[LifeExpectancyRecord],
+    band_feature: &str,
+    value_feature: &str,
+    group: fn(&LifeExpectancyRecord) -> &str,
+    n_bands: usize,
+) -> Result<Vec<BandCell>, Box<dyn Error>> {
+    let (band_meta, value_meta) = (field_meta(band_feature)?, field_meta(value_feature)?);
+    let rows: Vec<(f64, f64, &str)> = records
+        .iter()
+        .filter_map(|record| Some(((band_meta.getter)(record)?, (value_meta.getter)(record)?, group(record))))
+        .filter(|(band, value, _)| band.is_finite() && value.is_finite())
+        .collect();
+    let keys: Vec<f64> = rows.iter().map(|row| row.0).collect();
+    let edges = quantile_bins(&keys, n_bands);
+    if edges.len() < 2 {
+        return Err(AnalysisError::empty(format!("{} bands of {}", band_meta.name, value_meta.name)).into());
+    }
+
+    let mut cells: BTreeMap<(usize, &str), Vec<Option<f64>>> = BTreeMap::new();
+    for (band, &(_, value, group)) in assign_bins(&keys, &edges).into_iter().zip(&rows) {
+        if let Some(band) = band {
+            cells.entry((band, group)).or_default().push(Some(value));
+        }
+    }
+
+    Ok(cells
+        .into_iter()
+        .filter_map(|((band, group), values)| {
+            let stats = summary_statistics(&values)?;
+            let ci = stats.std_dev.and_then(|std_dev| {
+                let t = StudentsT::new(0.0, 1.0, (stats.count - 1) as f64).ok()?.inverse_cdf(0.975);
+                let half = t * std_dev / (stats.count as f64).sqrt();
+                Some((stats.mean - half, stats.mean + half))
+            });
+            Some(BandCell {
+                band,
+                range: (edges[band], edges[band + 1]),
+                group: group.to_string(),
+                count: stats.count,
+                mean: stats.mean,
+                ci,
+                sparse: stats.count < BAND_MIN_OBSERVATIONS,
+            })
+        })
+        .collect())
+}
+
+pub(crate) fn print_banded_interaction(cells: &[BandCell], band_feature: &str, value_feature: &str) {
+    let (band_unit, value_unit) = (fields::unit_for(band_feature), fields::unit_for(value_feature));
+    println!("Mean {} by {} band:", value_feature, band_feature);
+    println!("{:<24} {:<12} {:>6} {:>14} {:>30}", "Band", "Group", "n", "Mean", "95% CI");
+    for cell in cells {
+        let band = format!("{}: {}–{}", cell.band + 1, format_value(cell.range.0, band_unit), format_value(cell.range.1, band_unit));
+        let ci = cell.ci.map_or("-".to_string(), |(low, high)| {
+            format!("{} – {}", format_value(low, value_unit), format_value(high, value_unit))
+        });
+        let flag = if cell.sparse { "  (sparse)" } else { "" };
+        println!(
+            "{:<24} {:<12} {:>6} {:>14} {:>30}{}",
+            band,
+            cell.group,
+            cell.count,
+            format_value(cell.mean, value_unit),
+            ci,
+            flag
+        );
+    }
+}
+
+// One line of the banding CSV
+#[derive(Serialize)]
+struct BandRow<'a> {
+    #[serde(rename = "Band")]
+    band: usize,
+    #[serde(rename = "Low")]
+    low: f64,
+    #[serde(rename = "High")]
+    high: f64,
+    #[serde(rename = "Group")]
+    group: &'a str,
+    #[serde(rename = "Count")]
+    count: usize,
+    #[serde(rename = "Mean")]
+    mean: f64,
+    #[serde(rename = "CI low")]
+    ci_low: Option<f64>,
+    #[serde(rename = "CI high")]
+    ci_high: Option<f64>,
+    #[serde(rename = "Sparse")]
+    sparse: bool,
+}
+
+pub(crate) fn export_banded_interaction_csv(cells: &[BandCell], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for cell in cells {
+        writer.serialize(BandRow {
+            band: cell.band + 1,
+            low: cell.range.0,
+            high: cell.range.1,
+            group: &cell.group,
+            count: cell.count,
+            mean: cell.mean,
+            ci_low: cell.ci.map(|ci| ci.0),
+            ci_high: cell.ci.map(|ci| ci.1),
+            sparse: cell.sparse,
+        })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_are_split_by_band_and_status() {
+        // Schooling 1..=16 cut into two bands at 8.5, life expectancy 50 + schooling;
+        // Developed holds 1..=6 and 15..=16, Developing 7..=14
+        let mut records: Vec<LifeExpectancyRecord> = (1..=16)
+            .map(|years| LifeExpectancyRecord {
+                status: if years <= 6 || years >= 15 { "Developed" } else { "Developing" }.to_string(),
+                schooling: Some(years as f64),
+                life_expectancy: Some(50.0 + years as f64),
+                ..Default::default()
+            })
+            .collect();
+        records.push(LifeExpectancyRecord { status: "Developed".to_string(), schooling: Some(3.0), ..Default::default() });
+
+        let cells = banded_interaction(&records, "Schooling", "Life expectancy", |record| record.status.as_str(), 2).unwrap();
+        let summary: Vec<(usize, &str, usize, f64, bool)> =
+            cells.iter().map(|cell| (cell.band, cell.group.as_str(), cell.count, cell.mean, cell.sparse)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, "Developed", 6, 53.5, false),
+                (0, "Developing", 2, 57.5, true),
+                (1, "Developed", 2, 65.5, true),
+                (1, "Developing", 6, 61.5, false),
+            ]
+        );
+        assert_eq!(cells[0].range, (1.0, 8.5));
+        assert_eq!(cells[3].range, (8.5, 16.0));
+
+        // t(5) interval around 51..=56: 53.5 ± 2.5706 * sqrt(3.5) / sqrt(6)
+        let (low, high) = cells[0].ci.unwrap();
+        assert!((low - 51.5367).abs() < 1e-3 && (high - 55.4633).abs() < 1e-3, "{} {}", low, high);
+    }
+
+    #[test]
+    fn unknown_features_and_empty_data_are_errors() {
+        let by_status: fn(&LifeExpectancyRecord) -> &str = |record| record.status.as_str();
+        assert!(banded_interaction(&[], "Happiness", "Life expectancy", by_status, 3).is_err());
+        let error = banded_interaction(&[LifeExpectancyRecord::default()], "Schooling", "Life expectancy", by_status, 3).unwrap_err();
+        assert!(error.downcast_ref::<AnalysisError>().is_some(), "{}", error);
+    }
+}
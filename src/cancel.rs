@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use crate::analysis_error::AnalysisError;
+
+// Shared flag asking long computations to stop. Clones share the flag; loops
+// call `check` once per outer iteration and return its error, so outputs that
+// were being written are dropped uncommitted and their temp files removed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub(crate) fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), AnalysisError> {
+        if self.is_cancelled() { Err(AnalysisError::Cancelled) } else { Ok(()) }
+    }
+}
+
+// Token the SIGINT handler cancels; set once by cancel_on_interrupt
+static INTERRUPT: OnceLock<CancellationToken> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    match INTERRUPT.get() {
+        // A second Ctrl-C while the first is being honoured stops right away
+        Some(token) if token.is_cancelled() => unsafe { libc::_exit(130) },
+        Some(token) => token.cancel(),
+        None => {}
+    }
+}
+
+// Cancel `token` on Ctrl-C instead of killing the process. Elsewhere than on
+// unix the default handler stays.
+pub(crate) fn cancel_on_interrupt(token: &CancellationToken) {
+    if INTERRUPT.set(token.clone()).is_err() {
+        return;
+    }
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::io::Write;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::graph::build_similarity_graph_from_rows;
+    use crate::output;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(AnalysisError::Cancelled)));
+    }
+
+    #[test]
+    fn a_graph_build_cancelled_from_another_thread_leaves_no_output() {
+        // Enough rows for the pairwise loop to run well past the cancellation
+        let n = 5_000;
+        let nodes: Vec<String> = (0..n).map(|i| format!("C{}", i)).collect();
+        let rows: Vec<Vec<f64>> = (0..n).map(|i| (0..3).map(|j| ((i * 3 + j) as f64).sin()).collect()).collect();
+        let path = scratch_dir("cancelled-graph").join("edges.csv");
+
+        let token = CancellationToken::new();
+        let canceller = {
+            let token = token.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                token.cancel();
+            })
+        };
+        let started = Instant::now();
+        let export = || -> Result<(), Box<dyn Error>> {
+            let mut writer = output::atomic_writer(&path)?;
+            writer.write_all(b"Source,Target,Weight\n")?;
+            let (graph, _) = build_similarity_graph_from_rows(&nodes, &rows, 2.0, None, &token)?;
+            for edge in graph.edge_indices() {
+                writeln!(writer, "{}", graph[edge])?;
+            }
+            writer.finish()?;
+            Ok(())
+        };
+        let error = export().unwrap_err();
+        canceller.join().unwrap();
+
+        assert!(matches!(error.downcast_ref::<AnalysisError>(), Some(AnalysisError::Cancelled)), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        // Neither the export nor its temporary file is left behind
+        assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 0);
+
+        let small = build_similarity_graph_from_rows(&nodes[..3], &rows[..3], 2.0, None, &CancellationToken::new());
+        assert_eq!(small.unwrap().0.node_count(), 3);
+    }
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::path::Path;
+use plotters::style::RGBColor;
+use serde::Serialize;
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::safe_float::{self, SafeF64};
+
+// What a chart draws, for re-rendering it elsewhere: written next to the image
+// as <name>.chart.json when the run asks for sidecars (--chart-json). Built from
+// the same values the chart function draws. Values that are not finite are
+// written as null.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ChartSpecJson {
+    pub(crate) chart_type: &'static str, // line, scatter, bar, area, heatmap, histogram or radar
+    pub(crate) title: String,
+    pub(crate) x_axis: AxisJson,
+    pub(crate) y_axis: AxisJson,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) secondary_y_axis: Option<AxisJson>, // for series with "axis": "secondary"
+    pub(crate) series: Vec<SeriesJson>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AxisJson {
+    pub(crate) label: String,
+    pub(crate) range: (SafeF64, SafeF64),
+    // Names of positions 0, 1, ... on a categorical axis (bars, heatmap rows)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) categories: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SeriesJson {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) color: Option<String>, // "#rrggbb"; None where a colormap colors each value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) axis: Option<&'static str>,
+    pub(crate) points: Vec<(SafeF64, SafeF64)>,
+    // Lower and upper end of each point's error bar, when drawn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) errors: Option<Vec<(SafeF64, SafeF64)>>,
+    // Why values of the points or errors are null, when some are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) null_reason: Option<String>,
+}
+
+impl AxisJson {
+    pub(crate) fn new(label: impl Into<String>, (low, high): (f64, f64)) -> Self {
+        AxisJson { label: label.into(), range: safe_float::safe_pair((low, high)), categories: Vec::new() }
+    }
+
+    // Categorical axis with one position per name
+    pub(crate) fn categories(label: impl Into<String>, categories: Vec<String>) -> Self {
+        let range = (0.0, categories.len().saturating_sub(1) as f64);
+        AxisJson { label: label.into(), range: safe_float::safe_pair(range), categories }
+    }
+}
+
+impl SeriesJson {
+    pub(crate) fn new(name: impl Into<String>, color: Option<RGBColor>, points: Vec<(f64, f64)>) -> Self {
+        let null_reason = safe_float::null_reason(points.iter().flat_map(|&(x, y)| [x, y]));
+        let points = points.into_iter().map(safe_float::safe_pair).collect();
+        SeriesJson { name: name.into(), color: color.map(hex), axis: None, points, errors: None, null_reason }
+    }
+
+    pub(crate) fn with_errors(mut self, errors: Vec<(f64, f64)>) -> Self {
+        let points = self.points.iter().map(|&(x, y)| (x.0, y.0));
+        self.null_reason = safe_float::null_reason(points.chain(errors.iter().copied()).flat_map(|(x, y)| [x, y]));
+        self.errors = Some(errors.into_iter().map(safe_float::safe_pair).collect());
+        self
+    }
+
+    pub(crate) fn on_secondary_axis(mut self) -> Self {
+        self.axis = Some("secondary");
+        self
+    }
+}
+
+impl ChartSpecJson {
+    pub(crate) fn new(chart_type: &'static str, title: impl Into<String>, x_axis: AxisJson, y_axis: AxisJson) -> Self {
+        ChartSpecJson { chart_type, title: title.into(), x_axis, y_axis, secondary_y_axis: None, series: Vec::new() }
+    }
+
+    pub(crate) fn series(mut self, series: SeriesJson) -> Self {
+        self.series.push(series);
+        self
+    }
+}
+
+pub(crate) fn hex(color: RGBColor) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+// Path of the sidecar of a chart: scatter_plot.png -> scatter_plot.chart.json
+pub(crate) fn sidecar_path(output_file: &str) -> std::path::PathBuf {
+    Path::new(output_file).with_extension("chart.json")
+}
+
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    #[serde(flatten)]
+    chart: &'a ChartSpecJson,
+    run: &'a RunMetadata,
+}
+
+// Write the sidecar of `output_file` when the run asks for them; `build` only
+// runs then
+pub(crate) fn write_sidecar(output_file: &str, run: &RunMetadata, build: impl FnOnce() -> ChartSpecJson) -> Result<(), Box<dyn Error>> {
+    if !run.chart_json {
+        return Ok(());
+    }
+    let chart = build();
+    let json = serde_json::to_vec_pretty(&Sidecar { chart: &chart, run })?;
+    output::atomic_write(sidecar_path(output_file), json)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_series_with_a_nan_is_still_valid_json() {
+        let series = SeriesJson::new("Slope", None, vec![(2000.0, f64::NAN), (2001.0, 1.5)]).with_errors(vec![(0.0, f64::INFINITY), (1.0, 2.0)]);
+        let spec = ChartSpecJson::new("line", "Trend", AxisJson::new("Year", (2000.0, 2001.0)), AxisJson::new("Slope", (f64::NAN, 1.5))).series(series);
+
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&spec).unwrap()).unwrap();
+        assert_eq!(json["y_axis"]["range"], serde_json::json!([null, 1.5]));
+        let series = &json["series"][0];
+        assert_eq!(series["points"], serde_json::json!([[2000.0, null], [2001.0, 1.5]]));
+        assert_eq!(series["errors"][0], serde_json::json!([0.0, null]));
+        assert_eq!(series["null_reason"], "2 values written as null: 1 not a number, 1 infinite");
+        let points: Vec<(f64, Option<f64>)> = serde_json::from_value(series["points"].clone()).unwrap();
+        assert_eq!(points, vec![(2000.0, None), (2001.0, Some(1.5))]);
+    }
+}
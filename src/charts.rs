@@ -0,0 +1,1439 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::ops::RangeInclusive;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use crate::analysis_error::AnalysisError;
+use crate::banding::{BandCell, BAND_MIN_OBSERVATIONS};
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+use crate::cluster_profiles::ClusterProfile;
+use crate::colormap::{self, PALETTE};
+use crate::eda_plots::{self, scatter_bounds, ChartText, ScatterOptions};
+use crate::eda_statistics::{assign_bins, linear_fit, quantile_bins, r_squared, rank_countries};
+use crate::fields::{self, FieldMeta, LifeExpectancyRecord};
+use crate::format::{format_tick, TickStyle};
+use crate::hierarchical::KCriterion;
+use crate::immunization::{ImmunizationReport, LOW_COVERAGE, VACCINES};
+use crate::metadata::{self, RunMetadata};
+use crate::missingness::MissingnessTest;
+use crate::output;
+use crate::plot_specs::{AxisLimits, Bins};
+use crate::series::{axis_label, country_year_pivot, field_meta, stack_layers, yearly_means_by_status, yearly_totals, YearlyMeans};
+use crate::status_gap::{GapBand, ResampleUnit, GAP_GROUPS};
+use crate::theme::PlotTheme;
+
+// Padded (min, max) of a set of values, for an axis range
+fn value_range(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if min > max {
+        return None;
+    }
+    let pad = if max > min { (max - min) * 0.05 } else { 1.0 };
+    Some((min - pad, max + pad))
+}
+
+// Yearly means of a field for one status group, at the years that have a value,
+// so fields with different year coverage are still matched up by year
+fn group_yearly_means(records: &[LifeExpectancyRecord], meta: &FieldMeta, group: &str) -> Result<BTreeMap<u16, f64>, Box<dyn Error>> {
+    yearly_means_by_status(records, meta)
+        .remove(group)
+        .ok_or_else(|| AnalysisError::empty(format!("{} for group \"{}\"", meta.name, group)).into())
+}
+
+// Rows and columns of the smallest near-square grid holding n panels
+fn grid_shape(n: usize) -> (usize, usize) {
+    let cols = (n as f64).sqrt().ceil().max(1.0) as usize;
+    (n.div_ceil(cols).max(1), cols)
+}
+
+// Years and padded value range one grid panel spans over all its groups; None
+// when the feature has no values, so the panel says "no data"
+fn panel_bounds(means: &YearlyMeans) -> Option<((u16, u16), (f64, f64))> {
+    let years = means.values().flat_map(|years| years.keys().copied());
+    let (first_year, last_year) = (years.clone().min()?, years.max()?);
+    let range = value_range(means.values().flat_map(|years| years.values().copied()))?;
+    Some(((first_year, last_year), range))
+}
+
+// One panel per feature with the Developed/Developing yearly averages, each on its
+// own y-axis, under a single shared legend
+pub(crate) fn plot_feature_grid(
+    records: &[LifeExpectancyRecord],
+    features: &[&str],
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let metas = features.iter().map(|feature| field_meta(feature)).collect::<Result<Vec<_>, _>>()?;
+    if records.is_empty() {
+        return Err(AnalysisError::empty("Feature grid").into());
+    }
+    let panels: Vec<YearlyMeans> = metas.iter().map(|meta| yearly_means_by_status(records, meta)).collect();
+    let groups: BTreeSet<String> = records.iter().map(|record| record.status.clone()).collect();
+
+    let (rows, cols) = grid_shape(features.len());
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (520 * cols as u32, 360 * rows as u32 + 80)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (header, body) = root.split_vertically(80);
+
+    // Shared title and legend
+    header.draw(&Text::new("Developed vs Developing averages per year", (20, 10), ("sans-serif", 30)))?;
+    for (i, group) in groups.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let x = 20 + 180 * i as i32;
+        header.draw(&PathElement::new([(x, 60), (x + 30, 60)], color.stroke_width(3)))?;
+        header.draw(&Text::new(group.clone(), (x + 38, 52), ("sans-serif", 16)))?;
+    }
+
+    for ((area, meta), means) in body.split_evenly((rows, cols)).iter().zip(&metas).zip(&panels) {
+        let Some(((first_year, last_year), (min, max))) = panel_bounds(means) else {
+            let (width, height) = area.dim_in_pixel();
+            area.draw(&Text::new(meta.name, (10, 10), ("sans-serif", 20)))?;
+            area.draw(&Text::new("no data", (width as i32 / 2 - 30, height as i32 / 2), ("sans-serif", 18)))?;
+            continue;
+        };
+
+        let mut chart = ChartBuilder::on(area)
+            .caption(meta.name, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(first_year as u32..last_year as u32, min..max)?;
+
+        chart.configure_mesh()
+            .x_labels(5)
+            .y_labels(5)
+            .label_style(("sans-serif", 12))
+            .draw()?;
+
+        for (i, group) in groups.iter().enumerate() {
+            if let Some(series) = means.get(group) {
+                let color = PALETTE[i % PALETTE.len()];
+                chart.draw_series(LineSeries::new(
+                    series.iter().map(|(&year, &mean)| (year as u32, mean)),
+                    color.stroke_width(2),
+                ))?;
+            }
+        }
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    Ok(())
+}
+
+// Panels of a faceted chart that share one row; more wrap into a grid
+const FACETS_PER_ROW: usize = 6;
+
+// Points of one facet, with the country of each
+type FacetPoints = (Vec<(f64, f64)>, Vec<String>);
+
+// The scatter of `x` against `y` once per value of `facet_by` (e.g. Developed and
+// Developing side by side), each panel with its regression line and R². Every
+// panel has the axis ranges of all the points, so the panels compare directly.
+// Facet values come from the records; blank ones are left out.
+pub(crate) fn plot_faceted_scatter(
+    records: &[LifeExpectancyRecord],
+    x: &str,
+    y: &str,
+    facet_by: fn(&LifeExpectancyRecord) -> &str,
+    output_file: &str,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let (x_meta, y_meta) = (field_meta(x)?, field_meta(y)?);
+    let mut facets: BTreeMap<&str, FacetPoints> = BTreeMap::new();
+    for record in records {
+        let facet = facet_by(record).trim();
+        let (Some(x), Some(y)) = ((x_meta.getter)(record), (y_meta.getter)(record)) else {
+            continue;
+        };
+        if !facet.is_empty() && x.is_finite() && y.is_finite() {
+            let (points, countries) = facets.entry(facet).or_default();
+            points.push((x, y));
+            countries.push(record.country.clone());
+        }
+    }
+    if facets.is_empty() {
+        return Err(AnalysisError::empty(format!("{} vs. {} facets", y_meta.name, x_meta.name)).into());
+    }
+
+    // The ranges draw_scatter would pick for all the points, fixed for every panel
+    let all: Vec<(f64, f64)> = facets.values().flat_map(|(points, _)| points.iter().copied()).collect();
+    let ((x_min, x_max), (y_min, y_max)) = scatter_bounds(&all, &ScatterOptions::default());
+    let options = ScatterOptions {
+        trend_line: true,
+        x_limits: AxisLimits::Fixed(x_min..x_max),
+        y_limits: AxisLimits::Fixed(y_min..y_max),
+        ..Default::default()
+    };
+    let (rows, cols) = if facets.len() <= FACETS_PER_ROW { (1, facets.len()) } else { grid_shape(facets.len()) };
+    let (x_desc, y_desc) = (axis_label(x_meta), axis_label(y_meta));
+    let caption = format!("{} vs. {}", y_meta.name, x_meta.name);
+    let panel_captions: Vec<String> = facets
+        .iter()
+        .map(|(facet, (points, _))| match r_squared(points) {
+            Some(r2) => format!("{} (n = {}, R² = {:.2})", facet, points.len(), r2),
+            None => format!("{} (n = {}, no fit)", facet, points.len()),
+        })
+        .collect();
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (640 * cols as u32, 560 * rows as u32 + 60)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (header, body) = root.split_vertically(60);
+    header.draw(&Text::new(caption.as_str(), (20, 15), ("sans-serif", 30)))?;
+    for ((area, (points, countries)), panel_caption) in body.split_evenly((rows, cols)).iter().zip(facets.values()).zip(&panel_captions) {
+        let text = ChartText { caption: panel_caption, x_desc: &x_desc, y_desc: &y_desc };
+        eda_plots::draw_scatter(area, points, countries, &text, &options, theme)?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // Each panel's points and regression line, named by its caption
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new(x_desc.as_str(), (x_min, x_max));
+        let mut spec = ChartSpecJson::new("scatter", caption.as_str(), x_axis, AxisJson::new(y_desc.as_str(), (y_min, y_max)));
+        for ((points, _), name) in facets.values().zip(&panel_captions) {
+            spec = spec.series(SeriesJson::new(name.as_str(), Some(RGBColor(190, 86, 131)), points.clone()));
+            if let Some((slope, intercept)) = linear_fit(points) {
+                let line = [x_min, x_max].map(|x| (x, slope * x + intercept)).to_vec();
+                spec = spec.series(SeriesJson::new(format!("{} trend line", name), Some(BLACK), line));
+            }
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Yearly means of two features for one status group, the left feature against the
+// left y-axis and the right feature against a secondary right y-axis. Both series
+// are plotted at their actual years, so differing coverage keeps them aligned.
+pub(crate) fn plot_dual_axis(
+    records: &[LifeExpectancyRecord],
+    left_feature: &str,
+    right_feature: &str,
+    group: &str,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let (left_meta, right_meta) = (field_meta(left_feature)?, field_meta(right_feature)?);
+    let (left, right) = (group_yearly_means(records, left_meta, group)?, group_yearly_means(records, right_meta, group)?);
+
+    let years: Vec<u16> = left.keys().chain(right.keys()).copied().collect();
+    let (first_year, last_year) = (*years.iter().min().unwrap(), *years.iter().max().unwrap());
+    let (left_min, left_max) = value_range(left.values().copied()).unwrap();
+    let (right_min, right_max) = value_range(right.values().copied()).unwrap();
+    let (left_color, right_color) = (RGBColor(200, 60, 40), RGBColor(31, 119, 180));
+    let caption = format!("{} and {} per year ({})", left_meta.name, right_meta.name, group);
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .right_y_label_area_size(70)
+        .build_cartesian_2d(first_year as u32..last_year as u32, left_min..left_max)?
+        .set_secondary_coord(first_year as u32..last_year as u32, right_min..right_max);
+
+    chart.configure_mesh()
+        .x_desc("Year")
+        .y_desc(axis_label(left_meta))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+    chart.configure_secondary_axes()
+        .y_desc(axis_label(right_meta))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    chart.draw_series(LineSeries::new(left.iter().map(|(&year, &mean)| (year as u32, mean)), left_color.stroke_width(3)))?
+        .label(format!("{} (left)", left_meta.name))
+        .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], left_color.stroke_width(3)));
+    chart.draw_secondary_series(LineSeries::new(right.iter().map(|(&year, &mean)| (year as u32, mean)), right_color.stroke_width(3)))?
+        .label(format!("{} (right)", right_meta.name))
+        .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], right_color.stroke_width(3)));
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let points = |means: &BTreeMap<u16, f64>| means.iter().map(|(&year, &mean)| (year as f64, mean)).collect();
+        let mut spec = ChartSpecJson::new(
+            "line",
+            caption,
+            AxisJson::new("Year", (first_year as f64, last_year as f64)),
+            AxisJson::new(axis_label(left_meta), (left_min, left_max)),
+        )
+        .series(SeriesJson::new(left_meta.name, Some(left_color), points(&left)))
+        .series(SeriesJson::new(right_meta.name, Some(right_color), points(&right)).on_secondary_axis());
+        spec.secondary_y_axis = Some(AxisJson::new(axis_label(right_meta), (right_min, right_max)));
+        spec
+    })?;
+    Ok(())
+}
+
+// Stacked area chart of yearly totals of several features, optionally 100% stacked
+pub(crate) fn plot_stacked_area(
+    records: &[LifeExpectancyRecord],
+    features: &[&str],
+    percent: bool,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let metas = features.iter().map(|feature| field_meta(feature)).collect::<Result<Vec<_>, _>>()?;
+    let stacked = stack_layers(&yearly_totals(records, &metas)?, percent);
+    let (Some(&first_year), Some(&last_year)) = (stacked.keys().next(), stacked.keys().next_back()) else {
+        return Err(AnalysisError::empty("Stacked area chart").into());
+    };
+    let top = stacked.values().filter_map(|edges| edges.last().copied()).fold(0.0, f64::max);
+    let y_max = if percent { 100.0 } else { top * 1.05 };
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{}: {}", if percent { "Share of yearly totals" } else { "Yearly totals" }, features.join(", "));
+    let y_desc = if percent { "% of total" } else { "Sum over countries" };
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 30))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(80)
+        .build_cartesian_2d(first_year as u32..last_year as u32, 0.0..y_max)?;
+
+    chart.configure_mesh()
+        .x_desc("Year")
+        .y_desc(y_desc)
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    // Draw the top layer first so each lower layer is painted over the one above it
+    for (layer, meta) in metas.iter().enumerate().rev() {
+        let color = PALETTE[layer % PALETTE.len()];
+        chart.draw_series(AreaSeries::new(
+            stacked.iter().map(|(&year, edges)| (year as u32, edges[layer])),
+            0.0,
+            color.mix(0.8),
+        ).border_style(color))?
+            .label(meta.name)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], color.filled()));
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // Each layer's upper edge, as drawn
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new("Year", (first_year as f64, last_year as f64));
+        let mut spec = ChartSpecJson::new("area", caption, x_axis, AxisJson::new(y_desc, (0.0, y_max)));
+        for (layer, meta) in metas.iter().enumerate() {
+            let points = stacked.iter().map(|(&year, edges)| (year as f64, edges[layer])).collect();
+            spec = spec.series(SeriesJson::new(meta.name, Some(PALETTE[layer % PALETTE.len()]), points));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// (country, rank in year_a, rank in year_b) of every country with life expectancy
+// in both years, biggest movers first. Both years are ranked over the same
+// countries with rank_countries, so a country missing either year moves no one.
+fn rank_moves(records: &[LifeExpectancyRecord], year_a: u16, year_b: u16) -> Vec<(String, usize, usize)> {
+    let values_in = |year: u16| -> HashMap<&str, f64> {
+        records
+            .iter()
+            .filter(|record| record.year == year)
+            .filter_map(|record| record.life_expectancy.map(|value| (record.country.as_str(), value)))
+            .collect()
+    };
+    let (values_a, values_b) = (values_in(year_a), values_in(year_b));
+    let ranks = |values: &HashMap<&str, f64>, other: &HashMap<&str, f64>| -> HashMap<String, usize> {
+        let shared = values
+            .iter()
+            .filter(|(country, _)| other.contains_key(*country))
+            .map(|(country, value)| (country.to_string(), *value));
+        rank_countries(shared).into_iter().enumerate().map(|(i, (country, _))| (country, i + 1)).collect()
+    };
+    let (ranks_a, ranks_b) = (ranks(&values_a, &values_b), ranks(&values_b, &values_a));
+
+    let mut moves: Vec<(String, usize, usize)> = ranks_a
+        .into_iter()
+        .map(|(country, rank_a)| {
+            let rank_b = ranks_b[&country];
+            (country, rank_a, rank_b)
+        })
+        .collect();
+    let moved = |&(_, rank_a, rank_b): &(String, usize, usize)| rank_a.abs_diff(rank_b);
+    moves.sort_by(|a, b| moved(b).cmp(&moved(a)).then_with(|| a.0.cmp(&b.0)));
+    moves
+}
+
+// Life expectancy rank of every country in year_a (left) and year_b (right),
+// joined by green/red lines for improvement/decline. Only countries with values in
+// both years are ranked; the top_n largest movers are drawn bold and labelled.
+pub(crate) fn plot_slopegraph(
+    records: &[LifeExpectancyRecord],
+    year_a: u16,
+    year_b: u16,
+    top_n: usize,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let moves = rank_moves(records, year_a, year_b);
+    if moves.is_empty() {
+        return Err(AnalysisError::empty(format!("Life expectancy in both {} and {}", year_a, year_b)).into());
+    }
+    let n = moves.len() as f64;
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1300, 1400)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    // Rank 1 at the top
+    let mut chart = ChartBuilder::on(&root)
+        .caption(format!("Life expectancy rank, {} vs {}", year_a, year_b), ("sans-serif", 32))
+        .margin(20)
+        .margin_top(50)
+        .margin_left(330)
+        .margin_right(330)
+        .build_cartesian_2d(0.0..1.0, n + 0.5..0.5)?;
+
+    let move_color = |rank_a: usize, rank_b: usize| match rank_b.cmp(&rank_a) {
+        std::cmp::Ordering::Less => RGBColor(44, 160, 44),
+        std::cmp::Ordering::Greater => RGBColor(214, 39, 40),
+        std::cmp::Ordering::Equal => RGBColor(150, 150, 150),
+    };
+    for (i, &(_, rank_a, rank_b)) in moves.iter().enumerate().rev() {
+        let color = move_color(rank_a, rank_b);
+        let style = if i < top_n { color.stroke_width(3) } else { color.mix(0.3).stroke_width(1) };
+        chart.draw_series(LineSeries::new([(0.0, rank_a as f64), (1.0, rank_b as f64)], style))?;
+    }
+
+    let font = ("sans-serif", 14).into_font();
+    let left = TextStyle::from(font.clone()).pos(Pos::new(HPos::Right, VPos::Center));
+    let right = TextStyle::from(font).pos(Pos::new(HPos::Left, VPos::Center));
+    for &(ref country, rank_a, rank_b) in moves.iter().take(top_n) {
+        let (xa, ya) = chart.backend_coord(&(0.0, rank_a as f64));
+        let (xb, yb) = chart.backend_coord(&(1.0, rank_b as f64));
+        root.draw(&Text::new(format!("{} #{}", country, rank_a), (xa - 8, ya), &left))?;
+        root.draw(&Text::new(format!("#{} {}", rank_b, country), (xb + 8, yb), &right))?;
+    }
+
+    // Year headings over the two rank axes
+    let (xa, top) = chart.backend_coord(&(0.0, 0.5));
+    let (xb, _) = chart.backend_coord(&(1.0, 0.5));
+    let heading = TextStyle::from(("sans-serif", 22).into_font()).pos(Pos::new(HPos::Center, VPos::Bottom));
+    root.draw(&Text::new(year_a.to_string(), (xa, top - 6), &heading))?;
+    root.draw(&Text::new(year_b.to_string(), (xb, top - 6), &heading))?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per country, biggest movers first, from (0, rank in A) to (1, rank in B)
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::categories("Year", vec![year_a.to_string(), year_b.to_string()]);
+        let mut spec = ChartSpecJson::new("line", format!("Life expectancy rank, {} vs {}", year_a, year_b), x_axis, AxisJson::new("Rank", (n + 0.5, 0.5)));
+        for &(ref country, rank_a, rank_b) in &moves {
+            let points = vec![(0.0, rank_a as f64), (1.0, rank_b as f64)];
+            spec = spec.series(SeriesJson::new(country.as_str(), Some(move_color(rank_a, rank_b)), points));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Row order of the country-year heatmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SortBy {
+    Mean,         // highest mean value first
+    Alphabetical, // by country name
+}
+
+// Heatmap rows top to bottom: by name, or by mean value with the countries that
+// have no value at all last
+fn heatmap_rows(pivot: &BTreeMap<String, BTreeMap<u16, f64>>, sort_by: SortBy) -> Vec<String> {
+    let rows: Vec<String> = pivot.keys().cloned().collect();
+    if sort_by == SortBy::Alphabetical {
+        return rows;
+    }
+    let means = pivot
+        .iter()
+        .filter(|(_, years)| !years.is_empty())
+        .map(|(country, years)| (country.clone(), years.values().sum::<f64>() / years.len() as f64));
+    let mut ranked: Vec<String> = rank_countries(means).into_iter().map(|(country, _)| country).collect();
+    ranked.extend(rows.into_iter().filter(|country| pivot[country].is_empty()));
+    ranked
+}
+
+// Label every k-th of n rows so that at most 40 row labels are drawn
+fn label_step(n: usize) -> usize {
+    n.div_ceil(40).max(1)
+}
+
+// One feature as a countries x years heatmap with a color bar; missing cells are
+// hatched grey. With `countries` only those rows are drawn and all are labelled,
+// otherwise every k-th row is labelled so ~190 names stay readable.
+pub(crate) fn plot_country_year_heatmap(
+    records: &[LifeExpectancyRecord],
+    feature: &str,
+    countries: Option<&[&str]>,
+    output_file: &str,
+    sort_by: SortBy,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let meta = field_meta(feature)?;
+    let mut pivot = country_year_pivot(records, meta);
+    if let Some(subset) = countries {
+        if let Some(missing) = subset.iter().find(|country| !pivot.contains_key(**country)) {
+            return Err(format!("Unknown country \"{}\"", missing).into());
+        }
+        pivot.retain(|country, _| subset.contains(&country.as_str()));
+    }
+
+    let rows = heatmap_rows(&pivot, sort_by);
+    let years: Vec<u16> = records.iter().map(|record| record.year).collect::<BTreeSet<_>>().into_iter().collect();
+    let values: Vec<f64> = pivot.values().flat_map(|years| years.values().copied()).collect();
+    let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    if rows.is_empty() || values.is_empty() {
+        return Err(AnalysisError::empty(format!("{} heatmap", meta.name)).into());
+    }
+    let label_every = if countries.is_some() { 1 } else { label_step(rows.len()) };
+
+    let height = (rows.len() as u32 * 12).clamp(400, 2400) + 120;
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1200, height)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (plot_area, legend_area) = root.split_horizontally(1080);
+
+    let (n_rows, n_cols) = (rows.len() as u32, years.len() as u32);
+    let caption = format!("{} by country and year", axis_label(meta));
+    let mut chart = ChartBuilder::on(&plot_area)
+        .caption(&caption, ("sans-serif", 30))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(260)
+        // Integer ranges include their end value, so n cells need 0..n - 1
+        .build_cartesian_2d((0..n_cols - 1).into_segmented(), (0..n_rows - 1).into_segmented())?;
+
+    // Row 0 is drawn at the top
+    let row_label = |value: &SegmentValue<u32>| match value {
+        SegmentValue::CenterOf(y) if *y < n_rows => {
+            let row = (n_rows - 1 - y) as usize;
+            if row.is_multiple_of(label_every) { rows[row].clone() } else { String::new() }
+        }
+        _ => String::new(),
+    };
+    let year_label = |value: &SegmentValue<u32>| match value {
+        SegmentValue::CenterOf(x) => years.get(*x as usize).map(|year| year.to_string()).unwrap_or_default(),
+        _ => String::new(),
+    };
+    chart.configure_mesh()
+        .disable_mesh()
+        .x_labels(years.len())
+        .y_labels(rows.len())
+        .x_label_formatter(&year_label)
+        .y_label_formatter(&row_label)
+        .label_style(("sans-serif", 12))
+        .draw()?;
+
+    let missing = RGBColor(200, 200, 200);
+    for (row, country) in rows.iter().enumerate() {
+        let y = n_rows - 1 - row as u32;
+        for (col, year) in years.iter().enumerate() {
+            let x = col as u32;
+            let cell = [(SegmentValue::Exact(x), SegmentValue::Exact(y)), (SegmentValue::Exact(x + 1), SegmentValue::Exact(y + 1))];
+            match pivot[country].get(year) {
+                Some(value) => {
+                    let t = if max > min { (value - min) / (max - min) } else { 0.5 };
+                    chart.draw_series(std::iter::once(Rectangle::new(cell, colormap::sample(t).filled())))?;
+                }
+                None => {
+                    chart.draw_series(std::iter::once(Rectangle::new(cell.clone(), missing.filled())))?;
+                    chart.draw_series(std::iter::once(PathElement::new(
+                        vec![(cell[0].0.clone(), cell[0].1.clone()), (cell[1].0.clone(), cell[1].1.clone())],
+                        WHITE,
+                    )))?;
+                }
+            }
+        }
+    }
+
+    colormap::draw_color_bar(&legend_area, colormap::Colormap::Sequential, (min, max), meta.name)?;
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per row, top first, with (year index, value) points; missing cells are null
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::categories("Year", years.iter().map(|year| year.to_string()).collect());
+        let mut spec = ChartSpecJson::new("heatmap", caption, x_axis, AxisJson::categories("Country", rows.clone()));
+        for country in &rows {
+            let points = years.iter().enumerate().map(|(col, year)| (col as f64, pivot[country].get(year).copied().unwrap_or(f64::NAN)));
+            spec = spec.series(SeriesJson::new(country.as_str(), None, points.collect()));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Raw and cleaned distributions of one field on shared bins: raw as an outline,
+// cleaned filled, with the number of imputed values noted in the corner
+pub(crate) fn plot_cleaning_comparison(
+    raw: &[f64],
+    cleaned: &[f64],
+    feature_name: &str,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let all: Vec<f64> = raw.iter().chain(cleaned).copied().collect();
+    if all.is_empty() {
+        return Err(AnalysisError::empty(format!("Cleaning comparison of {}", feature_name)).into());
+    }
+    let binning = Bins::covering(&all, 30);
+    let (raw_counts, cleaned_counts) = (binning.counts(raw), binning.counts(cleaned));
+    let max_count = raw_counts.iter().chain(&cleaned_counts).copied().max().unwrap_or(0).max(1);
+    let unit = fields::unit_for(feature_name);
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{} before and after cleaning", feature_name);
+    let x_desc = match field_meta(feature_name) {
+        Ok(meta) => axis_label(meta),
+        Err(_) => feature_name.to_string(),
+    };
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 36))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(60)
+        .build_cartesian_2d(binning.min..binning.max, 0.0..(max_count as f64 * 1.1))?;
+
+    chart
+        .configure_mesh()
+        .x_desc(&x_desc)
+        .y_desc("Count")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| crate::format::format_value(*x, unit))
+        .draw()?;
+
+    let filled = PALETTE[2].mix(0.45).filled();
+    chart
+        .draw_series(cleaned_counts.iter().enumerate().map(|(i, &count)| {
+            let start = binning.start(i);
+            Rectangle::new([(start, 0.0), (start + binning.width, count as f64)], filled)
+        }))?
+        .label(format!("Cleaned ({} values)", cleaned.len()))
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 14, y + 5)], filled));
+
+    let outline = PALETTE[1].stroke_width(2);
+    chart
+        .draw_series(raw_counts.iter().enumerate().map(|(i, &count)| {
+            let start = binning.start(i);
+            Rectangle::new([(start, 0.0), (start + binning.width, count as f64)], outline)
+        }))?
+        .label(format!("Raw ({} values)", raw.len()))
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 14, y + 5)], outline));
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    let imputed = cleaned.len().saturating_sub(raw.len());
+    let (width, _) = root.dim_in_pixel();
+    let style = TextStyle::from(("sans-serif", 18).into_font()).pos(Pos::new(HPos::Right, VPos::Top));
+    root.draw(&Text::new(format!("{} values imputed", imputed), (width as i32 - 30, 130), style))?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // (bin start, count) per bin; every bin is binning.width wide
+    chart_json::write_sidecar(output_file, run, || {
+        let bars = |counts: &[usize]| counts.iter().enumerate().map(|(i, &count)| (binning.start(i), count as f64)).collect();
+        ChartSpecJson::new(
+            "histogram",
+            caption,
+            AxisJson::new(x_desc, (binning.min, binning.max)),
+            AxisJson::new("Count", (0.0, max_count as f64 * 1.1)),
+        )
+        .series(SeriesJson::new(format!("Cleaned ({} values)", cleaned.len()), Some(PALETTE[2]), bars(&cleaned_counts)))
+        .series(SeriesJson::new(format!("Raw ({} values)", raw.len()), Some(PALETTE[1]), bars(&raw_counts)))
+    })?;
+    Ok(())
+}
+
+// The per-year gap of a GapBand as a line over its 95% interval, shaded between
+// the lower and upper bounds; years without an interval break the shading
+pub(crate) fn plot_status_gap(band: &GapBand, meta: &FieldMeta, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let (first_year, last_year) = match (band.years.first(), band.years.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Err(AnalysisError::empty(format!("{} gap", meta.name)).into()),
+    };
+    let values = band.gap.iter().chain(&band.lower).chain(&band.upper).copied().filter(|value| value.is_finite());
+    let (min, max) = value_range(values.chain([0.0])).unwrap();
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{} gap, {} minus {}", meta.name, GAP_GROUPS.0, GAP_GROUPS.1);
+    let unit = match band.unit {
+        ResampleUnit::Row => "rows",
+        ResampleUnit::Country => "countries",
+    };
+    let band_label = format!("95% interval ({} resamples of {})", band.resamples, unit);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(first_year as f64..(last_year as f64).max(first_year as f64 + 1.0), min..max)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Year")
+        .y_desc(format!("Gap in {}", axis_label(meta)))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .draw()?;
+
+    // One polygon per run of years with an interval: the upper bound forward, then
+    // the lower bound back
+    let shade = PALETTE[0].mix(0.25);
+    let rows: Vec<(f64, f64, f64)> =
+        band.years.iter().zip(band.lower.iter().zip(&band.upper)).map(|(&year, (&lower, &upper))| (year as f64, lower, upper)).collect();
+    for run_of_years in rows.split(|(_, lower, upper)| !lower.is_finite() || !upper.is_finite()) {
+        if run_of_years.is_empty() {
+            continue;
+        }
+        let outline: Vec<(f64, f64)> = run_of_years
+            .iter()
+            .map(|&(year, _, upper)| (year, upper))
+            .chain(run_of_years.iter().rev().map(|&(year, lower, _)| (year, lower)))
+            .collect();
+        chart.draw_series(std::iter::once(Polygon::new(outline, shade.filled())))?;
+    }
+    chart
+        .draw_series(std::iter::once(PathElement::new([(first_year as f64, 0.0), (last_year as f64, 0.0)], BLACK.mix(0.5))))?;
+    let points: Vec<(f64, f64)> = band.years.iter().zip(&band.gap).map(|(&year, &gap)| (year as f64, gap)).collect();
+    chart
+        .draw_series(LineSeries::new(points.iter().copied(), PALETTE[0].stroke_width(3)))?
+        .label("Gap")
+        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], PALETTE[0].stroke_width(3)));
+    chart
+        .draw_series(std::iter::empty::<Polygon<(f64, f64)>>())?
+        .label(band_label.as_str())
+        .legend(move |(x, y)| Rectangle::new([(x, y - 6), (x + 20, y + 6)], shade.filled()));
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .background_style(WHITE.mix(0.9))
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let errors = band.lower.iter().copied().zip(band.upper.iter().copied()).collect();
+        ChartSpecJson::new("line", caption, AxisJson::new("Year", (first_year as f64, last_year as f64)), AxisJson::new(axis_label(meta), (min, max)))
+            .series(SeriesJson::new(format!("Gap, {}", band_label), Some(PALETTE[0]), points).with_errors(errors))
+    })?;
+    Ok(())
+}
+
+// Mean vaccine coverage per year, one panel per status on a shared 0-100% axis,
+// one line per vaccine, with the LOW_COVERAGE threshold dashed
+pub(crate) fn plot_coverage_by_status(report: &ImmunizationReport, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let statuses = report.statuses();
+    let years: Vec<u16> = report.cells.iter().map(|cell| cell.year).collect();
+    let (Some(&first_year), Some(&last_year)) = (years.iter().min(), years.iter().max()) else {
+        return Err(AnalysisError::empty("Immunization coverage").into());
+    };
+    let x_range = first_year as f64..(last_year as f64).max(first_year as f64 + 1.0);
+    let caption = "Mean immunization coverage by status";
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (640 * statuses.len() as u32, 620)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (header, body) = root.split_vertically(60);
+    header.draw(&Text::new(caption, (20, 15), ("sans-serif", 30)))?;
+    for (area, status) in body.split_evenly((1, statuses.len())).iter().zip(&statuses) {
+        let mut chart = ChartBuilder::on(area)
+            .caption(*status, ("sans-serif", 22))
+            .margin(15)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(x_range.clone(), 0.0..100.0)?;
+        chart
+            .configure_mesh()
+            .x_desc("Year")
+            .y_desc("Coverage (% of one-year-olds)")
+            .axis_desc_style(("sans-serif", 16))
+            .label_style(("sans-serif", 13))
+            .x_label_formatter(&|x| format!("{:.0}", x))
+            .draw()?;
+
+        let threshold_style = BLACK.mix(0.5).stroke_width(1);
+        let (start, end) = (x_range.start, x_range.end);
+        let dashes = (0..40).map(|i| start + (end - start) * i as f64 / 40.0).step_by(2);
+        chart
+            .draw_series(dashes.map(|x| PathElement::new([(x, LOW_COVERAGE), (x + (end - start) / 40.0, LOW_COVERAGE)], threshold_style)))?
+            .label(format!("{:.0}% coverage", LOW_COVERAGE))
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 8, y)], threshold_style));
+        for (i, vaccine) in VACCINES.iter().enumerate() {
+            let color = PALETTE[i % PALETTE.len()];
+            let points = report.series(vaccine, status).into_iter().map(|(year, mean)| (year as f64, mean));
+            chart
+                .draw_series(LineSeries::new(points, color.stroke_width(2)))?
+                .label(*vaccine)
+                .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(2)));
+        }
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::LowerRight)
+            .background_style(WHITE.mix(0.9))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per vaccine and status, named "Polio, Developed"
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new("Year", (x_range.start, x_range.end));
+        let mut spec = ChartSpecJson::new("line", caption, x_axis, AxisJson::new("Coverage (% of one-year-olds)", (0.0, 100.0)));
+        for status in &statuses {
+            for (i, vaccine) in VACCINES.iter().enumerate() {
+                let points = report.series(vaccine, status).into_iter().map(|(year, mean)| (year as f64, mean)).collect();
+                spec = spec.series(SeriesJson::new(format!("{}, {}", vaccine, status), Some(PALETTE[i % PALETTE.len()]), points));
+            }
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Mean of value_feature within each quantile bin of bin_feature, as bars with
+// error bars of one standard error. Records missing either value are left out.
+pub(crate) fn plot_binned_means(
+    records: &[LifeExpectancyRecord],
+    bin_feature: &str,
+    value_feature: &str,
+    n_bins: usize,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let (bin_meta, value_meta) = (field_meta(bin_feature)?, field_meta(value_feature)?);
+    let (keys, values): (Vec<f64>, Vec<f64>) = records
+        .iter()
+        .filter_map(|record| Some(((bin_meta.getter)(record)?, (value_meta.getter)(record)?)))
+        .filter(|(key, value)| key.is_finite() && value.is_finite())
+        .unzip();
+    if keys.is_empty() {
+        return Err(AnalysisError::empty(format!("{} by {} bins", value_meta.name, bin_meta.name)).into());
+    }
+    let edges = quantile_bins(&keys, n_bins);
+    if edges.len() < 2 {
+        return Err(format!("Not enough distinct {} values to form bins", bin_meta.name).into());
+    }
+
+    // (mean, standard error, count) per bin
+    let bins = edges.len() - 1;
+    let mut groups: Vec<Vec<f64>> = vec![Vec::new(); bins];
+    for (bin, value) in assign_bins(&keys, &edges).into_iter().zip(&values) {
+        if let Some(bin) = bin {
+            groups[bin].push(*value);
+        }
+    }
+    let stats: Vec<(f64, f64, usize)> = groups
+        .iter()
+        .map(|group| {
+            let n = group.len().max(1) as f64;
+            let mean = group.iter().sum::<f64>() / n;
+            let variance = group.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+            (mean, (variance / n).sqrt(), group.len())
+        })
+        .collect();
+    let top = stats.iter().map(|(mean, se, _)| mean + se).fold(0.0, f64::max) * 1.1;
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("Mean {} by {} quantile", value_meta.name, bin_meta.name);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(20)
+        .x_label_area_size(60)
+        .y_label_area_size(70)
+        .build_cartesian_2d((0..bins as u32 - 1).into_segmented(), 0.0..top.max(1e-9))?;
+
+    let bin_label = |bin: usize| {
+        format!(
+            "Q{} ({}–{})",
+            bin + 1,
+            format_tick(edges[bin], TickStyle::Si),
+            format_tick(edges[bin + 1], TickStyle::Si)
+        )
+    };
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(bins)
+        .x_label_formatter(&|x| match x {
+            SegmentValue::CenterOf(bin) => bin_label(*bin as usize),
+            _ => String::new(),
+        })
+        .x_desc(axis_label(bin_meta))
+        .y_desc(format!("Mean {}", axis_label(value_meta)))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    chart.draw_series(stats.iter().enumerate().map(|(bin, &(mean, _, _))| {
+        let bin = bin as u32;
+        let mut bar = Rectangle::new([(SegmentValue::Exact(bin), 0.0), (SegmentValue::Exact(bin + 1), mean)], PALETTE[2].filled());
+        bar.set_margin(0, 0, 20, 20);
+        bar
+    }))?;
+    chart.draw_series(stats.iter().enumerate().map(|(bin, &(mean, se, _))| {
+        ErrorBar::new_vertical(SegmentValue::CenterOf(bin as u32), mean - se, mean, mean + se, BLACK.stroke_width(2), 30)
+    }))?;
+
+    // Observation count above each bar
+    let style = TextStyle::from(("sans-serif", 14).into_font()).pos(Pos::new(HPos::Center, VPos::Bottom));
+    for (bin, &(mean, se, count)) in stats.iter().enumerate() {
+        let (x, y) = chart.backend_coord(&(SegmentValue::CenterOf(bin as u32), mean + se));
+        root.draw(&Text::new(format!("n = {}", count), (x, y - 6), &style))?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::categories(axis_label(bin_meta), (0..bins).map(bin_label).collect());
+        let means = stats.iter().enumerate().map(|(bin, &(mean, _, _))| (bin as f64, mean)).collect();
+        let errors = stats.iter().map(|&(mean, se, _)| (mean - se, mean + se)).collect();
+        ChartSpecJson::new("bar", caption, x_axis, AxisJson::new(format!("Mean {}", axis_label(value_meta)), (0.0, top.max(1e-9))))
+            .series(SeriesJson::new(value_meta.name, Some(PALETTE[2]), means).with_errors(errors))
+    })?;
+    Ok(())
+}
+
+// Cluster profiles as a features x clusters grid of z-scores on a diverging scale
+// centred on the global mean, each cell labelled with its value; features without
+// a score are left grey
+pub(crate) fn plot_cluster_profiles(profiles: &[ClusterProfile], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let Some(first) = profiles.first().filter(|first| !first.scores.is_empty()) else {
+        return Err(AnalysisError::empty("Cluster profiles").into());
+    };
+    let features: Vec<&str> = first.scores.iter().map(|&(name, _)| name).collect();
+    let limit = profiles
+        .iter()
+        .flat_map(|profile| profile.scores.iter().filter_map(|&(_, score)| score))
+        .fold(0.0, |limit: f64, score| limit.max(score.abs()))
+        .max(1e-9);
+
+    let (n_cols, n_rows) = (profiles.len() as u32, features.len() as u32);
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (300 + 110 * n_cols + 120, 140 + 32 * n_rows)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (plot_area, legend_area) = root.split_horizontally(300 + 110 * n_cols);
+
+    let mut chart = ChartBuilder::on(&plot_area)
+        .caption("Cluster feature profiles (z-score)", ("sans-serif", 26))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(280)
+        .build_cartesian_2d((0..n_cols - 1).into_segmented(), (0..n_rows - 1).into_segmented())?;
+
+    // Row 0 is drawn at the top
+    let feature_label = |value: &SegmentValue<u32>| match value {
+        SegmentValue::CenterOf(y) if *y < n_rows => features[(n_rows - 1 - y) as usize].to_string(),
+        _ => String::new(),
+    };
+    let cluster_label = |value: &SegmentValue<u32>| match value {
+        SegmentValue::CenterOf(x) => profiles
+            .get(*x as usize)
+            .map(|profile| format!("{} (n={})", profile.cluster, profile.countries))
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    chart.configure_mesh()
+        .disable_mesh()
+        .x_labels(profiles.len())
+        .y_labels(features.len())
+        .x_label_formatter(&cluster_label)
+        .y_label_formatter(&feature_label)
+        .x_desc("Cluster")
+        .label_style(("sans-serif", 14))
+        .draw()?;
+
+    let style = TextStyle::from(("sans-serif", 13).into_font()).pos(Pos::new(HPos::Center, VPos::Center));
+    for (col, profile) in profiles.iter().enumerate() {
+        let x = col as u32;
+        for (row, &(_, score)) in profile.scores.iter().enumerate() {
+            let y = n_rows - 1 - row as u32;
+            let cell = [(SegmentValue::Exact(x), SegmentValue::Exact(y)), (SegmentValue::Exact(x + 1), SegmentValue::Exact(y + 1))];
+            let color = score.map_or(RGBColor(200, 200, 200), |score| colormap::sample_diverging(score, -limit, limit));
+            chart.draw_series(std::iter::once(Rectangle::new(cell, color.filled())))?;
+            if let Some(score) = score {
+                let (px, py) = chart.backend_coord(&(SegmentValue::CenterOf(x), SegmentValue::CenterOf(y)));
+                root.draw(&Text::new(format!("{:+.2}", score), (px, py), &style))?;
+            }
+        }
+    }
+
+    colormap::draw_color_bar(&legend_area, colormap::Colormap::Diverging, (-limit, limit), "z-score")?;
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per feature with (cluster index, z-score) points; missing scores are null
+    chart_json::write_sidecar(output_file, run, || {
+        let clusters = profiles.iter().map(|profile| format!("{} (n={})", profile.cluster, profile.countries)).collect();
+        let rows = features.iter().map(|feature| feature.to_string()).collect();
+        let mut spec = ChartSpecJson::new("heatmap", "Cluster feature profiles (z-score)", AxisJson::categories("Cluster", clusters), AxisJson::categories("Feature", rows));
+        for (row, feature) in features.iter().enumerate() {
+            let points = profiles.iter().enumerate().map(|(col, profile)| (col as f64, profile.scores[row].1.unwrap_or(f64::NAN)));
+            spec = spec.series(SeriesJson::new(*feature, None, points.collect()));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Score of every tried number of clusters, with the selected k highlighted
+pub(crate) fn plot_k_scores(
+    k_range: RangeInclusive<usize>,
+    scores: &[f64],
+    selected: usize,
+    criterion: KCriterion,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let points: Vec<(f64, f64)> = k_range.zip(scores).map(|(k, &score)| (k as f64, score)).filter(|(_, score)| score.is_finite()).collect();
+    let (Some((x_min, x_max)), Some((y_min, y_max))) =
+        (value_range(points.iter().map(|p| p.0)), value_range(points.iter().map(|p| p.1)))
+    else {
+        return Err(AnalysisError::empty("Cluster count scores").into());
+    };
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1024, 640)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{:?} by number of clusters", criterion);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+    chart
+        .configure_mesh()
+        .x_labels(points.len())
+        .x_label_formatter(&|k| format!("{:.0}", k))
+        .x_desc("Clusters (k)")
+        .y_desc(format!("{:?}", criterion))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    chart.draw_series(LineSeries::new(points.iter().copied(), PALETTE[2].stroke_width(2)))?;
+    chart.draw_series(points.iter().map(|&point| Circle::new(point, 4, PALETTE[2].filled())))?;
+    if let Some(&best) = points.iter().find(|(k, _)| *k == selected as f64) {
+        chart.draw_series(std::iter::once(Circle::new(best, 9, BLACK.stroke_width(2))))?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let y_axis = AxisJson::new(format!("{:?}", criterion), (y_min, y_max));
+        let mut spec = ChartSpecJson::new("line", caption, AxisJson::new("Clusters (k)", (x_min, x_max)), y_axis)
+            .series(SeriesJson::new(format!("{:?}", criterion), Some(PALETTE[2]), points.clone()));
+        if let Some(&best) = points.iter().find(|(k, _)| *k == selected as f64) {
+            spec = spec.series(SeriesJson::new("Selected", Some(BLACK), vec![best]));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Mean of the value feature per band, one line per group with 95% interval bars.
+// Sparse cells are drawn as hollow markers.
+pub(crate) fn plot_banded_interaction(
+    cells: &[BandCell],
+    band_feature: &str,
+    value_feature: &str,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let (band_meta, value_meta) = (field_meta(band_feature)?, field_meta(value_feature)?);
+    let (Some(bands), Some((y_min, y_max))) = (
+        cells.iter().map(|cell| cell.band + 1).max(),
+        value_range(cells.iter().flat_map(|cell| {
+            let (low, high) = cell.ci.unwrap_or((cell.mean, cell.mean));
+            [low, high]
+        })),
+    ) else {
+        return Err(AnalysisError::empty(format!("{} by {} band", value_meta.name, band_meta.name)).into());
+    };
+    let mut edges = vec![(0.0, 0.0); bands];
+    let mut groups: BTreeMap<&str, Vec<&BandCell>> = BTreeMap::new();
+    for cell in cells {
+        edges[cell.band] = cell.range;
+        groups.entry(cell.group.as_str()).or_default().push(cell);
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("Mean {} by {} band", value_meta.name, band_meta.name);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(20)
+        .x_label_area_size(60)
+        .y_label_area_size(70)
+        .build_cartesian_2d((0..bands as u32 - 1).into_segmented(), y_min..y_max)?;
+    let band_label = |band: usize| {
+        let (low, high) = edges[band];
+        format!("{} ({}–{})", band + 1, format_tick(low, TickStyle::Si), format_tick(high, TickStyle::Si))
+    };
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(bands)
+        .x_label_formatter(&|x| match x {
+            SegmentValue::CenterOf(band) => band_label(*band as usize),
+            _ => String::new(),
+        })
+        .x_desc(format!("{} quantile band", axis_label(band_meta)))
+        .y_desc(format!("Mean {}", axis_label(value_meta)))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    for (i, (group, cells)) in groups.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let at = |cell: &BandCell| SegmentValue::CenterOf(cell.band as u32);
+        chart
+            .draw_series(LineSeries::new(cells.iter().map(|cell| (at(cell), cell.mean)), color.stroke_width(2)))?
+            .label(*group)
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(3)));
+        chart.draw_series(cells.iter().filter_map(|cell| {
+            let (low, high) = cell.ci?;
+            Some(ErrorBar::new_vertical(at(cell), low, cell.mean, high, color.stroke_width(1), 12))
+        }))?;
+        chart.draw_series(cells.iter().map(|cell| {
+            let style = if cell.sparse { color.stroke_width(2) } else { color.filled() };
+            Circle::new((at(cell), cell.mean), 6, style)
+        }))?;
+    }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    let style = TextStyle::from(("sans-serif", 14).into_font()).pos(Pos::new(HPos::Right, VPos::Bottom));
+    let (width, height) = root.dim_in_pixel();
+    root.draw(&Text::new(
+        format!("hollow: fewer than {} values", BAND_MIN_OBSERVATIONS),
+        (width as i32 - 30, height as i32 - 20),
+        &style,
+    ))?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per group with (band index, mean) points and their 95% intervals (null below two values)
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::categories(format!("{} quantile band", axis_label(band_meta)), (0..bands).map(band_label).collect());
+        let mut spec = ChartSpecJson::new("line", caption, x_axis, AxisJson::new(format!("Mean {}", axis_label(value_meta)), (y_min, y_max)));
+        for (i, (group, cells)) in groups.iter().enumerate() {
+            let points = cells.iter().map(|cell| (cell.band as f64, cell.mean)).collect();
+            let errors = cells.iter().map(|cell| cell.ci.unwrap_or((f64::NAN, f64::NAN))).collect();
+            spec = spec.series(SeriesJson::new(*group, Some(PALETTE[i % PALETTE.len()]), points).with_errors(errors));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Missing fraction per group as grouped bars, one bar per test (field), all of
+// them over the same grouping
+pub(crate) fn plot_missingness(tests: &[&MissingnessTest], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let Some(first) = tests.first().filter(|first| !first.groups.is_empty()) else {
+        return Err(AnalysisError::empty("Missing values by group").into());
+    };
+    let groups: Vec<&str> = first.groups.iter().map(|(group, _)| group.as_str()).collect();
+    let slots = tests.len() as i32 + 1; // a gap after each group's bars
+    let top = tests
+        .iter()
+        .flat_map(|test| test.groups.iter().map(|(_, fraction)| fraction * 100.0))
+        .fold(0.0, f64::max)
+        .max(1.0)
+        * 1.15;
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("Missing values by {}", first.group_by);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 32))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(0..groups.len() as i32 * slots, 0.0..top)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(groups.len() * slots as usize)
+        .x_label_formatter(&|x| if x % slots == 0 { groups.get((x / slots) as usize).map_or(String::new(), |g| g.to_string()) } else { String::new() })
+        .x_desc(first.group_by)
+        .y_desc("% missing")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    for (i, test) in tests.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let at = |group: usize| group as i32 * slots + i as i32;
+        let label = match test.chi_squared {
+            Some(chi) => format!("{} (chi2 p = {:.1e})", test.field, chi.p_value),
+            None => test.field.to_string(),
+        };
+        chart
+            .draw_series(test.groups.iter().enumerate().map(|(group, (_, fraction))| {
+                Rectangle::new([(at(group), 0.0), (at(group) + 1, fraction * 100.0)], color.filled())
+            }))?
+            .label(label)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 14, y + 5)], color.filled()));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::categories(first.group_by, groups.iter().map(|group| group.to_string()).collect());
+        let mut spec = ChartSpecJson::new("bar", caption, x_axis, AxisJson::new("% missing", (0.0, top)));
+        for (i, test) in tests.iter().enumerate() {
+            let bars = test.groups.iter().enumerate().map(|(group, (_, fraction))| (group as f64, fraction * 100.0)).collect();
+            spec = spec.series(SeriesJson::new(test.field, Some(PALETTE[i % PALETTE.len()]), bars));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn record(country: &str, year: u16, status: &str, measles: Option<f64>, hiv_aids: Option<f64>) -> LifeExpectancyRecord {
+        LifeExpectancyRecord { country: country.to_string(), year, status: status.to_string(), measles, hiv_aids, ..Default::default() }
+    }
+
+    #[test]
+    fn sidecar_holds_the_plotted_scores() {
+        let dir = scratch_dir("k-scores-sidecar");
+        let output_file = dir.join("k_scores.png");
+        let output_file = output_file.to_str().unwrap();
+        let mut run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        run.chart_json = true;
+        // The NaN score is not drawn, so it is not in the sidecar either
+        let scores = [0.25, 0.5, f64::NAN, 0.125];
+        plot_k_scores(2..=5, &scores, 3, KCriterion::Silhouette, output_file, &run).unwrap();
+
+        let text = std::fs::read_to_string(chart_json::sidecar_path(output_file)).unwrap();
+        let sidecar: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(sidecar["chart_type"], "line");
+        assert_eq!(sidecar["x_axis"]["label"], "Clusters (k)");
+        let series = sidecar["series"].as_array().unwrap();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0]["name"], "Silhouette");
+        assert_eq!(series[0]["color"], chart_json::hex(PALETTE[2]));
+        assert_eq!(series[0]["points"], serde_json::json!([[2.0, 0.25], [3.0, 0.5], [5.0, 0.125]]));
+        assert_eq!(series[1]["points"], serde_json::json!([[3.0, 0.5]]));
+        assert_eq!(sidecar["run"]["input_hash"], run.input_hash);
+    }
+
+    #[test]
+    fn no_sidecar_unless_the_run_asks() {
+        let dir = scratch_dir("k-scores-no-sidecar");
+        let output_file = dir.join("k_scores.png");
+        let output_file = output_file.to_str().unwrap();
+        let run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        plot_k_scores(2..=3, &[0.1, 0.2], 2, KCriterion::Modularity, output_file, &run).unwrap();
+        assert!(std::path::Path::new(output_file).exists());
+        assert!(!chart_json::sidecar_path(output_file).exists());
+    }
+
+    #[test]
+    fn facets_share_the_axes_of_all_points() {
+        let dir = scratch_dir("faceted-scatter");
+        let output_file = dir.join("faceted.png");
+        let output_file = output_file.to_str().unwrap();
+        let mut run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        run.chart_json = true;
+        let record = |status: &str, gdp: f64, life: f64| LifeExpectancyRecord {
+            country: format!("{} {}", status, gdp),
+            status: status.to_string(),
+            gdp: Some(gdp),
+            life_expectancy: Some(life),
+            ..Default::default()
+        };
+        let mut records: Vec<LifeExpectancyRecord> = (1..=4).map(|x| record("Developed", x as f64, 2.0 * x as f64)).collect();
+        records.extend((10..=13).map(|x| record("Developing", x as f64, 40.0 - x as f64)));
+        // No status: left out, and its point does not widen the axes
+        records.push(record("", 1000.0, 1000.0));
+        let by_status: fn(&LifeExpectancyRecord) -> &str = |record| record.status.as_str();
+        plot_faceted_scatter(&records, "GDP", "Life expectancy", by_status, output_file, &PlotTheme::default(), &run).unwrap();
+
+        let sidecar: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(chart_json::sidecar_path(output_file)).unwrap()).unwrap();
+        let drawn: Vec<(f64, f64)> = records[..8].iter().map(|record| (record.gdp.unwrap(), record.life_expectancy.unwrap())).collect();
+        let ((x_min, x_max), (y_min, y_max)) = scatter_bounds(&drawn, &ScatterOptions::default());
+        assert_eq!(sidecar["x_axis"]["range"], serde_json::json!([x_min, x_max]));
+        assert_eq!(sidecar["y_axis"]["range"], serde_json::json!([y_min, y_max]));
+        let names: Vec<&str> = sidecar["series"].as_array().unwrap().iter().map(|series| series["name"].as_str().unwrap()).collect();
+        assert_eq!(
+            names,
+            [
+                "Developed (n = 4, R² = 1.00)",
+                "Developed (n = 4, R² = 1.00) trend line",
+                "Developing (n = 4, R² = 1.00)",
+                "Developing (n = 4, R² = 1.00) trend line"
+            ]
+        );
+        // Each trend line spans the shared x range: y = 2x and y = 40 - x
+        assert_eq!(sidecar["series"][1]["points"], serde_json::json!([[x_min, 2.0 * x_min], [x_max, 2.0 * x_max]]));
+        assert_eq!(sidecar["series"][3]["points"], serde_json::json!([[x_min, 40.0 - x_min], [x_max, 40.0 - x_max]]));
+    }
+
+    #[test]
+    fn many_panels_wrap_into_a_grid() {
+        assert_eq!(grid_shape(1), (1, 1));
+        assert_eq!(grid_shape(7), (3, 3));
+        assert_eq!(grid_shape(9), (3, 3));
+        assert_eq!(grid_shape(10), (3, 4));
+    }
+
+    #[test]
+    fn heatmap_rows_sort_by_mean_with_empty_countries_last() {
+        let pivot: BTreeMap<String, BTreeMap<u16, f64>> = [
+            ("Chad", vec![(2000, 50.0), (2001, 54.0)]),
+            ("Albania", vec![(2000, 76.0)]),
+            ("Niue", vec![]),
+            ("Japan", vec![(2000, 81.0), (2001, 83.0)]),
+        ]
+        .into_iter()
+        .map(|(country, years)| (country.to_string(), years.into_iter().collect()))
+        .collect();
+        assert_eq!(heatmap_rows(&pivot, SortBy::Mean), ["Japan", "Albania", "Chad", "Niue"]);
+        assert_eq!(heatmap_rows(&pivot, SortBy::Alphabetical), ["Albania", "Chad", "Japan", "Niue"]);
+
+        // ~190 countries get every fifth row labelled
+        assert_eq!((label_step(0), label_step(40), label_step(41), label_step(193)), (1, 1, 2, 5));
+    }
+
+    #[test]
+    fn panels_span_the_years_and_values_of_both_groups() {
+        let record = |status: &str, year: u16, gdp: Option<f64>| LifeExpectancyRecord {
+            country: "A".to_string(),
+            status: status.to_string(),
+            year,
+            gdp,
+            ..Default::default()
+        };
+        let records = [
+            record("Developed", 2001, Some(30.0)),
+            record("Developed", 2002, Some(10.0)),
+            record("Developing", 2000, Some(20.0)),
+            record("Developing", 2003, None),
+        ];
+        let gdp = panel_bounds(&yearly_means_by_status(&records, field_meta("GDP").unwrap()));
+        // The year without a value does not widen the panel; values are padded by 5%
+        assert_eq!(gdp, Some(((2000, 2002), (9.0, 31.0))));
+        assert_eq!(panel_bounds(&yearly_means_by_status(&records, field_meta("Measles").unwrap())), None);
+    }
+
+    #[test]
+    fn group_means_keep_the_years_of_each_field() {
+        let records = [
+            record("A", 2000, "Developing", Some(2.0), None),
+            record("A", 2001, "Developing", Some(4.0), Some(0.5)),
+            record("A", 2002, "Developing", None, Some(1.5)),
+            record("B", 2001, "Developed", Some(8.0), Some(0.1)),
+        ];
+        let measles = group_yearly_means(&records, field_meta("Measles").unwrap(), "Developing").unwrap();
+        let hiv_aids = group_yearly_means(&records, field_meta("HIV/AIDS").unwrap(), "Developing").unwrap();
+        assert_eq!(measles.into_iter().collect::<Vec<_>>(), vec![(2000, 2.0), (2001, 4.0)]);
+        assert_eq!(hiv_aids.into_iter().collect::<Vec<_>>(), vec![(2001, 0.5), (2002, 1.5)]);
+        let error = group_yearly_means(&records, field_meta("Measles").unwrap(), "Least developed").unwrap_err();
+        assert_eq!(error.to_string(), "Measles for group \"Least developed\": no rows or values to analyse");
+    }
+
+    #[test]
+    fn rank_moves_skip_countries_missing_either_year() {
+        let life = |country: &str, year: u16, value: Option<f64>| {
+            let mut record = record(country, year, "", None, None);
+            record.life_expectancy = value;
+            record
+        };
+        let records = [
+            life("A", 2000, Some(80.0)),
+            life("B", 2000, Some(70.0)),
+            life("C", 2000, Some(60.0)),
+            life("D", 2000, Some(90.0)),
+            life("A", 2015, Some(71.0)),
+            life("B", 2015, Some(72.0)),
+            life("C", 2015, Some(85.0)),
+            life("D", 2015, None),
+            life("E", 2015, Some(99.0)),
+        ];
+        // D (2015 blank) and E (no 2000 row) would otherwise hold rank 1 in one year;
+        // equal moves are listed by name
+        let moves = rank_moves(&records, 2000, 2015);
+        let expected = [("A", 1, 3), ("C", 3, 1), ("B", 2, 2)].map(|(country, a, b)| (country.to_string(), a, b));
+        assert_eq!(moves, expected);
+        assert!(rank_moves(&records, 2000, 2001).is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+use std::error::Error;
+use std::path::Path;
+use crate::workspace::WORKSPACE_DIR;
+
+// Subcommands accepted on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    All,   // full analysis (default when no subcommand is given)
+    Graph,   // similarity graph build, export and clustering only
+    Cluster, // clustering of the graph stored in the workspace
+    Diff(String, String), // compare two versions of the dataset
+    Trend(String),        // per-country trend slopes of one field
+    Trajectory(String),   // clusters of countries by how one field changed over the years
+    Describe,             // summary statistics streamed from the raw file
+    GraphDiff(f64, f64),  // compare similarity graphs built at two thresholds
+    Lag(String, String),  // correlation of one field with another some years later
+    Demo,                 // full analysis of a generated dataset, for trying the crate without the WHO file
+    Similar,              // the countries most similar to --country
+    Radar,                // percentile profile of --country
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CliArgs {
+    pub(crate) command: Command,
+    // Restrict the graph to one development status
+    pub(crate) status: Option<String>,
+    // CSV mapping Country to Region for the neighbor purity test
+    pub(crate) regions: Option<String>,
+    // Rebuild instead of loading from the workspace
+    pub(crate) no_cache: bool,
+    // Directory of the workspace, in place of WORKSPACE_DIR
+    pub(crate) workspace: Option<String>,
+    // Column selection for the similarity graph
+    pub(crate) features: Option<String>,
+    // Path of the TOML config file
+    pub(crate) config: Option<String>,
+    // fail, skip or log malformed rows while cleaning
+    pub(crate) row_errors: Option<String>,
+    // who or v2: layout of the input file; detected from its header when None
+    pub(crate) schema: Option<String>,
+    // point, comma or auto: decimal separator of the input's numbers (default point)
+    pub(crate) decimal: Option<String>,
+    // Shuffles for permutation-based edge pruning; off when None
+    pub(crate) permutations: Option<usize>,
+    // Null percentile an edge must exceed when pruning (default 95)
+    pub(crate) null_percentile: Option<f64>,
+    // Number of clusters, or modularity / silhouette to select it (default silhouette)
+    pub(crate) clusters: Option<String>,
+    // One cluster representative per development status
+    pub(crate) per_status: bool,
+    // Comma-separated pipeline steps to run (default: all)
+    pub(crate) steps: Option<String>,
+    // Continue with the remaining steps after one fails
+    pub(crate) keep_going: bool,
+    // Validate inputs and settings without producing any output
+    pub(crate) dry_run: bool,
+    // Run every step, even those whose inputs match the last run's manifest
+    pub(crate) force: bool,
+    // Before/after histograms of every imputed field in cleaning/
+    pub(crate) cleaning_plots: bool,
+    // Recompute inconsistent percentage expenditure values from GDP while cleaning
+    pub(crate) correct_expenditure: bool,
+    // Also write the cleaned data as one file per "country" or "year" in cleaned/
+    pub(crate) partition: Option<String>,
+    // latest, majority or keep: one Status per country while cleaning
+    pub(crate) unify_status: Option<String>,
+    // Importance of each selected graph feature, e.g. 2,1,0
+    pub(crate) feature_weights: Option<Vec<f64>>,
+    // Feature weights of graph B in graph-diff; B uses the raw features when None
+    pub(crate) compare_weights: Option<Vec<f64>>,
+    // Append graph exports to existing files instead of replacing them
+    pub(crate) append: bool,
+    // Constant columns added to every exported graph row, e.g. Year=2005
+    pub(crate) tags: Vec<(String, String)>,
+    // Winsorize columns before correlating in the heatmap
+    pub(crate) robust: bool,
+    // Winsorizing quantiles, e.g. 0.01 and 0.99 from "1,99"
+    pub(crate) winsor: Option<(f64, f64)>,
+    // Decimals of computed values in graph exports; shortest exact form when None
+    pub(crate) precision: Option<usize>,
+    // Rows a graph export writes at most; the rest are counted and reported
+    pub(crate) max_output_rows: Option<usize>,
+    // Rows between flushes of a graph export to disk; when the write buffer fills if None
+    pub(crate) flush_every: Option<usize>,
+    // Seed of the run's random steps and of the demo dataset, in place of [random] seed
+    pub(crate) seed: Option<u64>,
+    // Share of demo values left blank, between 0 and 1 (default 0.05)
+    pub(crate) missing: Option<f64>,
+    // Write a <chart>.chart.json sidecar next to every chart
+    pub(crate) chart_json: bool,
+    // JSON of image hashes the run's charts are compared with (e.g. demo runs)
+    pub(crate) snapshots: Option<String>,
+    // Write the --snapshots file from this run instead of comparing with it
+    pub(crate) update_snapshots: bool,
+    // Query country of the similar and radar commands
+    pub(crate) country: Option<String>,
+    // Number of countries listed by the similar command (default 10)
+    pub(crate) neighbors: Option<usize>,
+    // cosine or dtw: how the trajectory command compares two countries
+    pub(crate) similarity: Option<String>,
+    // row or country: what the bootstrap of the status gap resamples (default country)
+    pub(crate) bootstrap_unit: Option<String>,
+    // degree, betweenness or betweenness:N: score the cluster representatives maximise
+    pub(crate) centrality: Option<String>,
+    // Directory for Gephi's edges.csv and nodes.csv, written by the graph stage
+    pub(crate) gephi: Option<String>,
+    // Graphviz file for the graph with the edges inside each cluster bundled into one
+    pub(crate) dot: Option<String>,
+    // error, drop or keep: graph features missing in more than --max-missing of the rows
+    pub(crate) missing_features: Option<String>,
+    // Largest missing share of a graph feature before that policy applies (default 0.2)
+    pub(crate) max_missing: Option<f64>,
+    // Column the console tables are sorted on and whether descending, from "Column[:asc|:desc]"
+    pub(crate) sort_by: Option<(String, bool)>,
+}
+
+impl CliArgs {
+    // Where cached intermediate results and converted inputs are kept
+    pub(crate) fn workspace_dir(&self) -> &Path {
+        Path::new(self.workspace.as_deref().unwrap_or(WORKSPACE_DIR))
+    }
+}
+
+fn parse_weights(value: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+    Ok(value
+        .split(',')
+        .map(|weight| weight.trim().parse::<f64>().map_err(|_| format!("Invalid weight: {}", weight.trim())))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+// Parse the arguments following the program name
+pub(crate) fn parse_args<I>(args: I) -> Result<CliArgs, Box<dyn Error>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut parsed = CliArgs {
+        command: Command::All,
+        status: None,
+        regions: None,
+        no_cache: false,
+        workspace: None,
+        features: None,
+        config: None,
+        row_errors: None,
+        schema: None,
+        decimal: None,
+        permutations: None,
+        null_percentile: None,
+        clusters: None,
+        per_status: false,
+        steps: None,
+        keep_going: false,
+        dry_run: false,
+        force: false,
+        cleaning_plots: false,
+        correct_expenditure: false,
+        partition: None,
+        unify_status: None,
+        feature_weights: None,
+        compare_weights: None,
+        append: false,
+        tags: Vec::new(),
+        robust: false,
+        winsor: None,
+        precision: None,
+        max_output_rows: None,
+        flush_every: None,
+        seed: None,
+        missing: None,
+        chart_json: false,
+        snapshots: None,
+        update_snapshots: false,
+        country: None,
+        neighbors: None,
+        gephi: None,
+        similarity: None,
+        bootstrap_unit: None,
+        centrality: None,
+        dot: None,
+        missing_features: None,
+        max_missing: None,
+        sort_by: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "graph" => parsed.command = Command::Graph,
+            "cluster" => parsed.command = Command::Cluster,
+            "describe" => parsed.command = Command::Describe,
+            "demo" => parsed.command = Command::Demo,
+            "similar" => parsed.command = Command::Similar,
+            "radar" => parsed.command = Command::Radar,
+            "diff" => {
+                let usage = "diff requires two CSV files: diff <a.csv> <b.csv>";
+                let a = args.next().ok_or(usage)?;
+                let b = args.next().ok_or(usage)?;
+                parsed.command = Command::Diff(a, b);
+            }
+            "graph-diff" => {
+                let usage = "graph-diff requires two similarity thresholds: graph-diff 0.8 0.9";
+                let mut threshold = || -> Result<f64, Box<dyn Error>> {
+                    let value = args.next().ok_or(usage)?;
+                    Ok(value.parse().map_err(|_| format!("Invalid threshold: {}", value))?)
+                };
+                parsed.command = Command::GraphDiff(threshold()?, threshold()?);
+            }
+            "lag" => {
+                let usage = "lag requires two field names: lag GDP \"Life expectancy\"";
+                let x = args.next().ok_or(usage)?;
+                let y = args.next().ok_or(usage)?;
+                parsed.command = Command::Lag(x, y);
+            }
+            "trajectory" => {
+                let feature = args.next().ok_or("trajectory requires a field name: trajectory \"Life expectancy\"")?;
+                parsed.command = Command::Trajectory(feature);
+            }
+            "trend" => {
+                let feature = args.next().ok_or("trend requires a field name: trend \"Life expectancy\"")?;
+                parsed.command = Command::Trend(feature);
+            }
+            "--no-cache" => parsed.no_cache = true,
+            "--per-status" => parsed.per_status = true,
+            "--keep-going" => parsed.keep_going = true,
+            "--dry-run" => parsed.dry_run = true,
+            "--force" => parsed.force = true,
+            "--chart-json" => parsed.chart_json = true,
+            "--update-snapshots" => parsed.update_snapshots = true,
+            "--cleaning-plots" => parsed.cleaning_plots = true,
+            "--correct-expenditure" => parsed.correct_expenditure = true,
+            "--append" => parsed.append = true,
+            "--tag" => {
+                let value = args.next().ok_or("--tag requires a column and value (e.g. Year=2005)")?;
+                let (name, tag) = value.split_once('=').ok_or_else(|| format!("Invalid tag (expected NAME=VALUE): {}", value))?;
+                if name.trim().is_empty() {
+                    return Err(format!("Invalid tag (empty column name): {}", value).into());
+                }
+                parsed.tags.push((name.trim().to_string(), tag.trim().to_string()));
+            }
+            "--steps" => {
+                let value = args.next().ok_or("--steps requires a list of steps (e.g. clean,eda,graph)")?;
+                parsed.steps = Some(value);
+            }
+            "--workspace" => {
+                let value = args.next().ok_or("--workspace requires a directory")?;
+                parsed.workspace = Some(value);
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config requires a file path")?;
+                parsed.config = Some(value);
+            }
+            "--partition" => {
+                let value = args.next().ok_or("--partition requires country or year")?;
+                parsed.partition = Some(value);
+            }
+            "--clusters" => {
+                let value = args.next().ok_or("--clusters requires a number, modularity or silhouette")?;
+                parsed.clusters = Some(value);
+            }
+            "--unify-status" => {
+                let value = args.next().ok_or("--unify-status requires latest, majority or keep")?;
+                parsed.unify_status = Some(value);
+            }
+            "--row-errors" => {
+                let value = args.next().ok_or("--row-errors requires fail, skip or log")?;
+                parsed.row_errors = Some(value);
+            }
+            "--snapshots" => {
+                let value = args.next().ok_or("--snapshots requires a file path")?;
+                parsed.snapshots = Some(value);
+            }
+            "--similarity" => {
+                let value = args.next().ok_or("--similarity requires cosine or dtw")?;
+                parsed.similarity = Some(value);
+            }
+            "--bootstrap-unit" => {
+                let value = args.next().ok_or("--bootstrap-unit requires row or country")?;
+                parsed.bootstrap_unit = Some(value);
+            }
+            "--schema" => {
+                let value = args.next().ok_or("--schema requires who or v2")?;
+                parsed.schema = Some(value);
+            }
+            "--decimal" => {
+                let value = args.next().ok_or("--decimal requires point, comma or auto")?;
+                parsed.decimal = Some(value);
+            }
+            "--features" => {
+                let value = args.next().ok_or("--features requires a column selection (e.g. \"GDP, Schooling\")")?;
+                parsed.features = Some(value);
+            }
+            "--permutations" => {
+                let value = args.next().ok_or("--permutations requires a count (e.g. 20)")?;
+                let count: usize = value.parse().map_err(|_| format!("Invalid permutation count: {}", value))?;
+                parsed.permutations = Some(count);
+            }
+            "--null-percentile" => {
+                let value = args.next().ok_or("--null-percentile requires a value between 0 and 100")?;
+                let percentile: f64 = value.parse().map_err(|_| format!("Invalid percentile: {}", value))?;
+                if !(0.0..=100.0).contains(&percentile) {
+                    return Err(format!("Percentile must be between 0 and 100: {}", value).into());
+                }
+                parsed.null_percentile = Some(percentile);
+            }
+            "--robust" => parsed.robust = true,
+            "--winsor" => {
+                let value = args.next().ok_or("--winsor requires lower and upper percentiles (e.g. 1,99)")?;
+                let invalid = || format!("Invalid winsorizing percentiles (expected LOW,HIGH with 0 <= LOW < HIGH <= 100): {}", value);
+                let (low, high) = value.split_once(',').ok_or_else(invalid)?;
+                let (low, high): (f64, f64) = (low.trim().parse().map_err(|_| invalid())?, high.trim().parse().map_err(|_| invalid())?);
+                if !(0.0 <= low && low < high && high <= 100.0) {
+                    return Err(invalid().into());
+                }
+                parsed.winsor = Some((low / 100.0, high / 100.0));
+            }
+            "--precision" => {
+                let value = args.next().ok_or("--precision requires a number of decimals (e.g. 4)")?;
+                let decimals: usize = value.parse().map_err(|_| format!("Invalid precision: {}", value))?;
+                parsed.precision = Some(decimals);
+            }
+            "--max-output-rows" => {
+                let value = args.next().ok_or("--max-output-rows requires a number of rows (e.g. 100000)")?;
+                let rows: usize = value.parse().map_err(|_| format!("Invalid row limit: {}", value))?;
+                parsed.max_output_rows = Some(rows);
+            }
+            "--flush-every" => {
+                let value = args.next().ok_or("--flush-every requires a number of rows (e.g. 10000)")?;
+                let rows: usize = value.parse().ok().filter(|&rows| rows > 0).ok_or_else(|| format!("Invalid flush interval: {}", value))?;
+                parsed.flush_every = Some(rows);
+            }
+            "--sort-by" => {
+                let value = args.next().ok_or("--sort-by requires a column name (e.g. \"d\" or \"Mean:asc\")")?;
+                let (column, descending) = match value.rsplit_once(':') {
+                    Some((column, "asc")) => (column, false),
+                    Some((column, "desc")) => (column, true),
+                    _ => (value.as_str(), true),
+                };
+                if column.trim().is_empty() {
+                    return Err(format!("Invalid sort column: {}", value).into());
+                }
+                parsed.sort_by = Some((column.trim().to_string(), descending));
+            }
+            "--missing-features" => {
+                let value = args.next().ok_or("--missing-features requires error, drop or keep")?;
+                parsed.missing_features = Some(value);
+            }
+            "--max-missing" => {
+                let value = args.next().ok_or("--max-missing requires a fraction between 0 and 1 (e.g. 0.2)")?;
+                let fraction: f64 = value.parse().map_err(|_| format!("Invalid missing fraction: {}", value))?;
+                if !(0.0..=1.0).contains(&fraction) {
+                    return Err(format!("Missing fraction must be between 0 and 1: {}", value).into());
+                }
+                parsed.max_missing = Some(fraction);
+            }
+            "--centrality" => {
+                let value = args.next().ok_or("--centrality requires degree, betweenness or betweenness:N")?;
+                parsed.centrality = Some(value);
+            }
+            "--gephi" => {
+                let value = args.next().ok_or("--gephi requires an output directory (e.g. gephi)")?;
+                parsed.gephi = Some(value);
+            }
+            "--dot" => {
+                let value = args.next().ok_or("--dot requires an output file (e.g. graph_bundled.dot)")?;
+                parsed.dot = Some(value);
+            }
+            "--country" => {
+                let value = args.next().ok_or("--country requires a country name (e.g. Japan)")?;
+                parsed.country = Some(value);
+            }
+            "--n" => {
+                let value = args.next().ok_or("--n requires a number of countries (e.g. 10)")?;
+                let n: usize = value.parse().map_err(|_| format!("Invalid number of countries: {}", value))?;
+                if n == 0 {
+                    return Err("--n must be at least 1".into());
+                }
+                parsed.neighbors = Some(n);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a number (e.g. 7)")?;
+                parsed.seed = Some(value.parse().map_err(|_| format!("Invalid seed: {}", value))?);
+            }
+            "--missing" => {
+                let value = args.next().ok_or("--missing requires a fraction between 0 and 1 (e.g. 0.05)")?;
+                let fraction: f64 = value.parse().map_err(|_| format!("Invalid missing fraction: {}", value))?;
+                if !(0.0..=1.0).contains(&fraction) {
+                    return Err(format!("Missing fraction must be between 0 and 1: {}", value).into());
+                }
+                parsed.missing = Some(fraction);
+            }
+            "--weights" => {
+                let value = args.next().ok_or("--weights requires one weight per feature (e.g. 2,1,0)")?;
+                parsed.feature_weights = Some(parse_weights(&value)?);
+            }
+            "--compare-weights" => {
+                let value = args.next().ok_or("--compare-weights requires one weight per feature (e.g. 1,1,1)")?;
+                parsed.compare_weights = Some(parse_weights(&value)?);
+            }
+            "--regions" => {
+                let value = args.next().ok_or("--regions requires a CSV file with Country and Region columns")?;
+                parsed.regions = Some(value);
+            }
+            "--status" => {
+                let value = args.next().ok_or("--status requires a value (e.g. Developing)")?;
+                parsed.status = Some(value);
+            }
+            other => return Err(format!("Unknown argument: {}", other).into()),
+        }
+    }
+
+    if parsed.update_snapshots && parsed.snapshots.is_none() {
+        return Err("--update-snapshots requires --snapshots FILE".into());
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::eda_statistics::summary_statistics;
+use crate::fields::{FieldMeta, LifeExpectancyRecord};
+use crate::table::{Cell, Table, TableOptions};
+
+// Mean of every selected feature within one cluster, as a z-score against the
+// mean and standard deviation over all records
+#[derive(Debug, Clone)]
+pub(crate) struct ClusterProfile {
+    pub(crate) cluster: usize,
+    pub(crate) countries: usize,
+    pub(crate) scores: Vec<(&'static str, Option<f64>)>, // None: no value in the cluster, or a constant feature
+}
+
+impl ClusterProfile {
+    // The n features furthest above (or, with `above` false, below) the global mean
+    pub(crate) fn extremes(&self, n: usize, above: bool) -> Vec<(&'static str, f64)> {
+        let mut scores: Vec<(&'static str, f64)> = self
+            .scores
+            .iter()
+            .filter_map(|&(name, score)| Some((name, score?)))
+            .filter(|&(_, score)| if above { score > 0.0 } else { score < 0.0 })
+            .collect();
+        scores.sort_by(|a, b| if above { b.1.total_cmp(&a.1) } else { a.1.total_cmp(&b.1) });
+        scores.truncate(n);
+        scores
+    }
+}
+
+// Profile of every cluster in `clusters` (country -> cluster id), in cluster id
+// order. Records of countries outside `clusters` only count towards the global
+// mean and standard deviation.
+pub(crate) fn cluster_feature_profiles(
+    clusters: &HashMap<String, usize>,
+    records: &[LifeExpectancyRecord],
+    features: &[&FieldMeta],
+) -> Vec<ClusterProfile> {
+    let mut members: BTreeMap<usize, Vec<&LifeExpectancyRecord>> = BTreeMap::new();
+    for record in records {
+        if let Some(&cluster) = clusters.get(&record.country) {
+            members.entry(cluster).or_default().push(record);
+        }
+    }
+    let mut sizes: BTreeMap<usize, usize> = BTreeMap::new();
+    for &cluster in clusters.values() {
+        *sizes.entry(cluster).or_insert(0) += 1;
+    }
+
+    let global: Vec<Option<(f64, f64)>> = features
+        .iter()
+        .map(|meta| {
+            let values: Vec<Option<f64>> = records.iter().map(|record| (meta.getter)(record)).collect();
+            let stats = summary_statistics(&values)?;
+            Some((stats.mean, stats.std_dev.filter(|&std| std > 0.0)?))
+        })
+        .collect();
+
+    sizes
+        .into_iter()
+        .map(|(cluster, countries)| {
+            let rows = members.get(&cluster).map(Vec::as_slice).unwrap_or_default();
+            let scores = features
+                .iter()
+                .zip(&global)
+                .map(|(meta, global)| {
+                    let values: Vec<f64> = rows.iter().filter_map(|record| (meta.getter)(record)).collect();
+                    let score = global.zip((!values.is_empty()).then_some(values)).map(|((mean, std), values)| {
+                        (values.iter().sum::<f64>() / values.len() as f64 - mean) / std
+                    });
+                    (meta.name, score)
+                })
+                .collect();
+            ClusterProfile { cluster, countries, scores }
+        })
+        .collect()
+}
+
+// Each cluster's three most above- and below-average features
+pub(crate) fn print_cluster_profiles(profiles: &[ClusterProfile], options: &TableOptions) {
+    let describe = |scores: Vec<(&str, f64)>| {
+        let parts: Vec<String> = scores.iter().map(|(name, score)| format!("{} {:+.2}", name, score)).collect();
+        if parts.is_empty() { "-".to_string() } else { parts.join(", ") }
+    };
+    println!("Cluster profiles (z-score of the cluster mean):");
+    let mut table = Table::new(&["Cluster", "Countries", "Above average", "Below average"]);
+    for profile in profiles {
+        table = table.add_row(vec![
+            Cell::count(profile.cluster),
+            Cell::count(profile.countries),
+            Cell::text(describe(profile.extremes(3, true))),
+            Cell::text(describe(profile.extremes(3, false))),
+        ]);
+    }
+    table.print(options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields;
+
+    fn record(country: &str, gdp: f64, schooling: Option<f64>) -> LifeExpectancyRecord {
+        LifeExpectancyRecord { country: country.to_string(), gdp: Some(gdp), schooling, ..Default::default() }
+    }
+
+    #[test]
+    fn cluster_means_are_scored_against_the_global_mean() {
+        let records = [record("A", 1.0, Some(10.0)), record("B", 3.0, Some(10.0)), record("C", 5.0, None), record("D", 3.0, None)];
+        let clusters: HashMap<String, usize> = [("A", 0), ("B", 0), ("C", 1)].iter().map(|&(c, k)| (c.to_string(), k)).collect();
+        let features = [fields::field("GDP").unwrap(), fields::field("Schooling").unwrap()];
+        let profiles = cluster_feature_profiles(&clusters, &records, &features);
+
+        // GDP has mean 3 and standard deviation sqrt(8/3) over all four records
+        let std = (8.0_f64 / 3.0).sqrt();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!((profiles[0].cluster, profiles[0].countries), (0, 2));
+        assert_eq!(profiles[0].scores, vec![("GDP", Some(-1.0 / std)), ("Schooling", None)]);
+        assert_eq!(profiles[1].scores, vec![("GDP", Some(2.0 / std)), ("Schooling", None)]);
+        assert_eq!(profiles[1].extremes(3, true), vec![("GDP", 2.0 / std)]);
+        assert!(profiles[1].extremes(3, false).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use plotters::prelude::*;
+use crate::format::{format_tick, TickStyle};
+
+// Color ramps for counts, magnitudes and signed values
+
+// Viridis-like control points from light to dark, evenly spaced over [0, 1]
+const SEQUENTIAL: [(u8, u8, u8); 5] = [
+    (253, 231, 37),
+    (94, 201, 98),
+    (33, 145, 140),
+    (59, 82, 139),
+    (68, 1, 84),
+];
+
+// RdBu-like control points from red (low) through white to blue (high)
+const DIVERGING: [(u8, u8, u8); 5] = [
+    (178, 24, 43),
+    (239, 138, 98),
+    (247, 247, 247),
+    (103, 169, 207),
+    (33, 102, 172),
+];
+
+// Which ramp a color-coded plot uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Colormap {
+    Sequential,
+    Diverging,
+}
+
+impl Colormap {
+    // Color at position t in [0, 1] along this ramp
+    pub(crate) fn at(self, t: f64) -> RGBColor {
+        match self {
+            Colormap::Sequential => interpolate(&SEQUENTIAL, t),
+            Colormap::Diverging => interpolate(&DIVERGING, t),
+        }
+    }
+}
+
+// Piecewise-linear interpolation between evenly spaced control points; t is
+// clamped to [0, 1] and non-finite t maps to the first point
+fn interpolate(points: &[(u8, u8, u8)], t: f64) -> RGBColor {
+    let t = if t.is_finite() { t.clamp(0.0, 1.0) } else { 0.0 };
+    let scaled = t * (points.len() - 1) as f64;
+    let i = (scaled.floor() as usize).min(points.len() - 2);
+    let f = scaled - i as f64;
+    let (a, b) = (points[i], points[i + 1]);
+    let lerp = |x: u8, y: u8| (x as f64 + (y as f64 - x as f64) * f).round() as u8;
+    RGBColor(lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2))
+}
+
+// Sequential color at position t in [0, 1] (clamped)
+pub(crate) fn sample(t: f64) -> RGBColor {
+    Colormap::Sequential.at(t)
+}
+
+// Diverging color for v in [vmin, vmax]; the middle of the range is white
+pub(crate) fn sample_diverging(v: f64, vmin: f64, vmax: f64) -> RGBColor {
+    let t = if vmax > vmin { (v - vmin) / (vmax - vmin) } else { 0.5 };
+    Colormap::Diverging.at(t)
+}
+
+// Vertical color ramp from min to max with a few labelled ticks, used as the
+// legend of color-coded plots
+pub(crate) fn draw_color_bar<DB: DrawingBackend>(
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    colormap: Colormap,
+    (min, max): (f64, f64),
+    title: &str,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let (_, height) = area.dim_in_pixel();
+    let (top, bottom) = (60, height as i32 - 60);
+    let steps = 50;
+    for step in 0..steps {
+        let t = step as f64 / steps as f64;
+        let y1 = bottom - ((bottom - top) as f64 * t) as i32;
+        let y0 = bottom - ((bottom - top) as f64 * (t + 1.0 / steps as f64)) as i32;
+        // One extra pixel so rounding never leaves a gap between steps
+        area.draw(&Rectangle::new([(10, y0), (35, y1 + 1)], colormap.at(t).filled()))?;
+    }
+
+    for tick in 0..=4 {
+        let t = tick as f64 / 4.0;
+        let y = bottom - ((bottom - top) as f64 * t) as i32;
+        let label = format_tick(min + (max - min) * t, TickStyle::Si);
+        area.draw(&Text::new(label, (40, y - 7), ("sans-serif", 14)))?;
+    }
+    area.draw(&Text::new(title.to_string(), (10, top - 25), ("sans-serif", 15)))?;
+
+    Ok(())
+}
+
+// Colors cycled through for grouped series
+pub(crate) const PALETTE: [RGBColor; 6] = [
+    RGBColor(190, 86, 131),
+    RGBColor(110, 48, 75),
+    RGBColor(31, 119, 180),
+    RGBColor(255, 127, 14),
+    RGBColor(44, 160, 44),
+    RGBColor(148, 103, 189),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_ramp_is_pinned_at_its_ends_and_middle() {
+        assert_eq!(sample(0.0), RGBColor(253, 231, 37));
+        assert_eq!(sample(0.5), RGBColor(33, 145, 140));
+        assert_eq!(sample(1.0), RGBColor(68, 1, 84));
+        // Halfway between the first two control points
+        assert_eq!(sample(0.125), RGBColor(174, 216, 68));
+    }
+
+    #[test]
+    fn diverging_ramp_is_white_in_the_middle_of_the_range() {
+        assert_eq!(sample_diverging(-1.0, -1.0, 1.0), RGBColor(178, 24, 43));
+        assert_eq!(sample_diverging(0.0, -1.0, 1.0), RGBColor(247, 247, 247));
+        assert_eq!(sample_diverging(1.0, -1.0, 1.0), RGBColor(33, 102, 172));
+        assert_eq!(sample_diverging(5.0, 5.0, 5.0), RGBColor(247, 247, 247));
+    }
+
+    #[test]
+    fn positions_outside_the_ramp_are_clamped() {
+        assert_eq!(sample(-3.0), sample(0.0));
+        assert_eq!(sample(7.0), sample(1.0));
+        assert_eq!(sample(f64::NAN), sample(0.0));
+    }
+}
@@ -0,0 +1,202 @@
+use std::error::Error;
+use csv::{Reader, StringRecord};
+
+// Header as used throughout the program: surrounding spaces trimmed and inner runs
+// of whitespace collapsed, so " thinness  1-19 years" becomes "thinness 1-19 years"
+pub(crate) fn canonical_header(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Header row with every name canonical, ready for deserializing records
+pub(crate) fn canonical_headers(headers: &StringRecord) -> StringRecord {
+    headers.iter().map(canonical_header).collect()
+}
+
+// Canonical header names of a CSV file, in column order
+pub(crate) fn read_headers(file_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut reader = Reader::from_path(file_path)?;
+    Ok(reader.headers()?.iter().map(canonical_header).collect())
+}
+
+// Canonical, lowercase form used for name matching
+fn normalize(name: &str) -> String {
+    canonical_header(name).to_lowercase()
+}
+
+// Index of the header matching `name`, ignoring case and surrounding spaces
+pub(crate) fn find_column(headers: &[String], name: &str) -> Option<usize> {
+    let wanted = normalize(name);
+    headers.iter().position(|header| normalize(header) == wanted)
+}
+
+// Indices of the named columns of a CSV file, in the order of `names`, matched as
+// by find_column so the columns may come in any order. Every name the file lacks
+// is reported together with the headers it has.
+pub(crate) fn resolve_columns(file_path: &str, names: &[&str]) -> Result<Vec<usize>, Box<dyn Error>> {
+    let headers = read_headers(file_path)?;
+    let found: Vec<Option<usize>> = names.iter().map(|name| find_column(&headers, name)).collect();
+    let missing: Vec<String> = names.iter().zip(&found).filter(|(_, index)| index.is_none()).map(|(name, _)| format!("\"{}\"", name)).collect();
+    if !missing.is_empty() {
+        return Err(format!("{} has no column {}; available columns: {}", file_path, missing.join(", "), headers.join(", ")).into());
+    }
+    Ok(found.into_iter().flatten().collect())
+}
+
+// Columns referenced by one selection term (a name, an index, or a range)
+fn resolve_term(headers: &[String], term: &str, position: usize, token: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+    let error = |message: String| -> Box<dyn Error> {
+        format!("Invalid selection token {} (\"{}\"): {}", position, token, message).into()
+    };
+    let check = |index: usize| {
+        if index < headers.len() {
+            Ok(index)
+        } else {
+            Err(error(format!("column {} is out of range (file has {} columns)", index, headers.len())))
+        }
+    };
+
+    // Numeric ranges: a..b (exclusive) or a..=b (inclusive)
+    if let Some((start, end)) = term.split_once("..") {
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => (end, true),
+            None => (end, false),
+        };
+        let start: usize = start.trim().parse().map_err(|_| error("range start is not a number".into()))?;
+        let end: usize = end.trim().parse().map_err(|_| error("range end is not a number".into()))?;
+        let end = if inclusive {
+            end.checked_add(1).ok_or_else(|| error("range end is too large".into()))?
+        } else {
+            end
+        };
+        if start >= end {
+            return Err(error("range is empty".into()));
+        }
+        return (start..end).map(check).collect();
+    }
+
+    if let Ok(index) = term.parse::<usize>() {
+        return Ok(vec![check(index)?]);
+    }
+
+    match find_column(headers, term) {
+        Some(index) => Ok(vec![index]),
+        None => Err(error(format!(
+            "unknown column \"{}\"; available columns: {}",
+            term,
+            headers.iter().map(|h| h.trim()).collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+// Parse a comma-separated column selection such as
+// "GDP, Schooling, Life expectancy, !Population, 5..8".
+// Names are matched against the headers, `a..b` selects an index range and
+// a leading `!` excludes a term. A selection of only exclusions starts from all columns.
+pub(crate) fn parse_selection(headers: &[String], selection: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut included: Vec<usize> = Vec::new();
+    let mut excluded: Vec<usize> = Vec::new();
+    let mut has_inclusions = false;
+
+    for (i, token) in selection.split(',').enumerate() {
+        let position = i + 1;
+        let trimmed = token.trim();
+        let (term, exclude) = match trimmed.strip_prefix('!') {
+            Some(rest) => (rest.trim(), true),
+            None => (trimmed, false),
+        };
+        if term.is_empty() {
+            return Err(format!("Invalid selection token {} (\"{}\"): empty term", position, trimmed).into());
+        }
+
+        let columns = resolve_term(headers, term, position, trimmed)?;
+        if exclude {
+            excluded.extend(columns);
+        } else {
+            has_inclusions = true;
+            included.extend(columns);
+        }
+    }
+
+    if !has_inclusions {
+        included = (0..headers.len()).collect();
+    }
+
+    // Keep first-mention order without duplicates
+    let mut selected = Vec::new();
+    for index in included {
+        if !excluded.contains(&index) && !selected.contains(&index) {
+            selected.push(index);
+        }
+    }
+
+    if selected.is_empty() {
+        return Err(format!("Selection \"{}\" matches no columns", selection).into());
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Vec<String> {
+        ["Country", "Year", "Status", "Life expectancy", "Adult Mortality", "thinness 1-19 years", "GDP", "Population"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn names_match_ignoring_case_and_spaces() {
+        let headers = headers();
+        assert_eq!(parse_selection(&headers, " life EXPECTANCY ,thinness  1-19 years").unwrap(), vec![3, 5]);
+        assert_eq!(find_column(&headers, " gdp "), Some(6));
+        assert_eq!(canonical_header(" thinness  1-19 years "), "thinness 1-19 years");
+    }
+
+    #[test]
+    fn ranges_and_indices_select_columns_in_order() {
+        let headers = headers();
+        assert_eq!(parse_selection(&headers, "3..5").unwrap(), vec![3, 4]);
+        assert_eq!(parse_selection(&headers, "3..=5, 1").unwrap(), vec![3, 4, 5, 1]);
+        assert_eq!(parse_selection(&headers, "GDP, 6, 5..7").unwrap(), vec![6, 5]);
+        assert!(parse_selection(&headers, "5..5").unwrap_err().to_string().contains("range is empty"));
+        assert!(parse_selection(&headers, "2..20").unwrap_err().to_string().contains("out of range"));
+        let error = parse_selection(&headers, &format!("0..={}", usize::MAX)).unwrap_err().to_string();
+        assert!(error.contains("range end is too large"), "{}", error);
+    }
+
+    #[test]
+    fn exclusions_remove_columns() {
+        let headers = headers();
+        assert_eq!(parse_selection(&headers, "3..8, !Population, !4").unwrap(), vec![3, 5, 6]);
+        // Only exclusions start from every column
+        assert_eq!(parse_selection(&headers, "!0..3, !GDP").unwrap(), vec![3, 4, 5, 7]);
+        assert!(parse_selection(&headers, "GDP, !GDP").unwrap_err().to_string().contains("matches no columns"));
+    }
+
+    #[test]
+    fn unknown_and_empty_terms_name_the_token() {
+        let headers = headers();
+        let error = parse_selection(&headers, "GDP, Happiness").unwrap_err().to_string();
+        assert!(error.starts_with("Invalid selection token 2 (\"Happiness\")"), "{}", error);
+        assert!(error.contains("available columns: Country, Year"));
+        assert!(parse_selection(&headers, "GDP,,Year").unwrap_err().to_string().contains("token 2 (\"\"): empty term"));
+        assert!(parse_selection(&headers, "a..3").unwrap_err().to_string().contains("range start is not a number"));
+    }
+
+    #[test]
+    fn resolved_columns_follow_reordered_and_padded_headers() {
+        let dir = crate::test_support::scratch_dir("resolve-columns");
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "Life expectancy ,  STATUS, year,Country, BMI \n80.6,Developed,2015,Denmark,58.2\n").unwrap();
+        let input = input.to_str().unwrap();
+        assert_eq!(resolve_columns(input, &["Country", "Year", "Status", "Life expectancy", "BMI"]).unwrap(), vec![3, 2, 1, 0, 4]);
+
+        let error = resolve_columns(input, &["Country", "GDP", "Schooling"]).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!("{} has no column \"GDP\", \"Schooling\"; available columns: Life expectancy, STATUS, year, Country, BMI", input)
+        );
+    }
+}
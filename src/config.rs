@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::plot_specs::PlotSpec;
+use crate::theme::PlotTheme;
+
+// Default location of the analysis configuration
+pub(crate) const DEFAULT_CONFIG_FILE: &str = "analysis.toml";
+
+// Settings read from the TOML config; every section is optional
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Config {
+    #[serde(default)]
+    pub(crate) plots: Vec<PlotSpec>,
+    #[serde(default)]
+    pub(crate) theme: PlotTheme,
+    // Rows of these entities are dropped on load, on top of DEFAULT_EXCLUDED_ENTITIES
+    #[serde(default)]
+    pub(crate) exclude_entities: Vec<String>,
+    // Derived columns by name, each an arithmetic expression over record columns
+    // (see expression::Expr), e.g. mortality_ratio = "Adult Mortality / (infant deaths + 1)"
+    #[serde(default)]
+    pub(crate) derived: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) random: RandomConfig,
+    #[serde(default)]
+    pub(crate) graph: GraphConfig,
+}
+
+// Seed of the run's random steps ([random] section). Each step draws from its own
+// seed derived from this one, so a step's results do not depend on which other
+// steps ran or how much randomness they used.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub(crate) struct RandomConfig {
+    pub(crate) seed: u64,
+}
+
+impl Default for RandomConfig {
+    fn default() -> Self {
+        RandomConfig { seed: 42 }
+    }
+}
+
+impl RandomConfig {
+    // Seed of the named step: the first 8 bytes of SHA-256 over the run's seed and
+    // the name, the same on every platform and release
+    pub(crate) fn seed_for(&self, step: &str) -> u64 {
+        let digest = Sha256::new().chain_update(self.seed.to_le_bytes()).chain_update(step.as_bytes()).finalize();
+        u64::from_le_bytes(digest[..8].try_into().expect("SHA-256 is 32 bytes"))
+    }
+}
+
+// Settings of the similarity graph ([graph] section)
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub(crate) struct GraphConfig {
+    // Smallest similarity, between 0 and 1, of two rows joined by an edge
+    pub(crate) threshold: f64,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        GraphConfig { threshold: 0.8 }
+    }
+}
+
+// Load the config file, falling back to defaults when it does not exist
+pub(crate) fn load_config(path: &str) -> Result<Config, Box<dyn Error>> {
+    if !Path::new(path).exists() {
+        return Ok(Config::default());
+    }
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path, e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_step_gets_its_own_stable_seed() {
+        let config: Config = toml::from_str("[random]\nseed = 7\n").unwrap();
+        assert_eq!(config.random.seed, 7);
+        assert_eq!(load_config("no such config.toml").unwrap().random.seed, 42);
+
+        let random = RandomConfig::default();
+        assert_eq!(random.seed_for("gap_bootstrap"), random.seed_for("gap_bootstrap"));
+        assert_ne!(random.seed_for("gap_bootstrap"), random.seed_for("null_model"));
+        assert_ne!(random.seed_for("gap_bootstrap"), config.random.seed_for("gap_bootstrap"));
+        // The first 8 bytes (little-endian) of SHA-256 over 42u64 LE and "gap_bootstrap"
+        assert_eq!(random.seed_for("gap_bootstrap"), 10951121701049581156);
+    }
+
+    #[cfg(feature = "plots")]
+    #[test]
+    fn the_run_seed_decides_the_bootstrap_intervals() {
+        use crate::fields::FIELDS;
+        use crate::status_gap::{bootstrap_gap, GapBand, ResampleUnit};
+
+        let records = crate::synthetic::generate_synthetic_dataset(12, 2000..=2005, 3);
+        let meta = FIELDS.iter().find(|meta| meta.name == "Life expectancy").unwrap();
+        let band = |seed: u64| -> GapBand {
+            let random = RandomConfig { seed };
+            bootstrap_gap(&records, meta, ResampleUnit::Row, 50, random.seed_for("gap_bootstrap")).unwrap()
+        };
+        let (first, again, other) = (band(42), band(42), band(7));
+        assert_eq!((&first.lower, &first.upper, first.average), (&again.lower, &again.upper, again.average));
+        assert_eq!(first.gap, other.gap);
+        assert_ne!((&first.lower, &first.upper), (&other.lower, &other.upper));
+    }
+}
@@ -0,0 +1,237 @@
+use std::error::Error;
+use ndarray::Array2;
+use crate::analysis_error::AnalysisError;
+use crate::eda_statistics::{winsorize_column, PairedColumns, TargetCorrelation};
+use crate::hierarchical::{hierarchical_cluster, Linkage};
+use crate::load_clean::Dataset;
+#[cfg(feature = "plots")]
+use crate::{eda_plots, metadata::RunMetadata};
+
+// How the columns are prepared before the Pearson correlation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CorrelationMethod {
+    Pearson,
+    Winsorized(f64, f64), // every column clamped to these quantiles first (see winsorize_column)
+}
+
+// Pairwise correlations of a set of columns, computed once and shared by the
+// heatmap, the strongest-pair ranking and the correlations with a target. Each
+// cell uses the rows where both columns have a value (pairwise deletion); cells
+// with fewer than `min_observations` such rows are NaN.
+#[derive(Debug, Clone)]
+pub(crate) struct CorrelationAnalysis {
+    pub(crate) labels: Vec<String>,
+    pub(crate) matrix: Array2<f64>,
+    pub(crate) pairs: Array2<usize>, // rows with both values, per cell
+    pub(crate) method: CorrelationMethod,
+    #[cfg(feature = "plots")]
+    pub(crate) min_observations: usize,
+}
+
+impl CorrelationAnalysis {
+    // Correlations of the named numeric columns of the dataset
+    pub(crate) fn compute(
+        dataset: &Dataset,
+        features: &[&str],
+        method: CorrelationMethod,
+        min_observations: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        if features.is_empty() || dataset.rows.is_empty() {
+            return Err(AnalysisError::empty("Correlation matrix").into());
+        }
+        let mut columns = features.iter().map(|name| dataset.numeric_column(name)).collect::<Result<Vec<_>, _>>()?;
+        if let CorrelationMethod::Winsorized(low, high) = method {
+            columns = columns.iter().map(|column| winsorize_column(column, (low, high))).collect();
+        }
+
+        let n = columns.len();
+        let mut matrix = Array2::from_elem((n, n), f64::NAN);
+        let mut pairs = Array2::zeros((n, n));
+        for i in 0..n {
+            for j in i..n {
+                let paired = PairedColumns::from_rows(columns[i].iter().zip(&columns[j]).map(|(&x, &y)| (x, y, "")));
+                pairs[(i, j)] = paired.len();
+                pairs[(j, i)] = paired.len();
+                if let Some(correlation) = paired.correlation(min_observations) {
+                    matrix[(i, j)] = correlation;
+                    matrix[(j, i)] = correlation;
+                }
+            }
+        }
+        let labels = features.iter().map(|name| name.to_string()).collect();
+        Ok(CorrelationAnalysis {
+            labels,
+            matrix,
+            pairs,
+            method,
+            #[cfg(feature = "plots")]
+            min_observations,
+        })
+    }
+
+    pub(crate) fn index_of(&self, name: &str) -> Option<usize> {
+        self.labels.iter().position(|label| label.eq_ignore_ascii_case(name))
+    }
+
+    #[cfg(feature = "plots")]
+    pub(crate) fn heatmap(&self, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+        eda_plots::create_correlation_heatmap(&self.matrix, output_file, &self.labels, self.min_observations, run)
+    }
+
+    // The k most strongly correlated pairs of distinct columns, by |r|
+    pub(crate) fn top_pairs(&self, k: usize) -> Vec<(&str, &str, f64)> {
+        let n = self.labels.len();
+        let mut pairs: Vec<(&str, &str, f64)> = (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .filter(|&(i, j)| !self.matrix[(i, j)].is_nan())
+            .map(|(i, j)| (self.labels[i].as_str(), self.labels[j].as_str(), self.matrix[(i, j)]))
+            .collect();
+        pairs.sort_by(|a, b| b.2.abs().total_cmp(&a.2.abs()));
+        pairs.truncate(k);
+        pairs
+    }
+
+    // Every other column with its correlation to `target` (None when masked) and
+    // its pair count, strongest first; None when `target` is not a column
+    pub(crate) fn with_target(&self, target: &str) -> Option<Vec<(&str, Option<f64>, usize)>> {
+        let t = self.index_of(target)?;
+        let mut correlations: Vec<(&str, Option<f64>, usize)> = (0..self.labels.len())
+            .filter(|&i| i != t)
+            .map(|i| {
+                let value = self.matrix[(i, t)];
+                (self.labels[i].as_str(), (!value.is_nan()).then_some(value), self.pairs[(i, t)])
+            })
+            .collect();
+        let strength = |correlation: Option<f64>| correlation.map_or(-1.0, f64::abs);
+        correlations.sort_by(|a, b| strength(b.1).total_cmp(&strength(a.1)));
+        Some(correlations)
+    }
+
+    // Column indices with strongly correlated columns (either sign) next to each
+    // other: the leaf order of an average-linkage clustering on |r|
+    pub(crate) fn feature_order_by_clustering(&self) -> Vec<usize> {
+        let similarity = self.matrix.mapv(|value| if value.is_nan() { 0.0 } else { value.abs() });
+        hierarchical_cluster(&self.labels, &similarity, Linkage::Average).leaf_order()
+    }
+}
+
+// Correlations with `target` from a plain and a winsorized analysis of the same
+// columns, in the order of the plain ones; None when `target` is not a column
+pub(crate) fn target_correlations(plain: &CorrelationAnalysis, robust: &CorrelationAnalysis, target: &str) -> Option<Vec<TargetCorrelation>> {
+    let robust = robust.with_target(target)?;
+    let correlations = plain
+        .with_target(target)?
+        .into_iter()
+        .map(|(field, pearson, observations)| TargetCorrelation {
+            field: field.to_string(),
+            observations,
+            pearson,
+            robust: robust.iter().find(|r| r.0 == field).and_then(|r| r.1),
+        })
+        .collect();
+    Some(correlations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset<S: AsRef<str>>(rows: &[[S; 3]]) -> Dataset {
+        Dataset {
+            headers: ["A", "B", "C"].iter().map(|name| name.to_string()).collect(),
+            rows: rows.iter().map(|row| row.iter().map(AsRef::as_ref).collect()).collect(),
+        }
+    }
+
+    #[test]
+    fn cells_with_too_few_pairs_are_masked() {
+        // A and B overlap on three rows only; A and C on all six
+        let data = dataset(&[
+            ["1", "2", "1"],
+            ["2", "4", "2"],
+            ["3", "5", "3"],
+            ["4", "", "5"],
+            ["5", "", "4"],
+            ["6", "", "6"],
+        ]);
+        let analysis = CorrelationAnalysis::compute(&data, &["A", "B", "C"], CorrelationMethod::Pearson, 5).unwrap();
+        assert_eq!(analysis.pairs[(0, 1)], 3);
+        assert!(analysis.matrix[(0, 1)].is_nan() && analysis.matrix[(1, 0)].is_nan());
+        assert!(analysis.matrix[(0, 2)] > 0.9);
+        assert_eq!(analysis.top_pairs(5), vec![("A", "C", analysis.matrix[(0, 2)])]);
+        assert_eq!(analysis.with_target("b").unwrap(), vec![("A", None, 3), ("C", None, 3)]);
+
+        let unmasked = CorrelationAnalysis::compute(&data, &["A", "B"], CorrelationMethod::Pearson, 3).unwrap();
+        assert!((unmasked.matrix[(0, 1)] - 9.0 / 84.0_f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn one_outlier_swings_pearson_but_not_the_winsorized_correlation() {
+        let row = |x: f64, y: f64| [x.to_string(), y.to_string(), (x * 0.5).to_string()];
+        let mut rows: Vec<[String; 3]> = (0..200).map(f64::from).map(|x| row(x, 2.0 * x + 3.0 * x.sin())).collect();
+        let clean = CorrelationAnalysis::compute(&dataset(&rows), &["A", "B"], CorrelationMethod::Pearson, 5).unwrap();
+        assert!(clean.matrix[(0, 1)] > 0.99);
+
+        rows[199] = row(1e4, -1e4);
+        let data = dataset(&rows);
+        let plain = CorrelationAnalysis::compute(&data, &["A", "B", "C"], CorrelationMethod::Pearson, 5).unwrap();
+        let robust = CorrelationAnalysis::compute(&data, &["A", "B", "C"], CorrelationMethod::Winsorized(0.01, 0.99), 5).unwrap();
+        assert!(plain.matrix[(0, 1)] < -0.9);
+        assert!((robust.matrix[(0, 1)] - clean.matrix[(0, 1)]).abs() < 0.05);
+
+        let table = target_correlations(&plain, &robust, "B").unwrap();
+        let swing: Vec<(&str, f64)> = table.iter().map(|row| (row.field.as_str(), row.robust.unwrap() - row.pearson.unwrap())).collect();
+        assert_eq!(swing.len(), 2);
+        assert!(swing.iter().all(|&(_, difference)| difference > 1.8), "{:?}", swing);
+        assert!(target_correlations(&plain, &robust, "Happiness").is_none());
+    }
+
+    // Pearson correlation over the rows where both cells parse, computed directly
+    fn pearson(data: &Dataset, x: usize, y: usize) -> f64 {
+        let pairs: Vec<(f64, f64)> = data.rows.iter().filter_map(|row| Some((row[x].parse::<f64>().ok()?, row[y].parse::<f64>().ok()?))).collect();
+        let n = pairs.len() as f64;
+        let (mean_x, mean_y) = (pairs.iter().map(|p| p.0).sum::<f64>() / n, pairs.iter().map(|p| p.1).sum::<f64>() / n);
+        let covariance: f64 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let (var_x, var_y): (f64, f64) = (pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum(), pairs.iter().map(|p| (p.1 - mean_y).powi(2)).sum());
+        covariance / (var_x * var_y).sqrt()
+    }
+
+    #[test]
+    fn cached_outputs_agree_with_direct_computations() {
+        // B follows A closely, C barely; every seventh B is blank
+        let rows: Vec<[String; 3]> = (0..40)
+            .map(f64::from)
+            .map(|x| {
+                let b = if x as usize % 7 == 3 { String::new() } else { (x * x).to_string() };
+                [x.to_string(), b, (x * 1.3).sin().to_string()]
+            })
+            .collect();
+        let data = dataset(&rows);
+        let analysis = CorrelationAnalysis::compute(&data, &["A", "B", "C"], CorrelationMethod::Pearson, 5).unwrap();
+        let (ab, ac, bc) = (pearson(&data, 0, 1), pearson(&data, 0, 2), pearson(&data, 1, 2));
+        for (i, j, expected) in [(0, 1, ab), (0, 2, ac), (1, 2, bc)] {
+            assert!((analysis.matrix[(i, j)] - expected).abs() < 1e-12);
+            assert_eq!(analysis.matrix[(i, j)], analysis.matrix[(j, i)]);
+        }
+        assert_eq!(analysis.pairs[(0, 1)], 34);
+
+        let mut by_strength = vec![("A", "B", ab), ("A", "C", ac), ("B", "C", bc)];
+        by_strength.sort_by(|a, b| b.2.abs().total_cmp(&a.2.abs()));
+        let top = analysis.top_pairs(2);
+        assert_eq!(top.len(), 2);
+        for (cached, direct) in top.iter().zip(&by_strength) {
+            assert_eq!((cached.0, cached.1), (direct.0, direct.1));
+            assert!((cached.2 - direct.2).abs() < 1e-12);
+        }
+
+        let target = analysis.with_target("A").unwrap();
+        assert_eq!(target.iter().map(|row| (row.0, row.2)).collect::<Vec<_>>(), vec![("B", 34), ("C", 40)]);
+        assert!((target[0].1.unwrap() - ab).abs() < 1e-12 && (target[1].1.unwrap() - ac).abs() < 1e-12);
+
+        // A and B are the closest pair, so they end up next to each other
+        let order = analysis.feature_order_by_clustering();
+        let position = |column: usize| order.iter().position(|&i| i == column).unwrap();
+        assert_eq!(order.len(), 3);
+        assert_eq!(position(0).abs_diff(position(1)), 1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use petgraph::Graph;
+
+// Sorted unique country names with their positions. Built once per analysis and
+// shared by the per-country rows, the similarity matrix, the clusters and the
+// exports, so index i names the same country in all of them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CountryIndex {
+    names: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl CountryIndex {
+    pub(crate) fn new<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Self {
+        let mut names: Vec<String> = names.into_iter().map(|name| name.as_ref().to_string()).collect();
+        names.sort();
+        names.dedup();
+        let positions = names.iter().enumerate().map(|(i, name)| (name.clone(), i)).collect();
+        CountryIndex { names, positions }
+    }
+
+    pub(crate) fn index_of(&self, name: &str) -> Option<usize> {
+        self.positions.get(name).copied()
+    }
+
+    pub(crate) fn name_of(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    // Index of the country of every graph node, in node order; None for nodes of
+    // countries outside the index
+    pub(crate) fn node_countries(&self, graph: &Graph<String, f64>) -> Vec<Option<usize>> {
+        graph.node_indices().map(|node| self.index_of(&graph[node])).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::graph::{aggregate_by_country, calculate_similarity, export_node_metrics_csv, similarity_matrix, NodeMetrics};
+    use crate::hierarchical::{export_clusters_csv, hierarchical_cluster, Linkage};
+    use crate::metadata::RunMetadata;
+    use crate::output::ExportOptions;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn names_are_sorted_and_unique() {
+        let index = CountryIndex::new(["Peru", "Chad", "Peru", "Austria"]);
+        assert_eq!(index.names(), ["Austria", "Chad", "Peru"]);
+        assert_eq!((index.index_of("Peru"), index.index_of("Atlantis")), (Some(2), None));
+        assert_eq!(index.name_of(1), "Chad");
+        assert_eq!(index.len(), 3);
+    }
+
+    // CSV rows without the provenance comments, split into fields
+    fn csv_rows(path: &std::path::Path) -> Vec<Vec<String>> {
+        let text = std::fs::read_to_string(path).unwrap();
+        text.lines().filter(|line| !line.starts_with('#')).skip(1).map(|line| line.split(',').map(str::to_string).collect()).collect()
+    }
+
+    #[test]
+    fn every_output_attributes_a_row_to_the_same_country() {
+        // Country-year rows in no particular order, one incomplete
+        let nodes: Vec<String> = ["Peru", "Chad", "Austria", "Peru", "Chad", "Benin"].iter().map(|name| name.to_string()).collect();
+        let rows = vec![vec![3.0, 1.0], vec![1.0, 4.0], vec![5.0, 5.0], vec![5.0, 1.0], vec![1.0, 2.0], vec![2.0]];
+        let (index, country_rows) = aggregate_by_country(&nodes, &rows, 2);
+        assert_eq!(index.names(), ["Austria", "Chad", "Peru"]);
+        assert_eq!(country_rows, vec![vec![5.0, 5.0], vec![1.0, 3.0], vec![4.0, 1.0]]);
+
+        // Row i of the matrix compares country i with every other
+        let matrix = similarity_matrix(&index, &country_rows, None).unwrap();
+        let row_of = |name: &str| &country_rows[index.index_of(name).unwrap()];
+        let (chad, peru) = (index.index_of("Chad").unwrap(), index.index_of("Peru").unwrap());
+        assert_eq!(matrix[(chad, peru)], calculate_similarity(row_of("Chad"), row_of("Peru")));
+
+        let mut graph = petgraph::Graph::<String, f64>::new();
+        for node in &nodes {
+            graph.add_node(node.clone());
+        }
+        let dir = scratch_dir("country-index-exports");
+        let run = RunMetadata::new(&dir.join("input.csv").to_string_lossy(), "none").unwrap();
+        let assignments = hierarchical_cluster(index.names(), &matrix, Linkage::Average).cut(2);
+        export_clusters_csv(&index, &assignments, dir.join("clusters.csv").to_str().unwrap(), &run).unwrap();
+        let mut metrics = NodeMetrics::new();
+        metrics.insert("Degree", vec![Some(0.0); nodes.len()]);
+        export_node_metrics_csv(&graph, &index, &metrics, dir.join("node_metrics.csv").to_str().unwrap(), &ExportOptions::default(), &run).unwrap();
+
+        // The same CountryId names the same country in both files and the index
+        let clusters = csv_rows(&dir.join("clusters.csv"));
+        assert_eq!(clusters.len(), 3);
+        let cluster_ids: HashMap<String, String> = clusters.iter().map(|row| (row[1].clone(), row[0].clone())).collect();
+        for row in &clusters {
+            assert_eq!(index.name_of(row[0].parse().unwrap()), row[1]);
+        }
+        let node_rows = csv_rows(&dir.join("node_metrics.csv"));
+        assert_eq!(node_rows.len(), nodes.len());
+        for row in &node_rows {
+            match cluster_ids.get(&row[0]) {
+                Some(id) => assert_eq!(&row[1], id, "{:?}", row),
+                None => assert_eq!((row[0].as_str(), row[1].as_str()), ("Benin", "")),
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use crate::analysis_error::AnalysisError;
+use crate::colormap::PALETTE;
+use crate::eda_plots::{self, ChartText, ScatterOptions};
+use crate::eda_statistics::paired_columns;
+use crate::load_clean::{Dataset, YearRow};
+use crate::metadata::{self, RunMetadata};
+use crate::output;
+use crate::pipeline::AnalysisResults;
+use crate::theme::PlotTheme;
+
+// The headline charts in one 2x2 image: the correlation heatmap, developed vs
+// developing life expectancy, GDP against life expectancy with its regression
+// line, and the size of every cluster. Needs the heatmap and graph results.
+pub(crate) fn render_dashboard(
+    dataset: &Dataset,
+    results: &AnalysisResults,
+    output_file: &str,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let correlations = results.correlations.as_ref().ok_or("the dashboard needs the heatmap step")?;
+    if results.clusters.is_empty() {
+        return Err(AnalysisError::empty("Cluster sizes").into());
+    }
+
+    let (year, status, life_expectancy) =
+        (dataset.column_index("Year")?, dataset.column_index("Status")?, dataset.column_index("Life expectancy")?);
+    let year_rows: Vec<YearRow> = dataset
+        .rows
+        .iter()
+        .filter_map(|row| Some((row.get(year)?.trim().parse().ok()?, row.clone())))
+        .collect();
+    let lines = eda_plots::status_lines(&year_rows, life_expectancy, status, output_file)?;
+
+    let paired = paired_columns(dataset, "GDP", "Life expectancy")?;
+    if paired.is_empty() {
+        return Err(AnalysisError::empty("Life expectancy vs. GDP").into());
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (2400, 1800)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let panels = root.split_evenly((2, 2));
+
+    eda_plots::draw_correlation_heatmap(&panels[0], &correlations.matrix, &correlations.labels, correlations.min_observations)?;
+    let text = ChartText {
+        caption: "Developed vs Developing Life Expectancy per Year",
+        x_desc: "Years",
+        y_desc: "Life expectancy",
+    };
+    eda_plots::draw_status_lines(&panels[1], &lines, &text, 0.0..100.0, theme)?;
+    let text = ChartText { caption: "Life Expectancy vs. GDP", x_desc: "GDP", y_desc: "Life expectancy" };
+    let options = ScatterOptions { trend_line: true, ..Default::default() };
+    eda_plots::draw_scatter(&panels[2], &paired.points(), &paired.countries, &text, &options, theme)?;
+    draw_cluster_sizes(&panels[3], results)?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    Ok(())
+}
+
+// Countries per cluster, colored like the cluster tile map
+fn draw_cluster_sizes<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, results: &AnalysisResults) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let clusters = results.clusters.values().max().map_or(0, |&max| max + 1);
+    let mut sizes = vec![0; clusters];
+    for &cluster in results.clusters.values() {
+        sizes[cluster] += 1;
+    }
+    let max_size = sizes.iter().copied().max().unwrap_or(1);
+
+    let mut chart = ChartBuilder::on(area)
+        .caption("Countries per Cluster", ("sans-serif", 30))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(50)
+        .build_cartesian_2d((0..clusters as u32 - 1).into_segmented(), 0..max_size + max_size / 10 + 1)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_desc("Cluster")
+        .y_desc("Countries")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| match x {
+            SegmentValue::CenterOf(x) => x.to_string(),
+            _ => String::new(),
+        })
+        .draw()?;
+
+    chart.draw_series(sizes.iter().enumerate().map(|(i, &size)| {
+        let color = PALETTE[i % PALETTE.len()];
+        let mut bar = Rectangle::new(
+            [(SegmentValue::Exact(i as u32), 0), (SegmentValue::Exact(i as u32 + 1), size)],
+            color.filled(),
+        );
+        bar.set_margin(0, 0, 15, 15);
+        bar
+    }))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correlation::{CorrelationAnalysis, CorrelationMethod};
+
+    #[test]
+    fn the_dashboard_needs_the_heatmap_and_graph_results() {
+        let dir = crate::test_support::scratch_dir("dashboard");
+        let input = dir.join("input.csv");
+        let lines: Vec<String> = std::fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().take(97).map(String::from).collect();
+        std::fs::write(&input, lines.join("\n")).unwrap();
+        let input = input.to_str().unwrap();
+        let dataset = Dataset::load(input).unwrap();
+        let run = RunMetadata::new(input, "none").unwrap();
+        let output_file = dir.join("dashboard.png");
+        let output_file = output_file.to_str().unwrap();
+
+        let mut results = AnalysisResults::default();
+        let error = render_dashboard(&dataset, &results, output_file, &PlotTheme::default(), &run).unwrap_err();
+        assert_eq!(error.to_string(), "the dashboard needs the heatmap step");
+        let features = ["Life expectancy", "GDP", "Schooling"];
+        results.correlations = Some(CorrelationAnalysis::compute(&dataset, &features, CorrelationMethod::Pearson, 5).unwrap());
+        let error = render_dashboard(&dataset, &results, output_file, &PlotTheme::default(), &run).unwrap_err();
+        assert!(matches!(error.downcast_ref::<AnalysisError>(), Some(AnalysisError::EmptyInput { .. })));
+
+        let countries = dataset.text_column("Country").unwrap();
+        results.clusters = countries.iter().map(|country| (country.clone(), country.len() % 3)).collect();
+        render_dashboard(&dataset, &results, output_file, &PlotTheme::default(), &run).unwrap();
+        // Width and height are the first fields of the PNG header chunk
+        let png = std::fs::read(output_file).unwrap();
+        let dimension = |at: usize| u32::from_be_bytes(png[at..at + 4].try_into().unwrap());
+        assert_eq!((dimension(16), dimension(20)), (2400, 1800));
+    }
+}
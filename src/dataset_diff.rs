@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::error::Error;
+use csv::Writer;
+use serde::Serialize;
+use crate::eda_statistics::{summary_statistics, SummaryStats};
+use crate::fields::{self, FIELDS};
+use crate::format::format_option;
+use crate::load_clean::Dataset;
+use crate::metadata::RunMetadata;
+use crate::output;
+
+// Country + Year identifying one row
+pub(crate) type RowKey = (String, u16);
+
+// Summary of one registry column in both datasets; None where a side has no values
+#[derive(Debug)]
+pub(crate) struct ColumnDiff {
+    pub(crate) field: &'static str,
+    pub(crate) a: Option<SummaryStats>,
+    pub(crate) b: Option<SummaryStats>,
+}
+
+impl ColumnDiff {
+    pub(crate) fn mean_delta(&self) -> Option<f64> {
+        Some(self.b.as_ref()?.mean - self.a.as_ref()?.mean)
+    }
+
+    pub(crate) fn std_delta(&self) -> Option<f64> {
+        Some(self.b.as_ref()?.std_dev? - self.a.as_ref()?.std_dev?)
+    }
+}
+
+// A value that differs between matching rows. A missing value on exactly one side
+// always counts as a difference.
+#[derive(Debug)]
+pub(crate) struct ValueDelta {
+    pub(crate) country: String,
+    pub(crate) year: u16,
+    pub(crate) field: &'static str,
+    pub(crate) a: Option<f64>,
+    pub(crate) b: Option<f64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct DatasetDiff {
+    pub(crate) columns: Vec<ColumnDiff>,
+    pub(crate) only_in_a: Vec<RowKey>,
+    pub(crate) only_in_b: Vec<RowKey>,
+    pub(crate) deltas: Vec<ValueDelta>,
+    // Registry fields with a column in one file only, in registry order
+    pub(crate) fields_only_in_a: Vec<&'static str>,
+    pub(crate) fields_only_in_b: Vec<&'static str>,
+}
+
+// Row index by Country + Year; rows without a parsable year are left out and a
+// repeated key keeps its first row
+fn index_rows(dataset: &Dataset) -> Result<HashMap<RowKey, usize>, Box<dyn Error>> {
+    let countries = dataset.text_column("Country")?;
+    let years = dataset.years()?;
+    let mut index = HashMap::new();
+    for (row, (country, year)) in countries.into_iter().zip(years).enumerate() {
+        if let Some(year) = year {
+            index.entry((country, year)).or_insert(row);
+        }
+    }
+    Ok(index)
+}
+
+// Compare two versions of the dataset: per-column summaries, rows present on one
+// side only, and per-value differences larger than `tolerance` in matching rows.
+// Only registry fields present in both files are compared; the others are listed
+// by the side that has them.
+pub(crate) fn compare_datasets(a: &Dataset, b: &Dataset, tolerance: f64) -> Result<DatasetDiff, Box<dyn Error>> {
+    let (index_a, index_b) = (index_rows(a)?, index_rows(b)?);
+
+    let mut only_in_a: Vec<RowKey> = index_a.keys().filter(|key| !index_b.contains_key(*key)).cloned().collect();
+    let mut only_in_b: Vec<RowKey> = index_b.keys().filter(|key| !index_a.contains_key(*key)).cloned().collect();
+    only_in_a.sort();
+    only_in_b.sort();
+    let mut shared: Vec<(&RowKey, usize, usize)> =
+        index_a.iter().filter_map(|(key, &row_a)| Some((key, row_a, *index_b.get(key)?))).collect();
+    shared.sort();
+
+    let mut columns = Vec::new();
+    let mut deltas = Vec::new();
+    let (mut fields_only_in_a, mut fields_only_in_b) = (Vec::new(), Vec::new());
+    for meta in FIELDS {
+        let (values_a, values_b) = match (a.numeric_column(meta.name), b.numeric_column(meta.name)) {
+            (Ok(values_a), Ok(values_b)) => (values_a, values_b),
+            (Ok(_), Err(_)) => {
+                fields_only_in_a.push(meta.name);
+                continue;
+            }
+            (Err(_), Ok(_)) => {
+                fields_only_in_b.push(meta.name);
+                continue;
+            }
+            (Err(_), Err(_)) => continue,
+        };
+        columns.push(ColumnDiff {
+            field: meta.name,
+            a: summary_statistics(&values_a),
+            b: summary_statistics(&values_b),
+        });
+
+        for &((country, year), row_a, row_b) in &shared {
+            let (value_a, value_b) = (values_a[row_a], values_b[row_b]);
+            let differs = match (value_a, value_b) {
+                (Some(x), Some(y)) => (x - y).abs() > tolerance,
+                (None, None) => false,
+                _ => true,
+            };
+            if differs {
+                deltas.push(ValueDelta {
+                    country: country.clone(),
+                    year: *year,
+                    field: meta.name,
+                    a: value_a,
+                    b: value_b,
+                });
+            }
+        }
+    }
+
+    Ok(DatasetDiff { columns, only_in_a, only_in_b, deltas, fields_only_in_a, fields_only_in_b })
+}
+
+// One line of dataset_diff.csv
+#[derive(Serialize)]
+struct DiffRow<'a> {
+    #[serde(rename = "Kind")]
+    kind: &'static str, // value, only_in_a or only_in_b
+    #[serde(rename = "Country")]
+    country: &'a str,
+    #[serde(rename = "Year")]
+    year: u16,
+    #[serde(rename = "Field")]
+    field: &'a str,
+    #[serde(rename = "A")]
+    a: Option<f64>,
+    #[serde(rename = "B")]
+    b: Option<f64>,
+}
+
+pub(crate) fn export_diff_csv(diff: &DatasetDiff, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for (kind, keys) in [("only_in_a", &diff.only_in_a), ("only_in_b", &diff.only_in_b)] {
+        for (country, year) in keys {
+            writer.serialize(DiffRow { kind, country, year: *year, field: "", a: None, b: None })?;
+        }
+    }
+    for delta in &diff.deltas {
+        writer.serialize(DiffRow {
+            kind: "value",
+            country: &delta.country,
+            year: delta.year,
+            field: delta.field,
+            a: delta.a,
+            b: delta.b,
+        })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+// Field names joined by commas, "none" for an empty list
+fn list_or_none(fields: &[&str]) -> String {
+    if fields.is_empty() {
+        "none".to_string()
+    } else {
+        fields.join(", ")
+    }
+}
+
+pub(crate) fn print_diff(diff: &DatasetDiff) {
+    println!("Rows only in A: {}, only in B: {}", diff.only_in_a.len(), diff.only_in_b.len());
+    for (country, year) in diff.only_in_a.iter().take(5) {
+        println!("  A only: {} {}", country, year);
+    }
+    for (country, year) in diff.only_in_b.iter().take(5) {
+        println!("  B only: {} {}", country, year);
+    }
+
+    if !diff.fields_only_in_a.is_empty() || !diff.fields_only_in_b.is_empty() {
+        println!("Fields only in A: {}; only in B: {}", list_or_none(&diff.fields_only_in_a), list_or_none(&diff.fields_only_in_b));
+    }
+
+    println!("{:<32} {:>20} {:>20} {:>20} {:>20}", "Field", "Mean A", "Mean B", "Mean B - A", "Std B - A");
+    for column in &diff.columns {
+        let unit = fields::unit_for(column.field);
+        println!(
+            "{:<32} {:>20} {:>20} {:>20} {:>20}",
+            column.field,
+            format_option(column.a.as_ref().map(|stats| stats.mean), unit),
+            format_option(column.b.as_ref().map(|stats| stats.mean), unit),
+            format_option(column.mean_delta(), unit),
+            format_option(column.std_delta(), unit),
+        );
+    }
+
+    println!("Changed values in matching rows: {}", diff.deltas.len());
+    for delta in diff.deltas.iter().take(10) {
+        let unit = fields::unit_for(delta.field);
+        println!(
+            "  {} {} {}: {} -> {}",
+            delta.country,
+            delta.year,
+            delta.field,
+            format_option(delta.a, unit),
+            format_option(delta.b, unit)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dataset of a small CSV written to the scratch directory `dir`
+    fn dataset(dir: &std::path::Path, name: &str, text: &str) -> Dataset {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        Dataset::load(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn added_removed_and_changed_rows_are_reported() {
+        let dir = crate::test_support::scratch_dir("dataset-diff");
+        let a = dataset(
+            &dir,
+            "a.csv",
+            "Country,Year,Life expectancy,GDP,BMI\nA,2014,70,100,20\nA,2015,71,,21\nB,2015,60,50,22\nC,2015,55,10,23\n",
+        );
+        let b = dataset(
+            &dir,
+            "b.csv",
+            "Country,Year,Life expectancy,GDP,Schooling\nA,2014,70.0000000001,100,12\nA,2015,72,300,12\nB,2015,60,,11\nD,2015,65,20,10\n",
+        );
+        let diff = compare_datasets(&a, &b, 1e-6).unwrap();
+
+        assert_eq!(diff.only_in_a, vec![("C".to_string(), 2015)]);
+        assert_eq!(diff.only_in_b, vec![("D".to_string(), 2015)]);
+        // Within the tolerance A 2014 is unchanged; a value missing on one side
+        // always differs, and on both sides never does
+        let deltas: Vec<_> =
+            diff.deltas.iter().map(|delta| (delta.country.as_str(), delta.year, delta.field, delta.a, delta.b)).collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ("A", 2015, "Life expectancy", Some(71.0), Some(72.0)),
+                ("A", 2015, "GDP", None, Some(300.0)),
+                ("B", 2015, "GDP", Some(50.0), None),
+            ]
+        );
+
+        // BMI and Schooling are each in one file only, so only the shared fields
+        // get summaries
+        let fields: Vec<&str> = diff.columns.iter().map(|column| column.field).collect();
+        assert_eq!(fields, vec!["Life expectancy", "GDP"]);
+        assert_eq!((diff.fields_only_in_a.as_slice(), diff.fields_only_in_b.as_slice()), (&["BMI"][..], &["Schooling"][..]));
+        let life_expectancy = &diff.columns[0];
+        assert!((life_expectancy.mean_delta().unwrap() - (267.0 - 256.0) / 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_diff_file_lists_rows_before_values() {
+        let _recording = crate::test_support::RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = crate::test_support::scratch_dir("dataset-diff-export");
+        let a = dataset(&dir, "a.csv", "Country,Year,GDP\nA,2015,1\nB,2015,2\n");
+        let b = dataset(&dir, "b.csv", "Country,Year,GDP\nA,2015,3\nC,2015,2\n");
+        let output_file = dir.join("dataset_diff.csv");
+        let run = RunMetadata::new(dir.join("a.csv").to_str().unwrap(), "none").unwrap();
+        export_diff_csv(&compare_datasets(&a, &b, 0.0).unwrap(), output_file.to_str().unwrap(), &run).unwrap();
+
+        let text = std::fs::read_to_string(&output_file).unwrap();
+        let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(lines, vec!["Kind,Country,Year,Field,A,B", "only_in_a,B,2015,,,", "only_in_b,C,2015,,,", "value,A,2015,GDP,1.0,3.0"]);
+    }
+}
+
@@ -0,0 +1,1021 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::ops::Range;
+use csv::StringRecord;
+use ndarray::Array2;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::analysis_error::AnalysisError;
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+use crate::colormap;
+use crate::columns;
+use crate::eda_statistics::{
+    bin_points, extreme_annotations, grouped_mean, linear_fit, means_only, paired_columns, report_skipped, PairedColumns,
+};
+use crate::graph::aggregate_by_country;
+use crate::format::{format_tick, TickStyle};
+use crate::load_clean::{get_f64, Dataset, YearRow};
+use crate::metadata::{self, RunMetadata};
+use crate::output;
+use crate::plot_specs::{beyond_range_label, pin_points, AxisLimits, Bins};
+use crate::theme::PlotTheme;
+
+// Caption and axis descriptions of one chart
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChartText<'a> {
+    pub(crate) caption: &'a str,
+    pub(crate) x_desc: &'a str,
+    pub(crate) y_desc: &'a str,
+}
+
+// Pixel size of the correlation heatmap
+const HEATMAP_SIZE: (u32, u32) = (1224, 1124);
+
+/// Heatmap of a correlation_matrix; NaN cells (fewer than `min_observations`
+/// paired rows) are drawn grey with an "n<k" note. render_correlation_heatmap
+/// draws the same chart into memory instead of a file:
+///
+/// ```
+/// use ndarray::array;
+/// use project::eda_plots::render_correlation_heatmap;
+///
+/// let matrix = array![[1.0, -0.6], [-0.6, 1.0]];
+/// let names = vec!["GDP".to_string(), "Adult Mortality".to_string()];
+/// let pixels = render_correlation_heatmap(&matrix, &names, 10)?;
+/// assert_eq!(pixels.len(), 1224 * 1124 * 3);
+/// assert!(pixels.iter().any(|&channel| channel != 255));
+///
+/// // Every matrix row needs a name
+/// assert!(render_correlation_heatmap(&matrix, &names[..1], 10).is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn create_correlation_heatmap(
+    correlation_matrix: &Array2<f64>,
+    output_file: &str,
+    feature_names: &[String], // one per matrix row, in matrix order
+    min_observations: usize,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    check_heatmap_labels(correlation_matrix, feature_names)?;
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), HEATMAP_SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    draw_correlation_heatmap(&root, correlation_matrix, feature_names, min_observations)?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || heatmap_spec("Feature Correlation Heatmap", correlation_matrix, feature_names))?;
+    println!("Heatmap saved to {}", output::display_path(output_file));
+    Ok(())
+}
+
+/// The heatmap of create_correlation_heatmap as RGB pixels, row by row, without
+/// the provenance footer
+pub fn render_correlation_heatmap(
+    correlation_matrix: &Array2<f64>,
+    feature_names: &[String],
+    min_observations: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (width, height) = HEATMAP_SIZE;
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, HEATMAP_SIZE).into_drawing_area();
+        root.fill(&WHITE)?;
+        draw_correlation_heatmap(&root, correlation_matrix, feature_names, min_observations)?;
+        root.present()?;
+    }
+    Ok(pixels)
+}
+
+// One series per row of the matrix, with (column, value) points; NaN cells are null
+pub(crate) fn heatmap_spec(title: &str, matrix: &Array2<f64>, names: &[String]) -> ChartSpecJson {
+    let axis = |label: &str| AxisJson::categories(label, names.to_vec());
+    let mut spec = ChartSpecJson::new("heatmap", title, axis("Features"), axis("Features"));
+    for (i, row) in matrix.rows().into_iter().enumerate() {
+        let name = names.get(i).cloned().unwrap_or_default();
+        spec = spec.series(SeriesJson::new(name, None, row.iter().enumerate().map(|(j, &value)| (j as f64, value)).collect()));
+    }
+    spec
+}
+
+// A label per matrix row, so no row is drawn under another column's name
+fn check_heatmap_labels(correlation_matrix: &Array2<f64>, feature_names: &[String]) -> Result<(), Box<dyn Error>> {
+    let (rows, cols) = correlation_matrix.dim();
+    if rows != cols || feature_names.len() != rows {
+        return Err(format!(
+            "Correlation heatmap: {} feature names for {} included columns ({}x{} matrix); names given: {}",
+            feature_names.len(),
+            rows,
+            rows,
+            cols,
+            feature_names.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// The heatmap with its color bar in the rightmost 100 pixels of `area`
+pub(crate) fn draw_correlation_heatmap<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    correlation_matrix: &Array2<f64>,
+    feature_names: &[String],
+    min_observations: usize,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    check_heatmap_labels(correlation_matrix, feature_names)?;
+    let cols = correlation_matrix.nrows();
+    let (width, _) = area.dim_in_pixel();
+    let (plot_area, legend_area) = area.split_horizontally(width.saturating_sub(100));
+
+    let mut chart = ChartBuilder::on(&plot_area)
+        .caption("Feature Correlation Heatmap", ("sans-serif", 30))
+        .margin(5)
+        .x_label_area_size(210)
+        .y_label_area_size(190)
+        // Integer ranges include their end value, so n cells need 0..n - 1
+        .build_cartesian_2d((0..cols as u32 - 1).into_segmented(), (0..cols as u32 - 1).into_segmented())?;
+
+    // Feature names at the cell centers; row 0 is drawn at the top
+    let label = |index: u32| feature_names.get(index as usize).cloned().unwrap_or_default();
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(cols)
+        .y_labels(cols)
+        .x_label_formatter(&|_| String::new())
+        .y_label_formatter(&|y| match y {
+            SegmentValue::CenterOf(y) if (*y as usize) < cols => label(cols as u32 - 1 - *y),
+            _ => String::new(),
+        })
+        .label_style(("sans-serif", 13))
+        .x_desc("Features")
+        .y_desc("Features")
+        .axis_desc_style(("sans-serif", 20))
+        .draw()?;
+    // Column names run down from below each column; the mesh would center them on the axis
+    let column_style = TextStyle::from(("sans-serif", 13).into_font().transform(FontTransform::Rotate90))
+        .pos(Pos::new(HPos::Left, VPos::Center));
+    for j in 0..cols as u32 {
+        let (x, y) = chart.backend_coord(&(SegmentValue::CenterOf(j), SegmentValue::Exact(0)));
+        plot_area.draw(&Text::new(label(j), (x, y + 8), &column_style))?;
+    }
+
+    // Draw heatmap rectangles; masked cells are grey with the threshold noted
+    let masked_label = format!("n<{}", min_observations);
+    let note_style = TextStyle::from(("sans-serif", 11).into_font())
+        .color(&WHITE)
+        .pos(Pos::new(HPos::Center, VPos::Center));
+    for i in 0..cols {
+        for j in 0..cols {
+            let value = correlation_matrix[(i, j)];
+            let (x0, y0) = (j as u32, cols as u32 - i as u32 - 1);
+            let color = correlation_color(value);
+            let cell = [(SegmentValue::Exact(x0), SegmentValue::Exact(y0)), (SegmentValue::Exact(x0 + 1), SegmentValue::Exact(y0 + 1))];
+            chart.draw_series(std::iter::once(Rectangle::new(cell, color.filled())))?;
+            if value.is_nan() {
+                let center = chart.backend_coord(&(SegmentValue::CenterOf(x0), SegmentValue::CenterOf(y0)));
+                plot_area.draw(&Text::new(masked_label.clone(), center, &note_style))?;
+            }
+        }
+    }
+
+    colormap::draw_color_bar(&legend_area, colormap::Colormap::Diverging, (-1.0, 1.0), "Correlation")?;
+    // Key for the masked cells below the color bar
+    let (_, height) = legend_area.dim_in_pixel();
+    let key_y = height as i32 - 45;
+    legend_area.draw(&Rectangle::new([(10, key_y), (35, key_y + 14)], MASKED_GREY.filled()))?;
+    legend_area.draw(&Text::new(masked_label, (40, key_y), ("sans-serif", 13)))?;
+    Ok(())
+}
+
+// Fill of heatmap cells with too few observations
+const MASKED_GREY: RGBColor = RGBColor(160, 160, 160);
+
+// Fill of a heatmap cell: red at -1 through white at 0 to blue at 1, and grey
+// for a missing (NaN) correlation, which must not look like a zero or a -1
+pub(crate) fn correlation_color(value: f64) -> RGBColor {
+    if value.is_nan() {
+        MASKED_GREY
+    } else {
+        colormap::sample_diverging(value, -1.0, 1.0)
+    }
+}
+
+// Point opacity that shrinks as the point count grows so dense regions stay readable
+fn scatter_alpha(n_points: usize) -> f64 {
+    (20.0 / (n_points.max(1) as f64).sqrt()).clamp(0.05, 0.8)
+}
+
+// Per-call options for create_scatter_plot and draw_scatter
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScatterOptions {
+    pub(crate) hexbin: bool,              // draw a binned density grid instead of individual points
+    pub(crate) annotate_extremes: usize, // label this many points farthest from the trend line
+    pub(crate) trend_line: bool,         // overlay the least-squares regression line
+    pub(crate) x_limits: AxisLimits,     // Full keeps the axis at 0..max
+    pub(crate) y_limits: AxisLimits,
+    pub(crate) aggregate_per_country: bool, // one marker per country at the mean of its years
+    pub(crate) jitter: Option<Jitter>,       // spread stacked yearly points; ignored when aggregating
+}
+
+// Seeded random offset of every drawn point, up to `fraction` of each axis span
+// either way, so the years of one country no longer hide behind each other. Only
+// the markers move; the trend line, annotations and sidecar use the true values.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Jitter {
+    pub(crate) fraction: f64,
+    pub(crate) seed: u64,
+}
+
+// Each point moved by its jitter offset, the same offsets for the same seed
+fn jittered(points: &[(f64, f64)], jitter: Jitter, (x_span, y_span): (f64, f64)) -> Vec<(f64, f64)> {
+    let mut rng = StdRng::seed_from_u64(jitter.seed);
+    let (dx, dy) = (x_span * jitter.fraction, y_span * jitter.fraction);
+    points
+        .iter()
+        .map(|&(x, y)| (x + rng.gen_range(-1.0..=1.0) * dx, y + rng.gen_range(-1.0..=1.0) * dy))
+        .collect()
+}
+
+// Points of a scatter with the country of each: every row as it is, or with
+// aggregate_per_country the mean point of each country's years, in country order
+pub(crate) fn scatter_points(paired: &PairedColumns, options: &ScatterOptions) -> (Vec<(f64, f64)>, Vec<String>) {
+    if !options.aggregate_per_country {
+        return (paired.points(), paired.countries.clone());
+    }
+    let rows: Vec<Vec<f64>> = paired.points().into_iter().map(|(x, y)| vec![x, y]).collect();
+    let (index, means) = aggregate_by_country(&paired.countries, &rows, 2);
+    (means.into_iter().map(|row| (row[0], row[1])).collect(), index.names().to_vec())
+}
+
+// Points pinned to the chart edge by axis limits, as small crosses, with their
+// count in the top-right corner
+pub(crate) fn draw_pinned<DB, X, Y>(
+    chart: &mut ChartContext<DB, Cartesian2d<X, Y>>,
+    pinned: &[(X::ValueType, Y::ValueType)],
+) -> Result<(), Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+    X: Ranged,
+    Y: Ranged,
+    X::ValueType: Clone + 'static,
+    Y::ValueType: Clone + 'static,
+{
+    if pinned.is_empty() {
+        return Ok(());
+    }
+    chart.draw_series(pinned.iter().map(|point| Cross::new(point.clone(), 4, BLACK.stroke_width(1))))?;
+    let area = chart.plotting_area().strip_coord_spec();
+    let (width, _) = area.dim_in_pixel();
+    let label = beyond_range_label(pinned.len());
+    let style = TextStyle::from(("sans-serif", 14).into_font()).pos(Pos::new(HPos::Right, VPos::Top));
+    area.draw(&Text::new(label, (width as i32 - 8, 8), style))?;
+    Ok(())
+}
+
+pub(crate) fn create_scatter_plot(
+    dataset: &Dataset,
+    output_file: &str,
+    income_column: &str,
+    schooling_column: &str,
+    options: &ScatterOptions,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let paired = paired_columns(dataset, income_column, schooling_column)?;
+    report_skipped(output_file, paired.dropped);
+    if paired.is_empty() {
+        return Err(AnalysisError::empty(format!("Scatter plot {}", output_file)).into());
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let caption = if options.aggregate_per_country { "Income vs. Schooling Rates (country means)" } else { "Income vs. Schooling Rates" };
+    let text = ChartText { caption, x_desc: "Income", y_desc: "Schooling Rates" };
+    let (points, countries) = scatter_points(&paired, options);
+    draw_scatter(&root, &points, &countries, &text, options, theme)?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || scatter_spec(&text, &points, options))?;
+    println!("Scatter plot saved to {}", output::display_path(output_file));
+    Ok(())
+}
+
+// Axis ranges of a scatter: the limits, or 0..max
+pub(crate) fn scatter_bounds(points: &[(f64, f64)], options: &ScatterOptions) -> ((f64, f64), (f64, f64)) {
+    let axis_bounds = |limits: &AxisLimits, values: Vec<f64>| {
+        limits.bounds(&values).unwrap_or((0.0, values.iter().copied().fold(f64::NAN, f64::max)))
+    };
+    (
+        axis_bounds(&options.x_limits, points.iter().map(|p| p.0).collect()),
+        axis_bounds(&options.y_limits, points.iter().map(|p| p.1).collect()),
+    )
+}
+
+// Scatter of `points` (labelled by `countries` for the annotations) on axes
+// starting at zero; `points` must not be empty
+pub(crate) fn draw_scatter<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    points: &[(f64, f64)],
+    countries: &[String],
+    text: &ChartText,
+    options: &ScatterOptions,
+    theme: &PlotTheme,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let hexbin = options.hexbin;
+    // Leave room on the right for the count legend in binned mode
+    let (width, _) = area.dim_in_pixel();
+    let (plot_area, legend_area) = area.split_horizontally(if hexbin { width.saturating_sub(120) } else { width });
+
+    let ((x_min, x_max), (y_min, y_max)) = scatter_bounds(points, options);
+    // Points beyond the axis limits are drawn apart, pinned to the edge
+    let mut pinned = Vec::new();
+    let mut inside = Vec::new();
+    let mut inside_countries = Vec::new();
+    for ((point, beyond), country) in pin_points(points, (x_min, x_max), (y_min, y_max)).into_iter().zip(countries) {
+        if beyond {
+            pinned.push(point);
+        } else {
+            inside.push(point);
+            inside_countries.push(country.clone());
+        }
+    }
+
+    let mut chart = ChartBuilder::on(&plot_area)
+        .caption(text.caption, ("sans-serif", 30))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+
+    chart.configure_mesh()
+        .x_desc(text.x_desc)
+        .y_desc(text.y_desc)
+        .x_label_formatter(&|x| format_tick(*x, theme.x_ticks))
+        .y_label_formatter(&|y| format_tick(*y, theme.y_ticks))
+        .draw()?;
+
+    if hexbin {
+        let (nx, ny) = (40, 30);
+        let cells = bin_points(&inside, (nx, ny), (x_min, x_max), (y_min, y_max));
+        let max_count = cells.iter().map(|&(_, count)| count).max().unwrap_or(1);
+        let (cell_w, cell_h) = ((x_max - x_min) / nx as f64, (y_max - y_min) / ny as f64);
+
+        chart.draw_series(cells.iter().map(|&((cx, cy), count)| {
+            let (x0, y0) = (x_min + cx as f64 * cell_w, y_min + cy as f64 * cell_h);
+            Rectangle::new(
+                [(x0, y0), (x0 + cell_w, y0 + cell_h)],
+                colormap::sample(count as f64 / max_count as f64).filled(),
+            )
+        }))?;
+
+        colormap::draw_color_bar(&legend_area, colormap::Colormap::Sequential, (0.0, max_count as f64), "Count")?;
+    } else {
+        let alpha = scatter_alpha(inside.len());
+        let markers = match options.jitter.filter(|_| !options.aggregate_per_country) {
+            Some(jitter) => jittered(&inside, jitter, (x_max - x_min, y_max - y_min)),
+            None => inside.clone(),
+        };
+        chart.draw_series(
+            markers.iter().map(|&(x, y)| Circle::new((x, y), 3, RGBAColor(190, 86, 131, alpha).filled())),
+        )?;
+    }
+    draw_pinned(&mut chart, &pinned)?;
+
+    if options.trend_line {
+        if let Some((slope, intercept)) = linear_fit(points) {
+            chart.draw_series(LineSeries::new(
+                [x_min, x_max].map(|x| (x, slope * x + intercept)),
+                BLACK.stroke_width(2),
+            ))?;
+        }
+    }
+
+    if options.annotate_extremes > 0 {
+        let annotations = extreme_annotations(&inside, &inside_countries, options.annotate_extremes);
+        chart.draw_series(annotations.into_iter().map(|(point, label, offset)| {
+            EmptyElement::at(point)
+                + Circle::new((0, 0), 4, BLACK.stroke_width(1))
+                + Text::new(label, offset, ("sans-serif", 13))
+        }))?;
+    }
+    Ok(())
+}
+
+// The points of a scatter with its axis ranges as draw_scatter computes them,
+// and the regression line when drawn
+fn scatter_spec(text: &ChartText, points: &[(f64, f64)], options: &ScatterOptions) -> ChartSpecJson {
+    let (x_range, y_range) = scatter_bounds(points, options);
+    let mut spec = ChartSpecJson::new("scatter", text.caption, AxisJson::new(text.x_desc, x_range), AxisJson::new(text.y_desc, y_range))
+        .series(SeriesJson::new("Countries", Some(RGBColor(190, 86, 131)), points.to_vec()));
+    if let Some((slope, intercept)) = linear_fit(points).filter(|_| options.trend_line) {
+        let line = [x_range.0, x_range.1].map(|x| (x, slope * x + intercept)).to_vec();
+        spec = spec.series(SeriesJson::new("Trend line", Some(BLACK), line));
+    }
+    spec
+}
+
+// One axis of a scatter with marginal histograms: its column and whether it is
+// drawn on a log scale (log10 of the values, ticks labelled with the values)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MarginalAxis<'a> {
+    pub(crate) column: &'a str,
+    pub(crate) log: bool,
+}
+
+// Pixel split of a marginal scatter below its title strip: the main panel and
+// the thickness of the histogram above it and the one to its right
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MarginalLayout {
+    pub(crate) main: (u32, u32),
+    pub(crate) margin: u32,
+}
+
+// Margins take `fraction` of the shorter side, at least 60 pixels, and never
+// more than half of either side
+pub(crate) fn marginal_layout((width, height): (u32, u32), fraction: f64) -> MarginalLayout {
+    let margin = ((width.min(height) as f64 * fraction).round() as u32).max(60).min(width.min(height) / 2);
+    MarginalLayout { main: (width - margin, height - margin), margin }
+}
+
+// Label areas and gaps shared by the three panels so the histograms line up
+// with the scatter: the top histogram has the scatter's left label area and
+// right margin, the right histogram its top gap and bottom label area
+const MARGINAL_X_LABELS: u32 = 50;
+const MARGINAL_Y_LABELS: u32 = 70;
+const MARGINAL_GAP: u32 = 6;
+const MARGINAL_EDGE: u32 = 20;
+const MARGINAL_BINS: usize = 30;
+
+// Values of one axis as drawn, and the padded range around them
+fn marginal_values(values: &[f64], log: bool) -> (Vec<f64>, (f64, f64)) {
+    let values: Vec<f64> = if log { values.iter().map(|v| v.log10()).collect() } else { values.to_vec() };
+    let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    let pad = if max > min { (max - min) * 0.04 } else { 1.0 };
+    (values, (min - pad, max + pad))
+}
+
+// Tick label of a value on a marginal axis
+fn marginal_tick(value: f64, log: bool, style: TickStyle) -> String {
+    if log { format_tick(10f64.powf(value), TickStyle::Si) } else { format_tick(value, style) }
+}
+
+// Scatter of two columns with the histogram of x above it and of y to its
+// right. The histograms use the scatter's axis ranges for their bins and the
+// same pixel extent, so every bar sits over or beside the points it counts.
+// Rows missing either value, or with a value at or below zero on a log axis,
+// are left out.
+pub(crate) fn plot_scatter_with_marginals(
+    dataset: &Dataset,
+    (x, y): (MarginalAxis, MarginalAxis),
+    text: &ChartText,
+    output_file: &str,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let paired = paired_columns(dataset, x.column, y.column)?;
+    let kept: Vec<(f64, f64)> =
+        paired.points().into_iter().filter(|&(px, py)| (!x.log || px > 0.0) && (!y.log || py > 0.0)).collect();
+    report_skipped(output_file, paired.dropped + paired.len() - kept.len());
+    if kept.is_empty() {
+        return Err(AnalysisError::empty(format!("Scatter plot {}", output_file)).into());
+    }
+    let (xs, x_range) = marginal_values(&kept.iter().map(|p| p.0).collect::<Vec<_>>(), x.log);
+    let (ys, y_range) = marginal_values(&kept.iter().map(|p| p.1).collect::<Vec<_>>(), y.log);
+    let (x_bins, y_bins) = (Bins::spanning(x_range, MARGINAL_BINS), Bins::spanning(y_range, MARGINAL_BINS));
+    let (x_counts, y_counts) = (x_bins.counts(&xs), y_bins.counts(&ys));
+
+    let (width, height) = (1100u32, 900u32);
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (width, height)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (title, body) = root.split_vertically(50);
+    title.draw(&Text::new(
+        text.caption,
+        (width as i32 / 2, 12),
+        TextStyle::from(("sans-serif", 28).into_font()).pos(Pos::new(HPos::Center, VPos::Top)),
+    ))?;
+    let layout = marginal_layout(body.dim_in_pixel(), 0.18);
+    let (top, bottom) = body.split_vertically(layout.margin);
+    let (top_hist, _) = top.split_horizontally(layout.main.0);
+    let (main, right_hist) = bottom.split_horizontally(layout.main.0);
+    let color = RGBColor(190, 86, 131);
+
+    let mut scatter = ChartBuilder::on(&main)
+        .margin_top(MARGINAL_GAP)
+        .margin_right(MARGINAL_GAP)
+        .margin_left(MARGINAL_EDGE)
+        .margin_bottom(MARGINAL_EDGE)
+        .x_label_area_size(MARGINAL_X_LABELS)
+        .y_label_area_size(MARGINAL_Y_LABELS)
+        .build_cartesian_2d(x_range.0..x_range.1, y_range.0..y_range.1)?;
+    scatter
+        .configure_mesh()
+        .x_desc(text.x_desc)
+        .y_desc(text.y_desc)
+        .x_label_formatter(&|v| marginal_tick(*v, x.log, theme.x_ticks))
+        .y_label_formatter(&|v| marginal_tick(*v, y.log, theme.y_ticks))
+        .draw()?;
+    let alpha = scatter_alpha(kept.len());
+    scatter.draw_series(xs.iter().zip(&ys).map(|(&px, &py)| Circle::new((px, py), 3, color.mix(alpha).filled())))?;
+
+    let x_max = x_counts.iter().copied().max().unwrap_or(0).max(1) as f64 * 1.05;
+    let mut top_chart = ChartBuilder::on(&top_hist)
+        .margin_top(MARGINAL_GAP)
+        .margin_bottom(MARGINAL_GAP)
+        .margin_right(MARGINAL_GAP)
+        .margin_left(MARGINAL_EDGE)
+        .y_label_area_size(MARGINAL_Y_LABELS)
+        .build_cartesian_2d(x_range.0..x_range.1, 0.0..x_max)?;
+    top_chart.configure_mesh().disable_x_mesh().light_line_style(WHITE).disable_x_axis().y_labels(3).y_label_formatter(&|v| format!("{:.0}", v)).draw()?;
+    top_chart.draw_series(x_counts.iter().enumerate().map(|(i, &count)| {
+        let start = x_bins.start(i);
+        Rectangle::new([(start, 0.0), (start + x_bins.width, count as f64)], color.mix(0.6).filled())
+    }))?;
+
+    let y_max = y_counts.iter().copied().max().unwrap_or(0).max(1) as f64 * 1.05;
+    let mut right_chart = ChartBuilder::on(&right_hist)
+        .margin_top(MARGINAL_GAP)
+        .margin_bottom(MARGINAL_EDGE)
+        .margin_left(MARGINAL_GAP)
+        .margin_right(MARGINAL_GAP)
+        .x_label_area_size(MARGINAL_X_LABELS)
+        .build_cartesian_2d(0.0..y_max, y_range.0..y_range.1)?;
+    right_chart.configure_mesh().disable_y_mesh().light_line_style(WHITE).disable_y_axis().x_labels(3).x_label_formatter(&|v| format!("{:.0}", v)).draw()?;
+    right_chart.draw_series(y_counts.iter().enumerate().map(|(i, &count)| {
+        let start = y_bins.start(i);
+        Rectangle::new([(0.0, start), (count as f64, start + y_bins.width)], color.mix(0.6).filled())
+    }))?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // The scatter in drawn coordinates (log10 on a log axis), then each
+    // histogram as (bin start, count)
+    chart_json::write_sidecar(output_file, run, || {
+        let axis = |column: &str, log: bool, range| AxisJson::new(if log { format!("log10 {}", column) } else { column.to_string() }, range);
+        let histogram = |bins: &Bins, counts: &[usize]| (0..bins.len()).map(|i| (bins.start(i), counts[i] as f64)).collect();
+        ChartSpecJson::new("scatter", text.caption, axis(x.column, x.log, x_range), axis(y.column, y.log, y_range))
+            .series(SeriesJson::new("Countries", Some(color), xs.iter().copied().zip(ys.iter().copied()).collect()))
+            .series(SeriesJson::new(format!("{} histogram", x.column), Some(color), histogram(&x_bins, &x_counts)))
+            .series(SeriesJson::new(format!("{} histogram", y.column), Some(color), histogram(&y_bins, &y_counts)))
+    })?;
+    println!("Scatter plot with marginals saved to {}", output::display_path(output_file));
+    Ok(())
+}
+
+// Yearly means of one feature for the Developed and Developing groups, with
+// years missing from a group drawn at zero
+#[derive(Debug, Clone)]
+pub(crate) struct StatusLines {
+    pub(crate) years: Vec<u16>,
+    pub(crate) developed: Vec<f64>,
+    pub(crate) developing: Vec<f64>,
+}
+
+// Status lines of the feature in `feature_column`; `context` names the output in
+// the skipped-rows note and the empty-input error
+pub(crate) fn status_lines(
+    rows: &[YearRow],
+    feature_column: usize,
+    status_column: usize,
+    context: &str,
+) -> Result<StatusLines, Box<dyn Error>> {
+    let grouped = grouped_mean(
+        rows,
+        |(year, record)| Some((*year, record.get(status_column).unwrap_or("").to_string())),
+        |(_, record)| get_f64(record, feature_column),
+    );
+    report_skipped(context, grouped.skipped);
+    let averages: HashMap<(u16, String), f64> = means_only(grouped);
+
+    // Each year once, in numeric order
+    let years: Vec<u16> = averages.keys().map(|(year, _)| *year).collect::<BTreeSet<_>>().into_iter().collect();
+    if years.is_empty() {
+        return Err(AnalysisError::empty(context).into());
+    }
+    let mut developed = Vec::new();
+    let mut developing = Vec::new();
+
+    for year in &years {
+        developed.push(averages.get(&(*year, "Developed".to_string())).copied().unwrap_or(0.0));
+        developing.push(averages.get(&(*year, "Developing".to_string())).copied().unwrap_or(0.0));
+    }
+    Ok(StatusLines { years, developed, developing })
+}
+
+pub(crate) fn draw_status_lines<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    lines: &StatusLines,
+    text: &ChartText,
+    y_range: Range<f64>,
+    theme: &PlotTheme,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let StatusLines { years, developed, developing } = lines;
+    let mut chart = ChartBuilder::on(area)
+        .caption(text.caption, ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0..years.len() as u32, y_range)?;
+
+    chart.configure_mesh()
+        .x_labels(years.len())
+        .y_desc(text.y_desc)
+        .x_desc(text.x_desc)
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| years.get(*x as usize).map(|year| year.to_string()).unwrap_or_default())
+        .y_label_formatter(&|y| format_tick(*y, theme.y_ticks))
+        .draw()?;
+
+    chart.draw_series(LineSeries::new(
+        (0..developed.len()).map(|x| x as u32).zip(developed.iter().copied()),
+        &RED,
+    ))?
+        .label("Developed")
+        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], RED));
+
+    chart.draw_series(LineSeries::new(
+        (0..developing.len()).map(|x| x as u32).zip(developing.iter().copied()),
+        &BLUE,
+    ))?
+        .label("Developing")
+        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BLUE));
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+    Ok(())
+}
+
+fn save_status_lines(
+    lines: &StatusLines,
+    text: &ChartText,
+    y_range: Range<f64>,
+    output_file: &str,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+    draw_status_lines(&root, lines, text, y_range.clone(), theme)?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let years = lines.years.iter().map(|year| year.to_string()).collect();
+        let points = |values: &[f64]| values.iter().enumerate().map(|(x, &y)| (x as f64, y)).collect();
+        ChartSpecJson::new("line", text.caption, AxisJson::categories(text.x_desc, years), AxisJson::new(text.y_desc, (y_range.start, y_range.end)))
+            .series(SeriesJson::new("Developed", Some(RED), points(&lines.developed)))
+            .series(SeriesJson::new("Developing", Some(BLUE), points(&lines.developing)))
+    })?;
+    Ok(())
+}
+
+pub(crate) fn create_developed_vs_developing_plot(
+    rows: &[YearRow],
+    output_file: &str,
+    feature_column: usize,
+    status_column: usize,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let lines = status_lines(rows, feature_column, status_column, output_file)?;
+    let text = ChartText {
+        caption: "Developed vs Developing Adult Mortality Averages per Year ",
+        x_desc: "Years",
+        y_desc: "Adult Mortality Averages ",
+    };
+    save_status_lines(&lines, &text, 0.0..250.0, output_file, theme, run)
+}
+// same chart as the one above, differences in the Y-axis view
+pub(crate) fn create_developed_vs_developing_plot_infant(
+    rows: &[YearRow],
+    output_file: &str,
+    feature_column: usize,
+    status_column: usize,
+    theme: &PlotTheme,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let lines = status_lines(rows, feature_column, status_column, output_file)?;
+    let text = ChartText {
+        caption: "Developed vs Developing Infant Mortality Averages per Year ",
+        x_desc: "Years",
+        y_desc: "Infant Mortality Averages ",
+    };
+    save_status_lines(&lines, &text, 0.0..50.0, output_file, theme, run)
+}
+
+// Mean of each named feature per status, the columns looked up by header name
+pub(crate) fn create_features_comparison_bar_plot(
+    file_path: &str,
+    output_file: &str,
+    feature_names: &[&str],
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let feature_columns = columns::resolve_columns(file_path, feature_names)?;
+    let status_column = columns::resolve_columns(file_path, &["Status"])?[0];
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let records: Vec<StringRecord> = reader.records().collect::<Result<_, _>>()?;
+
+    // One item per (record, feature) so every feature is averaged per status
+    let items = records
+        .iter()
+        .flat_map(|record| feature_columns.iter().zip(feature_names).map(move |(&col, &name)| (record, col, name)));
+    let grouped = grouped_mean(
+        items,
+        |(record, _, name)| Some((name.to_string(), record.get(status_column).unwrap_or("").to_string())),
+        |(record, col, _)| get_f64(record, *col),
+    );
+    report_skipped(output_file, grouped.skipped);
+    let averages: HashMap<(String, String), f64> = means_only(grouped);
+    // Also covers an empty feature list, which would leave the y range NaN
+    if averages.is_empty() {
+        return Err(AnalysisError::empty(output_file).into());
+    }
+
+    let developed_averages: Vec<f64> = feature_names
+        .iter()
+        .map(|&name| *averages.get(&(name.to_string(), "Developed".to_string())).unwrap_or(&0.0))
+        .collect();
+
+    let developing_averages: Vec<f64> = feature_names
+        .iter()
+        .map(|&name| *averages.get(&(name.to_string(), "Developing".to_string())).unwrap_or(&0.0))
+        .collect();
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let max_avg = developed_averages
+        .iter()
+        .chain(developing_averages.iter())
+        .cloned()
+        .fold(f64::NAN, f64::max);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Comparison of Features Between Developed and Developing Countries", ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0..(feature_names.len() as i32 * 2), 0.0..(max_avg * 1.2))?;
+
+    chart
+        .configure_mesh()
+        .x_labels(feature_names.len() * 2)
+        .y_desc("Average")
+        .x_desc("Features")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| {
+            let index = (*x as usize) / 2;
+            feature_names.get(index).unwrap_or(&"").to_string()
+        })
+        .draw()?;
+
+    // Plot Developed
+    chart.draw_series(developed_averages.iter().enumerate().map(|(i, avg)| {
+        Rectangle::new(
+            [(i as i32 * 2, 0.0), (i as i32 * 2 + 1, *avg)],
+            ShapeStyle {
+                color: RGBAColor(190, 86, 131, 1f64), // #A5CBC3 for Developing
+                filled: true,
+                stroke_width: 0,
+            },
+        )
+    }))?
+        .label("Developed")
+        .legend(|(x, y)| Rectangle::new(
+            [(x, y - 5), (x + 10, y + 5)],
+            ShapeStyle {
+                color: RGBAColor(190, 86, 131, 1f64), // #A5CBC3 for Developing
+                filled: true,
+                stroke_width: 0,
+            },
+        ));
+
+    // Plot Developing
+    chart.draw_series(developing_averages.iter().enumerate().map(|(i, avg)| {
+        Rectangle::new(
+            [(i as i32 * 2 + 1, 0.0), (i as i32 * 2 + 2, *avg)],
+            ShapeStyle {
+                color: RGBAColor(110, 48, 75, 1f64),
+                filled: true,
+                stroke_width: 0,
+            },
+        )
+    }))?
+        .label("Developing")
+        .legend(|(x, y)| Rectangle::new(
+            [(x, y - 5), (x + 10, y + 5)],
+            ShapeStyle {
+                color: RGBAColor(110, 48, 75, 1f64),
+                filled: true,
+                stroke_width: 0,
+            },));
+
+    chart
+        .configure_series_labels()
+        .label_font(("sans-serif", 15))
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let names = feature_names.iter().map(|name| name.to_string()).collect();
+        let bars = |values: &[f64]| values.iter().enumerate().map(|(i, &avg)| (i as f64, avg)).collect();
+        ChartSpecJson::new(
+            "bar",
+            "Comparison of Features Between Developed and Developing Countries",
+            AxisJson::categories("Features", names),
+            AxisJson::new("Average", (0.0, max_avg * 1.2)),
+        )
+        .series(SeriesJson::new("Developed", Some(RGBColor(190, 86, 131)), bars(&developed_averages)))
+        .series(SeriesJson::new("Developing", Some(RGBColor(110, 48, 75)), bars(&developing_averages)))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scatter_alpha_fades_with_the_point_count() {
+        assert_eq!(scatter_alpha(0), 0.8);
+        assert_eq!(scatter_alpha(100), 0.8);
+        assert_eq!(scatter_alpha(1_600), 0.5);
+        assert_eq!(scatter_alpha(1_000_000), 0.05);
+        assert!(scatter_alpha(2_000) < scatter_alpha(1_000));
+    }
+
+    #[test]
+    fn mismatched_heatmap_labels_list_the_headers() {
+        let matrix = Array2::from_elem((3, 3), 0.5);
+        let names: Vec<String> = ["Life expectancy", "GDP"].iter().map(|name| name.to_string()).collect();
+        let error = check_heatmap_labels(&matrix, &names).unwrap_err().to_string();
+        assert!(error.contains("2 feature names for 3 included columns (3x3 matrix)"), "{}", error);
+        assert!(error.ends_with("names given: Life expectancy, GDP"), "{}", error);
+
+        let run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        let output_file = crate::test_support::scratch_dir("heatmap-labels").join("heatmap.png");
+        assert!(create_correlation_heatmap(&matrix, output_file.to_str().unwrap(), &names, 5, &run).is_err());
+        assert!(!output_file.exists());
+        assert!(check_heatmap_labels(&Array2::zeros((2, 2)), &names).is_ok());
+    }
+
+    #[test]
+    fn marginal_layout_splits_the_canvas() {
+        let layout = marginal_layout((1100, 850), 0.18);
+        assert_eq!(layout, MarginalLayout { main: (947, 697), margin: 153 });
+        // At least 60 pixels, but never more than half of the shorter side
+        assert_eq!(marginal_layout((1000, 200), 0.1).margin, 60);
+        assert_eq!(marginal_layout((200, 100), 0.18), MarginalLayout { main: (150, 50), margin: 50 });
+    }
+
+    #[test]
+    fn marginal_values_pad_the_drawn_range() {
+        let (values, range) = marginal_values(&[1.0, 10.0, 100.0], true);
+        assert_eq!(values, vec![0.0, 1.0, 2.0]);
+        assert!((range.0 + 0.08).abs() < 1e-12 && (range.1 - 2.08).abs() < 1e-12);
+        assert_eq!(marginal_values(&[5.0], false).1, (4.0, 6.0));
+        assert_eq!(marginal_tick(3.0, true, TickStyle::Plain), format_tick(1000.0, TickStyle::Si));
+    }
+
+    #[test]
+    fn marginal_histograms_share_the_scatter_ranges() {
+        let dir = crate::test_support::scratch_dir("marginal-scatter");
+        let output_file = dir.join("marginals.png");
+        let output_file = output_file.to_str().unwrap();
+        let row = |gdp: String, life_expectancy: String| csv::StringRecord::from(vec!["A".to_string(), gdp, life_expectancy]);
+        let mut rows: Vec<csv::StringRecord> = (1..=40).map(|i| row((i * 250).to_string(), (50 + i % 30).to_string())).collect();
+        // Not drawable on the log axis, and missing a value
+        rows.push(row("0".to_string(), "70".to_string()));
+        rows.push(row("500".to_string(), String::new()));
+        let headers = ["Country", "GDP", "Life expectancy"].iter().map(|name| name.to_string()).collect();
+        let dataset = Dataset { headers, rows };
+        let mut run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        run.chart_json = true;
+        let text = ChartText { caption: "GDP (log) vs Life expectancy", x_desc: "GDP", y_desc: "Life expectancy" };
+        let axes = (MarginalAxis { column: "GDP", log: true }, MarginalAxis { column: "Life expectancy", log: false });
+        plot_scatter_with_marginals(&dataset, axes, &text, output_file, &PlotTheme::default(), &run).unwrap();
+
+        let sidecar: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(chart_json::sidecar_path(output_file)).unwrap()).unwrap();
+        let series = sidecar["series"].as_array().unwrap();
+        assert_eq!(series[0]["points"].as_array().unwrap().len(), 40);
+        for (histogram, axis) in [(&series[1], "x_axis"), (&series[2], "y_axis")] {
+            let bars = histogram["points"].as_array().unwrap();
+            assert_eq!(bars.len(), MARGINAL_BINS);
+            // The first bin starts at the axis minimum and the counts cover every point
+            assert_eq!(bars[0][0], sidecar[axis]["range"][0]);
+            assert_eq!(bars.iter().map(|bar| bar[1].as_f64().unwrap()).sum::<f64>(), 40.0);
+        }
+        assert_eq!(sidecar["x_axis"]["label"], "log10 GDP");
+    }
+
+    #[test]
+    fn aggregation_draws_one_point_per_country() {
+        let rows = [("Peru", Some(1.0), Some(2.0)), ("Chad", Some(3.0), Some(1.0)), ("Peru", Some(3.0), Some(4.0)), ("Chad", None, Some(5.0)), ("Benin", Some(2.0), Some(2.0))];
+        let paired = PairedColumns::from_rows(rows.iter().map(|&(country, x, y)| (x, y, country)));
+        let (points, countries) = scatter_points(&paired, &ScatterOptions::default());
+        assert_eq!(points.len(), 4);
+
+        let options = ScatterOptions { aggregate_per_country: true, ..Default::default() };
+        let (points, countries_aggregated) = scatter_points(&paired, &options);
+        let distinct: std::collections::BTreeSet<&String> = countries.iter().collect();
+        assert_eq!(points.len(), distinct.len());
+        assert_eq!(countries_aggregated, ["Benin", "Chad", "Peru"]);
+        assert_eq!(points, vec![(2.0, 2.0), (3.0, 1.0), (2.0, 3.0)]);
+    }
+
+    #[test]
+    fn jitter_is_seeded_and_bounded() {
+        let points: Vec<(f64, f64)> = (0..200).map(|i| (i as f64, 50.0)).collect();
+        let jitter = Jitter { fraction: 0.01, seed: 42 };
+        let moved = jittered(&points, jitter, (200.0, 10.0));
+        assert_eq!(moved, jittered(&points, jitter, (200.0, 10.0)));
+        assert_ne!(moved, jittered(&points, Jitter { seed: 43, ..jitter }, (200.0, 10.0)));
+        for (&(x, y), &(jx, jy)) in points.iter().zip(&moved) {
+            assert!((jx - x).abs() <= 2.0 && (jy - y).abs() <= 0.1);
+        }
+        assert!(moved.iter().any(|&(_, y)| y != 50.0));
+    }
+
+    #[test]
+    fn correlation_colors_run_from_red_through_white_to_blue() {
+        assert_eq!(correlation_color(-1.0), RGBColor(178, 24, 43));
+        assert_eq!(correlation_color(-0.5), RGBColor(239, 138, 98));
+        assert_eq!(correlation_color(0.0), RGBColor(247, 247, 247));
+        assert_eq!(correlation_color(0.5), RGBColor(103, 169, 207));
+        assert_eq!(correlation_color(1.0), RGBColor(33, 102, 172));
+        // Missing is grey, unlike a zero or any other correlation
+        assert_eq!(correlation_color(f64::NAN), MASKED_GREY);
+        assert!([-1.0, -0.5, 0.0, 0.5, 1.0].iter().all(|&value| correlation_color(value) != MASKED_GREY));
+    }
+
+    // Pearson correlations and row pairs of the fixture below, row i and column j
+    // being the i-th and j-th listed column; checked against an independent
+    // computation from the written CSV
+    const FIXTURE_CORRELATIONS: [[f64; 5]; 5] = [
+        [1.0, -0.9673430298421861, 0.8687924452053667, 0.7668049338320178, 0.5461745194252587],
+        [-0.9673430298421861, 1.0, -0.8494449969845038, -0.7238226315860941, -0.5392494179444919],
+        [0.8687924452053667, -0.8494449969845038, 1.0, 0.6853810909570831, 0.5054852839635352],
+        [0.7668049338320178, -0.7238226315860941, 0.6853810909570831, 1.0, 0.6975222288165028],
+        [0.5461745194252587, -0.5392494179444919, 0.5054852839635352, 0.6975222288165028, 1.0],
+    ];
+
+    const FIXTURE_PAIRS: [[usize; 5]; 5] = [
+        [188, 173, 179, 185, 184],
+        [173, 184, 176, 180, 182],
+        [179, 176, 191, 187, 188],
+        [185, 180, 187, 196, 192],
+        [184, 182, 188, 192, 196],
+    ];
+
+    #[test]
+    fn the_fixture_correlations_are_pinned() {
+        let dir = crate::test_support::scratch_dir("correlation-fixture");
+        let path = dir.join("fixture.csv");
+        let path = path.to_str().unwrap();
+        let mut records = crate::synthetic::generate_synthetic_dataset(20, 2000..=2009, 42);
+        crate::synthetic::blank_values(&mut records, 0.05, 7);
+        crate::synthetic::write_dataset_csv(&records, path).unwrap();
+        let dataset = Dataset::load(path).unwrap();
+        let columns = ["Life expectancy", "Adult Mortality", "GDP", "Schooling", "BMI"];
+        let analysis = crate::correlation::CorrelationAnalysis::compute(&dataset, &columns, crate::correlation::CorrelationMethod::Pearson, 5).unwrap();
+        assert_eq!(analysis.labels, columns);
+        for (i, (expected, pairs)) in FIXTURE_CORRELATIONS.iter().zip(FIXTURE_PAIRS).enumerate() {
+            for j in 0..columns.len() {
+                let value = analysis.matrix[(i, j)];
+                assert!((value - expected[j]).abs() < 1e-9, "{} vs {}: {} instead of {}", columns[i], columns[j], value, expected[j]);
+                assert_eq!(analysis.pairs[(i, j)], pairs[j], "{} vs {}", columns[i], columns[j]);
+            }
+        }
+    }
+}
@@ -1,167 +1,324 @@
+#[cfg(feature = "plots")]
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+#[cfg(feature = "plots")]
+use std::collections::HashSet;
 use std::error::Error;
-use csv::Reader;
-use ndarray::Array2;
+use csv::Writer;
+use ndarray::Array1;
 use ordered_float::NotNan;
-use plotters::backend::BitMapBackend;
-use plotters::chart::ChartBuilder;
-use plotters::element::{Circle, PathElement, Rectangle};
-use plotters::prelude::*;
+use serde::Serialize;
+use statrs::statistics::{Data, Max, Median, Min};
+use crate::analysis_error::AnalysisError;
+use crate::fields::{self, LifeExpectancyRecord, FIELDS};
+#[cfg(feature = "plots")]
+use crate::load_clean::Dataset;
+use crate::load_clean::{get_f64, YearRow};
+use crate::format::{format_option, format_value, Unit};
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::table::{Cell, Table, TableOptions};
+
+// Min-heap of (value, country), popped in order by rank_countries
+#[cfg(feature = "plots")]
+type YearHeap = BinaryHeap<Reverse<(NotNan<f64>, String)>>;
+
+// Compensated (Neumaier-Kahan) running sum. Large columns such as Population
+// reach ~1e13 in total, where naive accumulation drops the low-order digits of
+// every addend and the result depends on the order of the additions.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KahanSum {
+    sum: f64,
+    compensation: f64, // low-order part lost from `sum` so far
+}
+
+impl KahanSum {
+    pub(crate) fn add(&mut self, value: f64) {
+        let total = self.sum + value;
+        if self.sum.abs() >= value.abs() {
+            self.compensation += (self.sum - total) + value;
+        } else {
+            self.compensation += (value - total) + self.sum;
+        }
+        self.sum = total;
+    }
+
+    pub(crate) fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl std::iter::Sum<f64> for KahanSum {
+    fn sum<I: Iterator<Item = f64>>(iter: I) -> Self {
+        let mut total = KahanSum::default();
+        iter.for_each(|value| total.add(value));
+        total
+    }
+}
+
+// Compensated sum of an iterator
+pub(crate) fn kahan_sum(values: impl IntoIterator<Item = f64>) -> f64 {
+    values.into_iter().sum::<KahanSum>().value()
+}
+
+// Compensated mean; None when there are no values
+fn kahan_mean(values: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let (mut total, mut count) = (KahanSum::default(), 0);
+    for value in values {
+        total.add(value);
+        count += 1;
+    }
+    (count > 0).then(|| total.value() / count as f64)
+}
+
+// Listed countries and their life expectancy per year, years in order
+pub(crate) type TopCountries = BTreeMap<u16, Vec<(String, f64)>>;
+
+// End of the ranking find_top_countries lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RankOrder {
+    Highest, // best performers, highest value first
+    Lowest,  // worst performers, lowest value first
+}
+
+impl RankOrder {
+    // Value the heap ranks by, lowest first
+    fn key(self, value: NotNan<f64>) -> NotNan<f64> {
+        match self {
+            RankOrder::Highest => -value,
+            RankOrder::Lowest => value,
+        }
+    }
+
+    fn heading(self, n: usize) -> String {
+        match self {
+            RankOrder::Highest => format!("Top {} countries by life expectancy (highest first)", n),
+            RankOrder::Lowest => format!("Bottom {} countries by life expectancy (lowest first)", n),
+        }
+    }
+}
 
 // EDA + Statistics
-pub(crate) fn find_top_countries(file_path: &str, country_column: usize, year_column: usize, life_expectancy_column: usize) -> Result<(), Box<dyn Error>> {
-    let mut reader = Reader::from_path(file_path)?;
-
-    let mut year_data: HashMap<String, BinaryHeap<Reverse<(NotNan<f64>, String)>>> = HashMap::new();
-
-    for result in reader.records() {
-        let record = result?;
-        let country = record.get(country_column).unwrap_or("").to_string();
-        let year = record.get(year_column).unwrap_or("").to_string();
-        let life_expectancy = record
-            .get(life_expectancy_column)
-            .unwrap_or("0")
-            .parse::<f64>()
-            .ok()
-            .and_then(|val| NotNan::new(val).ok())
-            .unwrap_or_else(|| NotNan::new(0.0).unwrap());
-
-        year_data
-            .entry(year.clone())
-            .or_insert_with(BinaryHeap::new)
-            .push(Reverse((life_expectancy, country)));
-    }
-
-    for (year, mut heap) in year_data {
-        println!("Top 5 countries in year {}:", year);
-        let mut top_countries = Vec::new();
-        while let Some(Reverse((life_expectancy, country))) = heap.pop() {
-            top_countries.push((country, life_expectancy));
-            if top_countries.len() == 5 {
-                break;
-            }
+// The n countries at the `order` end of every year's life expectancy ranking,
+// most extreme first, ties by name; a year with fewer countries lists them all.
+// Rows without a value are skipped rather than ranked, so a blank cell cannot
+// pass for the lowest value. Each year keeps a max-heap of at most n entries
+// whose root is dropped when a more extreme value arrives, so memory stays O(n)
+// per year however many rows there are; countries are borrowed from the rows and
+// only copied for the result.
+pub(crate) fn find_top_countries(
+    rows: &[YearRow],
+    country_column: usize,
+    life_expectancy_column: usize,
+    n: usize,
+    order: RankOrder,
+) -> Result<TopCountries, AnalysisError> {
+    let mut year_data: BTreeMap<u16, BinaryHeap<(NotNan<f64>, &str)>> = BTreeMap::new();
+    let mut skipped = 0;
+
+    for (year, record) in rows {
+        let country = record.get(country_column).unwrap_or("");
+        let Some(life_expectancy) = get_f64(record, life_expectancy_column).and_then(|val| NotNan::new(val).ok()) else {
+            skipped += 1;
+            continue;
+        };
+
+        let heap = year_data.entry(*year).or_insert_with(|| BinaryHeap::with_capacity(n + 1));
+        heap.push((order.key(life_expectancy), country));
+        if heap.len() > n {
+            heap.pop();
         }
+    }
+    report_skipped(if order == RankOrder::Highest { "Top countries" } else { "Bottom countries" }, skipped);
+    if year_data.is_empty() {
+        return Err(AnalysisError::empty("Top countries"));
+    }
 
-        for (country, life_expectancy) in top_countries {
-            println!("{}: {:.2}", country, life_expectancy);
+    Ok(year_data
+        .into_iter()
+        .map(|(year, heap)| {
+            let listed = heap
+                .into_sorted_vec()
+                .into_iter()
+                .map(|(key, country)| (country.to_string(), order.key(key).into_inner()));
+            (year, listed.collect())
+        })
+        .collect())
+}
+
+// The lists of find_top_countries, one block per year headed by its direction
+pub(crate) fn print_top_countries(top: &TopCountries, n: usize, order: RankOrder) {
+    let heading = order.heading(n);
+    for (year, countries) in top {
+        println!("{} in year {}:", heading, year);
+        for (country, life_expectancy) in countries {
+            println!("{}: {}", country, format_value(*life_expectancy, Unit::Years));
         }
         println!();
     }
+}
 
-    Ok(())
+// Pops up to n entries off the heap, lowest value first
+#[cfg(feature = "plots")]
+fn pop_lowest(heap: &mut YearHeap, n: usize) -> Vec<(String, NotNan<f64>)> {
+    let mut popped = Vec::new();
+    while popped.len() < n {
+        let Some(Reverse((value, country))) = heap.pop() else { break };
+        popped.push((country, value));
+    }
+    popped
 }
 
-// Calculate the correlation between two variables
-pub(crate) fn create_correlation_heatmap(
-    file_path: &str,
-    output_file: &str,
-    exclude_columns: &[usize], // Columns to exclude (e.g., Year, Country)
-    feature_names: &[String],  // Names of all columns (for labeling the heatmap)
-) -> Result<(), Box<dyn Error>> {
-    // Load CSV data
-    let mut reader = csv::Reader::from_path(file_path)?;
-    // let headers = reader.headers()?.clone();
-
-    // Parse the data into a matrix
-    let mut data_matrix: Vec<Vec<f64>> = Vec::new();
-    for record in reader.records() {
-        let record = record?;
-        let row: Vec<f64> = record
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| !exclude_columns.contains(i)) // Exclude specific columns
-            .map(|(_, value)| value.parse::<f64>().unwrap_or(0.0)) // Parse as f64, default to 0.0
-            .collect();
-        data_matrix.push(row);
-    }
-
-    let data = Array2::from_shape_vec(
-        (data_matrix.len(), data_matrix[0].len()),
-        data_matrix.into_iter().flatten().collect(),
-    )?;
-
-    let (_, cols) = data.dim();
-    if cols == 0 {
-        return Err("No columns to process".into());
-    }
-
-    // Calculate the correlation matrix
-    let mut correlation_matrix = Array2::zeros((cols, cols));
-    for i in 0..cols {
-        for j in 0..cols {
-            let col_i = data.column(i);
-            let col_j = data.column(j);
-            let correlation = calculate_correlation(&col_i.view(), &col_j.view()).unwrap_or(0.0);
-            correlation_matrix[(i, j)] = correlation;
-        }
+// Countries ordered from highest to lowest value (rank 1 first), NaNs dropped
+#[cfg(feature = "plots")]
+pub(crate) fn rank_countries(values: impl IntoIterator<Item = (String, f64)>) -> Vec<(String, f64)> {
+    let mut heap: YearHeap = values
+        .into_iter()
+        .filter_map(|(country, value)| NotNan::new(value).ok().map(|value| Reverse((value, country))))
+        .collect();
+    let mut ranked = pop_lowest(&mut heap, usize::MAX);
+    ranked.reverse();
+    ranked.into_iter().map(|(country, value)| (country, value.into_inner())).collect()
+}
+
+// Percentile rank of `value` among `values`, 0 to 100: the share of values below
+// it plus half the share equal to it. NaNs are ignored; None without any values.
+pub(crate) fn percentile_rank(values: &[f64], value: f64) -> Option<f64> {
+    let values: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if values.is_empty() || value.is_nan() {
+        return None;
     }
+    let below = values.iter().filter(|&&v| v < value).count() as f64;
+    let equal = values.iter().filter(|&&v| v == value).count() as f64;
+    Some(100.0 * (below + 0.5 * equal) / values.len() as f64)
+}
 
-    let root = BitMapBackend::new(output_file, (1024, 1024)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Feature Correlation Heatmap", ("sans-serif", 30))
-        .margin(5)
-        .x_label_area_size(60)
-        .y_label_area_size(60)
-        .build_cartesian_2d(0..cols as u32, 0..cols as u32)?;
-
-    chart
-        .configure_mesh()
-        .disable_mesh()
-        .x_labels(cols)
-        .y_labels(cols)
-        .x_desc("Features")
-        .y_desc("Features")
-        .label_style(("sans-serif", 15))
-        .axis_desc_style(("sans-serif", 20))
-        .draw()?;
-
-    // Add labels for axes (feature names)
-    chart.configure_mesh().x_label_formatter(&|x| {
-        feature_names
-            .get(*x as usize)
-            .cloned()
-            .unwrap_or_else(|| "Unknown".to_string())
-    });
-    chart.configure_mesh().y_label_formatter(&|y| {
-        feature_names
-            .get(*y as usize)
-            .cloned()
-            .unwrap_or_else(|| "Unknown".to_string())
-    });
-
-    // Draw heatmap rectangles
-    for i in 0..cols {
-        for j in 0..cols {
-            let value = correlation_matrix[(i, j)];
-            let color = if value >= 0.0 {
-                RGBColor((255.0 * (1.0 - value)) as u8, (255.0 * value) as u8, 0)
-            } else {
-                RGBColor(0, (255.0 * (1.0 + value)) as u8, (255.0 * (-value)) as u8)
-            };
-            chart.draw_series(std::iter::once(Rectangle::new(
-                [
-                    (j as u32, cols as u32 - i as u32 - 1),
-                    ((j + 1) as u32, cols as u32 - i as u32),
-                ],
-                color.filled(),
-            )))?;
+// Default winsorizing limits of the robust correlations, as quantiles
+pub(crate) const WINSOR_LIMITS: (f64, f64) = (0.01, 0.99);
+
+// Two columns aligned row by row, keeping the rows where both have a finite value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct PairedColumns {
+    pub(crate) x: Vec<f64>,
+    pub(crate) y: Vec<f64>,
+    pub(crate) dropped: usize,          // rows missing x, y or both
+    pub(crate) countries: Vec<String>, // country of every kept row
+}
+
+impl PairedColumns {
+    // Pair (x, y, country) rows; NaN and infinite values count as missing
+    pub(crate) fn from_rows<'a>(rows: impl IntoIterator<Item = (Option<f64>, Option<f64>, &'a str)>) -> Self {
+        let mut paired = PairedColumns::default();
+        for (x, y, country) in rows {
+            match (x.filter(|x| x.is_finite()), y.filter(|y| y.is_finite())) {
+                (Some(x), Some(y)) => {
+                    paired.x.push(x);
+                    paired.y.push(y);
+                    paired.countries.push(country.to_string());
+                }
+                _ => paired.dropped += 1,
+            }
         }
+        paired
     }
 
-    println!("Heatmap saved to {}", output_file);
-    Ok(())
+    pub(crate) fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    #[cfg(feature = "plots")]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    #[cfg(feature = "plots")]
+    pub(crate) fn points(&self) -> Vec<(f64, f64)> {
+        self.x.iter().copied().zip(self.y.iter().copied()).collect()
+    }
+
+    // None below `min_observations` pairs, 0 when either side is constant
+    pub(crate) fn correlation(&self, min_observations: usize) -> Option<f64> {
+        (self.len() >= min_observations).then(|| {
+            calculate_correlation(&Array1::from(self.x.clone()).view(), &Array1::from(self.y.clone()).view()).unwrap_or(0.0)
+        })
+    }
+}
+
+// Columns `x` and `y` of the dataset by header name, paired row by row
+#[cfg(feature = "plots")]
+pub(crate) fn paired_columns(dataset: &Dataset, x: &str, y: &str) -> Result<PairedColumns, Box<dyn Error>> {
+    let (xs, ys, countries) = (dataset.numeric_column(x)?, dataset.numeric_column(y)?, dataset.text_column("Country")?);
+    Ok(PairedColumns::from_rows(xs.into_iter().zip(ys).zip(&countries).map(|((x, y), country)| (x, y, country.as_str()))))
+}
+
+// Value at quantile p of sorted values, interpolating linearly between order
+// statistics; `sorted` must not be empty
+pub(crate) fn quantile(sorted: &[f64], p: f64) -> f64 {
+    let position = (sorted.len() - 1) as f64 * p.clamp(0.0, 1.0);
+    let (low, high) = (position.floor() as usize, position.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (position - low as f64)
 }
 
-// Helper function to calculate correlation
-fn calculate_correlation(x: &ndarray::ArrayView1<f64>, y: &ndarray::ArrayView1<f64>) -> Option<f64> {
-    let x_mean = x.mean()?;
-    let y_mean = y.mean()?;
-    let numerator = x.iter().zip(y.iter()).map(|(&xi, &yi)| (xi - x_mean) * (yi - y_mean)).sum::<f64>();
-    let denominator_x = x.iter().map(|&xi| (xi - x_mean).powi(2)).sum::<f64>().sqrt();
-    let denominator_y = y.iter().map(|&yi| (yi - y_mean).powi(2)).sum::<f64>().sqrt();
+// Values clamped to the given quantiles of their own finite values, so a few
+// extreme values cannot dominate a mean or correlation. Non-finite values pass
+// through unchanged.
+pub(crate) fn winsorize(values: &[f64], (low, high): (f64, f64)) -> Vec<f64> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|value| value.is_finite()).collect();
+    if sorted.is_empty() {
+        return values.to_vec();
+    }
+    sorted.sort_by(f64::total_cmp);
+    let (floor, ceiling) = (quantile(&sorted, low), quantile(&sorted, high));
+    values.iter().map(|&value| if value.is_finite() { value.clamp(floor, ceiling) } else { value }).collect()
+}
+
+// winsorize over the present values of a column, keeping missing cells missing
+pub(crate) fn winsorize_column(column: &[Option<f64>], limits: (f64, f64)) -> Vec<Option<f64>> {
+    let present: Vec<f64> = column.iter().flatten().copied().collect();
+    let mut clamped = winsorize(&present, limits).into_iter();
+    column.iter().map(|value| value.and_then(|_| clamped.next())).collect()
+}
+
+// Correlation of one field with the target, plain and on winsorized values
+#[derive(Debug, Clone)]
+pub(crate) struct TargetCorrelation {
+    pub(crate) field: String,
+    pub(crate) observations: usize, // rows with both values
+    pub(crate) pearson: Option<f64>,
+    pub(crate) robust: Option<f64>,
+}
+
+// Plain and winsorized correlations side by side; a large difference flags a
+// relationship driven by outliers
+pub(crate) fn print_target_correlations(target: &str, correlations: &[TargetCorrelation], winsor: (f64, f64), options: &TableOptions) {
+    let percent = |p: f64| format!("{}%", p * 100.0);
+    println!(
+        "Correlation with {} (robust: winsorized at {}/{}):",
+        target,
+        percent(winsor.0),
+        percent(winsor.1)
+    );
+    let mut table = Table::new(&["Field", "n", "Pearson", "Robust", "Change"]);
+    for correlation in correlations {
+        let change = correlation.pearson.zip(correlation.robust).map(|(plain, robust)| robust - plain);
+        table = table.add_row(vec![
+            Cell::text(&correlation.field),
+            Cell::count(correlation.observations),
+            Cell::signed(correlation.pearson, 3),
+            Cell::signed(correlation.robust, 3),
+            Cell::signed(change, 3),
+        ]);
+    }
+    table.print(options);
+}
+
+// Calculate the correlation between two variables
+pub(crate) fn calculate_correlation(x: &ndarray::ArrayView1<f64>, y: &ndarray::ArrayView1<f64>) -> Option<f64> {
+    let x_mean = kahan_mean(x.iter().copied())?;
+    let y_mean = kahan_mean(y.iter().copied())?;
+    let numerator = kahan_sum(x.iter().zip(y.iter()).map(|(&xi, &yi)| (xi - x_mean) * (yi - y_mean)));
+    let denominator_x = kahan_sum(x.iter().map(|&xi| (xi - x_mean).powi(2))).sqrt();
+    let denominator_y = kahan_sum(y.iter().map(|&yi| (yi - y_mean).powi(2))).sqrt();
     if denominator_x > 0.0 && denominator_y > 0.0 {
         Some(numerator / (denominator_x * denominator_y))
     } else {
@@ -169,386 +326,791 @@ fn calculate_correlation(x: &ndarray::ArrayView1<f64>, y: &ndarray::ArrayView1<f
     }
 }
 
-pub(crate) fn create_scatter_plot(file_path: &str, output_file: &str, income_comp_column: usize, schooling_column: usize) -> Result<(), Box<dyn Error>> {
-    let mut reader = Reader::from_path(file_path)?;
+// Count points per cell of an nx-by-ny grid over the given ranges.
+// Returns ((column, row), count) for non-empty cells, sorted by cell.
+#[cfg(feature = "plots")]
+pub(crate) fn bin_points(
+    points: &[(f64, f64)],
+    (nx, ny): (usize, usize),
+    x_range: (f64, f64),
+    y_range: (f64, f64),
+) -> Vec<((usize, usize), usize)> {
+    let mut counts: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    let cell = |value: f64, (low, high): (f64, f64), n: usize| {
+        let t = if high > low { (value - low) / (high - low) } else { 0.0 };
+        ((t * n as f64) as usize).min(n - 1)
+    };
+
+    for &(x, y) in points {
+        if x < x_range.0 || x > x_range.1 || y < y_range.0 || y > y_range.1 {
+            continue;
+        }
+        *counts.entry((cell(x, x_range, nx), cell(y, y_range, ny))).or_default() += 1;
+    }
 
-    let mut income = Vec::new();
-    let mut schoolings = Vec::new();
+    counts.into_iter().collect()
+}
 
-    for result in reader.records() {
-        let record = result?;
+// Edges of `n_bins` equal-count bins over the finite values (linear interpolation
+// between order statistics). Tied values can make edges coincide; duplicates are
+// dropped, so fewer bins may come back. Empty when there are no finite values.
+pub(crate) fn quantile_bins(values: &[f64], n_bins: usize) -> Vec<f64> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|value| value.is_finite()).collect();
+    if sorted.is_empty() || n_bins == 0 {
+        return Vec::new();
+    }
+    sorted.sort_by(f64::total_cmp);
 
-        if let (Some(income_value), Some(schooling)) = (
-            record.get(income_comp_column),
-            record.get(schooling_column),
-        ) {
-            if let (Ok(income_value), Ok(schooling)) = (
-                income_value.parse::<f64>(),
-                schooling.parse::<f64>(),
-            ) {
-                income.push(income_value);
-                schoolings.push(schooling);
-            }
+    let mut edges: Vec<f64> = Vec::with_capacity(n_bins + 1);
+    for i in 0..=n_bins {
+        let edge = quantile(&sorted, i as f64 / n_bins as f64);
+        if edges.last().is_none_or(|&previous| edge > previous) {
+            edges.push(edge);
         }
     }
+    edges
+}
 
-    let root = BitMapBackend::new(output_file, (1024, 768)).into_drawing_area();
-    root.fill(&WHITE)?;
+// Bin of every value for the given edges: bin i is [edges[i], edges[i + 1]), the
+// last bin also includes its upper edge. None for NaN or out-of-range values.
+pub(crate) fn assign_bins(values: &[f64], edges: &[f64]) -> Vec<Option<usize>> {
+    values
+        .iter()
+        .map(|&value| {
+            if edges.len() < 2 || !(edges[0]..=edges[edges.len() - 1]).contains(&value) {
+                return None;
+            }
+            // Number of inner edges at or below the value
+            Some(edges[1..edges.len() - 1].partition_point(|&edge| edge <= value))
+        })
+        .collect()
+}
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Income vs. Schooling Rates", ("sans-serif", 30))
-        .margin(20)
-        .x_label_area_size(40)
-        .y_label_area_size(40)
-        .build_cartesian_2d(
-            0.0..income.iter().cloned().fold(f64::NAN, f64::max),
-            0.0..schoolings.iter().cloned().fold(f64::NAN, f64::max),
-        )?;
+// Least-squares line y = slope * x + intercept; None for fewer than two distinct x values
+pub(crate) fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    // A single point has no slope; checked first so nothing divides by zero
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let x_mean = kahan_sum(points.iter().map(|p| p.0)) / n;
+    let y_mean = kahan_sum(points.iter().map(|p| p.1)) / n;
+    let sxx = kahan_sum(points.iter().map(|p| (p.0 - x_mean).powi(2)));
+    let sxy = kahan_sum(points.iter().map(|p| (p.0 - x_mean) * (p.1 - y_mean)));
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, y_mean - slope * x_mean))
+}
 
-    chart.configure_mesh()
-        .x_desc("Income")
-        .y_desc("Schooling Rates")
-        .draw()?;
+// Share of the variance of y the least-squares line explains; None where
+// linear_fit has no line or y does not vary
+#[cfg(feature = "plots")]
+pub(crate) fn r_squared(points: &[(f64, f64)]) -> Option<f64> {
+    let (slope, intercept) = linear_fit(points)?;
+    let y_mean = kahan_sum(points.iter().map(|p| p.1)) / points.len() as f64;
+    let total = kahan_sum(points.iter().map(|p| (p.1 - y_mean).powi(2)));
+    let residual = kahan_sum(points.iter().map(|p| (p.1 - slope * p.0 - intercept).powi(2)));
+    (total > 0.0).then(|| 1.0 - residual / total)
+}
 
-    chart.draw_series(
-        income.iter().zip(schoolings.iter()).map(|(&x, &y)| {
-            Circle::new((x, y), 3, RGBAColor(190, 86, 131, 0.5).filled())
-        }),
-    )?;
+// A labelled point and the pixel offset of its label
+#[cfg(feature = "plots")]
+pub(crate) type Annotation = ((f64, f64), String, (i32, i32));
+
+// Labels for the n points farthest (by absolute residual) from the fitted trend,
+// at most one per distinct label.
+// Each entry is (point, label, pixel offset of the label). Walking the points in
+// (x, y) order, labels alternate right/left and above/below so that neighbouring
+// labels do not sit on top of each other.
+#[cfg(feature = "plots")]
+pub(crate) fn extreme_annotations(
+    points: &[(f64, f64)],
+    labels: &[String],
+    n: usize,
+) -> Vec<Annotation> {
+    let Some((slope, intercept)) = linear_fit(points) else {
+        return Vec::new();
+    };
+
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    let residual = |i: usize| (points[i].1 - (slope * points[i].0 + intercept)).abs();
+    order.sort_by(|&a, &b| residual(b).total_cmp(&residual(a)).then(a.cmp(&b)));
+    // Countries appear once per year, so keep only each label's most extreme point
+    let mut seen = HashSet::new();
+    order.retain(|&i| seen.insert(labels[i].as_str()));
+    order.truncate(n);
+    order.sort_by(|&a, &b| points[a].0.total_cmp(&points[b].0).then(points[a].1.total_cmp(&points[b].1)));
+
+    order
+        .into_iter()
+        .enumerate()
+        .map(|(rank, i)| {
+            let label = labels[i].clone();
+            // ~7 px per character at the 13 px label font
+            let dx = if rank % 2 == 0 { 6 } else { -6 - 7 * label.chars().count() as i32 };
+            let dy = if (rank / 2) % 2 == 0 { -16 } else { 4 };
+            (points[i], label, (dx, dy))
+        })
+        .collect()
+}
 
-    println!("Scatter plot saved to {}", output_file);
-    Ok(())
+// Per-group means with how many items had no usable value
+pub(crate) struct GroupedMeans<K> {
+    pub(crate) means: HashMap<K, (f64, usize)>, // mean and count of present values
+    pub(crate) skipped: usize,
+}
+
+// Mean and count of `value_fn` per `key_fn` group. Items without a key are left
+// out; items without a parsable value are skipped and counted rather than read as zero.
+pub(crate) fn grouped_mean<T, K, I>(
+    items: I,
+    key_fn: impl Fn(&T) -> Option<K>,
+    value_fn: impl Fn(&T) -> Option<f64>,
+) -> GroupedMeans<K>
+where
+    I: IntoIterator<Item = T>,
+    K: Eq + std::hash::Hash,
+{
+    let mut totals: HashMap<K, (KahanSum, usize)> = HashMap::new();
+    let mut skipped = 0;
+    for item in items {
+        let Some(key) = key_fn(&item) else { continue };
+        match value_fn(&item) {
+            Some(value) => {
+                let entry = totals.entry(key).or_default();
+                entry.0.add(value);
+                entry.1 += 1;
+            }
+            None => skipped += 1,
+        }
+    }
+    let means = totals
+        .into_iter()
+        .map(|(key, (sum, count))| (key, (sum.value() / count as f64, count)))
+        .collect();
+    GroupedMeans { means, skipped }
+}
+
+// Means only, for callers that do not need the counts
+#[cfg(feature = "plots")]
+pub(crate) fn means_only<K: Eq + std::hash::Hash>(grouped: GroupedMeans<K>) -> HashMap<K, f64> {
+    grouped.means.into_iter().map(|(key, (mean, _))| (key, mean)).collect()
+}
+
+// One line noting cells that were left out of a computation
+pub(crate) fn report_skipped(context: &str, skipped: usize) {
+    if skipped > 0 {
+        println!("{}: skipped {} missing or malformed cells", context, skipped);
+    }
 }
 
 // Calculate average life expectancy developing vs developed countries
 pub(crate) fn calculate_average_life_expectancy(
-    file_path: &str,
+    rows: &[YearRow],
     _country_column: usize,
     status_column: usize,
     life_expectancy_column: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = Reader::from_path(file_path)?;
-
-    let mut totals: HashMap<String, (f64, usize)> = HashMap::new();
-
-    for result in reader.records() {
-        let record = result?;
-        let country_status = record.get(status_column).unwrap_or("").to_string();
-        let life_expectancy = record
-            .get(life_expectancy_column)
-            .unwrap_or("0")
-            .parse::<f64>()
-            .unwrap_or(0.0);
-
-        if !country_status.is_empty() {
-            let entry = totals.entry(country_status).or_insert((0.0, 0));
-            entry.0 += life_expectancy;
-            entry.1 += 1;
-        }
+    let grouped = grouped_mean(
+        rows.iter().map(|(_, record)| record),
+        |record| record.get(status_column).filter(|status| !status.is_empty()).map(str::to_string),
+        |record| get_f64(record, life_expectancy_column),
+    );
+    report_skipped("Average life expectancy", grouped.skipped);
+    if grouped.means.is_empty() {
+        return Err(AnalysisError::empty("Average life expectancy").into());
     }
 
-    for (status, (total_life_expectancy, count)) in totals {
-        let average = total_life_expectancy / count as f64;
+    for (status, (average, _)) in grouped.means {
         println!(
-            "Average life expectancy for {} countries: {:.2}",
-            status, average
+            "Average life expectancy for {} countries: {}",
+            status,
+            format_value(average, Unit::Years)
         );
     }
 
     Ok(())
 }
 
-pub(crate) fn create_developed_vs_developing_plot(
-    file_path: &str,
-    output_file: &str,
-    feature_column: usize,
-    year_column: usize,
-    status_column: usize,
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::Reader::from_path(file_path)?;
-
-    let mut data: HashMap<(String, String), Vec<f64>> = HashMap::new();
-
-    for record in reader.records() {
-        let record = record?;
-        let year = record.get(year_column).unwrap_or("").to_string();
-        let status = record.get(status_column).unwrap_or("").to_string();
-        let feature_value: f64 = record
-            .get(feature_column)
-            .unwrap_or("0")
-            .parse()
-            .unwrap_or(0.0);
-
-        data.entry((year, status))
-            .or_insert_with(Vec::new)
-            .push(feature_value);
-    }
-
-    let mut averages: HashMap<(String, String), f64> = HashMap::new();
-    for ((year, status), values) in data {
-        let avg = values.iter().copied().sum::<f64>() / values.len() as f64;
-        averages.insert((year.clone(), status.clone()), avg);
-    }
-
-    let mut years: Vec<String> = averages.keys().map(|(year, _)| year.clone()).collect();
-    years.sort();
-    let mut developed = Vec::new();
-    let mut developing = Vec::new();
-
-    for year in &years {
-        developed.push(averages.get(&(year.clone(), "Developed".to_string())).copied().unwrap_or(0.0));
-        developing.push(averages.get(&(year.clone(), "Developing".to_string())).copied().unwrap_or(0.0));
-    }
-
-    let root = BitMapBackend::new(output_file, (1280, 720)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Developed vs Developing Adult Mortality Averages per Year ", ("sans-serif", 40))
-        .margin(10)
-        .x_label_area_size(50)
-        .y_label_area_size(50)
-        .build_cartesian_2d(0..years.len() as u32, 0.0..250.0)?;
-
-    chart.configure_mesh()
-        .x_labels(years.len())
-        .y_desc("Adult Mortality Averages ")
-        .x_desc("Years")
-        .axis_desc_style(("sans-serif", 20))
-        .label_style(("sans-serif", 15))
-        .x_label_formatter(&|x| years.get(*x as usize).unwrap_or(&"".to_string()).clone())
-        .draw()?;
-
-    chart.draw_series(LineSeries::new(
-        (0..developed.len()).map(|x| x as u32).zip(developed.iter().copied()),
-        &RED,
-    ))?
-        .label("Developed")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], &RED));
-
-    chart.draw_series(LineSeries::new(
-        (0..developing.len()).map(|x| x as u32).zip(developing.iter().copied()),
-        &BLUE,
-    ))?
-        .label("Developing")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], &BLUE));
-
-    chart
-        .configure_series_labels()
-        .background_style(&WHITE)
-        .border_style(&BLACK)
-        .draw()?;
+/// Summary statistics of one column, with statrs' Options already resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryStats {
+    pub count: usize,
+    pub missing: usize,
+    pub mean: f64,
+    pub std_dev: Option<f64>, // undefined for a single value
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+}
 
-    Ok(())
+/// Summary of the present values; None when the column has no values at all
+///
+/// ```
+/// use project::eda_statistics::summary_statistics;
+/// use project::synthetic::generate_synthetic_dataset;
+///
+/// let mut records = generate_synthetic_dataset(5, 2000..=2003, 2);
+/// records[0].life_expectancy = None;
+/// let values: Vec<Option<f64>> = records.iter().map(|record| record.life_expectancy).collect();
+///
+/// let stats = summary_statistics(&values).unwrap();
+/// assert_eq!((stats.count, stats.missing), (19, 1));
+/// assert!(stats.min <= stats.median && stats.median <= stats.max);
+/// assert!((20.0..=100.0).contains(&stats.mean));
+///
+/// assert_eq!(summary_statistics(&[None, None]), None);
+/// let single = summary_statistics(&[Some(4.0)]).unwrap();
+/// assert_eq!((single.mean, single.std_dev), (4.0, None));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn summary_statistics(values: &[Option<f64>]) -> Option<SummaryStats> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    let count = present.len();
+    let mean = kahan_mean(present.iter().copied())?;
+    // Sample standard deviation, from compensated sums of squared deviations
+    let std_dev = (count > 1)
+        .then(|| (kahan_sum(present.iter().map(|v| (v - mean).powi(2))) / (count - 1) as f64).sqrt())
+        .filter(|s| s.is_finite());
+    let data = Data::new(present);
+
+    Some(SummaryStats {
+        count,
+        missing: values.len() - count,
+        mean,
+        std_dev,
+        min: data.min(),
+        median: data.median(),
+        max: data.max(),
+    })
 }
-// same code as the one above, differences in the chart size, Y-axis view
-pub(crate) fn create_developed_vs_developing_plot_infant(
-    file_path: &str,
-    output_file: &str,
-    feature_column: usize,
-    year_column: usize,
-    status_column: usize,
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::Reader::from_path(file_path)?;
-
-    let mut data: HashMap<(String, String), Vec<f64>> = HashMap::new();
-
-    for record in reader.records() {
-        let record = record?;
-        let year = record.get(year_column).unwrap_or("").to_string();
-        let status = record.get(status_column).unwrap_or("").to_string();
-        let feature_value: f64 = record
-            .get(feature_column)
-            .unwrap_or("0")
-            .parse()
-            .unwrap_or(0.0);
-
-        data.entry((year, status))
-            .or_insert_with(Vec::new)
-            .push(feature_value);
-    }
-
-    let mut averages: HashMap<(String, String), f64> = HashMap::new();
-    for ((year, status), values) in data {
-        let avg = values.iter().copied().sum::<f64>() / values.len() as f64;
-        averages.insert((year.clone(), status.clone()), avg);
-    }
-
-    let mut years: Vec<String> = averages.keys().map(|(year, _)| year.clone()).collect();
-    years.sort();
-    let mut developed = Vec::new();
-    let mut developing = Vec::new();
-
-    for year in &years {
-        developed.push(averages.get(&(year.clone(), "Developed".to_string())).copied().unwrap_or(0.0));
-        developing.push(averages.get(&(year.clone(), "Developing".to_string())).copied().unwrap_or(0.0));
-    }
-
-    let root = BitMapBackend::new(output_file, (1280, 720)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Developed vs Developing Infant Mortality Averages per Year ", ("sans-serif", 40))
-        .margin(10)
-        .x_label_area_size(50)
-        .y_label_area_size(50)
-        .build_cartesian_2d(0..years.len() as u32, 0.0..50.0)?;
-
-    chart.configure_mesh()
-        .x_labels(years.len())
-        .y_desc("Infant Mortality Averages ")
-        .x_desc("Years")
-        .axis_desc_style(("sans-serif", 20))
-        .label_style(("sans-serif", 15))
-        .x_label_formatter(&|x| years.get(*x as usize).unwrap_or(&"".to_string()).clone())
-        .draw()?;
-
-    chart.draw_series(LineSeries::new(
-        (0..developed.len()).map(|x| x as u32).zip(developed.iter().copied()),
-        &RED,
-    ))?
-        .label("Developed")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], &RED));
-
-    chart.draw_series(LineSeries::new(
-        (0..developing.len()).map(|x| x as u32).zip(developing.iter().copied()),
-        &BLUE,
-    ))?
-        .label("Developing")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], &BLUE));
-
-    chart
-        .configure_series_labels()
-        .background_style(&WHITE)
-        .border_style(&BLACK)
-        .draw()?;
 
-    Ok(())
+// Summary statistics for every registry field and derived column with at least
+// one value, derived columns last
+pub(crate) fn describe(records: &[LifeExpectancyRecord]) -> Vec<(String, SummaryStats)> {
+    let derived = fields::derived_fields();
+    let columns = FIELDS
+        .iter()
+        .map(|field| (field.name.to_string(), records.iter().map(field.getter).collect::<Vec<_>>()))
+        .chain(derived.iter().map(|field| (field.name.clone(), records.iter().map(|r| (field.compute)(r)).collect())));
+    columns.filter_map(|(name, values)| Some((name, summary_statistics(&values)?))).collect()
 }
 
-pub(crate) fn create_features_comparison_bar_plot(
-    file_path: &str,
-    output_file: &str,
-    feature_columns: &[usize],
-    _year_column: usize,
-    status_column: usize,
-    feature_names: &[&str],
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::Reader::from_path(file_path)?;
+// describe() computed separately for each development status, sorted by status
+pub(crate) fn describe_by_status(records: &[LifeExpectancyRecord]) -> Vec<(String, Vec<(String, SummaryStats)>)> {
+    let mut by_status: BTreeMap<&str, Vec<LifeExpectancyRecord>> = BTreeMap::new();
+    for record in records {
+        by_status.entry(record.status.as_str()).or_default().push(record.clone());
+    }
 
-    let mut data: HashMap<(String, String), Vec<f64>> = HashMap::new();
+    by_status
+        .into_iter()
+        .map(|(status, group)| (status.to_string(), describe(&group)))
+        .collect()
+}
 
-    for record in reader.records() {
-        let record = record?;
-        let status = record.get(status_column).unwrap_or("").to_string();
+// Fewest values each status needs for an effect size of a field
+pub(crate) const EFFECT_SIZE_MIN_OBSERVATIONS: usize = 10;
+
+// Cohen's d (Developed minus Developing, over the pooled sample standard
+// deviation) and the point-biserial correlation with Developed = 1 for every
+// registry field, largest |d| first. Fields with fewer than
+// EFFECT_SIZE_MIN_OBSERVATIONS values in either group, or no spread, are left out.
+pub(crate) fn effect_sizes_by_status(records: &[LifeExpectancyRecord]) -> Vec<(String, f64, f64)> {
+    let indicator = |record: &LifeExpectancyRecord| match record.status.as_str() {
+        "Developed" => Some(1.0),
+        "Developing" => Some(0.0),
+        _ => None,
+    };
+    let mut effects: Vec<(String, f64, f64)> = FIELDS
+        .iter()
+        .filter_map(|field| {
+            let group = |status: &str| -> Vec<Option<f64>> {
+                records.iter().filter(|record| record.status == status).map(field.getter).collect()
+            };
+            let (developed, developing) = (summary_statistics(&group("Developed"))?, summary_statistics(&group("Developing"))?);
+            let (n1, n2) = (developed.count, developing.count);
+            if n1 < EFFECT_SIZE_MIN_OBSERVATIONS || n2 < EFFECT_SIZE_MIN_OBSERVATIONS {
+                return None;
+            }
+            let (s1, s2) = (developed.std_dev?, developing.std_dev?);
+            let pooled = (((n1 - 1) as f64 * s1 * s1 + (n2 - 1) as f64 * s2 * s2) / (n1 + n2 - 2) as f64).sqrt();
+            let d = (developed.mean - developing.mean) / pooled;
 
-        for (&col, &feature_name) in feature_columns.iter().zip(feature_names.iter()) {
-            let feature_value: f64 = record
-                .get(col)
-                .unwrap_or("0")
-                .parse()
-                .unwrap_or(0.0);
+            let paired = PairedColumns::from_rows(records.iter().map(|record| (indicator(record), (field.getter)(record), "")));
+            let r = paired.correlation(EFFECT_SIZE_MIN_OBSERVATIONS)?;
+            d.is_finite().then(|| (field.name.to_string(), d, r))
+        })
+        .collect();
+    effects.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    effects
+}
 
-            data.entry((feature_name.to_string(), status.clone()))
-                .or_insert_with(Vec::new)
-                .push(feature_value);
-        }
+pub(crate) fn print_effect_sizes(effects: &[(String, f64, f64)], options: &TableOptions) {
+    println!("Developed vs Developing effect sizes (positive: higher in Developed):");
+    let mut table = Table::new(&["Field", "d", "r_pb"]);
+    for (field, d, r) in effects {
+        table = table.add_row(vec![Cell::text(field), Cell::signed(Some(*d), 3), Cell::signed(Some(*r), 3)]);
     }
+    table.print(options);
+}
 
-    let averages: HashMap<(String, String), f64> = data
-        .into_iter()
-        .map(|((feature, status), values)| {
-            let avg = values.iter().copied().sum::<f64>() / values.len() as f64;
-            ((feature, status), avg)
-        })
-        .collect();
+// One line of effect_sizes.csv
+#[derive(Serialize)]
+struct EffectSizeRow<'a> {
+    #[serde(rename = "Field")]
+    field: &'a str,
+    #[serde(rename = "Cohens d")]
+    cohens_d: f64,
+    #[serde(rename = "Point-biserial r")]
+    point_biserial: f64,
+}
 
-    let developed_averages: Vec<f64> = feature_names
-        .iter()
-        .map(|&name| *averages.get(&(name.to_string(), "Developed".to_string())).unwrap_or(&0.0))
-        .collect();
+pub(crate) fn export_effect_sizes_csv(effects: &[(String, f64, f64)], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for (field, cohens_d, point_biserial) in effects {
+        writer.serialize(EffectSizeRow { field, cohens_d: *cohens_d, point_biserial: *point_biserial })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
 
-    let developing_averages: Vec<f64> = feature_names
-        .iter()
-        .map(|&name| *averages.get(&(name.to_string(), "Developing".to_string())).unwrap_or(&0.0))
-        .collect();
+// One value of a field with every country-year holding it, sorted by country then year
+#[derive(Debug, Clone)]
+pub(crate) struct Extreme {
+    pub(crate) value: f64,
+    pub(crate) holders: Vec<(String, u16)>,
+}
 
-    let root = BitMapBackend::new(output_file, (1280, 720)).into_drawing_area();
-    root.fill(&WHITE)?;
+impl Extreme {
+    // "Country Year", with the number of other holders of a tied value
+    pub(crate) fn attribution(&self) -> String {
+        let (country, year) = &self.holders[0];
+        match self.holders.len() {
+            1 => format!("{} {}", country, year),
+            n => format!("{} {} (+{} tied)", country, year, n - 1),
+        }
+    }
+}
+
+// Smallest and largest value of a field, each with the next distinct value as a
+// check that the extreme is not a lone outlier
+#[derive(Debug, Clone)]
+pub(crate) struct FeatureExtremes {
+    pub(crate) field: &'static str,
+    pub(crate) min: Extreme,
+    pub(crate) second_min: Option<Extreme>,
+    pub(crate) max: Extreme,
+    pub(crate) second_max: Option<Extreme>,
+}
 
-    let max_avg = developed_averages
+// Extremes of every registry field over its present, finite values; fields
+// without any are left out
+pub(crate) fn extremes_table(records: &[LifeExpectancyRecord]) -> Vec<FeatureExtremes> {
+    FIELDS
         .iter()
-        .chain(developing_averages.iter())
-        .cloned()
-        .fold(0.0 / 0.0, f64::max);
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Comparison of Features Between Developed and Developing Countries", ("sans-serif", 40))
-        .margin(10)
-        .x_label_area_size(50)
-        .y_label_area_size(50)
-        .build_cartesian_2d(0..(feature_names.len() as i32 * 2), 0.0..(max_avg * 1.2))?;
-
-    chart
-        .configure_mesh()
-        .x_labels(feature_names.len() * 2)
-        .y_desc("Average")
-        .x_desc("Features")
-        .axis_desc_style(("sans-serif", 20))
-        .label_style(("sans-serif", 15))
-        .x_label_formatter(&|x| {
-            let index = (*x as usize) / 2;
-            feature_names.get(index).unwrap_or(&"").to_string()
+        .filter_map(|field| {
+            let mut values: Vec<(f64, &str, u16)> = records
+                .iter()
+                .filter_map(|record| Some(((field.getter)(record)?, record.country.as_str(), record.year)))
+                .filter(|(value, _, _)| value.is_finite())
+                .collect();
+            values.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| (a.1, a.2).cmp(&(b.1, b.2))));
+
+            // Distinct values in ascending order with their holders
+            let mut distinct: Vec<Extreme> = Vec::new();
+            for (value, country, year) in values {
+                match distinct.last_mut() {
+                    Some(last) if last.value == value => last.holders.push((country.to_string(), year)),
+                    _ => distinct.push(Extreme { value, holders: vec![(country.to_string(), year)] }),
+                }
+            }
+            let last = distinct.len().checked_sub(1)?;
+            Some(FeatureExtremes {
+                field: field.name,
+                min: distinct[0].clone(),
+                second_min: (last >= 1).then(|| distinct[1].clone()),
+                max: distinct[last].clone(),
+                second_max: (last >= 1).then(|| distinct[last - 1].clone()),
+            })
         })
-        .draw()?;
-
-    // Plot Developed
-    chart.draw_series(developed_averages.iter().enumerate().map(|(i, avg)| {
-        Rectangle::new(
-            [(i as i32 * 2, 0.0), (i as i32 * 2 + 1, *avg)],
-            ShapeStyle {
-                color: RGBAColor(190, 86, 131, 1f64), // #A5CBC3 for Developing
-                filled: true,
-                stroke_width: 0,
-            },
-        )
-    }))?
-        .label("Developed")
-        .legend(|(x, y)| Rectangle::new(
-            [(x, y - 5), (x + 10, y + 5)],
-            ShapeStyle {
-                color: RGBAColor(190, 86, 131, 1f64), // #A5CBC3 for Developing
-                filled: true,
-                stroke_width: 0,
-            },
-        ));
-
-    // Plot Developing
-    chart.draw_series(developing_averages.iter().enumerate().map(|(i, avg)| {
-        Rectangle::new(
-            [(i as i32 * 2 + 1, 0.0), (i as i32 * 2 + 2, *avg)],
-            ShapeStyle {
-                color: RGBAColor(110, 48, 75, 1f64),
-                filled: true,
-                stroke_width: 0,
-            },
-        )
-    }))?
-        .label("Developing")
-        .legend(|(x, y)| Rectangle::new(
-            [(x, y - 5), (x + 10, y + 5)],
-            ShapeStyle {
-                color: RGBAColor(110, 48, 75, 1f64),
-                filled: true,
-                stroke_width: 0,
-            },));
-
-    chart
-        .configure_series_labels()
-        .label_font(("sans-serif", 15))
-        .background_style(&WHITE.mix(0.8))
-        .border_style(&BLACK)
-        .draw()?;
+        .collect()
+}
+
+pub(crate) fn print_extremes(extremes: &[FeatureExtremes], options: &TableOptions) {
+    println!("Extreme values per field (runner-up: next distinct value):");
+    let mut table = Table::new(&["Field", "Min", "Where", "Runner-up min", "Max", "Where", "Runner-up max"]);
+    for extreme in extremes {
+        let unit = fields::unit_for(extreme.field);
+        let value = |e: &Extreme| Cell::formatted(Some(e.value), format_value(e.value, unit));
+        let runner_up = |e: &Option<Extreme>| e.as_ref().map_or(Cell::formatted(None, "-"), value);
+        table = table.add_row(vec![
+            Cell::text(extreme.field),
+            value(&extreme.min),
+            Cell::text(extreme.min.attribution()),
+            runner_up(&extreme.second_min),
+            value(&extreme.max),
+            Cell::text(extreme.max.attribution()),
+            runner_up(&extreme.second_max),
+        ]);
+    }
+    table.print(options);
+}
 
+// One line of extremes.csv: one per (field, extreme, holder)
+#[derive(Serialize)]
+struct ExtremeRow<'a> {
+    #[serde(rename = "Field")]
+    field: &'a str,
+    #[serde(rename = "Extreme")]
+    extreme: &'a str,
+    #[serde(rename = "Value")]
+    value: f64,
+    #[serde(rename = "Country")]
+    country: &'a str,
+    #[serde(rename = "Year")]
+    year: u16,
+}
+
+pub(crate) fn export_extremes_csv(extremes: &[FeatureExtremes], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for extreme in extremes {
+        let kinds = [
+            ("min", Some(&extreme.min)),
+            ("runner-up min", extreme.second_min.as_ref()),
+            ("max", Some(&extreme.max)),
+            ("runner-up max", extreme.second_max.as_ref()),
+        ];
+        for (kind, value) in kinds.into_iter().filter_map(|(kind, value)| Some((kind, value?))) {
+            for (country, year) in &value.holders {
+                writer.serialize(ExtremeRow { field: extreme.field, extreme: kind, value: value.value, country, year: *year })?;
+            }
+        }
+    }
+    writer.into_inner()?.finish()?;
     Ok(())
 }
+
+pub(crate) fn print_describe(summaries: &[(String, SummaryStats)], options: &TableOptions) {
+    let mut table = Table::new(&["Field", "Count", "Missing", "Mean", "Std Dev", "Min", "Median", "Max"]);
+    for (field, stats) in summaries {
+        let unit = fields::unit_for(field);
+        let value = |v: f64| Cell::formatted(Some(v), format_value(v, unit));
+        table = table.add_row(vec![
+            Cell::text(field),
+            Cell::count(stats.count),
+            Cell::count(stats.missing),
+            value(stats.mean),
+            Cell::formatted(stats.std_dev, format_option(stats.std_dev, unit)),
+            value(stats.min),
+            value(stats.median),
+            value(stats.max),
+        ]);
+    }
+    table.print(options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn bin_points_counts_points_per_cell() {
+        let points = [(0.0, 0.0), (0.4, 0.1), (0.6, 0.9), (1.0, 1.0), (1.0, 0.0), (1.5, 0.5), (0.5, -0.1)];
+        let cells = bin_points(&points, (2, 2), (0.0, 1.0), (0.0, 1.0));
+        // The upper edges belong to the last cell; points outside the ranges are left out
+        assert_eq!(cells, vec![((0, 0), 2), ((1, 0), 1), ((1, 1), 2)]);
+        assert_eq!(bin_points(&points, (3, 1), (0.0, 0.0), (0.0, 1.0)), vec![((0, 0), 1)]);
+        assert!(bin_points(&[], (4, 4), (0.0, 1.0), (0.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn kahan_sum_keeps_small_addends_of_a_large_total() {
+        let n = 1_000_000;
+        let values = || std::iter::once(1e8).chain(std::iter::repeat_n(0.1, n));
+        let exact = 1e8 + 0.1 * n as f64;
+        let naive: f64 = values().sum();
+        let compensated: KahanSum = values().sum();
+        assert!((naive - exact).abs() > 1e-3);
+        assert!((compensated.value() - exact).abs() < 1e-6);
+    }
+
+    #[test]
+    fn kahan_sum_does_not_depend_on_the_order() {
+        let values = [1e16, 1.0, -1e16, 1.0];
+        let forward: KahanSum = values.iter().copied().sum();
+        let backward: KahanSum = values.iter().rev().copied().sum();
+        assert_eq!(forward.value(), 2.0);
+        assert_eq!(backward.value(), 2.0);
+    }
+
+    #[test]
+    fn grouped_mean_averages_only_the_present_values() {
+        // Half of the cells are blank; reading them as zero would halve the mean
+        let cells = [("Developed", "80"), ("Developed", ""), ("Developed", "76"), ("Developed", "n/a"), ("", "50")];
+        let grouped = grouped_mean(cells, |(status, _)| Some(*status).filter(|s| !s.is_empty()), |(_, value)| value.parse::<f64>().ok());
+        assert_eq!(grouped.means.len(), 1);
+        assert_eq!(grouped.means["Developed"], (78.0, 2));
+        assert_eq!(grouped.skipped, 2);
+    }
+
+    fn year_rows(cells: &[(u16, &str, &str)]) -> Vec<YearRow> {
+        cells.iter().map(|&(year, country, value)| (year, csv::StringRecord::from(vec![country, value]))).collect()
+    }
+
+    #[test]
+    fn blank_cells_are_not_ranked_as_zero() {
+        let rows = year_rows(&[(2014, "A", "70"), (2014, "B", ""), (2014, "C", "60.5"), (2014, "D", "abc"), (2015, "A", "")]);
+        let lowest = find_top_countries(&rows, 0, 1, 1, RankOrder::Lowest).unwrap();
+        // 2015 has no value at all, so it has no list rather than a zero for A
+        assert_eq!(lowest, TopCountries::from([(2014, vec![("C".to_string(), 60.5)])]));
+        assert!(matches!(find_top_countries(&year_rows(&[(2014, "B", "")]), 0, 1, 1, RankOrder::Highest), Err(AnalysisError::EmptyInput { .. })));
+    }
+
+    // Rows of an in-memory CSV of country, year and life expectancy
+    fn csv_year_rows(text: &str) -> Vec<YearRow> {
+        csv::Reader::from_reader(text.as_bytes())
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (record[1].parse().unwrap(), record)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ties_blanks_and_short_years_from_a_csv() {
+        let rows = csv_year_rows(
+            "Country,Year,Life expectancy\n\
+             Chad,2015,52\nBelgium,2015,81\nAustria,2015,81\nDenmark,2015,\nEgypt,2015,70.5\n\
+             Chad,2014,51\nDenmark,2014,80\n",
+        );
+        let top = find_top_countries(&rows, 0, 2, 3, RankOrder::Highest).unwrap();
+        // Years come back in order whatever order the rows are in; Austria and
+        // Belgium tie and are listed by name; Denmark's blank 2015 is skipped
+        // rather than ranked; 2014 has fewer countries than asked for
+        assert_eq!(top.keys().copied().collect::<Vec<_>>(), vec![2014, 2015]);
+        assert_eq!(
+            top[&2015],
+            vec![("Austria".to_string(), 81.0), ("Belgium".to_string(), 81.0), ("Egypt".to_string(), 70.5)]
+        );
+        assert_eq!(top[&2014], vec![("Denmark".to_string(), 80.0), ("Chad".to_string(), 51.0)]);
+        let all = find_top_countries(&rows, 0, 2, 10, RankOrder::Highest).unwrap();
+        assert_eq!(all[&2015].len(), 4);
+    }
+
+    #[test]
+    fn bottom_three_of_a_fixture_file() {
+        let dir = crate::test_support::scratch_dir("bottom-three");
+        let input = dir.join("input.csv");
+        std::fs::write(
+            &input,
+            "Country,Year,Status,Life expectancy\n\
+             Angola,2015,Developing,52.4\nBhutan,2015,Developing,69.8\nChad,2015,Developing,53.1\n\
+             Denmark,2015,Developed,80.6\nEritrea,2015,Developing,\nFiji,2015,Developing,69.9\n\
+             Guinea,2015,Developing,59.0\nHaiti,2015,Developing,63.5\n",
+        )
+        .unwrap();
+        let input = input.to_str().unwrap();
+        let columns = crate::columns::resolve_columns(input, &["Country", "Year", "Life expectancy"]).unwrap();
+        let rows = crate::load_clean::read_year_rows(input, columns[1], &crate::load_clean::RowErrorPolicy::Fail).unwrap();
+
+        // Eritrea has no value, so it is left out instead of ranking lowest as 0
+        let bottom = find_top_countries(&rows, columns[0], columns[2], 3, RankOrder::Lowest).unwrap();
+        let expected = vec![("Angola".to_string(), 52.4), ("Chad".to_string(), 53.1), ("Guinea".to_string(), 59.0)];
+        assert_eq!(bottom, TopCountries::from([(2015, expected)]));
+        assert_eq!(RankOrder::Lowest.heading(3), "Bottom 3 countries by life expectancy (lowest first)");
+        assert_eq!(RankOrder::Highest.heading(3), "Top 3 countries by life expectancy (highest first)");
+    }
+
+    #[test]
+    fn effect_sizes_match_a_hand_computed_example() {
+        // Schooling: Developed 11/13 and Developing 9/11, five of each, so both groups have
+        // variance 10/9, d = 2 / sqrt(10/9) and r_pb = (2 / sqrt(2)) * sqrt(0.25).
+        // Life expectancy is half as far apart; GDP has only 9 Developed values.
+        let mut records = Vec::new();
+        for i in 0..10 {
+            let step = (i % 2) as f64 * 2.0;
+            let gdp = (i < 9).then_some(1000.0 + step);
+            records.push(LifeExpectancyRecord { status: "Developed".to_string(), schooling: Some(11.0 + step), life_expectancy: Some(70.0 + step), gdp, ..Default::default() });
+            records.push(LifeExpectancyRecord { status: "Developing".to_string(), schooling: Some(9.0 + step), life_expectancy: Some(69.0 + step), gdp: Some(500.0 + step), ..Default::default() });
+        }
+        records.push(LifeExpectancyRecord { schooling: Some(100.0), ..Default::default() });
+
+        let effects = effect_sizes_by_status(&records);
+        let names: Vec<&str> = effects.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, ["Schooling", "Life expectancy"]);
+        assert!((effects[0].1 - 2.0 / (10.0f64 / 9.0).sqrt()).abs() < 1e-12);
+        assert!((effects[0].2 - 0.5f64.sqrt()).abs() < 1e-12);
+        assert!((effects[1].1 - 1.0 / (10.0f64 / 9.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn extremes_name_every_holder_of_a_tied_value() {
+        let record = |country: &str, year: u16, schooling: Option<f64>| LifeExpectancyRecord { country: country.to_string(), year, schooling, ..Default::default() };
+        let records = [
+            record("Niger", 2001, Some(1.0)),
+            record("Chad", 2003, Some(1.0)),
+            record("Mali", 2000, Some(2.5)),
+            record("Chad", 2001, Some(1.0)),
+            record("Japan", 2014, Some(15.3)),
+            record("Norway", 2014, Some(17.9)),
+            record("Peru", 2010, None),
+            record("Atlantis", 2010, Some(f64::NAN)),
+        ];
+        let extremes = extremes_table(&records);
+        assert_eq!(extremes.len(), 1);
+        let schooling = &extremes[0];
+        assert_eq!(schooling.field, "Schooling");
+
+        // Ties are listed by country, then year
+        assert_eq!(schooling.min.value, 1.0);
+        assert_eq!(schooling.min.holders, vec![("Chad".to_string(), 2001), ("Chad".to_string(), 2003), ("Niger".to_string(), 2001)]);
+        assert_eq!(schooling.min.attribution(), "Chad 2001 (+2 tied)");
+        assert_eq!(schooling.second_min.as_ref().unwrap().attribution(), "Mali 2000");
+        assert_eq!(schooling.max.attribution(), "Norway 2014");
+        assert_eq!(schooling.second_max.as_ref().unwrap().value, 15.3);
+
+        // A single value is both extremes, with no runner-up
+        let single = extremes_table(&records[4..5]);
+        assert_eq!((single[0].min.value, single[0].max.value), (15.3, 15.3));
+        assert!(single[0].second_min.is_none() && single[0].second_max.is_none());
+    }
+
+    #[test]
+    fn quantile_bins_pin_edges_and_assignments() {
+        let values: Vec<f64> = (1..=10).map(f64::from).chain([f64::NAN]).collect();
+        let edges = quantile_bins(&values, 4);
+        assert_eq!(edges, vec![1.0, 3.25, 5.5, 7.75, 10.0]);
+        let bins = assign_bins(&[1.0, 3.0, 3.25, 5.5, 10.0, 11.0, f64::NAN], &edges);
+        assert_eq!(bins, vec![Some(0), Some(0), Some(1), Some(2), Some(3), None, None]);
+    }
+
+    #[test]
+    fn tied_values_merge_quantile_edges() {
+        assert_eq!(quantile_bins(&[2.0, 2.0, 2.0, 2.0, 8.0], 4), vec![2.0, 8.0]);
+        assert!(quantile_bins(&[f64::NAN], 3).is_empty());
+        assert_eq!(assign_bins(&[2.0], &[2.0]), vec![None]);
+    }
+
+    #[test]
+    fn paired_columns_drop_rows_missing_either_value() {
+        let rows = [
+            (Some(1.0), Some(2.0), "A"),
+            (None, Some(3.0), "B"),
+            (Some(4.0), None, "C"),
+            (None, None, "D"),
+            (Some(f64::NAN), Some(5.0), "E"),
+            (Some(6.0), Some(f64::INFINITY), "F"),
+            (Some(7.0), Some(8.0), "G"),
+        ];
+        let paired = PairedColumns::from_rows(rows);
+        assert_eq!((paired.x.as_slice(), paired.y.as_slice()), (&[1.0, 7.0][..], &[2.0, 8.0][..]));
+        assert_eq!(paired.countries, vec!["A", "G"]);
+        assert_eq!((paired.len(), paired.dropped), (2, 5));
+        assert_eq!(paired.correlation(3), None);
+        assert!((paired.correlation(2).unwrap() - 1.0).abs() < 1e-12);
+        // A constant side has no defined correlation and reads as 0
+        let constant = PairedColumns::from_rows([(Some(1.0), Some(5.0), "A"), (Some(2.0), Some(5.0), "B")]);
+        assert_eq!(constant.correlation(2), Some(0.0));
+    }
+
+    #[test]
+    fn one_value_has_no_slope_or_spread() {
+        assert_eq!(linear_fit(&[(2013.0, 70.0)]), None);
+        assert_eq!(linear_fit(&[(2013.0, 70.0), (2013.0, 72.0)]), None);
+        assert_eq!(linear_fit(&[(0.0, 1.0), (2.0, 5.0)]), Some((2.0, 1.0)));
+
+        let stats = summary_statistics(&[None, Some(70.0)]).unwrap();
+        assert_eq!((stats.count, stats.missing, stats.std_dev), (1, 1, None));
+        assert_eq!((stats.mean, stats.min, stats.median, stats.max), (70.0, 70.0, 70.0, 70.0));
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn annotations_label_each_country_once_and_alternate_sides() {
+        // A line y = x with two far points for "Far" and one for "Low"
+        let mut points: Vec<(f64, f64)> = (0..10).map(|x| (x as f64, x as f64)).collect();
+        let mut labels: Vec<String> = (0..10).map(|x| format!("L{}", x)).collect();
+        points.extend([(4.5, 20.0), (5.0, 15.0), (3.0, -10.0)]);
+        labels.extend(["Far", "Far", "Low"].map(String::from));
+
+        let annotations = extreme_annotations(&points, &labels, 3);
+        // The second "Far" point gives way to the next largest residual, L9
+        let expected = vec![
+            ((3.0, -10.0), "Low".to_string(), (6, -16)),
+            ((4.5, 20.0), "Far".to_string(), (-6 - 7 * 3, -16)),
+            ((9.0, 9.0), "L9".to_string(), (6, 4)),
+        ];
+        assert_eq!(annotations, expected);
+        assert_eq!(extreme_annotations(&points, &labels, 1)[0].1, "Far");
+        assert!(extreme_annotations(&points[..1], &labels[..1], 3).is_empty());
+    }
+
+    // Rows of (country, life expectancy) for `records`, every value rounded to
+    // whole years so that ties are common
+    fn rounded_year_rows(records: &[LifeExpectancyRecord]) -> Vec<YearRow> {
+        records
+            .iter()
+            .map(|record| {
+                let value = record.life_expectancy.map_or(String::new(), |value| value.round().to_string());
+                (record.year, csv::StringRecord::from(vec![record.country.clone(), value]))
+            })
+            .collect()
+    }
+
+    // What find_top_countries lists, computed by sorting every year's rows in full
+    fn sorted_top_countries(rows: &[YearRow], n: usize, order: RankOrder) -> TopCountries {
+        let mut years: BTreeMap<u16, Vec<(NotNan<f64>, &str)>> = BTreeMap::new();
+        for (year, record) in rows {
+            if let Some(value) = get_f64(record, 1).and_then(|value| NotNan::new(value).ok()) {
+                years.entry(*year).or_default().push((order.key(value), record.get(0).unwrap()));
+            }
+        }
+        years
+            .into_iter()
+            .map(|(year, mut ranked)| {
+                ranked.sort();
+                (year, ranked.into_iter().take(n).map(|(key, country)| (country.to_string(), order.key(key).into_inner())).collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bounded_heaps_list_what_a_full_sort_does() {
+        let mut records = crate::synthetic::generate_synthetic_dataset(60, 2000..=2008, 11);
+        crate::synthetic::blank_values(&mut records, 0.1, 2);
+        let rows = rounded_year_rows(&records);
+        for order in [RankOrder::Highest, RankOrder::Lowest] {
+            for n in [1, 5, 59, 100] {
+                let top = find_top_countries(&rows, 0, 1, n, order).unwrap();
+                assert_eq!(top, sorted_top_countries(&rows, n, order), "{:?} {}", order, n);
+            }
+        }
+    }
+
+    // cargo test --release top_countries_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn top_countries_benchmark() {
+        let records = crate::synthetic::generate_synthetic_dataset(62_500, 2000..=2015, 5);
+        let rows = rounded_year_rows(&records);
+        let start = std::time::Instant::now();
+        let sorted = sorted_top_countries(&rows, 5, RankOrder::Highest);
+        let sort_time = start.elapsed();
+        let start = std::time::Instant::now();
+        let top = find_top_countries(&rows, 0, 1, 5, RankOrder::Highest).unwrap();
+        let heap_time = start.elapsed();
+        assert_eq!(top, sorted);
+        println!(
+            "{} rows: full sort {:?}, bounded heaps {:?} ({:.1}x)",
+            rows.len(),
+            sort_time,
+            heap_time,
+            sort_time.as_secs_f64() / heap_time.as_secs_f64()
+        );
+    }
+}
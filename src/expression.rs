@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use crate::fields::{self, FieldMeta, LifeExpectancyRecord};
+
+// Arithmetic over record fields, as written in the [derived] section of the
+// config: numbers, column names, + - * / and parentheses, e.g.
+// "Adult Mortality / (infant deaths + 1)". Names with characters other than
+// letters, digits, spaces and underscores go in backticks: `HIV/AIDS` * 10.
+#[derive(Clone)]
+pub(crate) enum Expr {
+    Number(f64),
+    Field(&'static FieldMeta),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Expr {
+    // Value for one record: None when a field it uses is None, on division by
+    // zero, and for results that are not finite
+    pub(crate) fn eval(&self, record: &LifeExpectancyRecord) -> Option<f64> {
+        let value = match self {
+            Expr::Number(value) => *value,
+            Expr::Field(meta) => (meta.getter)(record)?,
+            Expr::Negate(inner) => -inner.eval(record)?,
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (left.eval(record)?, right.eval(record)?);
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide if right == 0.0 => return None,
+                    Operator::Divide => left / right,
+                }
+            }
+        };
+        Some(value).filter(|value| value.is_finite())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Operator(char),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("invalid number \"{}\"", number))?));
+        } else if c.is_alphabetic() || c == '_' {
+            // Names run on over spaces, so "infant deaths" is one name
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_' || **c == ' ') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name.trim_end().to_string()));
+        } else if c == '`' {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('`') => break,
+                    Some(c) => name.push(c),
+                    None => return Err(format!("unclosed backtick before \"{}\"", name)),
+                }
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            chars.next();
+            tokens.push(match c {
+                '+' | '-' | '*' | '/' => Token::Operator(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                other => return Err(format!("unexpected character '{}'", other)),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent over the tokens: sums of products of factors
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // Terms joined by + and -, left to right
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(&Token::Operator(c @ ('+' | '-'))) = self.peek() {
+            self.next();
+            let operator = if c == '+' { Operator::Add } else { Operator::Subtract };
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    // Factors joined by * and /, left to right
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(&Token::Operator(c @ ('*' | '/'))) = self.peek() {
+            self.next();
+            let operator = if c == '*' { Operator::Multiply } else { Operator::Divide };
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Name(name)) => fields::field(&name).map(Expr::Field).ok_or_else(|| {
+                format!("unknown column \"{}\" (put names with - or / in backticks, e.g. `HIV/AIDS`)", name)
+            }),
+            Some(Token::Operator('-')) => Ok(Expr::Negate(Box::new(self.factor()?))),
+            Some(Token::Open) => {
+                let expr = self.sum()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing closing parenthesis".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {}", describe_token(&token))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Number(value) => format!("number {}", value),
+        Token::Name(name) => format!("column \"{}\"", name),
+        Token::Operator(c) => format!("'{}'", c),
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+    }
+}
+
+pub(crate) fn parse_expression(text: &str) -> Result<Expr, Box<dyn Error>> {
+    let invalid = |message: String| -> Box<dyn Error> { format!("Invalid expression \"{}\": {}", text, message).into() };
+    let mut parser = Parser { tokens: tokenize(text).map_err(invalid)?, position: 0 };
+    let expr = parser.sum().map_err(invalid)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(invalid(format!("unexpected {}", describe_token(token)))),
+    }
+}
+
+// Parse every expression of the config's [derived] section and register it as a
+// derived column, in name order
+pub(crate) fn register_derived_columns(derived: &BTreeMap<String, String>) -> Result<(), Box<dyn Error>> {
+    for (name, source) in derived {
+        let expr = parse_expression(source).map_err(|e| format!("Derived column \"{}\": {}", name, e))?;
+        fields::register_derived(name, source, Arc::new(move |record| expr.eval(record)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> LifeExpectancyRecord {
+        LifeExpectancyRecord {
+            adult_mortality: Some(263.0),
+            infant_deaths: Some(62.0),
+            hiv_aids: Some(0.1),
+            gdp: Some(584.25),
+            population: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    fn value(text: &str) -> Option<f64> {
+        parse_expression(text).unwrap().eval(&record())
+    }
+
+    #[test]
+    fn operators_follow_precedence_and_associativity() {
+        assert_eq!(value("1 + 2 * 3"), Some(7.0));
+        assert_eq!(value("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(value("10 - 4 - 3"), Some(3.0));
+        assert_eq!(value("24 / 4 / 2"), Some(3.0));
+        assert_eq!(value("-2 * -(3 - 1)"), Some(4.0));
+        assert_eq!(value("2 - -1"), Some(3.0));
+        assert_eq!(value(".5 + 1.25"), Some(1.75));
+    }
+
+    #[test]
+    fn columns_are_read_from_the_record() {
+        assert_eq!(value("Adult Mortality / (infant deaths + 1)"), Some(263.0 / 63.0));
+        assert_eq!(value("adult  MORTALITY - infant deaths"), Some(201.0));
+        assert_eq!(value("`HIV/AIDS` * 10"), Some(1.0));
+        assert_eq!(value("GDP*2"), Some(1168.5));
+    }
+
+    #[test]
+    fn missing_values_and_bad_results_give_none() {
+        // Schooling is None, Population is 0
+        assert_eq!(value("Schooling + 1"), None);
+        assert_eq!(value("1 + 2 * (GDP - Schooling)"), None);
+        assert_eq!(value("-Schooling"), None);
+        assert_eq!(value("GDP / Population"), None);
+        assert_eq!(value("GDP / (infant deaths - 62)"), None);
+        assert_eq!(value("Population / GDP"), Some(0.0));
+        // Past f64::MAX
+        assert_eq!(value(&vec!["1000000000000"; 26].join(" * ")), None);
+    }
+
+    #[test]
+    fn errors_quote_the_expression_and_the_problem() {
+        let error = |text: &str| parse_expression(text).err().unwrap().to_string();
+        assert_eq!(error("GDP + Happiness"), "Invalid expression \"GDP + Happiness\": unknown column \"Happiness\" (put names with - or / in backticks, e.g. `HIV/AIDS`)");
+        assert!(error("HIV/AIDS").contains("unknown column \"HIV\""));
+        assert!(error("(GDP + 1").ends_with("missing closing parenthesis"));
+        assert!(error("GDP + 1)").ends_with("unexpected ')'"));
+        assert!(error("(GDP) 2").ends_with("unexpected number 2"));
+        // Names run on over spaces and digits
+        assert!(error("GDP 2").contains("unknown column \"GDP 2\""));
+        assert!(error("GDP * ").ends_with("unexpected end of expression"));
+        assert!(error("").ends_with("unexpected end of expression"));
+        assert!(error("GDP % 2").ends_with("unexpected character '%'"));
+        assert!(error("`HIV/AIDS * 2").contains("unclosed backtick"));
+        assert!(error("1.2.3").ends_with("invalid number \"1.2.3\""));
+        assert!(error("* 2").ends_with("unexpected '*'"));
+    }
+}
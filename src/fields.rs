@@ -0,0 +1,161 @@
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use crate::columns::canonical_header;
+use crate::format::Unit;
+
+// Metadata for one numeric field of LifeExpectancyRecord
+pub(crate) struct FieldMeta {
+    pub(crate) name: &'static str, // canonical header (see columns::canonical_header)
+    pub(crate) unit: Unit,
+    pub(crate) valid_range: (f64, f64), // inclusive plausible range
+    pub(crate) getter: fn(&LifeExpectancyRecord) -> Option<f64>,
+    pub(crate) setter: fn(&mut LifeExpectancyRecord, Option<f64>),
+}
+
+// Declares LifeExpectancyRecord and the FIELDS registry from one list, so adding
+// a numeric column here updates the struct, serde renames and every registry user.
+// Renames are canonical headers, so readers must canonicalize the header row first.
+macro_rules! life_expectancy_fields {
+    ($($field:ident: $name:literal, $unit:expr, $range:expr;)*) => {
+        /// One row of the WHO life expectancy dataset
+        #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+        pub struct LifeExpectancyRecord {
+            #[serde(rename = "Country")]
+            pub country: String,
+            #[serde(rename = "Year")]
+            pub year: u16,
+            #[serde(rename = "Status")]
+            pub status: String,
+            $(
+                #[serde(rename = $name)]
+                pub $field: Option<f64>,
+            )*
+        }
+
+        pub(crate) const FIELDS: &[FieldMeta] = &[
+            $(
+                FieldMeta {
+                    name: $name,
+                    unit: $unit,
+                    valid_range: $range,
+                    getter: |record| record.$field,
+                    setter: |record, value| record.$field = value,
+                },
+            )*
+        ];
+    };
+}
+
+life_expectancy_fields! {
+    life_expectancy: "Life expectancy", Unit::Years, (20.0, 100.0);
+    adult_mortality: "Adult Mortality", Unit::Per1000, (0.0, 1000.0);
+    infant_deaths: "infant deaths", Unit::Per1000, (0.0, 1000.0);
+    alcohol: "Alcohol", Unit::Litres, (0.0, 25.0);
+    percentage_expenditure: "percentage expenditure", Unit::Percent, (0.0, 100.0);
+    hepatitis_b: "Hepatitis B", Unit::Percent, (0.0, 100.0);
+    measles: "Measles", Unit::Per1000, (0.0, f64::INFINITY);
+    bmi: "BMI", Unit::Index, (10.0, 60.0);
+    under_five_deaths: "under-five deaths", Unit::Per1000, (0.0, 1000.0);
+    polio: "Polio", Unit::Percent, (0.0, 100.0);
+    total_expenditure: "Total expenditure", Unit::Percent, (0.0, 100.0);
+    diphtheria: "Diphtheria", Unit::Percent, (0.0, 100.0);
+    hiv_aids: "HIV/AIDS", Unit::Per1000, (0.0, 1000.0);
+    gdp: "GDP", Unit::Usd, (0.0, f64::INFINITY);
+    population: "Population", Unit::Count, (0.0, f64::INFINITY);
+    thinness_1_19_years: "thinness 1-19 years", Unit::Percent, (0.0, 100.0);
+    thinness_5_9_years: "thinness 5-9 years", Unit::Percent, (0.0, 100.0);
+    income_composition_of_resources: "Income composition of resources", Unit::Index, (0.0, 1.0);
+    schooling: "Schooling", Unit::Years, (0.0, 25.0);
+}
+
+// Registry entry for a field by header or name, ignoring case and spacing
+pub(crate) fn field(name: &str) -> Option<&'static FieldMeta> {
+    let wanted = canonical_header(name);
+    FIELDS.iter().find(|meta| meta.name.eq_ignore_ascii_case(&wanted))
+}
+
+// Unit of a column, defaulting to a plain number for non-registry columns
+pub(crate) fn unit_for(name: &str) -> Unit {
+    field(name).map_or(Unit::Index, |meta| meta.unit)
+}
+
+// Computes a derived column from one record; None where it has no value
+pub(crate) type DeriveFn = Arc<dyn Fn(&LifeExpectancyRecord) -> Option<f64> + Send + Sync>;
+
+// A column computed from the others, registered at run time (the [derived]
+// section of the config). Every dataset and feature row read afterwards gets it
+// as an extra column after those of the file.
+#[derive(Clone)]
+pub(crate) struct DerivedField {
+    pub(crate) name: String,
+    pub(crate) source: String, // how it is computed, for cache keys
+    pub(crate) compute: DeriveFn,
+}
+
+static DERIVED: RwLock<Vec<DerivedField>> = RwLock::new(Vec::new());
+
+// Add a derived column to the registry. Its name may not be that of a record
+// column or of another derived column.
+pub(crate) fn register_derived(name: &str, source: &str, compute: DeriveFn) -> Result<(), Box<dyn Error>> {
+    let name = canonical_header(name);
+    let mut derived = DERIVED.write().map_err(|_| "Derived column registry poisoned")?;
+    let taken = ["Country", "Year", "Status"].iter().any(|column| column.eq_ignore_ascii_case(&name))
+        || field(&name).is_some()
+        || derived.iter().any(|entry| entry.name.eq_ignore_ascii_case(&name));
+    if name.is_empty() || taken {
+        return Err(format!("Derived column \"{}\" needs a name no other column has", name).into());
+    }
+    derived.push(DerivedField { name, source: source.to_string(), compute });
+    Ok(())
+}
+
+// Registered derived columns, in registration order
+pub(crate) fn derived_fields() -> Vec<DerivedField> {
+    DERIVED.read().map(|derived| derived.clone()).unwrap_or_default()
+}
+
+// The registered columns and how each is computed, e.g.
+// derived=mortality_ratio:Adult Mortality / (infant deaths + 1)
+pub(crate) fn derived_key() -> String {
+    let entries: Vec<String> = derived_fields().iter().map(|field| format!("{}:{}", field.name, field.source)).collect();
+    format!("derived={}", entries.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_covers_every_numeric_field() {
+        // Every Option<f64> field serializes as null in a default record
+        let json = serde_json::to_value(LifeExpectancyRecord::default()).unwrap();
+        let mut numeric: Vec<&str> = json.as_object().unwrap().iter().filter(|(_, value)| value.is_null()).map(|(name, _)| name.as_str()).collect();
+        let mut registry: Vec<&str> = FIELDS.iter().map(|meta| meta.name).collect();
+        numeric.sort_unstable();
+        registry.sort_unstable();
+        assert_eq!(numeric, registry);
+    }
+
+    #[test]
+    fn getters_and_setters_address_their_own_field() {
+        let mut record = LifeExpectancyRecord::default();
+        for (i, meta) in FIELDS.iter().enumerate() {
+            (meta.setter)(&mut record, Some(i as f64));
+        }
+        let json = serde_json::to_value(&record).unwrap();
+        for (i, meta) in FIELDS.iter().enumerate() {
+            assert_eq!((meta.getter)(&record), Some(i as f64));
+            assert_eq!(json[meta.name], i as f64, "{}", meta.name);
+        }
+    }
+
+    #[test]
+    fn fields_are_found_by_any_spelling_of_their_header() {
+        assert_eq!(field("life EXPECTANCY").map(|meta| meta.name), Some("Life expectancy"));
+        assert_eq!(field(" thinness  1-19 years").map(|meta| meta.name), Some("thinness 1-19 years"));
+        assert!(field("Country").is_none());
+        assert_eq!(unit_for("GDP"), Unit::Usd);
+        assert_eq!(unit_for("Happiness"), Unit::Index);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use crate::correlation::{CorrelationAnalysis, CorrelationMethod};
+use crate::format::{format_value, group_thousands, Unit};
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::pipeline::AnalysisResults;
+use crate::series::{field_meta, yearly_means_by_status};
+
+// The target the correlation findings are about
+const TARGET: &str = "Life expectancy";
+
+// One drafted sentence for the write-up, with the values it quotes and the chart
+// that shows them (None when no chart does)
+#[derive(Debug, Clone)]
+pub(crate) struct Finding {
+    pub(crate) text: String,
+    pub(crate) numbers: Vec<(&'static str, f64)>,
+    pub(crate) chart: Option<&'static str>,
+}
+
+// Findings from every step that completed, in the order the steps ran; steps
+// that did not run contribute nothing
+pub(crate) fn findings(results: &AnalysisResults) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if let Some(report) = &results.cleaning {
+        findings.push(Finding {
+            text: format!(
+                "Cleaning kept {} country-years and imputed {} missing values.",
+                group_thousands(&results.records.len().to_string()),
+                group_thousands(&report.imputed.to_string())
+            ),
+            numbers: vec![("records", results.records.len() as f64), ("imputed", report.imputed as f64)],
+            chart: None,
+        });
+    }
+    if let Some(analysis) = &results.correlations {
+        findings.extend(correlation_findings(analysis));
+    }
+    findings.extend(status_gap_finding(results));
+    findings.extend(cluster_finding(results));
+    findings
+}
+
+// The strongest positive and the strongest negative correlation with TARGET
+fn correlation_findings(analysis: &CorrelationAnalysis) -> Vec<Finding> {
+    let Some(correlations) = analysis.with_target(TARGET) else {
+        return Vec::new();
+    };
+    let method = match analysis.method {
+        CorrelationMethod::Pearson => "",
+        CorrelationMethod::Winsorized(..) => " on winsorized columns",
+    };
+    let strongest = |positive: bool| {
+        correlations.iter().find_map(|&(feature, r, n)| r.filter(|r| (*r > 0.0) == positive).map(|r| (feature, r, n)))
+    };
+    [("positive", strongest(true)), ("negative", strongest(false))]
+        .into_iter()
+        .filter_map(|(sign, strongest)| {
+            let (feature, r, n) = strongest?;
+            Some(Finding {
+                text: format!(
+                    "{} shows the strongest {} correlation with {} (r = {:.2}, n = {}{}).",
+                    feature,
+                    sign,
+                    TARGET.to_lowercase(),
+                    r,
+                    group_thousands(&n.to_string()),
+                    method
+                ),
+                numbers: vec![("r", r), ("n", n as f64)],
+                chart: Some("correlation_heatmap.png"),
+            })
+        })
+        .collect()
+}
+
+// How the size of a gap moved between two years: "held" when both print the
+// same at the unit's precision, otherwise "narrowed" or "widened"
+pub(crate) fn gap_change(start: f64, end: f64, unit: Unit) -> &'static str {
+    if format_value(start.abs(), unit) == format_value(end.abs(), unit) {
+        "held"
+    } else if end.abs() < start.abs() {
+        "narrowed"
+    } else {
+        "widened"
+    }
+}
+
+// Developed minus Developing mean life expectancy in the first and last year
+// both statuses have a mean
+fn status_gap_finding(results: &AnalysisResults) -> Option<Finding> {
+    let meta = field_meta(TARGET).ok()?;
+    let means = yearly_means_by_status(&results.records, meta);
+    let (developed, developing) = (means.get("Developed")?, means.get("Developing")?);
+    let years: BTreeSet<u16> = developed.keys().filter(|year| developing.contains_key(year)).copied().collect();
+    let (&first, &last) = (years.first()?, years.last()?);
+    if first == last {
+        return None;
+    }
+    let gap = |year: u16| developed[&year] - developing[&year];
+    let (start, end) = (gap(first), gap(last));
+    let text = match gap_change(start, end, meta.unit) {
+        "held" => format!(
+            "The Developed–Developing {} gap held at {} between {} and {}.",
+            TARGET.to_lowercase(),
+            format_value(end, meta.unit),
+            first,
+            last
+        ),
+        change => format!(
+            "The Developed–Developing {} gap {} from {} to {} between {} and {}.",
+            TARGET.to_lowercase(),
+            change,
+            format_value(start, meta.unit),
+            format_value(end, meta.unit),
+            first,
+            last
+        ),
+    };
+    Some(Finding {
+        text,
+        numbers: vec![("first_year", first as f64), ("first_gap", start), ("last_year", last as f64), ("last_gap", end)],
+        chart: Some("dashboard.png"),
+    })
+}
+
+// How many clusters the graph step found and how large the biggest is
+fn cluster_finding(results: &AnalysisResults) -> Option<Finding> {
+    let k = results.clusters.values().max()? + 1;
+    let mut sizes = vec![0usize; k];
+    for &cluster in results.clusters.values() {
+        sizes[cluster] += 1;
+    }
+    let (largest, size) = sizes.iter().enumerate().max_by_key(|&(cluster, size)| (*size, std::cmp::Reverse(cluster)))?;
+    let countries = results.clusters.len();
+    Some(Finding {
+        text: format!(
+            "Average-linkage clustering splits {} countries into {} clusters; the largest, cluster {}, holds {} ({}).",
+            countries,
+            k,
+            largest,
+            size,
+            format_value(*size as f64 / countries as f64 * 100.0, Unit::Percent)
+        ),
+        numbers: vec![("countries", countries as f64), ("clusters", k as f64), ("largest", *size as f64)],
+        chart: Some("tile_map_clusters.png"),
+    })
+}
+
+pub(crate) fn print_findings(findings: &[Finding]) {
+    println!("Findings:");
+    for finding in findings {
+        println!("  {}", finding.text);
+    }
+}
+
+// The findings as a Markdown section to start the report with, under the run's
+// provenance: one bullet each naming its chart, with the unrounded numbers it
+// quotes in a comment for checking
+pub(crate) fn findings_markdown(findings: &[Finding], run: &RunMetadata) -> String {
+    let mut markdown = format!("# Findings\n\n<!-- {} -->\n\n", run.csv_comment().trim_start_matches("# ").trim_end());
+    for finding in findings {
+        let line = match finding.chart {
+            Some(chart) => format!("- {} (see `{}`).", finding.text.trim_end_matches('.'), chart),
+            None => format!("- {}", finding.text),
+        };
+        let numbers: Vec<String> = finding.numbers.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        markdown.push_str(&format!("{} <!-- {} -->\n", line, numbers.join(" ")));
+    }
+    markdown
+}
+
+pub(crate) fn export_findings_markdown(findings: &[Finding], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    output::atomic_write(output_file, findings_markdown(findings, run))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::LifeExpectancyRecord;
+
+    // Developed and Developing life expectancy in each of the given years
+    fn results(years: &[(u16, f64, f64)]) -> AnalysisResults {
+        let record = |year: u16, status: &str, life_expectancy: f64| LifeExpectancyRecord {
+            country: status.to_string(),
+            year,
+            status: status.to_string(),
+            life_expectancy: Some(life_expectancy),
+            ..Default::default()
+        };
+        let records = years
+            .iter()
+            .flat_map(|&(year, developed, developing)| [record(year, "Developed", developed), record(year, "Developing", developing)])
+            .collect();
+        AnalysisResults { records, ..Default::default() }
+    }
+
+    #[test]
+    fn the_gap_sentence_follows_the_sign_of_the_change() {
+        assert_eq!(gap_change(12.3, 9.8, Unit::Years), "narrowed");
+        assert_eq!(gap_change(9.8, 12.3, Unit::Years), "widened");
+        assert_eq!(gap_change(-12.3, 9.8, Unit::Years), "narrowed");
+        assert_eq!(gap_change(10.0, 10.04, Unit::Years), "held");
+
+        let narrowed = findings(&results(&[(2000, 80.0, 67.7), (2008, 81.0, 70.0), (2015, 82.0, 72.2)]));
+        assert_eq!(narrowed.len(), 1);
+        assert_eq!(narrowed[0].text, "The Developed–Developing life expectancy gap narrowed from 12.3 years to 9.8 years between 2000 and 2015.");
+        assert_eq!(narrowed[0].chart, Some("dashboard.png"));
+        let widened = findings(&results(&[(2000, 82.0, 72.2), (2015, 80.0, 67.7)]));
+        assert_eq!(widened[0].text, "The Developed–Developing life expectancy gap widened from 9.8 years to 12.3 years between 2000 and 2015.");
+        let held = findings(&results(&[(2000, 80.0, 70.0), (2015, 80.04, 70.0)]));
+        assert_eq!(held[0].text, "The Developed–Developing life expectancy gap held at 10.0 years between 2000 and 2015.");
+        // One shared year gives no change to report
+        assert!(findings(&results(&[(2000, 80.0, 70.0)])).is_empty());
+    }
+
+    #[test]
+    fn cluster_findings_name_the_largest_cluster() {
+        let mut results = results(&[]);
+        results.clusters = [("A", 1), ("B", 0), ("C", 1), ("D", 2)].iter().map(|&(country, cluster)| (country.to_string(), cluster)).collect();
+        let findings = findings(&results);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].text.starts_with("Average-linkage clustering splits 4 countries into 3 clusters; the largest, cluster 1, holds 2 ("), "{}", findings[0].text);
+        assert_eq!(findings[0].numbers, vec![("countries", 4.0), ("clusters", 3.0), ("largest", 2.0)]);
+    }
+}
@@ -0,0 +1,215 @@
+use serde::Deserialize;
+
+// Human-readable formatting of statistics with their units
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Unit {
+    Years,
+    Usd,
+    Per1000,
+    Percent,
+    Litres,
+    Count,
+    Index, // unitless ratio or score
+}
+
+impl Unit {
+    // Suffix appended after the number
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Unit::Years => "years",
+            Unit::Usd => "USD",
+            Unit::Per1000 => "per 1,000",
+            Unit::Percent => "%",
+            Unit::Litres => "L",
+            Unit::Count => "",
+            Unit::Index => "",
+        }
+    }
+
+    fn decimals(&self) -> usize {
+        match self {
+            Unit::Years | Unit::Per1000 | Unit::Percent => 1,
+            Unit::Usd | Unit::Litres => 2,
+            Unit::Count => 0,
+            Unit::Index => 3,
+        }
+    }
+}
+
+// Insert thousands separators into a formatted number, e.g. "-1234567.5" -> "-1,234,567.5"
+pub(crate) fn group_thousands(number: &str) -> String {
+    let (sign, rest) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number),
+    };
+    let (integer, fraction) = match rest.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (rest, None),
+    };
+
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, grouped, fraction),
+        None => format!("{}{}", sign, grouped),
+    }
+}
+
+// Value with thousands separators, unit-specific precision and the unit suffix,
+// e.g. "7,483.16 USD", "72.3 years", "85.0%"
+pub(crate) fn format_value(value: f64, unit: Unit) -> String {
+    if !value.is_finite() {
+        return "n/a".to_string();
+    }
+    let number = group_thousands(&format!("{:.*}", unit.decimals(), value));
+    match unit {
+        Unit::Percent => format!("{}%", number),
+        Unit::Count | Unit::Index => number,
+        _ => format!("{} {}", number, unit.label()),
+    }
+}
+
+// Same as format_value, with "n/a" for missing values
+pub(crate) fn format_option(value: Option<f64>, unit: Unit) -> String {
+    value.map_or_else(|| "n/a".to_string(), |v| format_value(v, unit))
+}
+
+// Axis tick label styles
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TickStyle {
+    #[default]
+    Plain, // plotters-like default, e.g. "12345678.0"
+    Si,        // "12.3M", "4.5k"
+    Thousands, // "1,234,567"
+    Fixed(usize), // fixed number of decimals
+}
+
+// Drop a trailing ".0" style fraction ("12.0" -> "12", "1.50" -> "1.5")
+fn trim_fraction(number: String) -> String {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        number
+    }
+}
+
+// Number for a CSV export: at most `precision` decimals with trailing zeros dropped,
+// or the shortest text that reads back as the same value when None
+pub(crate) fn format_float(value: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(decimals) => trim_fraction(format!("{:.*}", decimals, value)),
+        None => value.to_string(),
+    }
+}
+
+// Tick label for an axis value in the given style
+#[cfg(feature = "plots")]
+pub(crate) fn format_tick(value: f64, style: TickStyle) -> String {
+    if !value.is_finite() {
+        return String::new();
+    }
+    match style {
+        TickStyle::Plain => format!("{:.1}", value),
+        TickStyle::Fixed(decimals) => format!("{:.*}", decimals, value),
+        TickStyle::Thousands => {
+            let decimals = if value.fract() != 0.0 && value.abs() < 1000.0 { 2 } else { 0 };
+            group_thousands(&trim_fraction(format!("{:.*}", decimals, value)))
+        }
+        TickStyle::Si => {
+            // Ascending scales with their decimals; the largest one not exceeding
+            // |value| is used
+            const SCALES: [(f64, &str, usize); 5] = [(1.0, "", 2), (1e3, "k", 1), (1e6, "M", 1), (1e9, "B", 1), (1e12, "T", 1)];
+            let mut i = SCALES.iter().rposition(|&(scale, _, _)| value.abs() >= scale).unwrap_or(0);
+            // 999.999 rounds to "1000" and 999,999 to "1000k"; move up a scale so
+            // they read "1k" and "1M"
+            let rounded = |(scale, _, decimals): (f64, &str, usize)| {
+                let factor = 10f64.powi(decimals as i32);
+                (value.abs() / scale * factor).round() / factor
+            };
+            if rounded(SCALES[i]) >= 1000.0 && i + 1 < SCALES.len() {
+                i += 1;
+            }
+            let (scale, suffix, decimals) = SCALES[i];
+            format!("{}{}", trim_fraction(format!("{:.*}", decimals, value / scale)), suffix)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_carry_their_unit_and_precision() {
+        assert_eq!(format_value(72.345, Unit::Years), "72.3 years");
+        assert_eq!(format_value(7483.156, Unit::Usd), "7,483.16 USD");
+        assert_eq!(format_value(85.0, Unit::Percent), "85.0%");
+        assert_eq!(format_value(164.8, Unit::Per1000), "164.8 per 1,000");
+        assert_eq!(format_value(1_293_859_294.0, Unit::Count), "1,293,859,294");
+        assert_eq!(format_value(0.6789, Unit::Index), "0.679");
+        assert_eq!(format_value(-0.5, Unit::Litres), "-0.50 L");
+        assert_eq!(format_value(f64::NAN, Unit::Years), "n/a");
+        assert_eq!(format_option(None, Unit::Usd), "n/a");
+    }
+
+    #[test]
+    fn thousands_are_grouped_across_magnitudes() {
+        assert_eq!(group_thousands("0"), "0");
+        assert_eq!(group_thousands("999"), "999");
+        assert_eq!(group_thousands("1000"), "1,000");
+        assert_eq!(group_thousands("-1234567.5"), "-1,234,567.5");
+        assert_eq!(group_thousands("123456789012"), "123,456,789,012");
+    }
+
+    #[test]
+    fn export_floats_keep_at_most_the_precision() {
+        assert_eq!(format_float(0.1 + 0.2, None), "0.30000000000000004");
+        assert_eq!(format_float(0.1 + 0.2, Some(3)), "0.3");
+        assert_eq!(format_float(2.0, Some(2)), "2");
+        assert_eq!(format_float(1234.5678, Some(2)), "1234.57");
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn si_ticks_round_before_choosing_the_scale() {
+        let si = |value| format_tick(value, TickStyle::Si);
+        assert_eq!(si(0.5), "0.5");
+        assert_eq!(si(999.0), "999");
+        assert_eq!(si(999.994), "999.99");
+        assert_eq!(si(999.999), "1k");
+        assert_eq!(si(1000.0), "1k");
+        assert_eq!(si(1500.0), "1.5k");
+        assert_eq!(si(999_949.0), "999.9k");
+        assert_eq!(si(999_999.0), "1M");
+        assert_eq!(si(1e6), "1M");
+        assert_eq!(si(2.5e12), "2.5T");
+        assert_eq!(si(5e15), "5000T");
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn si_ticks_keep_the_sign() {
+        let si = |value| format_tick(value, TickStyle::Si);
+        assert_eq!(si(-999.0), "-999");
+        assert_eq!(si(-999.999), "-1k");
+        assert_eq!(si(-1000.0), "-1k");
+        assert_eq!(si(-1e6), "-1M");
+        assert_eq!(si(f64::NAN), "");
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn thousands_ticks_group_digits() {
+        assert_eq!(format_tick(1_234_567.0, TickStyle::Thousands), "1,234,567");
+        assert_eq!(format_tick(12.5, TickStyle::Thousands), "12.5");
+        assert_eq!(format_tick(-1_000.0, TickStyle::Thousands), "-1,000");
+    }
+}
@@ -1,34 +1,123 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::fs::File;
-use std::io;
+use std::io::Write;
+use std::path::Path;
 use petgraph::Graph;
 use petgraph::graph::NodeIndex;
-use io::Write;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+#[cfg(feature = "plots")]
+use plotters::prelude::*;
+use ndarray::Array2;
+use crate::analysis_error::AnalysisError;
+use crate::cancel::CancellationToken;
+use crate::country_index::CountryIndex;
+#[cfg(feature = "plots")]
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+use crate::columns;
+use crate::fields::{self, LifeExpectancyRecord};
+use crate::load_clean::{slug, EntityFilter, RecordLayout};
+#[cfg(feature = "plots")]
+use crate::metadata;
+use crate::metadata::RunMetadata;
+use crate::numeric::{parse_numeric, DecimalStyle};
+use crate::output::{self, ExportOptions, ExportSummary, RowSink};
 
-// graph algorithm
+// graph algorithm; rows of excluded entities get no node
 pub(crate) fn build_similarity_graph(
     file_path: &str,
     features: &[usize],
+    exclude: &EntityFilter,
     threshold: f64, // Similarity threshold
-) -> Result<Graph<String, f64>, Box<dyn std::error::Error>> {
-    let mut reader = csv::Reader::from_path(file_path)?;
-
-    let mut graph = Graph::<String, f64>::new();
-    let mut nodes = Vec::new();
-    let mut feature_data = Vec::new();
+    feature_weights: Option<&[f64]>,
+    significance: Option<&EdgeSignificance>,
+    cancel: &CancellationToken,
+) -> Result<PrunedGraph, Box<dyn std::error::Error>> {
+    let (nodes, feature_data) = read_feature_rows(file_path, features)?;
+    let (nodes, feature_data): FeatureRows =
+        nodes.into_iter().zip(feature_data).filter(|(country, _)| !exclude.excludes(country)).unzip();
+    if nodes.is_empty() {
+        return Err(AnalysisError::empty("Similarity graph").into());
+    }
+    let feature_data = match feature_weights {
+        Some(weights) => weight_features(&feature_data, weights)?,
+        None => feature_data,
+    };
+    Ok(build_similarity_graph_from_rows(&nodes, &feature_data, threshold, significance, cancel)?)
+}
 
-    for record in reader.records() {
-        let record = record?;
-        let country = record.get(0).unwrap_or("").to_string();
-        nodes.push(country);
-        let features_row: Vec<f64> = features
-            .iter()
-            .filter_map(|&idx| record.get(idx).and_then(|val| val.parse::<f64>().ok()))
-            .collect();
-        feature_data.push(features_row);
+// Standardize every feature (z-score over the complete rows) and scale it by its
+// weight, so a weight of 0 removes the feature from the similarity. Rows with a
+// missing feature become zero vectors, which are similar to nothing.
+pub(crate) fn weight_features(feature_data: &[Vec<f64>], weights: &[f64]) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+    let n_features = weights.len();
+    let longest = feature_data.iter().map(Vec::len).max().unwrap_or(n_features);
+    if longest != n_features {
+        return Err(format!("{} feature weights given for {} features", n_features, longest).into());
+    }
+    if let Some(weight) = weights.iter().find(|weight| !weight.is_finite() || **weight < 0.0) {
+        return Err(format!("Feature weights must be finite and non-negative, got {}", weight).into());
     }
 
+    let complete: Vec<&Vec<f64>> = feature_data.iter().filter(|row| row.len() == n_features).collect();
+    let count = complete.len().max(1) as f64;
+    let stats: Vec<(f64, f64)> = (0..n_features)
+        .map(|feature| {
+            let mean = complete.iter().map(|row| row[feature]).sum::<f64>() / count;
+            let variance = complete.iter().map(|row| (row[feature] - mean).powi(2)).sum::<f64>() / count;
+            (mean, variance.sqrt())
+        })
+        .collect();
+
+    Ok(feature_data
+        .iter()
+        .map(|row| {
+            if row.len() != n_features {
+                return vec![0.0; n_features];
+            }
+            row.iter()
+                .zip(&stats)
+                .zip(weights)
+                .map(|((value, (mean, std)), weight)| if *std > 0.0 { (value - mean) / std * weight } else { 0.0 })
+                .collect()
+        })
+        .collect())
+}
+
+// Permutation test for edge significance: each feature column is shuffled
+// independently `permutations` times and an edge is kept only if its similarity
+// exceeds the `percentile` of the resulting null similarities
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EdgeSignificance {
+    pub(crate) permutations: usize,
+    pub(crate) percentile: f64, // 0-100
+    pub(crate) seed: u64,
+}
+
+// Outcome of significance pruning compared to the plain threshold
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EdgePruning {
+    pub(crate) null_cutoff: f64,
+    pub(crate) threshold_edges: usize, // edges passing the threshold alone
+    pub(crate) removed: usize,         // of those, edges below the null cutoff
+}
+
+// Similarity graph with the pruning report when significance pruning was requested
+pub(crate) type PrunedGraph = (Graph<String, f64>, Option<EdgePruning>);
+
+// Same as above, over feature rows that were already loaded
+pub(crate) fn build_similarity_graph_from_rows(
+    nodes: &[String],
+    feature_data: &[Vec<f64>],
+    threshold: f64,
+    significance: Option<&EdgeSignificance>,
+    cancel: &CancellationToken,
+) -> Result<PrunedGraph, AnalysisError> {
+    let mut graph = Graph::<String, f64>::new();
+    let null_cutoff = significance.map(|options| null_similarity_cutoff(feature_data, options, cancel)).transpose()?;
+
     // Add nodes to the graph
     let node_indices: Vec<_> = nodes
         .iter()
@@ -36,20 +125,488 @@ pub(crate) fn build_similarity_graph(
         .collect();
 
     // Calculate pairwise similarity and add edges
+    let mut threshold_edges = 0;
     for i in 0..feature_data.len() {
+        cancel.check()?;
         for j in (i + 1)..feature_data.len() {
             let similarity = calculate_similarity(&feature_data[i], &feature_data[j]);
-            if similarity >= threshold {
+            if similarity < threshold {
+                continue;
+            }
+            threshold_edges += 1;
+            if null_cutoff.is_none_or(|cutoff| similarity > cutoff) {
                 graph.add_edge(node_indices[i], node_indices[j], similarity);
             }
         }
     }
 
+    let pruning = null_cutoff.map(|null_cutoff| EdgePruning {
+        null_cutoff,
+        threshold_edges,
+        removed: threshold_edges - graph.edge_count(),
+    });
+    Ok((graph, pruning))
+}
+
+/// Similarity graph over records already in memory: one node per record,
+/// labelled with its country, from the named fields. Every feature is
+/// standardized and weighted as by weight_features, equally when no weights are
+/// given, so no feature outweighs the others by its scale alone. A record missing
+/// a feature keeps its node but has similarity 0 to every other record.
+///
+/// ```
+/// use project::graph::build_similarity_graph_from_records;
+/// use project::synthetic::generate_synthetic_dataset;
+///
+/// let records = generate_synthetic_dataset(6, 2000..=2001, 3);
+/// let graph = build_similarity_graph_from_records(&records, &["GDP", "Schooling"], 0.9, Some(&[1.0, 1.0]))?;
+/// assert_eq!(graph.node_count(), 12);
+/// assert!(graph.edge_count() > 0);
+/// assert!(graph.edge_weights().all(|&similarity| similarity >= 0.9));
+///
+/// assert!(build_similarity_graph_from_records(&records, &["Happiness"], 0.9, None).is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn build_similarity_graph_from_records(
+    records: &[LifeExpectancyRecord],
+    features: &[&str],
+    threshold: f64,
+    feature_weights: Option<&[f64]>,
+) -> Result<Graph<String, f64>, Box<dyn Error>> {
+    let metas = features
+        .iter()
+        .map(|name| fields::field(name).ok_or_else(|| format!("Unknown graph feature \"{}\"", name)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let nodes: Vec<String> = records.iter().map(|record| record.country.clone()).collect();
+    // A missing value shortens the row, which weight_features turns into a zero vector
+    let rows: Vec<Vec<f64>> = records.iter().map(|record| metas.iter().filter_map(|meta| (meta.getter)(record)).collect()).collect();
+    let equal = vec![1.0; metas.len()];
+    let rows = weight_features(&rows, feature_weights.unwrap_or(&equal))?;
+    let (graph, _) = build_similarity_graph_from_rows(&nodes, &rows, threshold, None, &CancellationToken::new())?;
     Ok(graph)
 }
 
+// Pairs sampled from each permuted dataset; all pairs would be O(n^2) per permutation
+const NULL_PAIRS_PER_PERMUTATION: usize = 20_000;
+
+// Percentile of similarities between rows whose feature columns were shuffled
+// independently, which keeps each feature's distribution but breaks the link
+// between features of the same row. Only complete rows take part.
+pub(crate) fn null_similarity_cutoff(
+    feature_data: &[Vec<f64>],
+    options: &EdgeSignificance,
+    cancel: &CancellationToken,
+) -> Result<f64, AnalysisError> {
+    let n_features = feature_data.iter().map(Vec::len).max().unwrap_or(0);
+    let complete: Vec<&Vec<f64>> = feature_data.iter().filter(|row| row.len() == n_features).collect();
+    if complete.len() < 2 || n_features == 0 {
+        return Ok(f64::NEG_INFINITY);
+    }
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut columns: Vec<Vec<f64>> = (0..n_features)
+        .map(|feature| complete.iter().map(|row| row[feature]).collect())
+        .collect();
+    let mut null = Vec::with_capacity(options.permutations * NULL_PAIRS_PER_PERMUTATION);
+
+    for _ in 0..options.permutations {
+        cancel.check()?;
+        for column in columns.iter_mut() {
+            column.shuffle(&mut rng);
+        }
+        let row = |i: usize| -> Vec<f64> { columns.iter().map(|column| column[i]).collect() };
+        for _ in 0..NULL_PAIRS_PER_PERMUTATION {
+            let i = rng.gen_range(0..complete.len());
+            let j = rng.gen_range(0..complete.len());
+            if i != j {
+                null.push(calculate_similarity(&row(i), &row(j)));
+            }
+        }
+    }
+
+    if null.is_empty() {
+        return Ok(f64::NEG_INFINITY);
+    }
+    null.sort_by(|a, b| a.total_cmp(b));
+    let rank = (options.percentile / 100.0 * (null.len() - 1) as f64).round() as usize;
+    Ok(null[rank.min(null.len() - 1)])
+}
+
+// Country labels with the selected feature values of each row
+pub(crate) type FeatureRows = (Vec<String>, Vec<Vec<f64>>);
+
+// Country labels with the selected feature cells of each row; None where a cell
+// is blank, not a number or not finite
+pub(crate) type FeatureCells = (Vec<String>, Vec<Vec<Option<f64>>>);
+
+// Country label and selected feature values for every row; feature indices past
+// the file's columns are the registered derived columns
+pub(crate) fn read_feature_rows(
+    file_path: &str,
+    features: &[usize],
+) -> Result<FeatureRows, Box<dyn Error>> {
+    let (nodes, cells) = read_feature_cells(file_path, features)?;
+    Ok((nodes, cells.into_iter().map(|row| row.into_iter().flatten().collect()).collect()))
+}
+
+// Country label and selected feature cells for every row, missing cells kept in
+// place so the missing share of each feature can be counted from them
+pub(crate) fn read_feature_cells(
+    file_path: &str,
+    features: &[usize],
+) -> Result<FeatureCells, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let headers: Vec<String> = reader.headers()?.iter().map(columns::canonical_header).collect();
+    let layout = RecordLayout::new(&headers);
+    let mut nodes = Vec::new();
+    let mut cells = Vec::new();
+
+    for record in reader.records() {
+        let mut record = record?;
+        layout.extend(&mut record);
+        let country = record.get(0).unwrap_or("").to_string();
+        nodes.push(country);
+        let row: Vec<Option<f64>> = features
+            .iter()
+            .map(|&idx| {
+                let value = record.get(idx).and_then(|val| parse_numeric(val, DecimalStyle::Point)).map(|(val, _)| val);
+                value.filter(|val| val.is_finite())
+            })
+            .collect();
+        cells.push(row);
+    }
+
+    Ok((nodes, cells))
+}
+
+// Feature rows of the features the gate kept: each row holds its present values
+// of those features, so a row missing one is shorter than the others
+pub(crate) fn gated_feature_rows((nodes, cells): FeatureCells, gate: &FeatureGate) -> FeatureRows {
+    let rows = cells.into_iter().map(|row| gate.apply(&row).into_iter().flatten().collect()).collect();
+    (nodes, rows)
+}
+
+// Share of rows a graph feature may be missing before its MissingFeaturePolicy applies
+pub(crate) const MAX_MISSING_FRACTION: f64 = 0.2;
+
+// What to do with a graph feature missing in more than the allowed share of rows:
+// rows without it drop out of the country averages and become zero vectors in the
+// weighted similarity, so a sparse feature can dominate the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum MissingFeaturePolicy {
+    Error,
+    Drop, // leave the feature out of the selection, with a warning
+    #[default]
+    Keep, // use it anyway, with a warning
+}
+
+impl MissingFeaturePolicy {
+    // Policy from its command-line name ("error", "drop" or "keep")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "error" => Ok(MissingFeaturePolicy::Error),
+            "drop" => Ok(MissingFeaturePolicy::Drop),
+            "keep" => Ok(MissingFeaturePolicy::Keep),
+            other => Err(format!("Unknown missing feature policy \"{}\" (expected error, drop or keep)", other).into()),
+        }
+    }
+}
+
+// Missing share of every selected graph feature and which features were kept
+#[derive(Debug, Clone)]
+pub(crate) struct FeatureGate {
+    pub(crate) policy: MissingFeaturePolicy,
+    pub(crate) limit: f64,
+    pub(crate) fractions: Vec<(String, f64)>, // in selection order
+    pub(crate) kept: Vec<bool>,
+}
+
+impl FeatureGate {
+    // Features of the selection missing in more than `limit` of the rows
+    pub(crate) fn sparse(&self) -> impl Iterator<Item = &(String, f64)> {
+        self.fractions.iter().filter(move |(_, fraction)| *fraction > self.limit)
+    }
+
+    // The entries of a per-feature list (column indices, weights) that were kept;
+    // lists of another length are returned as they are, for the caller to reject
+    pub(crate) fn apply<T: Clone>(&self, values: &[T]) -> Vec<T> {
+        if values.len() != self.kept.len() {
+            return values.to_vec();
+        }
+        values.iter().zip(&self.kept).filter(|(_, &kept)| kept).map(|(value, _)| value.clone()).collect()
+    }
+
+    // One-line record for export comments, e.g.
+    // missing=GDP:0.152,Population:0.222;max_missing=0.2;policy=Drop;dropped=Population
+    pub(crate) fn comment(&self) -> String {
+        let fractions: Vec<String> = self.fractions.iter().map(|(name, fraction)| format!("{}:{:.3}", name, fraction)).collect();
+        let dropped: Vec<&str> =
+            self.fractions.iter().zip(&self.kept).filter(|(_, &kept)| !kept).map(|((name, _), _)| name.as_str()).collect();
+        format!("missing={};max_missing={};policy={:?};dropped={}", fractions.join(","), self.limit, self.policy, dropped.join(","))
+    }
+}
+
+// Check the missing share of each selected feature over the cells of the rows
+// that are not excluded and apply `policy` to the features above `limit`;
+// `names` are the features' headers, in the order of the cells
+pub(crate) fn gate_missing_features(
+    (nodes, cells): &FeatureCells,
+    names: &[String],
+    exclude: &EntityFilter,
+    limit: f64,
+    policy: MissingFeaturePolicy,
+) -> Result<FeatureGate, Box<dyn Error>> {
+    let (mut rows, mut missing) = (0, vec![0; names.len()]);
+    for (country, row) in nodes.iter().zip(cells) {
+        if exclude.excludes(country) {
+            continue;
+        }
+        rows += 1;
+        for (count, cell) in missing.iter_mut().zip(row) {
+            if cell.is_none() {
+                *count += 1;
+            }
+        }
+    }
+
+    let fractions: Vec<(String, f64)> = names
+        .iter()
+        .zip(&missing)
+        .map(|(name, &count)| (name.trim().to_string(), count as f64 / rows.max(1) as f64))
+        .collect();
+    let kept = fractions.iter().map(|(_, fraction)| policy != MissingFeaturePolicy::Drop || *fraction <= limit).collect();
+    let gate = FeatureGate { policy, limit, fractions, kept };
+
+    let sparse: Vec<String> = gate.sparse().map(|(name, fraction)| format!("{} ({:.1}% missing)", name, fraction * 100.0)).collect();
+    if sparse.is_empty() {
+        return Ok(gate);
+    }
+    match policy {
+        MissingFeaturePolicy::Error => {
+            return Err(format!(
+                "Graph features above {:.0}% missing: {} (use --missing-features drop or keep, or raise --max-missing)",
+                limit * 100.0,
+                sparse.join(", ")
+            )
+            .into())
+        }
+        MissingFeaturePolicy::Drop => println!("Warning: dropping sparse graph features: {}", sparse.join(", ")),
+        MissingFeaturePolicy::Keep => println!("Warning: graph features above {:.0}% missing: {}", limit * 100.0, sparse.join(", ")),
+    }
+    if !gate.kept.contains(&true) {
+        return Err("Every graph feature was dropped as too sparse".into());
+    }
+    Ok(gate)
+}
+
+// Countries with their feature rows, row i belonging to country i of the index
+pub(crate) type CountryRows = (CountryIndex, Vec<Vec<f64>>);
+
+// Average the complete feature rows of each country. The index holds the
+// countries with at least one complete row and is the one every per-country
+// result of the graph stage is keyed by. A country with a single complete row
+// is that row as it is.
+pub(crate) fn aggregate_by_country(nodes: &[String], feature_data: &[Vec<f64>], n_features: usize) -> CountryRows {
+    let mut sums: HashMap<&str, (Vec<f64>, usize)> = HashMap::new();
+    for (country, row) in nodes.iter().zip(feature_data) {
+        // Rows missing any feature would misalign the vector, so skip them
+        if row.len() != n_features {
+            continue;
+        }
+        let entry = sums.entry(country).or_insert_with(|| (vec![0.0; n_features], 0));
+        for (total, value) in entry.0.iter_mut().zip(row) {
+            *total += value;
+        }
+        entry.1 += 1;
+    }
+
+    let index = CountryIndex::new(sums.keys());
+    let rows = index
+        .names()
+        .iter()
+        .map(|country| {
+            let (totals, count) = &sums[country.as_str()];
+            totals.iter().map(|total| total / *count as f64).collect()
+        })
+        .collect();
+
+    (index, rows)
+}
+
+// Pairwise similarity matrix of the per-country rows of aggregate_by_country;
+// cell (i, j) compares countries i and j of the index
+pub(crate) fn similarity_matrix(
+    index: &CountryIndex,
+    feature_data: &[Vec<f64>],
+    feature_weights: Option<&[f64]>,
+) -> Result<Array2<f64>, Box<dyn Error>> {
+    if feature_data.len() != index.len() {
+        return Err(format!("{} feature rows for {} countries", feature_data.len(), index.len()).into());
+    }
+    let weighted;
+    let feature_data = match feature_weights {
+        Some(weights) => {
+            weighted = weight_features(feature_data, weights)?;
+            &weighted
+        }
+        None => feature_data,
+    };
+    let n = feature_data.len();
+    let mut matrix = Array2::zeros((n, n));
+    for i in 0..n {
+        matrix[(i, i)] = 1.0;
+        for j in (i + 1)..n {
+            let similarity = calculate_similarity(&feature_data[i], &feature_data[j]);
+            matrix[(i, j)] = similarity;
+            matrix[(j, i)] = similarity;
+        }
+    }
+    Ok(matrix)
+}
+
+// Common short names of countries the WHO file lists under their official names
+const COUNTRY_ALIASES: [(&str, &str); 17] = [
+    ("Bolivia", "Bolivia (Plurinational State of)"),
+    ("Ivory Coast", "Côte d'Ivoire"),
+    ("Iran", "Iran (Islamic Republic of)"),
+    ("Laos", "Lao People's Democratic Republic"),
+    ("Macedonia", "The former Yugoslav republic of Macedonia"),
+    ("Micronesia", "Micronesia (Federated States of)"),
+    ("Moldova", "Republic of Moldova"),
+    ("North Korea", "Democratic People's Republic of Korea"),
+    ("Russia", "Russian Federation"),
+    ("South Korea", "Republic of Korea"),
+    ("Syria", "Syrian Arab Republic"),
+    ("Tanzania", "United Republic of Tanzania"),
+    ("UK", "United Kingdom of Great Britain and Northern Ireland"),
+    ("United Kingdom", "United Kingdom of Great Britain and Northern Ireland"),
+    ("USA", "United States of America"),
+    ("United States", "United States of America"),
+    ("Vietnam", "Viet Nam"),
+];
+
+// Index of `query` in `countries`, ignoring case, accents and punctuation and
+// accepting the short names in COUNTRY_ALIASES. On a miss the error suggests the
+// closest names.
+pub(crate) fn find_country(countries: &[String], query: &str) -> Result<usize, Box<dyn Error>> {
+    let key = slug(query);
+    let official = COUNTRY_ALIASES.iter().find(|(alias, _)| slug(alias) == key).map(|(_, name)| slug(name));
+    if let Some(index) = countries.iter().position(|country| slug(country) == key || Some(slug(country)) == official) {
+        return Ok(index);
+    }
+
+    // Names containing the query first, then the fewest edits away
+    let mut ranked: Vec<(bool, usize, &str)> = countries
+        .iter()
+        .map(|country| {
+            let name = slug(country);
+            (!name.contains(&key), edit_distance(&name, &key), country.as_str())
+        })
+        .collect();
+    ranked.sort();
+    ranked.dedup();
+    let suggestions: Vec<&str> = ranked.iter().take(3).map(|&(_, _, country)| country).collect();
+    Err(format!("Unknown country \"{}\"; did you mean {}?", query, suggestions.join(", ")).into())
+}
+
+// Levenshtein distance between two strings, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(ca != cb)).min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// The `n` countries most similar to `country` over the per-country feature rows
+// of aggregate_by_country, most similar first and ties by name. Uses the same
+// similarity as the graph and similarity_matrix, without building either.
+pub(crate) fn most_similar(
+    index: &CountryIndex,
+    country_rows: &[Vec<f64>],
+    country: &str,
+    n: usize,
+    feature_weights: Option<&[f64]>,
+) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    let query = find_country(index.names(), country)?;
+    let weighted;
+    let rows = match feature_weights {
+        Some(weights) => {
+            weighted = weight_features(country_rows, weights)?;
+            &weighted
+        }
+        None => country_rows,
+    };
+    let mut neighbors: Vec<(String, f64)> = index
+        .names()
+        .iter()
+        .zip(rows)
+        .enumerate()
+        .filter(|&(i, _)| i != query)
+        .map(|(_, (other, row))| (other.clone(), calculate_similarity(&rows[query], row)))
+        .collect();
+    neighbors.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    neighbors.truncate(n);
+    Ok(neighbors)
+}
+
+// Map each country to its development status
+pub(crate) fn load_country_status(
+    file_path: &str,
+    country_column: usize,
+    status_column: usize,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let mut statuses = HashMap::new();
+
+    for record in reader.records() {
+        let record = record?;
+        let country = record.get(country_column).unwrap_or("").to_string();
+        let status = record.get(status_column).unwrap_or("").to_string();
+        statuses.insert(country, status);
+    }
+
+    Ok(statuses)
+}
+
+// Map each country of the records to its development status; a country whose
+// status changes between years keeps that of its last row
+pub(crate) fn record_statuses(records: &[LifeExpectancyRecord]) -> HashMap<String, String> {
+    records.iter().map(|record| (record.country.clone(), record.status.clone())).collect()
+}
+
+// Induced subgraph over the nodes whose label satisfies the predicate
+pub(crate) fn filter_graph_by(
+    graph: &Graph<String, f64>,
+    predicate: impl Fn(&str) -> bool,
+) -> Graph<String, f64> {
+    let mut filtered = Graph::<String, f64>::new();
+
+    // Old node index -> new node index for the surviving nodes
+    let mut remap: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    for node in graph.node_indices() {
+        if predicate(&graph[node]) {
+            remap.insert(node, filtered.add_node(graph[node].clone()));
+        }
+    }
+
+    for edge in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(edge).unwrap();
+        if let (Some(&new_a), Some(&new_b)) = (remap.get(&a), remap.get(&b)) {
+            filtered.add_edge(new_a, new_b, graph[edge]);
+        }
+    }
+
+    filtered
+}
+
 // Calculate similarity between two feature vectors
-fn calculate_similarity(vec1: &[f64], vec2: &[f64]) -> f64 {
+pub(crate) fn calculate_similarity(vec1: &[f64], vec2: &[f64]) -> f64 {
     let dot_product: f64 = vec1.iter().zip(vec2).map(|(x, y)| x * y).sum();
     let magnitude1: f64 = vec1.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
     let magnitude2: f64 = vec2.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
@@ -61,56 +618,308 @@ fn calculate_similarity(vec1: &[f64], vec2: &[f64]) -> f64 {
     }
 }
 
-// Perform graph clustering and identify representatives
-pub(crate) fn cluster_graph(graph: &Graph<String, f64>, _k: usize) -> HashMap<usize, String> {
+/// Identify cluster representatives: the node with the highest of `scores` (a
+/// Centrality, by node index) in each cluster or each status within it.
+/// `labels` holds the cluster of every node; nodes without one are skipped. Both
+/// `labels` and `scores` must have one entry per node of the graph.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use project::graph::{build_similarity_graph_from_records, cluster_graph};
+/// use project::synthetic::generate_synthetic_dataset;
+///
+/// let records = generate_synthetic_dataset(8, 2000..=2002, 5);
+/// let graph = build_similarity_graph_from_records(&records, &["GDP", "Schooling"], 0.8, Some(&[1.0, 1.0]))?;
+/// // One cluster per status, each represented by its best connected node
+/// let labels: Vec<Option<usize>> = records.iter().map(|record| Some((record.status == "Developed") as usize)).collect();
+/// let degrees: Vec<f64> = graph.node_indices().map(|node| graph.neighbors(node).count() as f64).collect();
+///
+/// let representatives = cluster_graph(&graph, &labels, None, &degrees)?;
+/// for (cluster, chosen) in &representatives {
+///     assert_eq!(chosen.len(), 1);
+///     let record = records.iter().find(|record| record.country == chosen[0].country).unwrap();
+///     assert_eq!((record.status == "Developed") as usize, *cluster);
+/// }
+///
+/// // Per status, every cluster gets one representative of each status it holds
+/// let statuses: HashMap<String, String> = records.iter().map(|r| (r.country.clone(), r.status.clone())).collect();
+/// let per_status = cluster_graph(&graph, &labels, Some(&statuses), &degrees)?;
+/// assert!(per_status.values().all(|chosen| chosen.iter().all(|rep| rep.status.is_some())));
+///
+/// assert!(cluster_graph(&graph, &labels[1..], None, &degrees).is_err());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn cluster_graph(
+    graph: &Graph<String, f64>,
+    labels: &[Option<usize>],
+    statuses: Option<&HashMap<String, String>>,
+    scores: &[f64],
+) -> Result<BTreeMap<usize, Vec<Representative>>, Box<dyn Error>> {
+    if labels.len() != graph.node_count() || scores.len() != graph.node_count() {
+        return Err(format!(
+            "cluster_graph needs a label and a score for each of the {} nodes, got {} labels and {} scores",
+            graph.node_count(),
+            labels.len(),
+            scores.len()
+        )
+        .into());
+    }
+
+    // Map each cluster to its nodes
+    let mut clusters: BTreeMap<usize, Vec<NodeIndex>> = BTreeMap::new();
+    for node in graph.node_indices() {
+        if let Some(cluster) = labels[node.index()] {
+            clusters.entry(cluster).or_default().push(node);
+        }
+    }
+
+    // Select a representative for each cluster, or for each status within it
+    let mut representatives = BTreeMap::new();
+    for (cluster_id, nodes) in clusters {
+        let mut groups: BTreeMap<Option<&str>, Vec<NodeIndex>> = BTreeMap::new();
+        for node in nodes {
+            let status = statuses.map(|statuses| statuses.get(&graph[node]).map_or("Unknown", String::as_str));
+            groups.entry(status).or_default().push(node);
+        }
+
+        let chosen: Vec<Representative> = groups
+            .into_iter()
+            .filter_map(|(status, nodes)| {
+                let node = select_representative(&nodes, scores)?;
+                Some(Representative {
+                    country: graph[node].clone(),
+                    status: status.map(str::to_string),
+                })
+            })
+            .collect();
+        representatives.insert(cluster_id, chosen);
+    }
+
+    Ok(representatives)
+}
+
+/// Representative country of a cluster, with its status when chosen per status
+#[derive(Debug, Clone, PartialEq)]
+pub struct Representative {
+    pub country: String,
+    pub status: Option<String>,
+}
+
+// Representatives of one cluster as printed: "Developed → Japan, Developing → Thailand"
+// when chosen per status, the bare country names otherwise
+pub(crate) fn describe_representatives(chosen: &[Representative]) -> String {
+    chosen
+        .iter()
+        .map(|rep| match &rep.status {
+            Some(status) => format!("{} → {}", status, rep.country),
+            None => rep.country.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Connected component id for every node, indexed by node index
+pub(crate) fn component_labels(graph: &Graph<String, f64>) -> Vec<usize> {
     use petgraph::unionfind::UnionFind;
 
-    // Determine connected components
     let mut uf = UnionFind::new(graph.node_count());
     for edge in graph.edge_indices() {
         let (a, b) = graph.edge_endpoints(edge).unwrap();
         uf.union(a.index(), b.index());
     }
 
-    // Map each component to its nodes
-    let mut clusters: HashMap<usize, Vec<NodeIndex>> = HashMap::new();
+    (0..graph.node_count()).map(|i| uf.find(i)).collect()
+}
+
+// Node counts per cluster and ground-truth label, e.g. cluster x Status
+pub(crate) type LabelTable = BTreeMap<usize, BTreeMap<String, usize>>;
+
+// Cross-tabulate cluster ids (indexed by node index) against a label per country;
+// countries without a label count as "Unknown"
+pub(crate) fn cluster_label_table(
+    graph: &Graph<String, f64>,
+    clusters: &[usize],
+    labels: &HashMap<String, String>,
+) -> LabelTable {
+    let mut table = LabelTable::new();
     for node in graph.node_indices() {
-        let component_id = uf.find(node.index());
-        clusters.entry(component_id).or_default().push(node);
+        let label = labels.get(&graph[node]).map_or("Unknown", String::as_str);
+        *table.entry(clusters[node.index()]).or_default().entry(label.to_string()).or_insert(0) += 1;
     }
+    table
+}
 
-    // Select a representative for each cluster
-    let mut representatives = HashMap::new();
-    for (cluster_id, nodes) in clusters {
-        if let Some(representative) = select_representative(&graph, &nodes) {
-            representatives.insert(cluster_id, graph[representative].clone());
+// Confusion-style table of the `limit` largest clusters, one column per label
+pub(crate) fn print_label_table(table: &LabelTable, limit: usize) {
+    let columns: BTreeSet<&str> = table.values().flat_map(|counts| counts.keys().map(String::as_str)).collect();
+    let mut rows: Vec<(usize, usize, &BTreeMap<String, usize>)> =
+        table.iter().map(|(&cluster, counts)| (cluster, counts.values().sum(), counts)).collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    print!("{:>8} {:>6}", "Cluster", "Size");
+    for column in &columns {
+        print!(" {:>12}", column);
+    }
+    println!();
+    for (cluster, size, counts) in rows.iter().take(limit) {
+        print!("{:>8} {:>6}", cluster, size);
+        for column in &columns {
+            print!(" {:>12}", counts.get(*column).copied().unwrap_or(0));
         }
+        println!();
+    }
+    if rows.len() > limit {
+        println!("... {} smaller clusters", rows.len() - limit);
     }
+}
 
-    representatives
+// Node of `nodes` with the highest centrality score (the last one on ties)
+fn select_representative(nodes: &[NodeIndex], scores: &[f64]) -> Option<NodeIndex> {
+    nodes.iter().copied().max_by(|a, b| scores[a.index()].total_cmp(&scores[b.index()]))
 }
 
-// Select a representative node based on centrality
-fn select_representative(
+// Node score a cluster's representative maximises
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Centrality {
+    #[default]
+    Degree,
+    Betweenness,
+    SampledBetweenness(usize), // approximate_betweenness from this many pivots
+}
+
+impl Centrality {
+    // From its command-line name: "degree", "betweenness", or "betweenness:N" for
+    // the estimate from N sampled pivots
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name.split_once(':') {
+            None if name == "degree" => Ok(Centrality::Degree),
+            None if name == "betweenness" => Ok(Centrality::Betweenness),
+            Some(("betweenness", samples)) => match samples.parse::<usize>() {
+                Ok(samples) if samples >= 1 => Ok(Centrality::SampledBetweenness(samples)),
+                _ => Err(format!("Invalid pivot count \"{}\" (expected a number from 1)", samples).into()),
+            },
+            _ => Err(format!("Unknown centrality \"{}\" (expected degree, betweenness or betweenness:N)", name).into()),
+        }
+    }
+
+    // Score of every node, indexed by node index; pivots are sampled with `seed`
+    pub(crate) fn scores(self, graph: &Graph<String, f64>, seed: u64, cancel: &CancellationToken) -> Result<Vec<f64>, AnalysisError> {
+        match self {
+            Centrality::Degree => Ok(graph.node_indices().map(|node| graph.edges(node).count() as f64).collect()),
+            Centrality::Betweenness => betweenness(graph, cancel),
+            Centrality::SampledBetweenness(samples) => approximate_betweenness(graph, samples, seed, cancel),
+        }
+    }
+}
+
+// Neighbours of every node by index, without self-loops
+fn adjacency_lists(graph: &Graph<String, f64>) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![Vec::new(); graph.node_count()];
+    for edge in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(edge).unwrap();
+        if a != b {
+            adjacency[a.index()].push(b.index());
+            adjacency[b.index()].push(a.index());
+        }
+    }
+    adjacency
+}
+
+// Add to `scores` the dependency of every node on the shortest paths (in hops)
+// from `source`: one breadth-first search and Brandes' backward accumulation
+fn accumulate_dependencies(adjacency: &[Vec<usize>], source: usize, scores: &mut [f64]) {
+    let n = adjacency.len();
+    let mut distance = vec![usize::MAX; n];
+    let mut paths = vec![0.0f64; n]; // shortest paths from the source; f64 as counts overflow
+    let mut order = Vec::with_capacity(n);
+    let mut queue = VecDeque::from([source]);
+    (distance[source], paths[source]) = (0, 1.0);
+    while let Some(v) = queue.pop_front() {
+        order.push(v);
+        for &w in &adjacency[v] {
+            if distance[w] == usize::MAX {
+                distance[w] = distance[v] + 1;
+                queue.push_back(w);
+            }
+            if distance[w] == distance[v] + 1 {
+                paths[w] += paths[v];
+            }
+        }
+    }
+    let mut dependency = vec![0.0; n];
+    for &w in order.iter().rev() {
+        for &v in &adjacency[w] {
+            if distance[v] != usize::MAX && distance[v] + 1 == distance[w] {
+                dependency[v] += paths[v] / paths[w] * (1.0 + dependency[w]);
+            }
+        }
+        if w != source {
+            scores[w] += dependency[w];
+        }
+    }
+}
+
+// Exact betweenness of every node over unweighted shortest paths (Brandes,
+// O(VE)), counting each unordered pair of endpoints once
+pub(crate) fn betweenness(graph: &Graph<String, f64>, cancel: &CancellationToken) -> Result<Vec<f64>, AnalysisError> {
+    let adjacency = adjacency_lists(graph);
+    let mut scores = vec![0.0; adjacency.len()];
+    for source in 0..adjacency.len() {
+        cancel.check()?;
+        accumulate_dependencies(&adjacency, source, &mut scores);
+    }
+    Ok(scores.into_iter().map(|score| score / 2.0).collect())
+}
+
+// Betweenness estimated from `samples` pivots drawn without replacement with
+// `seed`: the dependencies on paths from the pivots, scaled by n / samples, so
+// O(samples * E). The estimate is unbiased, and as each pivot's dependency on a
+// node lies in [0, n - 2], Hoeffding's inequality bounds its error per node:
+// P(|estimate - exact| >= eps * n * (n - 2) / 2) <= 2 exp(-2 * samples * eps^2).
+// With samples >= n it is the exact betweenness.
+pub(crate) fn approximate_betweenness(
     graph: &Graph<String, f64>,
-    nodes: &[NodeIndex],
-) -> Option<NodeIndex> {
-    nodes
-        .iter()
-        .max_by_key(|&&node| graph.edges(node).count())
-        .cloned()
+    samples: usize,
+    seed: u64,
+    cancel: &CancellationToken,
+) -> Result<Vec<f64>, AnalysisError> {
+    let n = graph.node_count();
+    if samples >= n {
+        return betweenness(graph, cancel);
+    }
+    let adjacency = adjacency_lists(graph);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut scores = vec![0.0; n];
+    for source in rand::seq::index::sample(&mut rng, n, samples) {
+        cancel.check()?;
+        accumulate_dependencies(&adjacency, source, &mut scores);
+    }
+    let scale = n as f64 / samples as f64 / 2.0;
+    Ok(scores.into_iter().map(|score| score * scale).collect())
 }
 
 // Visualize Graph Algorithm
 pub(crate) fn export_graph_to_csv(
     graph: &Graph<String, f64>,
     output_file: &str,
-) -> Result<(), Box<dyn Error>> {
-    // Open the output file for writing
-    let mut file = File::create(output_file)?;
+    feature_weights: Option<&[f64]>,
+    gate: &FeatureGate,
+    options: &ExportOptions,
+    run: &RunMetadata,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut comments: Vec<String> = feature_weights
+        .map(|weights| {
+            let weights: Vec<String> = weights.iter().map(f64::to_string).collect();
+            format!("feature_weights={}", weights.join(","))
+        })
+        .into_iter()
+        .collect();
+    comments.push(gate.comment());
 
-    // Write the CSV header
-    writeln!(file, "Source,Target,Weight")?;
+    // Open the output file, writing the CSV header unless appending to an existing one
+    let header = options.header(&["Source", "Target", "Weight"]);
+    let file = output::export_writer(output_file, &header, &comments, options.mode, Some(run))?;
+    let mut writer = options.rows(file);
 
     // Iterate over the edges in the graph
     for edge in graph.edge_indices() {
@@ -118,14 +927,979 @@ pub(crate) fn export_graph_to_csv(
         let weight = graph.edge_weight(edge).unwrap();
 
         // Write each edge as a row in the CSV file
-        writeln!(
-            file,
-            "{}, {}, {:.6}",
-            graph[source],
-            graph[target],
-            weight
-        )?;
+        let mut row = vec![graph[source].clone(), graph[target].clone(), options.format_float(*weight)];
+        row.extend(options.extra_values().map(str::to_string));
+        writer.write(&row)?;
+    }
+
+    writer.finish()
+}
+
+
+// Per-node metrics assembled from whichever analyses ran, indexed by node index.
+// Columns appear in insertion order; metrics never inserted are not exported.
+pub(crate) struct NodeMetrics {
+    columns: Vec<&'static str>,
+    values: HashMap<&'static str, Vec<Option<f64>>>,
+}
+
+impl NodeMetrics {
+    pub(crate) fn new() -> Self {
+        NodeMetrics { columns: Vec::new(), values: HashMap::new() }
+    }
+
+    pub(crate) fn insert(&mut self, metric: &'static str, values: Vec<Option<f64>>) {
+        if self.values.insert(metric, values).is_none() {
+            self.columns.push(metric);
+        }
+    }
+
+    // Metric computed per country of the index (e.g. on country averages), copied
+    // to each of its nodes; `node_countries` is CountryIndex::node_countries
+    pub(crate) fn insert_by_country(&mut self, node_countries: &[Option<usize>], metric: &'static str, by_country: &[f64]) {
+        let values = node_countries.iter().map(|country| country.map(|i| by_country[i])).collect();
+        self.insert(metric, values);
+    }
+}
+
+// Degree, strength, local clustering coefficient and component id of every node
+pub(crate) fn basic_node_metrics(graph: &Graph<String, f64>) -> NodeMetrics {
+    let n = graph.node_count();
+    let words = n.div_ceil(64);
+    // Undirected adjacency as bitsets so triangle counts are word-wise intersections
+    let mut adjacency = vec![vec![0u64; words]; n];
+    let mut strength = vec![0.0; n];
+    for edge in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(edge).unwrap();
+        let (a, b) = (a.index(), b.index());
+        if a == b {
+            continue;
+        }
+        adjacency[a][b / 64] |= 1 << (b % 64);
+        adjacency[b][a / 64] |= 1 << (a % 64);
+        strength[a] += graph[edge];
+        strength[b] += graph[edge];
+    }
+
+    let degree: Vec<usize> = adjacency.iter().map(|bits| bits.iter().map(|w| w.count_ones() as usize).sum()).collect();
+    let clustering = (0..n)
+        .map(|v| {
+            if degree[v] < 2 {
+                return Some(0.0);
+            }
+            // Each triangle through v is seen from both of its other corners
+            let mut links = 0usize;
+            for (w, &word) in adjacency[v].iter().enumerate() {
+                let mut bits = word;
+                while bits != 0 {
+                    let u = w * 64 + bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    links += adjacency[v].iter().zip(&adjacency[u]).map(|(x, y)| (x & y).count_ones() as usize).sum::<usize>();
+                }
+            }
+            let pairs = degree[v] * (degree[v] - 1);
+            Some(links as f64 / pairs as f64)
+        })
+        .collect();
+
+    let mut metrics = NodeMetrics::new();
+    metrics.insert("Degree", degree.iter().map(|&d| Some(d as f64)).collect());
+    metrics.insert("Strength", strength.into_iter().map(Some).collect());
+    metrics.insert("Clustering", clustering);
+    metrics.insert("Component", component_labels(graph).into_iter().map(|c| Some(c as f64)).collect());
+    metrics
+}
+
+// One row per node (country-year row of the input) with its country's position in
+// the index (empty outside it) and every available metric
+pub(crate) fn export_node_metrics_csv(
+    graph: &Graph<String, f64>,
+    index: &CountryIndex,
+    metrics: &NodeMetrics,
+    output_file: &str,
+    options: &ExportOptions,
+    run: &RunMetadata,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut columns = vec!["Country", "CountryId"];
+    columns.extend(&metrics.columns);
+    let header = options.header(&columns);
+    let file = output::export_writer(output_file, &header, &[], options.mode, Some(run))?;
+    let mut writer = options.rows(file);
+
+    for (node, country) in graph.node_indices().zip(index.node_countries(graph)) {
+        let mut row = vec![graph[node].clone(), country.map(|i| i.to_string()).unwrap_or_default()];
+        for metric in &metrics.columns {
+            let value = metrics.values[metric].get(node.index()).copied().flatten();
+            row.push(value.map(|v| options.format_float(v)).unwrap_or_default());
+        }
+        row.extend(options.extra_values().map(str::to_string));
+        writer.write(&row)?;
+    }
+
+    writer.finish()
+}
+
+// Gephi's spreadsheet import of the graph: edges.csv (Source, Target, Weight,
+// Type) and nodes.csv (Id, Label, ClusterId, Status) in `out_dir`. Nodes are
+// keyed by their index since a country has one node per year; `clusters` holds
+// the cluster of every country of the index. Gephi reads the first line as the
+// header, so these files carry no provenance comment, and edges.csv cut short by
+// the row limit no truncation comment either: the returned summary of its edges
+// is the only record of it. Of `export`, only the precision and row limit apply.
+pub(crate) fn export_gephi_csv(
+    graph: &Graph<String, f64>,
+    index: &CountryIndex,
+    clusters: &[usize],
+    statuses: &HashMap<String, String>,
+    out_dir: &str,
+    export: &ExportOptions,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let dir = Path::new(out_dir);
+    let mut file = output::atomic_writer(dir.join("edges.csv"))?;
+    file.write_all(b"Source,Target,Weight,Type\n")?;
+    let mut edges = RowSink::new(file, export.limit, false);
+    for edge in graph.edge_indices() {
+        let (source, target) = graph.edge_endpoints(edge).unwrap();
+        let row = [source.index().to_string(), target.index().to_string(), export.format_float(graph[edge]), "Undirected".to_string()];
+        edges.write(&row)?;
     }
+    let summary = edges.finish()?;
+
+    let mut nodes = csv::Writer::from_writer(output::atomic_writer(dir.join("nodes.csv"))?);
+    nodes.write_record(["Id", "Label", "ClusterId", "Status"])?;
+    for (node, id) in graph.node_indices().zip(index.node_countries(graph)) {
+        let country = &graph[node];
+        let cluster = id.map(|i| clusters[i].to_string()).unwrap_or_default();
+        let status = statuses.get(country).cloned().unwrap_or_default();
+        nodes.write_record([node.index().to_string(), country.clone(), cluster, status])?;
+    }
+    nodes.into_inner()?.finish()?;
+    Ok(summary)
+}
+
+// Null model: degree-preserving rewiring via double-edge swaps
+pub(crate) fn random_rewired_graph(graph: &Graph<String, f64>, seed: u64) -> Graph<String, f64> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Edges as (a, b, weight) with a < b so lookups are order independent
+    let mut edges: Vec<(usize, usize, f64)> = graph
+        .edge_indices()
+        .map(|edge| {
+            let (a, b) = graph.edge_endpoints(edge).unwrap();
+            let (a, b) = (a.index().min(b.index()), a.index().max(b.index()));
+            (a, b, graph[edge])
+        })
+        .collect();
+    let mut existing: HashSet<(usize, usize)> = edges.iter().map(|&(a, b, _)| (a, b)).collect();
+
+    // Attempt roughly one swap per edge
+    if edges.len() >= 2 {
+        for _ in 0..edges.len() {
+            let i = rng.gen_range(0..edges.len());
+            let j = rng.gen_range(0..edges.len());
+            if i == j {
+                continue;
+            }
+
+            let (a, b, _) = edges[i];
+            let (c, d, _) = edges[j];
+            // (a, b), (c, d) -> (a, d), (c, b) or (a, c), (b, d)
+            let ((p, q), (u, v)) = if rng.gen_bool(0.5) {
+                ((a, d), (c, b))
+            } else {
+                ((a, c), (b, d))
+            };
+            let first = (p.min(q), p.max(q));
+            let second = (u.min(v), u.max(v));
+
+            // Reject swaps creating self-loops or multi-edges
+            if p == q || u == v || first == second {
+                continue;
+            }
+            if existing.contains(&first) || existing.contains(&second) {
+                continue;
+            }
+
+            existing.remove(&(a, b));
+            existing.remove(&(c, d));
+            existing.insert(first);
+            existing.insert(second);
+            edges[i] = (first.0, first.1, edges[i].2);
+            edges[j] = (second.0, second.1, edges[j].2);
+        }
+    }
+
+    let mut rewired = Graph::<String, f64>::new();
+    let node_indices: Vec<_> = graph
+        .node_indices()
+        .map(|node| rewired.add_node(graph[node].clone()))
+        .collect();
+    for (a, b, weight) in edges {
+        rewired.add_edge(node_indices[a], node_indices[b], weight);
+    }
+
+    rewired
+}
+
+// Modularity of a partition (labels indexed by node index), ignoring edge weights
+pub(crate) fn modularity(graph: &Graph<String, f64>, labels: &[usize]) -> f64 {
+    let m = graph.edge_count() as f64;
+    if m == 0.0 {
+        return 0.0;
+    }
+
+    // Per community: internal edge count and total degree, summed in label order
+    // so the score is the same on every run
+    let mut internal: BTreeMap<usize, f64> = BTreeMap::new();
+    let mut degree: BTreeMap<usize, f64> = BTreeMap::new();
+    for edge in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(edge).unwrap();
+        let (ca, cb) = (labels[a.index()], labels[b.index()]);
+        if ca == cb {
+            *internal.entry(ca).or_default() += 1.0;
+        }
+        *degree.entry(ca).or_default() += 1.0;
+        *degree.entry(cb).or_default() += 1.0;
+    }
+
+    degree
+        .iter()
+        .map(|(community, &d)| {
+            let l = internal.get(community).copied().unwrap_or(0.0);
+            l / m - (d / (2.0 * m)).powi(2)
+        })
+        .sum()
+}
+
+pub(crate) struct NullModelComparison {
+    pub(crate) modularity: f64,
+    pub(crate) null_mean: f64,
+    pub(crate) null_std: f64,
+    pub(crate) p_value: f64,
+}
+
+// Compare the modularity of the real clustering against n rewired graphs
+pub(crate) fn compare_to_null_model(
+    graph: &Graph<String, f64>,
+    labels: &[usize],
+    n: usize,
+    seed: u64,
+    cancel: &CancellationToken,
+) -> Result<NullModelComparison, AnalysisError> {
+    let real = modularity(graph, labels);
+    let null: Vec<f64> = (0..n as u64)
+        .map(|i| {
+            cancel.check()?;
+            Ok(modularity(&random_rewired_graph(graph, seed.wrapping_add(i)), labels))
+        })
+        .collect::<Result<_, AnalysisError>>()?;
+
+    let count = null.len().max(1) as f64;
+    let null_mean = null.iter().sum::<f64>() / count;
+    let null_std = (null.iter().map(|q| (q - null_mean).powi(2)).sum::<f64>() / count).sqrt();
+    // Empirical p-value with the usual +1 correction
+    let at_least = null.iter().filter(|&&q| q >= real).count();
+    let p_value = (at_least + 1) as f64 / (null.len() + 1) as f64;
+
+    Ok(NullModelComparison {
+        modularity: real,
+        null_mean,
+        null_std,
+        p_value,
+    })
+}
+
+pub(crate) const SIMILARITY_BINS: usize = 100;
+
+// Streaming histogram of pairwise similarities over [-1, 1]
+pub(crate) struct SimilarityHistogram {
+    pub(crate) counts: [usize; SIMILARITY_BINS],
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) mean: f64,
+    pub(crate) total: usize,
+}
+
+impl SimilarityHistogram {
+    fn new() -> Self {
+        SimilarityHistogram {
+            counts: [0; SIMILARITY_BINS],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        let bin = (((value + 1.0) / 2.0) * SIMILARITY_BINS as f64) as usize;
+        self.counts[bin.min(SIMILARITY_BINS - 1)] += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.total += 1;
+        // Running mean avoids summing millions of values
+        self.mean += (value - self.mean) / self.total as f64;
+    }
+
+    // Lower edge of a bin
+    pub(crate) fn bin_start(bin: usize) -> f64 {
+        -1.0 + 2.0 * bin as f64 / SIMILARITY_BINS as f64
+    }
+
+    // Approximate percentile (0-100), interpolated within the containing bin
+    pub(crate) fn percentile(&self, p: f64) -> f64 {
+        if self.total == 0 {
+            return f64::NAN;
+        }
+        let target = p / 100.0 * self.total as f64;
+        let width = 2.0 / SIMILARITY_BINS as f64;
+        let mut cumulative = 0.0;
+        for (bin, &count) in self.counts.iter().enumerate() {
+            let next = cumulative + count as f64;
+            if count > 0 && next >= target {
+                let fraction = (target - cumulative) / count as f64;
+                let value = Self::bin_start(bin) + fraction * width;
+                return value.clamp(self.min, self.max);
+            }
+            cumulative = next;
+        }
+        self.max
+    }
+}
+
+// Distribution of all pairwise similarities, without storing the O(n^2) values
+pub(crate) fn similarity_distribution(feature_data: &[Vec<f64>], cancel: &CancellationToken) -> Result<SimilarityHistogram, AnalysisError> {
+    let mut histogram = SimilarityHistogram::new();
+
+    for i in 0..feature_data.len() {
+        cancel.check()?;
+        for j in (i + 1)..feature_data.len() {
+            histogram.add(calculate_similarity(&feature_data[i], &feature_data[j]));
+        }
+    }
+
+    Ok(histogram)
+}
+
+// Bar chart of the similarity histogram with a marker at the threshold
+#[cfg(feature = "plots")]
+pub(crate) fn plot_similarity_distribution(
+    histogram: &SimilarityHistogram,
+    threshold: f64,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let x_min = if histogram.total > 0 { histogram.min.min(threshold) } else { -1.0 };
+    let max_count = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Pairwise Similarity Distribution", ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(80)
+        .build_cartesian_2d(x_min..1.0, 0.0..(max_count as f64 * 1.1))?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Similarity")
+        .y_desc("Pairs")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    let width = 2.0 / SIMILARITY_BINS as f64;
+    chart.draw_series(histogram.counts.iter().enumerate().map(|(bin, &count)| {
+        let start = SimilarityHistogram::bin_start(bin);
+        Rectangle::new(
+            [(start, 0.0), (start + width, count as f64)],
+            RGBAColor(190, 86, 131, 1f64).filled(),
+        )
+    }))?;
+
+    chart
+        .draw_series(std::iter::once(PathElement::new(
+            [(threshold, 0.0), (threshold, max_count as f64 * 1.1)],
+            BLACK.stroke_width(2),
+        )))?
+        .label(format!("Threshold {:.2}", threshold))
+        .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BLACK));
 
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let bars = histogram.counts.iter().enumerate().map(|(bin, &count)| (SimilarityHistogram::bin_start(bin), count as f64)).collect();
+        let y_max = max_count as f64 * 1.1;
+        ChartSpecJson::new("histogram", "Pairwise Similarity Distribution", AxisJson::new("Similarity", (x_min, 1.0)), AxisJson::new("Pairs", (0.0, y_max)))
+            .series(SeriesJson::new("Pairs", Some(RGBColor(190, 86, 131)), bars))
+            .series(SeriesJson::new(format!("Threshold {:.2}", threshold), Some(BLACK), vec![(threshold, 0.0), (threshold, y_max)]))
+    })?;
+    println!("Similarity distribution saved to {}", output::display_path(output_file));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(country, status)| (country.to_string(), status.to_string())).collect()
+    }
+
+    #[test]
+    fn filter_graph_by_keeps_the_nodes_of_one_status() {
+        let statuses = statuses(&[("A", "Developed"), ("B", "Developing"), ("C", "Developed"), ("D", "Developing")]);
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<NodeIndex> = ["A", "B", "C", "A", "D"].iter().map(|country| graph.add_node(country.to_string())).collect();
+        graph.add_edge(nodes[0], nodes[2], 0.9);
+        graph.add_edge(nodes[0], nodes[1], 0.8);
+        graph.add_edge(nodes[2], nodes[3], 0.7);
+        graph.add_edge(nodes[1], nodes[4], 0.6);
+
+        let filtered = filter_graph_by(&graph, |country| statuses[country] == "Developed");
+        assert_eq!(filtered.node_count(), 3);
+        assert!(filtered.node_indices().all(|node| statuses[&filtered[node]] == "Developed"));
+        let mut weights: Vec<f64> = filtered.edge_indices().map(|edge| filtered[edge]).collect();
+        weights.sort_by(f64::total_cmp);
+        assert_eq!(weights, vec![0.7, 0.9]);
+    }
+
+    #[test]
+    fn similarity_histogram_counts_every_pair() {
+        let rows = vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0], vec![-1.0, 0.0]];
+        let histogram = similarity_distribution(&rows, &CancellationToken::new()).unwrap();
+        assert_eq!(histogram.total, 6);
+        assert_eq!((histogram.counts[0], histogram.counts[SIMILARITY_BINS / 2], histogram.counts[SIMILARITY_BINS - 1]), (2, 3, 1));
+        assert_eq!(histogram.counts.iter().sum::<usize>(), 6);
+        assert_eq!((histogram.min, histogram.max), (-1.0, 1.0));
+        assert!((histogram.mean + 1.0 / 6.0).abs() < 1e-12);
+        // The median falls a third of the way into the bin at 0
+        assert!((histogram.percentile(50.0) - 0.02 / 3.0).abs() < 1e-12);
+        assert_eq!(histogram.percentile(100.0), 1.0);
+    }
+
+    #[test]
+    fn similarity_histogram_of_one_row_is_empty() {
+        let histogram = similarity_distribution(&[vec![1.0, 2.0]], &CancellationToken::new()).unwrap();
+        assert_eq!(histogram.total, 0);
+        assert!(histogram.percentile(50.0).is_nan());
+    }
+
+    #[test]
+    fn cluster_graph_picks_the_top_score_of_every_cluster() {
+        let mut graph = Graph::<String, f64>::new();
+        for country in ["A", "B", "C", "D", "E"] {
+            graph.add_node(country.to_string());
+        }
+        let labels = [Some(1), Some(0), Some(1), None, Some(0)];
+        let scores = [0.2, 0.5, 0.9, 1.0, 0.1];
+
+        let representatives = cluster_graph(&graph, &labels, None, &scores).unwrap();
+        let chosen: Vec<(usize, &str)> = representatives.iter().map(|(&cluster, reps)| (cluster, reps[0].country.as_str())).collect();
+        assert_eq!(chosen, vec![(0, "B"), (1, "C")]);
+
+        let statuses = statuses(&[("A", "Developed"), ("B", "Developing"), ("C", "Developing"), ("E", "Developed")]);
+        let per_status = cluster_graph(&graph, &labels, Some(&statuses), &scores).unwrap();
+        let statuses_of_0: Vec<Option<&str>> = per_status[&0].iter().map(|rep| rep.status.as_deref()).collect();
+        assert_eq!(statuses_of_0, vec![Some("Developed"), Some("Developing")]);
+        assert_eq!(per_status[&1].len(), 2);
+
+        let error = cluster_graph(&graph, &labels[..4], None, &scores).unwrap_err().to_string();
+        assert_eq!(error, "cluster_graph needs a label and a score for each of the 5 nodes, got 4 labels and 5 scores");
+        assert!(cluster_graph(&graph, &labels, None, &scores[..2]).is_err());
+    }
+
+    #[test]
+    fn a_mixed_cluster_lists_one_representative_per_status() {
+        let mut graph = Graph::<String, f64>::new();
+        for country in ["Japan", "Thailand", "Germany", "Vietnam"] {
+            graph.add_node(country.to_string());
+        }
+        let statuses = statuses(&[("Japan", "Developed"), ("Thailand", "Developing"), ("Germany", "Developed"), ("Vietnam", "Developing")]);
+        let representatives = cluster_graph(&graph, &[Some(3); 4], Some(&statuses), &[0.9, 0.8, 0.1, 0.2]).unwrap();
+
+        assert_eq!(representatives.len(), 1);
+        assert_eq!(describe_representatives(&representatives[&3]), "Developed → Japan, Developing → Thailand");
+        let plain = cluster_graph(&graph, &[Some(3); 4], None, &[0.9, 0.8, 0.1, 0.2]).unwrap();
+        assert_eq!(describe_representatives(&plain[&3]), "Japan");
+    }
+
+    #[test]
+    fn node_metrics_export_only_the_computed_columns() {
+        // A triangle A-B-C with D hanging off C, and E on its own
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<NodeIndex> = ["A", "B", "C", "D", "E"].iter().map(|country| graph.add_node(country.to_string())).collect();
+        graph.add_edge(nodes[0], nodes[1], 0.5);
+        graph.add_edge(nodes[1], nodes[2], 0.5);
+        graph.add_edge(nodes[2], nodes[0], 0.5);
+        graph.add_edge(nodes[2], nodes[3], 0.25);
+        let metrics = basic_node_metrics(&graph);
+        assert_eq!(metrics.columns, vec!["Degree", "Strength", "Clustering", "Component"]);
+        assert_eq!(metrics.values["Degree"], vec![Some(2.0), Some(2.0), Some(3.0), Some(1.0), Some(0.0)]);
+        assert_eq!(metrics.values["Strength"][2], Some(1.25));
+        assert_eq!(metrics.values["Clustering"], vec![Some(1.0), Some(1.0), Some(1.0 / 3.0), Some(0.0), Some(0.0)]);
+        let components = &metrics.values["Component"];
+        assert!(components[..4].iter().all(|c| *c == components[0]) && components[4] != components[0]);
+
+        let dir = crate::test_support::scratch_dir("node-metrics");
+        let path = dir.join("node_metrics.csv");
+        let run = RunMetadata::new(&dir.join("input.csv").to_string_lossy(), "none").unwrap();
+        let index = CountryIndex::new(["A", "B", "C", "D"]);
+        let mut minimal = NodeMetrics::new();
+        minimal.insert("Degree", vec![Some(2.0), Some(2.0), None, Some(1.0), Some(0.0)]);
+        export_node_metrics_csv(&graph, &index, &minimal, path.to_str().unwrap(), &ExportOptions::default(), &run).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(lines, vec!["Country,CountryId,Degree", "A,0,2", "B,1,2", "C,2,", "D,3,1", "E,,0"]);
+    }
+
+    #[test]
+    fn a_zero_weight_removes_a_feature_from_the_similarity() {
+        let record = |country: &str, gdp: f64, schooling: f64, population: f64| LifeExpectancyRecord {
+            country: country.to_string(),
+            gdp: Some(gdp),
+            schooling: Some(schooling),
+            population: Some(population),
+            ..Default::default()
+        };
+        // A and B differ only in population
+        let records = [record("A", 1000.0, 12.0, 1e6), record("B", 1000.0, 12.0, 9e7), record("C", 200.0, 6.0, 5e6), record("D", 600.0, 9.0, 3e7)];
+        let features = ["GDP", "Schooling", "Population"];
+
+        let ignored = build_similarity_graph_from_records(&records, &features, 0.0, Some(&[1.0, 1.0, 0.0])).unwrap();
+        let between = |graph: &Graph<String, f64>| {
+            let edge = graph.find_edge(NodeIndex::new(0), NodeIndex::new(1));
+            edge.map(|edge| graph[edge])
+        };
+        assert!((between(&ignored).unwrap() - 1.0).abs() < 1e-12);
+        let counted = build_similarity_graph_from_records(&records, &features, 0.0, Some(&[1.0, 1.0, 1.0])).unwrap();
+        assert!(between(&counted).is_none_or(|similarity| similarity < 0.9));
+
+        let error = weight_features(&[vec![1.0, 2.0]], &[1.0]).unwrap_err().to_string();
+        assert_eq!(error, "1 feature weights given for 2 features");
+        assert!(weight_features(&[vec![1.0]], &[-1.0]).is_err());
+    }
+
+    #[test]
+    fn unweighted_record_graphs_are_standardized_and_skip_incomplete_records() {
+        let record = |country: &str, gdp: Option<f64>, schooling: f64| LifeExpectancyRecord {
+            country: country.to_string(),
+            gdp,
+            schooling: Some(schooling),
+            ..Default::default()
+        };
+        // On the raw scale GDP alone would make A, B and C near-identical
+        let records = [record("A", Some(1000.0), 6.0), record("B", Some(1010.0), 16.0), record("C", Some(990.0), 6.5), record("D", None, 6.0)];
+        let features = ["GDP", "Schooling"];
+        let unweighted = build_similarity_graph_from_records(&records, &features, -1.0, None).unwrap();
+        let equal = build_similarity_graph_from_records(&records, &features, -1.0, Some(&[1.0, 1.0])).unwrap();
+        let similarity = |graph: &Graph<String, f64>, a: usize, b: usize| graph.find_edge(NodeIndex::new(a), NodeIndex::new(b)).map(|edge| graph[edge]);
+
+        assert_eq!(similarity(&unweighted, 0, 1), similarity(&equal, 0, 1));
+        assert!(similarity(&unweighted, 0, 1).unwrap() < 0.0);
+        assert!(similarity(&unweighted, 0, 2).unwrap() > 0.0);
+        // D's schooling is not compared against the others' GDP: it is similar to none
+        assert_eq!(unweighted.node_count(), 4);
+        assert!((0..3).all(|other| similarity(&unweighted, other, 3) == Some(0.0)));
+    }
+
+    #[test]
+    fn yearly_exports_append_under_one_header() {
+        let dir = crate::test_support::scratch_dir("graph-append");
+        let path = dir.join("edges.csv");
+        let path = path.to_str().unwrap();
+        let run = RunMetadata::new(&dir.join("input.csv").to_string_lossy(), "none").unwrap();
+        let gate = FeatureGate { policy: MissingFeaturePolicy::Keep, limit: 0.2, fractions: Vec::new(), kept: Vec::new() };
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<NodeIndex> = ["A", "B", "C"].iter().map(|country| graph.add_node(country.to_string())).collect();
+        graph.add_edge(nodes[0], nodes[1], 0.5);
+        graph.add_edge(nodes[1], nodes[2], 0.75);
+
+        for year in ["2005", "2006"] {
+            let tags = [("Year".to_string(), year.to_string())];
+            let options = ExportOptions { mode: output::ExportMode::Append, extra_columns: &tags, ..Default::default() };
+            let summary = export_graph_to_csv(&graph, path, None, &gate, &options, &run).unwrap();
+            assert_eq!(summary.written, 2);
+        }
+        let written = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = written.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(lines, vec!["Source,Target,Weight,Year", "A,B,0.5,2005", "B,C,0.75,2005", "A,B,0.5,2006", "B,C,0.75,2006"]);
+
+        // Another set of columns would corrupt the file
+        let options = ExportOptions { mode: output::ExportMode::Append, ..Default::default() };
+        assert!(export_graph_to_csv(&graph, path, None, &gate, &options, &run).is_err());
+    }
+
+    #[test]
+    fn clusters_are_cross_tabulated_against_labels() {
+        let mut graph = Graph::<String, f64>::new();
+        for country in ["A", "B", "A", "C", "D"] {
+            graph.add_node(country.to_string());
+        }
+        let labels = statuses(&[("A", "Developed"), ("B", "Developing"), ("C", "Developing")]);
+        let table = cluster_label_table(&graph, &[0, 0, 0, 1, 1], &labels);
+        let counts = |pairs: &[(&str, usize)]| pairs.iter().map(|&(label, count)| (label.to_string(), count)).collect::<BTreeMap<_, _>>();
+        assert_eq!(table[&0], counts(&[("Developed", 2), ("Developing", 1)]));
+        assert_eq!(table[&1], counts(&[("Developing", 1), ("Unknown", 1)]));
+        assert_eq!(component_labels(&graph).len(), 5);
+    }
+
+    #[test]
+    fn gephi_edges_follow_the_export_precision() {
+        let dir = crate::test_support::scratch_dir("gephi-precision");
+        let mut graph = Graph::<String, f64>::new();
+        let (a, b) = (graph.add_node("A".to_string()), graph.add_node("B".to_string()));
+        graph.add_edge(a, b, 0.987654321);
+        let index = CountryIndex::new(["A", "B"]);
+        let export = ExportOptions { precision: Some(3), ..Default::default() };
+
+        export_gephi_csv(&graph, &index, &[0, 1], &HashMap::new(), dir.to_str().unwrap(), &export).unwrap();
+        let edges = std::fs::read_to_string(dir.join("edges.csv")).unwrap();
+        assert_eq!(edges, "Source,Target,Weight,Type\n0,1,0.988,Undirected\n");
+        let nodes = std::fs::read_to_string(dir.join("nodes.csv")).unwrap();
+        assert_eq!(nodes, "Id,Label,ClusterId,Status\n0,A,0,\n1,B,1,\n");
+    }
+
+    #[test]
+    fn most_similar_ranks_a_planted_twin_first() {
+        let mut records = crate::synthetic::generate_synthetic_dataset(30, 2000..=2004, 7);
+        // An exact copy of Country 007 and a near copy with slightly more schooling
+        let original: Vec<LifeExpectancyRecord> = records.iter().filter(|record| record.country == "Country 007").cloned().collect();
+        for (name, extra) in [("Country 007 Twin", 0.01), ("Country 007 Copy", 0.0)] {
+            records.extend(original.iter().map(|record| LifeExpectancyRecord {
+                country: name.to_string(),
+                schooling: record.schooling.map(|years| years + extra),
+                ..record.clone()
+            }));
+        }
+        let features = ["GDP", "Schooling", "Life expectancy"];
+        let (nodes, rows): (Vec<String>, Vec<Vec<f64>>) = records
+            .iter()
+            .filter_map(|record| {
+                let row = features.iter().map(|name| (fields::field(name).unwrap().getter)(record)).collect::<Option<Vec<f64>>>()?;
+                Some((record.country.clone(), row))
+            })
+            .unzip();
+        let (index, country_rows) = aggregate_by_country(&nodes, &rows, features.len());
+
+        let neighbors = most_similar(&index, &country_rows, " country 007 ", 5, None).unwrap();
+        assert_eq!(neighbors.len(), 5);
+        assert_eq!(neighbors[0], ("Country 007 Copy".to_string(), 1.0));
+        assert_eq!(neighbors[1].0, "Country 007 Twin");
+        assert!(neighbors.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(neighbors.iter().all(|(country, _)| country != "Country 007"));
+        assert_eq!(most_similar(&index, &country_rows, "Country 007", 5, None).unwrap(), neighbors);
+
+        let error = most_similar(&index, &country_rows, "Contry 007", 5, None).unwrap_err().to_string();
+        assert!(error.starts_with("Unknown country \"Contry 007\"; did you mean Country 007"), "{}", error);
+    }
+
+    #[test]
+    fn gephi_edge_endpoints_are_all_nodes() {
+        let dir = crate::test_support::scratch_dir("gephi-endpoints");
+        // One node per country-year, so a country can have several nodes
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<NodeIndex> =
+            ["Korea, Republic of", "Japan", "Korea, Republic of", "Chad"].iter().map(|country| graph.add_node(country.to_string())).collect();
+        for (a, b, weight) in [(0, 1, 0.9), (1, 2, 0.8), (2, 3, 0.5), (0, 3, 0.4)] {
+            graph.add_edge(nodes[a], nodes[b], weight);
+        }
+        let index = CountryIndex::new(["Chad", "Japan", "Korea, Republic of"]);
+        let labels = statuses(&[("Japan", "Developed"), ("Korea, Republic of", "Developing")]);
+        let summary = export_gephi_csv(&graph, &index, &[2, 0, 1], &labels, dir.to_str().unwrap(), &ExportOptions::default()).unwrap();
+        assert_eq!((summary.written, summary.omitted), (4, 0));
+
+        let mut reader = csv::Reader::from_path(dir.join("nodes.csv")).unwrap();
+        assert_eq!(reader.headers().unwrap(), vec!["Id", "Label", "ClusterId", "Status"]);
+        let node_rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        let ids: HashSet<&str> = node_rows.iter().map(|row| &row[0]).collect();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(node_rows[2].iter().collect::<Vec<_>>(), ["2", "Korea, Republic of", "1", "Developing"]);
+        assert_eq!(node_rows[3].iter().collect::<Vec<_>>(), ["3", "Chad", "2", ""]);
+
+        let mut reader = csv::Reader::from_path(dir.join("edges.csv")).unwrap();
+        assert_eq!(reader.headers().unwrap(), vec!["Source", "Target", "Weight", "Type"]);
+        let edge_rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(edge_rows.len(), 4);
+        for row in &edge_rows {
+            assert!(ids.contains(&row[0]) && ids.contains(&row[1]), "{:?}", row);
+            assert_eq!(&row[3], "Undirected");
+        }
+    }
+
+    #[test]
+    fn missing_feature_policies_handle_a_sparse_feature() {
+        // Population is missing in half of the countries, GDP in one; the World row
+        // misses both but is not a country
+        let dir = crate::test_support::scratch_dir("missing-features");
+        let path = dir.join("sparse.csv");
+        let mut text = String::from("Country,GDP,Population\nWorld,,\n");
+        for i in 0..10 {
+            let gdp = if i == 0 { String::new() } else { (i * 100).to_string() };
+            let population = if i % 2 == 0 { String::new() } else { (i * 1000).to_string() };
+            text.push_str(&format!("C{},{},{}\n", i, gdp, population));
+        }
+        std::fs::write(&path, text).unwrap();
+        let cells = read_feature_cells(path.to_str().unwrap(), &[1, 2]).unwrap();
+        let names = ["GDP".to_string(), "Population".to_string()];
+        let exclude = EntityFilter::new(&[]);
+        let gate = |policy| gate_missing_features(&cells, &names, &exclude, MAX_MISSING_FRACTION, policy);
+
+        let error = gate(MissingFeaturePolicy::Error).unwrap_err().to_string();
+        assert!(error.contains("Graph features above 20% missing: Population (50.0% missing)"), "{}", error);
+
+        let dropped = gate(MissingFeaturePolicy::Drop).unwrap();
+        assert_eq!(dropped.fractions, vec![("GDP".to_string(), 0.1), ("Population".to_string(), 0.5)]);
+        assert_eq!(dropped.kept, vec![true, false]);
+        assert_eq!(dropped.apply(&[1, 2]), vec![1]);
+        assert_eq!(dropped.comment(), "missing=GDP:0.100,Population:0.500;max_missing=0.2;policy=Drop;dropped=Population");
+        let (_, rows) = gated_feature_rows(cells.clone(), &dropped);
+        assert_eq!(rows[2], vec![100.0]);
+
+        let kept = gate(MissingFeaturePolicy::Keep).unwrap();
+        assert_eq!(kept.kept, vec![true, true]);
+        assert!(kept.comment().ends_with("policy=Keep;dropped="));
+
+        // Dropping every feature leaves nothing to build the graph from
+        let sparse_only = gate_missing_features(&cells, &names, &exclude, 0.05, MissingFeaturePolicy::Drop);
+        assert!(sparse_only.unwrap_err().to_string().contains("Every graph feature was dropped"));
+    }
+
+    // Rank of every value, ties sharing their average rank
+    fn average_ranks(values: &[f64]) -> Vec<f64> {
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+        let mut ranks = vec![0.0; values.len()];
+        let mut start = 0;
+        while start < order.len() {
+            let end = (start..order.len()).find(|&i| values[order[i]] != values[order[start]]).unwrap_or(order.len());
+            for &i in &order[start..end] {
+                ranks[i] = (start + end - 1) as f64 / 2.0;
+            }
+            start = end;
+        }
+        ranks
+    }
+
+    fn spearman(a: &[f64], b: &[f64]) -> f64 {
+        let (a, b) = (average_ranks(a), average_ranks(b));
+        let mean = (a.len() as f64 - 1.0) / 2.0;
+        let covariance: f64 = a.iter().zip(&b).map(|(x, y)| (x - mean) * (y - mean)).sum();
+        let spread = |ranks: &[f64]| ranks.iter().map(|x| (x - mean).powi(2)).sum::<f64>().sqrt();
+        covariance / (spread(&a) * spread(&b))
+    }
+
+    // Preferential attachment: every new node links to two earlier ones, picked
+    // in proportion to their degree, so a few hubs carry most shortest paths
+    fn hub_graph(nodes: usize, seed: u64) -> Graph<String, f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut graph = Graph::new();
+        let indices: Vec<NodeIndex> = (0..nodes).map(|i| graph.add_node(format!("N{} 2000", i))).collect();
+        let mut endpoints = vec![0, 1];
+        graph.add_edge(indices[0], indices[1], 1.0);
+        for node in 2..nodes {
+            let mut targets = Vec::new();
+            while targets.len() < 2.min(node) {
+                let target = endpoints[rng.gen_range(0..endpoints.len())];
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+            for target in targets {
+                graph.add_edge(indices[node], indices[target], 1.0);
+                endpoints.extend([node, target]);
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn exact_betweenness_counts_each_pair_once() {
+        // A path A - B - C - D: B lies on A-C and A-D, C on A-D and B-D
+        let mut graph = Graph::new();
+        let nodes: Vec<NodeIndex> = ["A", "B", "C", "D"].iter().map(|name| graph.add_node(name.to_string())).collect();
+        for pair in nodes.windows(2) {
+            graph.add_edge(pair[0], pair[1], 1.0);
+        }
+        graph.add_edge(nodes[3], nodes[3], 1.0);
+        let cancel = CancellationToken::new();
+        assert_eq!(betweenness(&graph, &cancel).unwrap(), vec![0.0, 2.0, 2.0, 0.0]);
+        assert_eq!(approximate_betweenness(&graph, 4, 7, &cancel).unwrap(), vec![0.0, 2.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn approximate_betweenness_ranks_like_the_exact_one() {
+        let graph = hub_graph(200, 11);
+        let cancel = CancellationToken::new();
+        let exact = betweenness(&graph, &cancel).unwrap();
+        let approximate = approximate_betweenness(&graph, 50, 3, &cancel).unwrap();
+        assert_eq!(approximate, approximate_betweenness(&graph, 50, 3, &cancel).unwrap());
+        assert_ne!(approximate, approximate_betweenness(&graph, 50, 4, &cancel).unwrap());
+
+        let correlation = spearman(&exact, &approximate);
+        assert!(correlation > 0.9, "rank correlation {}", correlation);
+        // The five most central nodes are found among the estimate's top ten
+        let top = |scores: &[f64], k: usize| {
+            let mut order: Vec<usize> = (0..scores.len()).collect();
+            order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+            order.truncate(k);
+            order
+        };
+        let estimated = top(&approximate, 10);
+        assert!(top(&exact, 5).iter().all(|node| estimated.contains(node)), "{:?} vs {:?}", top(&exact, 5), estimated);
+        // Scaled by n / samples, the estimate sums to about the exact total
+        let (total, estimate) = (exact.iter().sum::<f64>(), approximate.iter().sum::<f64>());
+        assert!((estimate / total - 1.0).abs() < 0.15, "{} vs {}", estimate, total);
+    }
+
+    #[test]
+    fn a_single_row_country_aggregates_to_its_row() {
+        let nodes: Vec<String> = ["Niue", "Chad", "Chad", "Chad"].iter().map(|name| name.to_string()).collect();
+        let rows = vec![vec![1.0, 2.0], vec![4.0, 6.0], vec![6.0, 8.0], vec![5.0]];
+        let (index, means) = aggregate_by_country(&nodes, &rows, 2);
+        assert_eq!(index.names(), ["Chad", "Niue"]);
+        assert_eq!(means, vec![vec![5.0, 7.0], vec![1.0, 2.0]]);
+    }
+
+    #[test]
+    fn a_dense_graph_export_is_cut_at_the_row_limit() {
+        let dir = crate::test_support::scratch_dir("dense-export");
+        let path = dir.join("edges.csv");
+        let path = path.to_str().unwrap();
+        let run = RunMetadata::new(&dir.join("input.csv").to_string_lossy(), "none").unwrap();
+        let gate = FeatureGate { policy: MissingFeaturePolicy::Keep, limit: 0.2, fractions: Vec::new(), kept: Vec::new() };
+        // Every pair of 60 nodes: 1,770 edges
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<NodeIndex> = (0..60).map(|i| graph.add_node(format!("C{}", i))).collect();
+        for (i, &a) in nodes.iter().enumerate() {
+            for &b in &nodes[i + 1..] {
+                graph.add_edge(a, b, 0.5);
+            }
+        }
+
+        let limit = output::RowLimit { max_rows: Some(100), flush_every: Some(7) };
+        let options = ExportOptions { limit, ..Default::default() };
+        let summary = export_graph_to_csv(&graph, path, None, &gate, &options, &run).unwrap();
+        assert_eq!((summary.written, summary.omitted), (100, 1670));
+        let warning = summary.warning(path).unwrap();
+        assert!(warning.contains("truncated to 100 of 1,770 rows (--max-output-rows)"), "{}", warning);
+
+        let written = std::fs::read_to_string(path).unwrap();
+        let rows: Vec<&str> = written.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!((rows[0], rows.len()), ("Source,Target,Weight", 101));
+        assert_eq!(written.lines().last().unwrap(), "# truncated: 100 of 1770 rows written, max_output_rows=100");
+
+        // Gephi's edges stop at the same limit, without the comment
+        let summary = export_gephi_csv(&graph, &CountryIndex::new(["C0"]), &[0], &HashMap::new(), dir.to_str().unwrap(), &options).unwrap();
+        assert_eq!((summary.written, summary.omitted), (100, 1670));
+        let edges = std::fs::read_to_string(dir.join("edges.csv")).unwrap();
+        assert_eq!(edges.lines().count(), 101);
+        assert!(!edges.contains('#'));
+        let unlimited = export_graph_to_csv(&graph, path, None, &gate, &ExportOptions::default(), &run).unwrap();
+        assert_eq!((unlimited.written, unlimited.omitted, unlimited.warning(path)), (1770, 0, None));
+    }
+
+    // Two cliques of `size` nodes joined by one edge; its labels are the cliques
+    fn two_cliques(size: usize) -> (Graph<String, f64>, Vec<usize>) {
+        let mut graph = Graph::new();
+        let nodes: Vec<NodeIndex> = (0..2 * size).map(|i| graph.add_node(format!("C{} 2000", i))).collect();
+        for clique in 0..2 {
+            for i in clique * size..(clique + 1) * size {
+                for j in i + 1..(clique + 1) * size {
+                    graph.add_edge(nodes[i], nodes[j], 1.0);
+                }
+            }
+        }
+        graph.add_edge(nodes[0], nodes[size], 0.5);
+        let labels = (0..2 * size).map(|i| i / size).collect();
+        (graph, labels)
+    }
+
+    // Endpoints of every edge, smaller index first, sorted
+    fn edge_pairs(graph: &Graph<String, f64>) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<(usize, usize)> = graph
+            .edge_indices()
+            .map(|edge| {
+                let (a, b) = graph.edge_endpoints(edge).unwrap();
+                (a.index().min(b.index()), a.index().max(b.index()))
+            })
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    #[test]
+    fn rewiring_keeps_degrees_without_loops_or_duplicate_edges() {
+        let (graph, _) = two_cliques(6);
+        let degrees = |graph: &Graph<String, f64>| -> Vec<usize> { graph.node_indices().map(|node| graph.neighbors_undirected(node).count()).collect() };
+
+        for seed in 0..20 {
+            let rewired = random_rewired_graph(&graph, seed);
+            assert_eq!(degrees(&rewired), degrees(&graph), "seed {}", seed);
+            assert_eq!(rewired.node_weights().collect::<Vec<_>>(), graph.node_weights().collect::<Vec<_>>());
+            let pairs = edge_pairs(&rewired);
+            assert!(pairs.iter().all(|(a, b)| a != b), "seed {}", seed);
+            let mut unique = pairs.clone();
+            unique.dedup();
+            assert_eq!(unique.len(), pairs.len(), "seed {}", seed);
+        }
+
+        assert_eq!(edge_pairs(&random_rewired_graph(&graph, 7)), edge_pairs(&random_rewired_graph(&graph, 7)));
+        assert_ne!(edge_pairs(&random_rewired_graph(&graph, 7)), edge_pairs(&graph));
+        assert_ne!(edge_pairs(&random_rewired_graph(&graph, 7)), edge_pairs(&random_rewired_graph(&graph, 8)));
+    }
+
+    #[test]
+    fn planted_communities_beat_the_rewired_null_model() {
+        let (graph, labels) = two_cliques(6);
+        let cancel = CancellationToken::new();
+        let comparison = compare_to_null_model(&graph, &labels, 19, 3, &cancel).unwrap();
+        // 30 of the 31 edges lie inside a clique and each clique holds half the degree
+        assert!((comparison.modularity - (30.0 / 31.0 - 0.5)).abs() < 1e-12);
+        assert!(comparison.null_mean < comparison.modularity);
+        assert_eq!(comparison.p_value, 1.0 / 20.0);
+
+        let again = compare_to_null_model(&graph, &labels, 19, 3, &cancel).unwrap();
+        assert_eq!((again.null_mean, again.null_std), (comparison.null_mean, comparison.null_std));
+        // One of the labels for everything leaves nothing to beat
+        let one = compare_to_null_model(&graph, &[0; 12], 19, 3, &cancel).unwrap();
+        assert_eq!((one.modularity, one.p_value), (0.0, 1.0));
+    }
+
+    #[test]
+    fn significance_pruning_drops_noise_and_keeps_a_twin() {
+        // 60 rows of 10 independent centered noise features and one planted twin
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut rows: Vec<Vec<f64>> = (0..60).map(|_| (0..10).map(|_| rng.gen_range(-1.0..1.0)).collect()).collect();
+        let twin: Vec<f64> = rows[0].iter().map(|value| value * 1.01 + 0.001).collect();
+        rows.push(twin);
+        let nodes: Vec<String> = (0..rows.len()).map(|i| format!("R{}", i)).collect();
+        let significance = EdgeSignificance { permutations: 5, percentile: 99.0, seed: 4 };
+        let cancel = CancellationToken::new();
+
+        let (plain, none) = build_similarity_graph_from_rows(&nodes, &rows, 0.0, None, &cancel).unwrap();
+        assert!(none.is_none());
+        let (pruned, pruning) = build_similarity_graph_from_rows(&nodes, &rows, 0.0, Some(&significance), &cancel).unwrap();
+        let pruning = pruning.unwrap();
+        assert_eq!(pruning.threshold_edges, plain.edge_count());
+        assert_eq!(pruning.removed, plain.edge_count() - pruned.edge_count());
+        // Noise pairs pass the null cutoff about as often as the percentile allows
+        let pairs = rows.len() * (rows.len() - 1) / 2;
+        assert!(pruned.edge_count() * 100 <= 3 * pairs, "{} of {} pairs kept", pruned.edge_count(), pairs);
+        assert!(pruning.removed * 10 >= 9 * pruning.threshold_edges);
+        assert!(pruned.find_edge(NodeIndex::new(0), NodeIndex::new(60)).is_some());
+
+        let (again, _) = build_similarity_graph_from_rows(&nodes, &rows, 0.0, Some(&significance), &cancel).unwrap();
+        assert_eq!(again.edge_count(), pruned.edge_count());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use petgraph::Graph;
+use crate::country_index::CountryIndex;
+use crate::format::group_thousands;
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::table::{Cell, Table, TableOptions};
+
+// Count and mean weight of a group of graph edges
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct EdgeStats {
+    pub(crate) edges: usize,
+    pub(crate) total_weight: f64,
+}
+
+impl EdgeStats {
+    fn push(&mut self, weight: f64) {
+        self.edges += 1;
+        self.total_weight += weight;
+    }
+
+    pub(crate) fn mean_weight(&self) -> f64 {
+        self.total_weight / self.edges.max(1) as f64
+    }
+}
+
+// The graph's edges grouped by the clusters of their countries. Nodes are
+// country-years, so an edge between two years of one country is inside its
+// cluster. Edges of nodes outside the index (countries without a complete
+// feature row) are only counted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EdgeBundles {
+    pub(crate) sizes: Vec<usize>, // countries per cluster
+    // Per cluster pair, lower cluster first; (c, c) holds the edges inside c
+    pub(crate) by_clusters: BTreeMap<(usize, usize), EdgeStats>,
+    // Edges between clusters per pair of country indices, lower index first
+    pub(crate) between_countries: BTreeMap<(usize, usize), EdgeStats>,
+    pub(crate) unclustered: usize, // edges with an end outside the index
+}
+
+impl EdgeBundles {
+    // Edges inside clusters, and between them
+    pub(crate) fn totals(&self) -> (usize, usize) {
+        self.by_clusters.iter().fold((0, 0), |(inside, between), (&(a, b), stats)| {
+            if a == b { (inside + stats.edges, between) } else { (inside, between + stats.edges) }
+        })
+    }
+}
+
+// Group the edges of `graph` by the clusters in `clusters`, which holds the
+// cluster of every country of `index`
+pub(crate) fn bundle_edges(graph: &Graph<String, f64>, index: &CountryIndex, clusters: &[usize]) -> EdgeBundles {
+    let mut bundles = EdgeBundles { sizes: vec![0; clusters.iter().max().map_or(0, |k| k + 1)], ..Default::default() };
+    for &cluster in clusters {
+        bundles.sizes[cluster] += 1;
+    }
+    let countries = index.node_countries(graph);
+    for edge in graph.edge_indices() {
+        let (source, target) = graph.edge_endpoints(edge).unwrap();
+        let (Some(a), Some(b)) = (countries[source.index()], countries[target.index()]) else {
+            bundles.unclustered += 1;
+            continue;
+        };
+        let (a, b) = (a.min(b), a.max(b));
+        let (ca, cb) = (clusters[a], clusters[b]);
+        bundles.by_clusters.entry((ca.min(cb), ca.max(cb))).or_default().push(graph[edge]);
+        if ca != cb {
+            bundles.between_countries.entry((a, b)).or_default().push(graph[edge]);
+        }
+    }
+    bundles
+}
+
+pub(crate) fn print_edge_bundles(bundles: &EdgeBundles, options: &TableOptions) {
+    let (inside, between) = bundles.totals();
+    println!(
+        "Graph edges by cluster pair: {} inside clusters, {} between them, {} left out (a country without a cluster)",
+        inside, between, bundles.unclustered
+    );
+    let mut table = Table::new(&["Clusters", "Edges", "Mean weight"]);
+    for (&(a, b), stats) in &bundles.by_clusters {
+        let pair = if a == b { format!("{} (inside)", a) } else { format!("{} - {}", a, b) };
+        table = table.add_row(vec![Cell::text(pair), Cell::count(stats.edges), Cell::number(Some(stats.mean_weight()), 3)]);
+    }
+    table.print(options);
+}
+
+// Quoted DOT string
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Cluster-level diagram of the graph in Graphviz DOT. Each cluster is a box with
+// a centroid node whose loop is a super edge standing for every edge inside the
+// cluster, labelled with their count and mean weight. Edges between clusters are
+// drawn individually between the countries they join, one line per country pair
+// with the pair's edges in its tooltip; only countries with such an edge are drawn.
+pub(crate) fn bundled_dot(bundles: &EdgeBundles, index: &CountryIndex, clusters: &[usize], run: &RunMetadata) -> String {
+    let (inside, between) = bundles.totals();
+    let caption = format!(
+        "Similarity graph bundled by cluster: each loop is a super edge for the raw edges inside its cluster \
+         ({} in all); the {} raw edges between clusters are drawn per country pair ({} lines); \
+         {} edges of countries without a cluster are left out",
+        group_thousands(&inside.to_string()),
+        group_thousands(&between.to_string()),
+        bundles.between_countries.len(),
+        group_thousands(&bundles.unclustered.to_string())
+    );
+    // Graphviz skips lines starting with '#', so the provenance comment can stay
+    let mut dot = run.csv_comment();
+    dot.push_str("graph bundled {\n");
+    dot.push_str(&format!("  label={};\n  labelloc=b;\n  fontname=\"sans-serif\";\n", quote(&caption)));
+    dot.push_str("  node [fontname=\"sans-serif\"];\n  edge [fontname=\"sans-serif\", fontsize=10];\n");
+
+    let mut linked = vec![false; index.len()];
+    for &(a, b) in bundles.between_countries.keys() {
+        (linked[a], linked[b]) = (true, true);
+    }
+    for (cluster, &size) in bundles.sizes.iter().enumerate() {
+        dot.push_str(&format!("  subgraph cluster_{} {{\n", cluster));
+        dot.push_str(&format!("    label={};\n", quote(&format!("Cluster {}", cluster))));
+        let centroid = format!("Cluster {}\\n{} countries", cluster, size);
+        dot.push_str(&format!("    c{} [label=\"{}\", shape=doublecircle];\n", cluster, centroid));
+        if let Some(stats) = bundles.by_clusters.get(&(cluster, cluster)) {
+            let label = format!("{} edges\\nmean {:.3}", group_thousands(&stats.edges.to_string()), stats.mean_weight());
+            dot.push_str(&format!("    c{0} -- c{0} [label=\"{1}\", penwidth=3];\n", cluster, label));
+        }
+        for country in (0..index.len()).filter(|&i| linked[i] && clusters[i] == cluster) {
+            dot.push_str(&format!("    n{} [label={}, shape=box];\n", country, quote(index.name_of(country))));
+        }
+        dot.push_str("  }\n");
+    }
+    for (&(a, b), stats) in &bundles.between_countries {
+        let tooltip = format!("{} edges, mean {:.3}", stats.edges, stats.mean_weight());
+        dot.push_str(&format!("  n{} -- n{} [tooltip={}];\n", a, b, quote(&tooltip)));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub(crate) fn export_bundled_dot(
+    bundles: &EdgeBundles,
+    index: &CountryIndex,
+    clusters: &[usize],
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    output::atomic_write(output_file, bundled_dot(bundles, index, clusters, run))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A has two years; clusters {A, B} and {C, D}; X has no cluster
+    fn bundled() -> (EdgeBundles, CountryIndex, Vec<usize>) {
+        let mut graph = Graph::<String, f64>::new();
+        let nodes: Vec<_> = ["A", "A", "B", "C", "D", "X"].iter().map(|country| graph.add_node(country.to_string())).collect();
+        for (a, b, weight) in [(0, 1, 0.9), (0, 2, 0.7), (2, 3, 0.5), (1, 3, 0.25), (0, 3, 0.45), (3, 4, 0.8), (4, 5, 0.6)] {
+            graph.add_edge(nodes[a], nodes[b], weight);
+        }
+        let index = CountryIndex::new(["A", "B", "C", "D"]);
+        let clusters = vec![0, 0, 1, 1];
+        (bundle_edges(&graph, &index, &clusters), index, clusters)
+    }
+
+    #[test]
+    fn edges_are_grouped_by_cluster_pair() {
+        let (bundles, _, _) = bundled();
+        assert_eq!(bundles.sizes, vec![2, 2]);
+        let summary: Vec<((usize, usize), usize, f64)> =
+            bundles.by_clusters.iter().map(|(&pair, stats)| (pair, stats.edges, stats.mean_weight())).collect();
+        assert_eq!(summary.len(), 3);
+        assert_eq!((summary[0].0, summary[0].1), ((0, 0), 2));
+        assert!((summary[0].2 - 0.8).abs() < 1e-12);
+        assert_eq!((summary[1].0, summary[1].1), ((0, 1), 3));
+        assert!((summary[1].2 - 0.4).abs() < 1e-12);
+        assert_eq!((summary[2].0, summary[2].1, summary[2].2), ((1, 1), 1, 0.8));
+
+        // Edges between clusters per country pair: A-C twice, B-C once
+        let pairs: Vec<((usize, usize), usize)> = bundles.between_countries.iter().map(|(&pair, stats)| (pair, stats.edges)).collect();
+        assert_eq!(pairs, vec![((0, 2), 2), ((1, 2), 1)]);
+        assert_eq!(bundles.totals(), (3, 3));
+        assert_eq!(bundles.unclustered, 1);
+        assert_eq!(EdgeStats::default().mean_weight(), 0.0);
+    }
+
+    #[test]
+    fn the_diagram_draws_super_edges_and_the_caption_counts_them() {
+        let (bundles, index, clusters) = bundled();
+        let run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        let dot = bundled_dot(&bundles, &index, &clusters, &run);
+        assert!(dot.contains("the raw edges inside its cluster (3 in all); the 3 raw edges between clusters are drawn per country pair (2 lines); 1 edges"), "{}", dot);
+        assert!(dot.contains("    c0 [label=\"Cluster 0\\n2 countries\", shape=doublecircle];\n"));
+        assert!(dot.contains("    c0 -- c0 [label=\"2 edges\\nmean 0.800\", penwidth=3];\n"));
+        assert!(dot.contains("    c1 -- c1 [label=\"1 edges\\nmean 0.800\", penwidth=3];\n"));
+        assert!(dot.contains("  n0 -- n2 [tooltip=\"2 edges, mean 0.350\"];\n"));
+        // D only has an edge inside its cluster, so it is not drawn on its own
+        assert!(dot.contains("    n2 [label=\"C\", shape=box];\n") && !dot.contains("n3 ["));
+        assert!(dot.trim_end().ends_with('}'));
+        assert_eq!(quote("Cote d\"Ivoire \\ x"), "\"Cote d\\\"Ivoire \\\\ x\"");
+    }
+}
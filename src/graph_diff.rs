@@ -0,0 +1,222 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use csv::Writer;
+use petgraph::Graph;
+use serde::Serialize;
+use crate::metadata::RunMetadata;
+use crate::output;
+
+// Unordered pair of country names identifying an edge, smaller name first
+pub(crate) type EdgeKey = (String, String);
+
+// Weight of an edge present in both graphs
+#[derive(Debug)]
+pub(crate) struct WeightDelta {
+    pub(crate) edge: EdgeKey,
+    pub(crate) a: f64,
+    pub(crate) b: f64,
+}
+
+// Degree of one country in both graphs (summed over its nodes)
+#[derive(Debug)]
+pub(crate) struct DegreeChange {
+    pub(crate) country: String,
+    pub(crate) a: usize,
+    pub(crate) b: usize,
+}
+
+impl DegreeChange {
+    pub(crate) fn delta(&self) -> i64 {
+        self.b as i64 - self.a as i64
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GraphDiff {
+    pub(crate) only_in_a: Vec<(EdgeKey, f64)>,
+    pub(crate) only_in_b: Vec<(EdgeKey, f64)>,
+    pub(crate) common: usize,
+    pub(crate) weight_deltas: Vec<WeightDelta>, // common edges whose weight moved by more than the tolerance
+    pub(crate) degree_changes: Vec<DegreeChange>, // largest change first; unchanged countries left out
+}
+
+// Edge weights by country-name pair. Nodes sharing a name (one per year) collapse
+// into one country, and a pair joined more than once keeps its strongest edge.
+fn edge_weights(graph: &Graph<String, f64>) -> HashMap<(&str, &str), f64> {
+    let mut weights = HashMap::new();
+    for edge in graph.edge_indices() {
+        let (source, target) = graph.edge_endpoints(edge).unwrap();
+        let (source, target) = (graph[source].as_str(), graph[target].as_str());
+        let key = if source <= target { (source, target) } else { (target, source) };
+        let weight = weights.entry(key).or_insert(f64::NEG_INFINITY);
+        *weight = f64::max(*weight, graph[edge]);
+    }
+    weights
+}
+
+fn degrees_by_country(graph: &Graph<String, f64>) -> HashMap<&str, usize> {
+    let mut degrees = HashMap::new();
+    for node in graph.node_indices() {
+        *degrees.entry(graph[node].as_str()).or_insert(0) += graph.neighbors_undirected(node).count();
+    }
+    degrees
+}
+
+fn owned((source, target): (&str, &str)) -> EdgeKey {
+    (source.to_string(), target.to_string())
+}
+
+// Compare two similarity graphs by country names, so graphs built from different
+// rows or with a different node order line up: edges on one side only, common
+// edges whose weight differs by more than `tolerance`, and degree changes.
+pub(crate) fn graph_diff(a: &Graph<String, f64>, b: &Graph<String, f64>, tolerance: f64) -> GraphDiff {
+    let (weights_a, weights_b) = (edge_weights(a), edge_weights(b));
+
+    let only = |from: &HashMap<(&str, &str), f64>, other: &HashMap<(&str, &str), f64>| {
+        let mut edges: Vec<(EdgeKey, f64)> = from
+            .iter()
+            .filter(|(key, _)| !other.contains_key(*key))
+            .map(|(&key, &weight)| (owned(key), weight))
+            .collect();
+        edges.sort_by(|x, y| x.0.cmp(&y.0));
+        edges
+    };
+    let (only_in_a, only_in_b) = (only(&weights_a, &weights_b), only(&weights_b, &weights_a));
+
+    let mut common = 0;
+    let mut weight_deltas = Vec::new();
+    for (&key, &weight_a) in &weights_a {
+        let Some(&weight_b) = weights_b.get(&key) else { continue };
+        common += 1;
+        if (weight_b - weight_a).abs() > tolerance {
+            weight_deltas.push(WeightDelta { edge: owned(key), a: weight_a, b: weight_b });
+        }
+    }
+    weight_deltas.sort_by(|x, y| (y.b - y.a).abs().total_cmp(&(x.b - x.a).abs()).then_with(|| x.edge.cmp(&y.edge)));
+
+    let (degrees_a, degrees_b) = (degrees_by_country(a), degrees_by_country(b));
+    let countries: BTreeSet<&str> = degrees_a.keys().chain(degrees_b.keys()).copied().collect();
+    let mut degree_changes: Vec<DegreeChange> = countries
+        .into_iter()
+        .map(|country| DegreeChange {
+            country: country.to_string(),
+            a: degrees_a.get(country).copied().unwrap_or(0),
+            b: degrees_b.get(country).copied().unwrap_or(0),
+        })
+        .filter(|change| change.a != change.b)
+        .collect();
+    // Stable sort keeps countries with equal changes in name order
+    degree_changes.sort_by_key(|change| std::cmp::Reverse(change.delta().abs()));
+
+    GraphDiff { only_in_a, only_in_b, common, weight_deltas, degree_changes }
+}
+
+// One line of graph_diff.csv
+#[derive(Serialize)]
+struct GraphDiffRow<'a> {
+    #[serde(rename = "Kind")]
+    kind: &'static str, // only_in_a, only_in_b, weight or degree
+    #[serde(rename = "Source")]
+    source: &'a str, // the country for degree rows
+    #[serde(rename = "Target")]
+    target: &'a str, // empty for degree rows
+    #[serde(rename = "A")]
+    a: Option<f64>,
+    #[serde(rename = "B")]
+    b: Option<f64>,
+}
+
+pub(crate) fn export_graph_diff_csv(diff: &GraphDiff, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for ((source, target), weight) in &diff.only_in_a {
+        writer.serialize(GraphDiffRow { kind: "only_in_a", source, target, a: Some(*weight), b: None })?;
+    }
+    for ((source, target), weight) in &diff.only_in_b {
+        writer.serialize(GraphDiffRow { kind: "only_in_b", source, target, a: None, b: Some(*weight) })?;
+    }
+    for delta in &diff.weight_deltas {
+        let (source, target) = &delta.edge;
+        writer.serialize(GraphDiffRow { kind: "weight", source, target, a: Some(delta.a), b: Some(delta.b) })?;
+    }
+    for change in &diff.degree_changes {
+        writer.serialize(GraphDiffRow {
+            kind: "degree",
+            source: &change.country,
+            target: "",
+            a: Some(change.a as f64),
+            b: Some(change.b as f64),
+        })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+pub(crate) fn print_graph_diff(diff: &GraphDiff) {
+    println!(
+        "Country pairs: {} in both, {} only in A, {} only in B",
+        diff.common,
+        diff.only_in_a.len(),
+        diff.only_in_b.len()
+    );
+    for ((source, target), weight) in diff.only_in_a.iter().take(5) {
+        println!("  A only: {} - {} ({:.4})", source, target, weight);
+    }
+    for ((source, target), weight) in diff.only_in_b.iter().take(5) {
+        println!("  B only: {} - {} ({:.4})", source, target, weight);
+    }
+
+    println!("Common pairs with a changed weight: {}", diff.weight_deltas.len());
+    for delta in diff.weight_deltas.iter().take(5) {
+        println!("  {} - {}: {:.4} -> {:.4}", delta.edge.0, delta.edge.1, delta.a, delta.b);
+    }
+
+    println!("Largest degree changes:");
+    for change in diff.degree_changes.iter().take(10) {
+        println!("  {}: {} -> {} ({:+})", change.country, change.a, change.b, change.delta());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(countries: &[&str], edges: &[(&str, &str, f64)]) -> Graph<String, f64> {
+        let mut graph = Graph::new();
+        let nodes: HashMap<&str, _> = countries.iter().map(|&country| (country, graph.add_node(country.to_string()))).collect();
+        for &(source, target, weight) in edges {
+            graph.add_edge(nodes[source], nodes[target], weight);
+        }
+        graph
+    }
+
+    #[test]
+    fn graphs_are_compared_by_country_names() {
+        let a = graph(&["A", "B", "C", "D"], &[("A", "B", 0.9), ("B", "C", 0.8), ("C", "D", 0.85)]);
+        // Other node order and edge directions, one weight moved and one edge swapped
+        let b = graph(&["D", "C", "B", "A"], &[("B", "A", 0.95), ("C", "B", 0.805), ("A", "D", 0.9)]);
+        let diff = graph_diff(&a, &b, 0.01);
+
+        let key = |source: &str, target: &str| (source.to_string(), target.to_string());
+        assert_eq!(diff.only_in_a, vec![(key("C", "D"), 0.85)]);
+        assert_eq!(diff.only_in_b, vec![(key("A", "D"), 0.9)]);
+        assert_eq!(diff.common, 2);
+        assert_eq!(diff.weight_deltas.len(), 1);
+        assert_eq!((&diff.weight_deltas[0].edge, diff.weight_deltas[0].a, diff.weight_deltas[0].b), (&key("A", "B"), 0.9, 0.95));
+        let changes: Vec<(&str, i64)> = diff.degree_changes.iter().map(|change| (change.country.as_str(), change.delta())).collect();
+        assert_eq!(changes, vec![("A", 1), ("C", -1)]);
+    }
+
+    #[test]
+    fn yearly_nodes_of_a_country_collapse_into_one() {
+        let mut a = graph(&["A", "B"], &[("A", "B", 0.9)]);
+        let (a2, b2) = (a.add_node("A".to_string()), a.add_node("B".to_string()));
+        a.add_edge(a2, b2, 0.95);
+        let b = graph(&["A", "B"], &[("A", "B", 0.95)]);
+        let diff = graph_diff(&a, &b, 0.01);
+        // The strongest of the repeated edges is compared
+        assert!(diff.only_in_a.is_empty() && diff.only_in_b.is_empty() && diff.weight_deltas.is_empty());
+        assert_eq!(diff.common, 1);
+        let changes: Vec<(&str, i64)> = diff.degree_changes.iter().map(|change| (change.country.as_str(), change.delta())).collect();
+        assert_eq!(changes, vec![("A", -1), ("B", -1)]);
+    }
+}
@@ -0,0 +1,437 @@
+use std::error::Error;
+use std::ops::RangeInclusive;
+use ndarray::Array2;
+use petgraph::Graph;
+use csv::Writer;
+use serde::Serialize;
+use crate::country_index::CountryIndex;
+use crate::graph::modularity;
+use crate::metadata::RunMetadata;
+use crate::output;
+
+// Hierarchical agglomerative clustering over a similarity matrix
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Linkage {
+    Single,   // similarity of the closest pair of members
+    Complete, // similarity of the farthest pair of members
+    Average,  // mean similarity over all member pairs
+}
+
+// One merge step; ids below the leaf count are leaves, id n + i is merge i
+#[derive(Debug, Clone)]
+pub(crate) struct Merge {
+    pub(crate) left: usize,
+    pub(crate) right: usize,
+    pub(crate) similarity: f64,
+    pub(crate) size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Dendrogram {
+    pub(crate) labels: Vec<String>,
+    pub(crate) merges: Vec<Merge>,
+}
+
+pub(crate) fn hierarchical_cluster(
+    labels: &[String],
+    similarity_matrix: &Array2<f64>,
+    linkage: Linkage,
+) -> Dendrogram {
+    let n = labels.len();
+    let mut similarity = similarity_matrix.clone();
+    let mut active: Vec<bool> = vec![true; n];
+    let mut sizes: Vec<usize> = vec![1; n];
+    // Current dendrogram id of each matrix slot
+    let mut ids: Vec<usize> = (0..n).collect();
+    let mut merges = Vec::new();
+
+    for step in 0..n.saturating_sub(1) {
+        // Most similar pair of active clusters; ties go to the first pair found
+        let mut best: Option<(usize, usize, f64)> = None;
+        for i in 0..n {
+            if !active[i] {
+                continue;
+            }
+            for j in (i + 1)..n {
+                if active[j] && best.is_none_or(|(_, _, s)| similarity[(i, j)] > s) {
+                    best = Some((i, j, similarity[(i, j)]));
+                }
+            }
+        }
+        let Some((i, j, best_similarity)) = best else {
+            break;
+        };
+
+        merges.push(Merge {
+            left: ids[i],
+            right: ids[j],
+            similarity: best_similarity,
+            size: sizes[i] + sizes[j],
+        });
+
+        // Lance-Williams update: the merged cluster lives on in slot i
+        for k in 0..n {
+            if !active[k] || k == i || k == j {
+                continue;
+            }
+            let (a, b) = (similarity[(i, k)], similarity[(j, k)]);
+            let updated = match linkage {
+                Linkage::Single => a.max(b),
+                Linkage::Complete => a.min(b),
+                Linkage::Average => {
+                    (a * sizes[i] as f64 + b * sizes[j] as f64) / (sizes[i] + sizes[j]) as f64
+                }
+            };
+            similarity[(i, k)] = updated;
+            similarity[(k, i)] = updated;
+        }
+
+        sizes[i] += sizes[j];
+        active[j] = false;
+        ids[i] = n + step;
+    }
+
+    Dendrogram {
+        labels: labels.to_vec(),
+        merges,
+    }
+}
+
+impl Dendrogram {
+    // Flat assignment into k clusters, labelled in order of first leaf
+    pub(crate) fn cut(&self, k: usize) -> Vec<usize> {
+        let n = self.labels.len();
+        let k = k.clamp(1, n.max(1));
+
+        // Replay merges until only k clusters remain
+        let mut parent: Vec<usize> = (0..n + self.merges.len()).collect();
+        for (step, merge) in self.merges.iter().take(n.saturating_sub(k)).enumerate() {
+            parent[merge.left] = n + step;
+            parent[merge.right] = n + step;
+        }
+
+        let root = |mut id: usize| {
+            while parent[id] != id {
+                id = parent[id];
+            }
+            id
+        };
+
+        let mut cluster_of_root: Vec<Option<usize>> = vec![None; parent.len()];
+        let mut next = 0;
+        (0..n)
+            .map(|leaf| {
+                let r = root(leaf);
+                *cluster_of_root[r].get_or_insert_with(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect()
+    }
+
+    // Indented text rendering of the merges, collapsing below max_depth
+    pub(crate) fn render_ascii(&self, max_depth: usize) -> String {
+        let mut output = String::new();
+        if let Some(root) = self.roots().last() {
+            self.render_node(*root, 0, max_depth, &mut output);
+        }
+        output
+    }
+
+    pub(crate) fn print_ascii(&self, max_depth: usize) {
+        print!("{}", self.render_ascii(max_depth));
+    }
+
+    // Leaf indices in dendrogram order, so leaves merged early sit side by side
+    pub(crate) fn leaf_order(&self) -> Vec<usize> {
+        self.roots().into_iter().flat_map(|root| self.leaf_ids(root)).collect()
+    }
+
+    fn leaf_ids(&self, id: usize) -> Vec<usize> {
+        let n = self.labels.len();
+        if id < n {
+            return vec![id];
+        }
+        let merge = &self.merges[id - n];
+        let mut leaves = self.leaf_ids(merge.left);
+        leaves.extend(self.leaf_ids(merge.right));
+        leaves
+    }
+
+    // Top-level cluster ids (one unless the matrix was empty)
+    fn roots(&self) -> Vec<usize> {
+        let n = self.labels.len();
+        if self.merges.is_empty() {
+            (0..n).collect()
+        } else {
+            vec![n + self.merges.len() - 1]
+        }
+    }
+
+    fn render_node(&self, id: usize, depth: usize, max_depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        let n = self.labels.len();
+        if id < n {
+            output.push_str(&format!("{}- {}\n", indent, self.labels[id]));
+            return;
+        }
+
+        let merge = &self.merges[id - n];
+        if depth >= max_depth {
+            let leaves = self.leaf_labels(id);
+            let preview = leaves.iter().take(5).copied().collect::<Vec<_>>().join(", ");
+            let more = if leaves.len() > 5 { ", ..." } else { "" };
+            output.push_str(&format!(
+                "{}+ ... {} countries ({}{})\n",
+                indent, merge.size, preview, more
+            ));
+            return;
+        }
+
+        output.push_str(&format!(
+            "{}+ [{:.4}] {} countries\n",
+            indent, merge.similarity, merge.size
+        ));
+        self.render_node(merge.left, depth + 1, max_depth, output);
+        self.render_node(merge.right, depth + 1, max_depth, output);
+    }
+
+    fn leaf_labels(&self, id: usize) -> Vec<&str> {
+        self.leaf_ids(id).into_iter().map(|leaf| self.labels[leaf].as_str()).collect()
+    }
+}
+
+// How the number of clusters is scored when it is not given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum KCriterion {
+    Modularity, // of the similarity graph with nodes labelled by their country's cluster
+    Silhouette, // mean silhouette over countries, with 1 - similarity as the distance
+}
+
+// Fixed number of clusters, or the best k of a sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClusterCount {
+    Fixed(usize),
+    Select(KCriterion),
+}
+
+impl ClusterCount {
+    // From its command-line form: a number, "modularity" or "silhouette"
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "modularity" => Ok(ClusterCount::Select(KCriterion::Modularity)),
+            "silhouette" => Ok(ClusterCount::Select(KCriterion::Silhouette)),
+            other => match other.parse::<usize>() {
+                Ok(k) if k >= 1 => Ok(ClusterCount::Fixed(k)),
+                _ => Err(format!("Invalid cluster count \"{}\" (expected a number from 1, modularity or silhouette)", other).into()),
+            },
+        }
+    }
+}
+
+// Mean silhouette of a flat assignment; singleton clusters score 0
+fn silhouette(similarity: &Array2<f64>, assignments: &[usize]) -> f64 {
+    let n = assignments.len();
+    let clusters = assignments.iter().max().map_or(0, |&max| max + 1);
+    if n == 0 || clusters < 2 {
+        return 0.0;
+    }
+    let mut sizes = vec![0usize; clusters];
+    for &cluster in assignments {
+        sizes[cluster] += 1;
+    }
+
+    let total: f64 = (0..n)
+        .map(|i| {
+            let own = assignments[i];
+            if sizes[own] < 2 {
+                return 0.0;
+            }
+            // Summed distance from i to every cluster
+            let mut distances = vec![0.0; clusters];
+            for j in (0..n).filter(|&j| j != i) {
+                distances[assignments[j]] += 1.0 - similarity[(i, j)];
+            }
+            let a = distances[own] / (sizes[own] - 1) as f64;
+            let b = (0..clusters)
+                .filter(|&cluster| cluster != own && sizes[cluster] > 0)
+                .map(|cluster| distances[cluster] / sizes[cluster] as f64)
+                .fold(f64::INFINITY, f64::min);
+            if a.max(b) > 0.0 { (b - a) / a.max(b) } else { 0.0 }
+        })
+        .sum();
+    total / n as f64
+}
+
+// Cut the dendrogram at every k in `k_range` and score each cut; returns the best
+// k (the smallest on ties) and the score of every k in order. The dendrogram
+// leaves are the countries of `index`, as are the rows of `similarity`, and graph
+// nodes are matched to them through the index. Deterministic, as neither the cuts
+// nor the scores are random.
+pub(crate) fn select_k(
+    dendrogram: &Dendrogram,
+    index: &CountryIndex,
+    similarity: &Array2<f64>,
+    graph: &Graph<String, f64>,
+    k_range: RangeInclusive<usize>,
+    criterion: KCriterion,
+) -> (usize, Vec<f64>) {
+    let node_countries = index.node_countries(graph);
+    let scores: Vec<f64> = k_range
+        .clone()
+        .map(|k| {
+            let assignments = dendrogram.cut(k);
+            match criterion {
+                KCriterion::Silhouette => silhouette(similarity, &assignments),
+                KCriterion::Modularity => {
+                    // Nodes of countries outside the dendrogram form one extra community
+                    let labels: Vec<usize> =
+                        node_countries.iter().map(|leaf| leaf.map_or(usize::MAX, |leaf| assignments[leaf])).collect();
+                    modularity(graph, &labels)
+                }
+            }
+        })
+        .collect();
+    let best = scores
+        .iter()
+        .enumerate()
+        .filter(|(_, score)| score.is_finite())
+        .fold(None, |best: Option<(usize, f64)>, (i, &score)| match best {
+            Some((_, top)) if score <= top => best,
+            _ => Some((i, score)),
+        })
+        .map_or(*k_range.start(), |(i, _)| k_range.start() + i);
+    (best, scores)
+}
+
+// One line of clusters.csv
+#[derive(Serialize)]
+struct ClusterRow<'a> {
+    #[serde(rename = "CountryId")]
+    id: usize,
+    #[serde(rename = "Country")]
+    country: &'a str,
+    #[serde(rename = "Cluster")]
+    cluster: usize,
+}
+
+// Cluster of every country of the index, one row per country in index order;
+// CountryId matches the column of the same name in node_metrics.csv
+pub(crate) fn export_clusters_csv(index: &CountryIndex, assignments: &[usize], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for (id, &cluster) in assignments.iter().enumerate() {
+        writer.serialize(ClusterRow { id, country: index.name_of(id), cluster })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Five points on a line at 0, 1, 5, 6 and 20, similar by negative distance
+    fn five_points() -> (Vec<String>, Array2<f64>) {
+        let positions = [0.0, 1.0, 5.0, 6.0, 20.0];
+        let labels = ["A", "B", "C", "D", "E"].iter().map(|label| label.to_string()).collect();
+        let matrix = Array2::from_shape_fn((5, 5), |(i, j)| -f64::abs(positions[i] - positions[j]));
+        (labels, matrix)
+    }
+
+    // Points at 0, 3, 7, 12 and 18, with gaps growing by one, on which every
+    // linkage merges in its own order
+    fn widening_gaps() -> (Vec<String>, Array2<f64>) {
+        let positions = [0.0, 3.0, 7.0, 12.0, 18.0];
+        let labels = ["A", "B", "C", "D", "E"].iter().map(|label| label.to_string()).collect();
+        let matrix = Array2::from_shape_fn((5, 5), |(i, j)| -f64::abs(positions[i] - positions[j]));
+        (labels, matrix)
+    }
+
+    #[test]
+    fn merges_the_closest_clusters_first() {
+        let (labels, matrix) = widening_gaps();
+        for (linkage, expected) in [
+            // Chains each point onto the growing cluster
+            (Linkage::Single, vec![(0, 1, 2, -3.0), (5, 2, 3, -4.0), (6, 3, 4, -5.0), (7, 4, 5, -6.0)]),
+            // Pairs C with D, then adds E, whose farthest member is nearer than A's
+            (Linkage::Complete, vec![(0, 1, 2, -3.0), (2, 3, 2, -5.0), (6, 4, 3, -11.0), (5, 7, 5, -18.0)]),
+            // Pairs C with D, then joins the two pairs (mean 8) before E (mean 8.5)
+            (Linkage::Average, vec![(0, 1, 2, -3.0), (2, 3, 2, -5.0), (5, 6, 4, -8.0), (7, 4, 5, -12.5)]),
+        ] {
+            let dendrogram = hierarchical_cluster(&labels, &matrix, linkage);
+            let merges: Vec<(usize, usize, usize, f64)> =
+                dendrogram.merges.iter().map(|merge| (merge.left, merge.right, merge.size, merge.similarity)).collect();
+            assert_eq!(merges, expected, "{:?}", linkage);
+        }
+    }
+
+    #[test]
+    fn ascii_dendrogram_collapses_below_max_depth() {
+        let (labels, matrix) = widening_gaps();
+        let dendrogram = hierarchical_cluster(&labels, &matrix, Linkage::Complete);
+        assert_eq!(
+            dendrogram.render_ascii(usize::MAX),
+            "\
++ [-18.0000] 5 countries
+  + [-3.0000] 2 countries
+    - A
+    - B
+  + [-11.0000] 3 countries
+    + [-5.0000] 2 countries
+      - C
+      - D
+    - E
+"
+        );
+        assert_eq!(
+            dendrogram.render_ascii(1),
+            "\
++ [-18.0000] 5 countries
+  + ... 2 countries (A, B)
+  + ... 3 countries (C, D, E)
+"
+        );
+        assert_eq!(dendrogram.render_ascii(0), "+ ... 5 countries (A, B, C, D, E)\n");
+    }
+
+    #[test]
+    fn cut_labels_clusters_in_order_of_first_leaf() {
+        let (labels, matrix) = five_points();
+        let dendrogram = hierarchical_cluster(&labels, &matrix, Linkage::Average);
+        assert_eq!(dendrogram.cut(1), vec![0; 5]);
+        assert_eq!(dendrogram.cut(2), vec![0, 0, 0, 0, 1]);
+        assert_eq!(dendrogram.cut(3), vec![0, 0, 1, 1, 2]);
+        assert_eq!(dendrogram.cut(9), vec![0, 1, 2, 3, 4]);
+        assert_eq!(dendrogram.leaf_order(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn select_k_finds_well_separated_groups() {
+        // Three groups of four countries around 0, 10 and 20
+        let positions: Vec<f64> = (0..12).map(|i| (i / 4) as f64 * 10.0 + (i % 4) as f64 * 0.5).collect();
+        let labels: Vec<String> = (0..12).map(|i| format!("C{:02}", i)).collect();
+        let index = CountryIndex::new(&labels);
+        let similarity = Array2::from_shape_fn((12, 12), |(i, j)| 1.0 / (1.0 + (positions[i] - positions[j]).abs()));
+        let dendrogram = hierarchical_cluster(&labels, &similarity, Linkage::Average);
+
+        // Graph nodes in reverse order, linked within their group only
+        let mut graph = Graph::new();
+        let nodes: Vec<_> = labels.iter().rev().map(|label| graph.add_node(label.clone())).collect();
+        for i in 0..12 {
+            for j in (i + 1)..12 {
+                if i / 4 == j / 4 {
+                    graph.add_edge(nodes[11 - i], nodes[11 - j], similarity[(i, j)]);
+                }
+            }
+        }
+
+        for criterion in [KCriterion::Silhouette, KCriterion::Modularity] {
+            let (k, scores) = select_k(&dendrogram, &index, &similarity, &graph, 2..=6, criterion);
+            assert_eq!(k, 3, "{:?}: {:?}", criterion, scores);
+            assert_eq!(scores.len(), 5);
+            assert_eq!(select_k(&dendrogram, &index, &similarity, &graph, 2..=6, criterion).1, scores);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+use crate::manifest::Artifact;
+use crate::output;
+
+// Bits an image's hash may differ from its snapshot by. Font rendering and
+// anti-aliasing move a few cells across the mean; a lost label, a swapped series
+// or an empty panel moves many more.
+pub(crate) const SNAPSHOT_TOLERANCE: u32 = 5;
+
+// Average hash of an RGB8 image: the image split into an 8x8 grid, one bit per
+// cell (row by row, first cell in the highest bit) set when the cell's mean
+// luminance is above the mean of all cells. Similar images differ in few bits.
+pub(crate) fn average_hash(pixels: &[u8], width: usize, height: usize) -> u64 {
+    // Each cell covers at least one pixel, so images under 8 pixels repeat theirs
+    let span = |cell: usize, size: usize| {
+        let start = (cell * size / 8).min(size.saturating_sub(1));
+        start..((cell + 1) * size / 8).max(start + 1)
+    };
+    let luminance = |x: usize, y: usize| {
+        let i = (y * width + x) * 3;
+        0.299 * pixels[i] as f64 + 0.587 * pixels[i + 1] as f64 + 0.114 * pixels[i + 2] as f64
+    };
+    let means: Vec<f64> = (0..64)
+        .map(|cell| {
+            let (rows, columns) = (span(cell / 8, height), span(cell % 8, width));
+            let area = (rows.len() * columns.len()) as f64;
+            rows.flat_map(|y| columns.clone().map(move |x| (x, y))).map(|(x, y)| luminance(x, y)).sum::<f64>() / area
+        })
+        .collect();
+    let mean = means.iter().sum::<f64>() / 64.0;
+    means.iter().fold(0, |hash, &cell| (hash << 1) | u64::from(cell > mean))
+}
+
+// Number of bits two hashes differ in
+pub(crate) fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Average hash of a PNG file; palette, grey and alpha images are read as RGB
+pub(crate) fn png_hash(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let samples = info.color_type.samples();
+    let rgb: Vec<u8> = buffer[..info.buffer_size()]
+        .chunks_exact(samples)
+        .flat_map(|pixel| if samples < 3 { [pixel[0]; 3] } else { [pixel[0], pixel[1], pixel[2]] })
+        .collect();
+    Ok(average_hash(&rgb, info.width as usize, info.height as usize))
+}
+
+// Hash of every image the run produced, by path
+fn image_hashes(artifacts: &[Artifact]) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    artifacts
+        .iter()
+        .filter(|artifact| artifact.kind == "image")
+        .map(|artifact| {
+            let hash = png_hash(Path::new(&artifact.path)).map_err(|e| format!("{}: {}", artifact.path, e))?;
+            Ok((artifact.path.clone(), format!("{:016x}", hash)))
+        })
+        .collect()
+}
+
+// Compare the run's images with the hashes stored in `snapshot_file`, failing
+// when one is gone or differs by more than SNAPSHOT_TOLERANCE bits; with `update`,
+// store this run's hashes instead. Meant for runs on the seeded demo dataset,
+// whose charts only change when the code drawing them does.
+pub(crate) fn check_snapshots(artifacts: &[Artifact], snapshot_file: &str, update: bool) -> Result<(), Box<dyn Error>> {
+    let hashes = image_hashes(artifacts)?;
+    if update {
+        output::atomic_write(snapshot_file, serde_json::to_vec_pretty(&hashes)?)?;
+        println!("Snapshots of {} images written to {}", hashes.len(), output::display_path(snapshot_file));
+        return Ok(());
+    }
+
+    let text = fs::read_to_string(snapshot_file)
+        .map_err(|e| format!("Cannot read snapshots {} ({}); write them with --update-snapshots", snapshot_file, e))?;
+    let expected: BTreeMap<String, String> = serde_json::from_str(&text).map_err(|e| format!("Invalid snapshots {}: {}", snapshot_file, e))?;
+    let mut failures = Vec::new();
+    for (path, stored) in &expected {
+        let stored = u64::from_str_radix(stored, 16).map_err(|_| format!("Invalid snapshot hash for {}: {}", path, stored))?;
+        match hashes.get(path) {
+            None => failures.push(format!("{}: not produced", path)),
+            Some(hash) => {
+                let bits = distance(stored, u64::from_str_radix(hash, 16)?);
+                if bits > SNAPSHOT_TOLERANCE {
+                    failures.push(format!("{}: {} bits from its snapshot", path, bits));
+                }
+            }
+        }
+    }
+    let unlisted: Vec<&str> = hashes.keys().filter(|path| !expected.contains_key(*path)).map(String::as_str).collect();
+    if !unlisted.is_empty() {
+        println!("Images without a snapshot: {}", unlisted.join(", "));
+    }
+    if !failures.is_empty() {
+        return Err(format!(
+            "{} of {} images differ from {} (more than {} bits):\n  {}",
+            failures.len(),
+            expected.len(),
+            snapshot_file,
+            SNAPSHOT_TOLERANCE,
+            failures.join("\n  ")
+        )
+        .into());
+    }
+    println!("Snapshots: {} images within {} bits of {}", expected.len(), SNAPSHOT_TOLERANCE, snapshot_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RGB8 image whose grey level at (x, y) is `level(x, y)`
+    fn image(width: usize, height: usize, level: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let level = &level;
+        (0..height).flat_map(|y| (0..width).flat_map(move |x| [level(x, y); 3])).collect()
+    }
+
+    #[test]
+    fn gradients_set_the_bright_half_of_the_grid() {
+        let across = image(64, 32, |x, _| (x * 255 / 63) as u8);
+        assert_eq!(average_hash(&across, 64, 32), 0x0f0f_0f0f_0f0f_0f0f);
+        let down = image(40, 80, |_, y| (y * 3) as u8);
+        assert_eq!(average_hash(&down, 40, 80), 0x0000_0000_ffff_ffff);
+        let reversed = image(64, 32, |x, _| 255 - (x * 255 / 63) as u8);
+        assert_eq!(distance(average_hash(&across, 64, 32), average_hash(&reversed, 64, 32)), 64);
+        assert_eq!(average_hash(&image(16, 16, |_, _| 200), 16, 16), 0);
+    }
+
+    #[test]
+    fn small_changes_move_few_bits() {
+        let base = image(64, 64, |x, _| (x * 4) as u8);
+        // A short dark label in one corner and a little noise everywhere
+        let changed = image(64, 64, |x, y| {
+            let noise = ((x * 7 + y * 13) % 5) as u8;
+            if x > 50 && y < 4 { 0 } else { (x * 4).min(250) as u8 + noise }
+        });
+        let bits = distance(average_hash(&base, 64, 64), average_hash(&changed, 64, 64));
+        assert!(bits <= SNAPSHOT_TOLERANCE, "{} bits", bits);
+        // Images smaller than the grid repeat their pixels instead of panicking
+        assert_eq!(average_hash(&image(2, 1, |x, _| (x * 255) as u8), 2, 1), 0x0f0f_0f0f_0f0f_0f0f);
+    }
+
+    #[test]
+    fn snapshots_fail_when_an_image_drifts() {
+        let dir = crate::test_support::scratch_dir("image-hash");
+        let path = dir.join("gradient.png");
+        let pixels = image(64, 32, |x, _| (x * 4) as u8);
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 64, 32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.write_header().unwrap().write_image_data(&pixels).unwrap();
+        assert_eq!(png_hash(&path).unwrap(), average_hash(&pixels, 64, 32));
+
+        let artifact = Artifact {
+            path: path.display().to_string(),
+            kind: "image".into(),
+            bytes: 0,
+            sha256: String::new(),
+            step: "eda".into(),
+            elapsed_seconds: 0.0,
+            cached: false,
+        };
+        let snapshots = dir.join("snapshots.json");
+        let snapshots = snapshots.to_str().unwrap();
+        check_snapshots(std::slice::from_ref(&artifact), snapshots, true).unwrap();
+        check_snapshots(std::slice::from_ref(&artifact), snapshots, false).unwrap();
+
+        let path = artifact.path.clone();
+        fs::write(snapshots, serde_json::to_string(&BTreeMap::from([(path.clone(), "f0f0f0f0f0f0f0f0")])).unwrap()).unwrap();
+        let error = check_snapshots(&[artifact], snapshots, false).unwrap_err().to_string();
+        assert!(error.contains(&format!("{}: 64 bits from its snapshot", path)), "{}", error);
+        assert!(check_snapshots(&[], snapshots, false).unwrap_err().to_string().contains("not produced"));
+    }
+}
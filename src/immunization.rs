@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use csv::Writer;
+use ndarray::Array1;
+use serde::Serialize;
+use crate::analysis_error::AnalysisError;
+use crate::eda_statistics::calculate_correlation;
+use crate::fields::LifeExpectancyRecord;
+use crate::format::{format_value, Unit};
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::series::field_meta;
+use crate::table::{Cell, Table, TableOptions};
+
+// Vaccines whose coverage, the percentage of one-year-olds immunized, the report covers
+pub(crate) const VACCINES: [&str; 3] = ["Polio", "Diphtheria", "Hepatitis B"];
+// Coverage below this percentage counts a country as under-immunized that year
+pub(crate) const LOW_COVERAGE: f64 = 80.0;
+// Death counts each coverage series is correlated with
+const DEATHS: [&str; 2] = ["infant deaths", "under-five deaths"];
+
+// Mean of one field over the countries of a status in a year
+#[derive(Debug, Clone)]
+pub(crate) struct CoverageCell {
+    pub(crate) year: u16,
+    pub(crate) status: String,
+    pub(crate) field: &'static str, // one of VACCINES, or Alcohol
+    pub(crate) mean: f64,
+    pub(crate) countries: usize,   // countries with a value
+    pub(crate) low: Option<usize>, // countries below LOW_COVERAGE; None for Alcohol
+}
+
+// Pearson correlation of a vaccine's coverage with a death count over the rows
+// holding both
+#[derive(Debug, Clone)]
+pub(crate) struct CoverageCorrelation {
+    pub(crate) vaccine: &'static str,
+    pub(crate) deaths: &'static str,
+    pub(crate) r: Option<f64>, // None when either column is constant
+    pub(crate) n: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ImmunizationReport {
+    pub(crate) cells: Vec<CoverageCell>, // by field in VACCINES order then Alcohol, then year, then status
+    pub(crate) correlations: Vec<CoverageCorrelation>,
+}
+
+impl ImmunizationReport {
+    // Yearly means of `field` for `status`
+    #[cfg(feature = "plots")]
+    pub(crate) fn series(&self, field: &str, status: &str) -> Vec<(u16, f64)> {
+        self.cells.iter().filter(|cell| cell.field == field && cell.status == status).map(|cell| (cell.year, cell.mean)).collect()
+    }
+
+    // Statuses with at least one cell, in name order
+    pub(crate) fn statuses(&self) -> Vec<&str> {
+        let mut statuses: Vec<&str> = self.cells.iter().map(|cell| cell.status.as_str()).collect();
+        statuses.sort_unstable();
+        statuses.dedup();
+        statuses
+    }
+}
+
+// Per year and status, the mean coverage of each vaccine with the number of
+// countries below LOW_COVERAGE, and mean alcohol consumption; plus how each
+// vaccine's coverage correlates with infant and under-five deaths. Coverage
+// recorded in single digits is kept here and flagged by validate_records.
+pub(crate) fn immunization_analysis(records: &[LifeExpectancyRecord]) -> Result<ImmunizationReport, Box<dyn Error>> {
+    let mut cells = Vec::new();
+    for field in VACCINES.into_iter().chain(["Alcohol"]) {
+        let meta = field_meta(field)?;
+        let mut groups: BTreeMap<(u16, &str), Vec<f64>> = BTreeMap::new();
+        for record in records {
+            if let Some(value) = (meta.getter)(record).filter(|value| value.is_finite()) {
+                groups.entry((record.year, record.status.as_str())).or_default().push(value);
+            }
+        }
+        for ((year, status), values) in groups {
+            cells.push(CoverageCell {
+                year,
+                status: status.to_string(),
+                field: meta.name,
+                mean: values.iter().sum::<f64>() / values.len() as f64,
+                countries: values.len(),
+                low: (field != "Alcohol").then(|| values.iter().filter(|&&value| value < LOW_COVERAGE).count()),
+            });
+        }
+    }
+    if cells.is_empty() {
+        return Err(AnalysisError::empty("Immunization coverage").into());
+    }
+
+    let mut correlations = Vec::new();
+    for vaccine in VACCINES {
+        let vaccine_meta = field_meta(vaccine)?;
+        for deaths in DEATHS {
+            let deaths_meta = field_meta(deaths)?;
+            let (x, y): (Vec<f64>, Vec<f64>) = records
+                .iter()
+                .filter_map(|record| Some(((vaccine_meta.getter)(record)?, (deaths_meta.getter)(record)?)))
+                .filter(|(x, y)| x.is_finite() && y.is_finite())
+                .unzip();
+            let r = calculate_correlation(&Array1::from(x.clone()).view(), &Array1::from(y).view());
+            correlations.push(CoverageCorrelation { vaccine: vaccine_meta.name, deaths: deaths_meta.name, r, n: x.len() });
+        }
+    }
+    Ok(ImmunizationReport { cells, correlations })
+}
+
+// Each field's first and last year per status, then the correlations
+pub(crate) fn print_immunization(report: &ImmunizationReport, options: &TableOptions) {
+    let (first, last) = report.cells.iter().fold((u16::MAX, u16::MIN), |(lo, hi), cell| (lo.min(cell.year), hi.max(cell.year)));
+    let find = |field: &str, status: &str, year: u16| report.cells.iter().find(|cell| cell.field == field && cell.status == status && cell.year == year);
+    let headers = [
+        "Field".to_string(),
+        "Status".to_string(),
+        format!("Mean {}", first),
+        format!("Mean {}", last),
+        format!("Below {:.0}% {}", LOW_COVERAGE, first),
+        format!("Below {:.0}% {}", LOW_COVERAGE, last),
+    ];
+    println!("Immunization coverage and alcohol consumption, {} to {}:", first, last);
+    let mut table = Table::new(&headers.iter().map(String::as_str).collect::<Vec<_>>());
+    for field in VACCINES.into_iter().chain(["Alcohol"]) {
+        let unit = if field == "Alcohol" { Unit::Litres } else { Unit::Percent };
+        for status in report.statuses() {
+            let (start, end) = (find(field, status, first), find(field, status, last));
+            let mean = |cell: Option<&CoverageCell>| Cell::formatted(cell.map(|cell| cell.mean), cell.map_or("-".to_string(), |cell| format_value(cell.mean, unit)));
+            let low = |cell: Option<&CoverageCell>| match cell.and_then(|cell| Some((cell.low?, cell.countries))) {
+                Some((low, countries)) => Cell::formatted(Some(low as f64), format!("{} of {}", low, countries)),
+                None => Cell::text("-"),
+            };
+            table = table.add_row(vec![Cell::text(field), Cell::text(status), mean(start), mean(end), low(start), low(end)]);
+        }
+    }
+    table.print(options);
+
+    println!("Correlation of coverage with deaths:");
+    let mut table = Table::new(&["Vaccine", "With", "r", "n"]);
+    for correlation in &report.correlations {
+        table = table.add_row(vec![
+            Cell::text(correlation.vaccine),
+            Cell::text(correlation.deaths),
+            Cell::signed(correlation.r, 3),
+            Cell::count(correlation.n),
+        ]);
+    }
+    table.print(options);
+}
+
+// One line of the immunization CSV
+#[derive(Serialize)]
+struct CoverageRow<'a> {
+    #[serde(rename = "Year")]
+    year: u16,
+    #[serde(rename = "Status")]
+    status: &'a str,
+    #[serde(rename = "Field")]
+    field: &'a str,
+    #[serde(rename = "Mean")]
+    mean: f64,
+    #[serde(rename = "Countries")]
+    countries: usize,
+    #[serde(rename = "Below 80%")]
+    low: Option<usize>,
+}
+
+// Every cell of the report, one row each
+pub(crate) fn export_immunization_csv(report: &ImmunizationReport, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for cell in &report.cells {
+        writer.serialize(CoverageRow {
+            year: cell.year,
+            status: &cell.status,
+            field: cell.field,
+            mean: cell.mean,
+            countries: cell.countries,
+            low: cell.low,
+        })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_clean::{validate_records, IssueKind};
+
+    fn record(country: &str, year: u16, status: &str, polio: Option<f64>, infant_deaths: f64) -> LifeExpectancyRecord {
+        LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            status: status.to_string(),
+            polio,
+            infant_deaths: Some(infant_deaths),
+            alcohol: Some(year as f64 - 2010.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn coverage_is_averaged_per_year_and_status_over_present_values() {
+        let records = [
+            record("A", 2015, "Developing", Some(90.0), 10.0),
+            record("B", 2015, "Developing", Some(6.0), 40.0),
+            record("C", 2015, "Developing", None, 30.0),
+            record("D", 2015, "Developed", Some(99.0), 1.0),
+            record("A", 2014, "Developing", Some(70.0), 12.0),
+        ];
+        let report = immunization_analysis(&records).unwrap();
+        let polio: Vec<(u16, &str, f64, usize, Option<usize>)> = report
+            .cells
+            .iter()
+            .filter(|cell| cell.field == "Polio")
+            .map(|cell| (cell.year, cell.status.as_str(), cell.mean, cell.countries, cell.low))
+            .collect();
+        // C's blank counts neither in the mean nor as a country below 80%
+        assert_eq!(polio, vec![(2014, "Developing", 70.0, 1, Some(1)), (2015, "Developed", 99.0, 1, Some(0)), (2015, "Developing", 48.0, 2, Some(1))]);
+        assert!(report.cells.iter().all(|cell| cell.field != "Diphtheria"));
+        let alcohol: Vec<(u16, f64, Option<usize>)> = report.cells.iter().filter(|cell| cell.field == "Alcohol").map(|cell| (cell.year, cell.mean, cell.low)).collect();
+        assert_eq!(alcohol, vec![(2014, 4.0, None), (2015, 5.0, None), (2015, 5.0, None)]);
+        assert_eq!(report.statuses(), vec!["Developed", "Developing"]);
+
+        let correlation = report.correlations.iter().find(|c| c.vaccine == "Polio" && c.deaths == "infant deaths").unwrap();
+        assert_eq!(correlation.n, 4);
+        assert!(correlation.r.unwrap() < -0.9);
+        let hepatitis = report.correlations.iter().find(|c| c.vaccine == "Hepatitis B").unwrap();
+        assert_eq!((hepatitis.r, hepatitis.n), (None, 0));
+
+        // Only B's single-digit coverage is flagged; the blank is not a value
+        let issues = validate_records(&records);
+        let flagged: Vec<(&str, &str, f64)> = issues
+            .iter()
+            .filter(|issue| issue.kind == IssueKind::SingleDigitCoverage)
+            .map(|issue| (issue.country.as_str(), issue.field, issue.value))
+            .collect();
+        assert_eq!(flagged, vec![("B", "Polio", 6.0)]);
+    }
+
+    #[test]
+    fn records_without_coverage_or_alcohol_are_empty_input() {
+        let records = [LifeExpectancyRecord { country: "A".to_string(), year: 2015, ..Default::default() }];
+        let error = immunization_analysis(&records).unwrap_err();
+        assert!(matches!(error.downcast_ref::<AnalysisError>(), Some(AnalysisError::EmptyInput { .. })));
+    }
+}
+
@@ -0,0 +1,518 @@
+// Final Project: analysis of the WHO life expectancy data. The binary in
+// main.rs only calls `run`; the modules below are also usable as a library.
+
+pub mod load_clean;
+pub mod eda_statistics;
+#[cfg(feature = "plots")]
+pub mod eda_plots;
+pub mod graph;
+mod cli;
+mod hierarchical;
+mod workspace;
+mod columns;
+mod config;
+mod plot_specs;
+#[cfg(feature = "plots")]
+mod spec_plots;
+mod format;
+pub mod fields;
+#[cfg(feature = "plots")]
+mod colormap;
+mod theme;
+#[cfg(feature = "plots")]
+mod charts;
+#[cfg(feature = "plots")]
+mod tile_map;
+#[cfg(feature = "plots")]
+mod dashboard;
+mod series;
+mod output;
+pub mod metadata;
+mod dataset_diff;
+mod pipeline;
+mod trends;
+mod analysis_error;
+mod streaming;
+mod graph_diff;
+mod graph_bundle;
+mod cluster_profiles;
+mod region_purity;
+pub mod synthetic;
+mod banding;
+mod missingness;
+mod table;
+mod parallel_csv;
+mod cancel;
+mod manifest;
+mod schema;
+mod findings;
+mod country_index;
+mod expression;
+mod trajectory;
+mod safe_float;
+mod radar;
+mod correlation;
+#[cfg(feature = "plots")]
+mod chart_json;
+#[cfg(feature = "plots")]
+mod image_hash;
+mod numeric;
+mod immunization;
+#[cfg(feature = "plots")]
+mod status_gap;
+#[cfg(test)]
+mod test_support;
+
+// imports
+
+use std::error::Error;
+
+
+
+// The command line program: parse the arguments and run the chosen command
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let args = cli::parse_args(std::env::args().skip(1))?;
+    let config_file = args.config.as_deref().unwrap_or(config::DEFAULT_CONFIG_FILE);
+    let mut config = config::load_config(config_file)?;
+    if let Some(seed) = args.seed {
+        config.random.seed = seed;
+    }
+    expression::register_derived_columns(&config.derived)?;
+    let file_path = if args.command == cli::Command::Demo { write_demo_data(&args, config.random.seed)? } else { "./Life Expectancy Data.csv" };
+    if let cli::Command::Diff(a, b) = &args.command {
+        return run_diff(a, b, config_file);
+    }
+    let mut run = metadata::RunMetadata::new(file_path, config_file)?;
+    // Ctrl-C stops the pairwise loops with an error; partial outputs are discarded
+    let cancel = cancel::CancellationToken::new();
+    cancel::cancel_on_interrupt(&cancel);
+    run.chart_json = args.chart_json;
+    run.seed = config.random.seed;
+    println!("Input {} ({})", file_path, run.input_hash);
+    // Other layouts of the dataset are converted to the WHO one up front
+    let decimals = args.decimal.as_deref().map(numeric::DecimalStyle::from_name).transpose()?.unwrap_or_default();
+    let decimal_point = schema::normalize_decimals(file_path, decimals, args.workspace_dir())?;
+    let variant = args.schema.as_deref().map(schema::SchemaVariant::from_name).transpose()?;
+    let normalized = schema::normalize_input(&decimal_point, variant, args.workspace_dir())?;
+    let file_path = normalized.as_str();
+
+    if args.command == cli::Command::Describe {
+        return run_describe(file_path, &args);
+    }
+    if let cli::Command::GraphDiff(threshold_a, threshold_b) = args.command {
+        return run_graph_diff(file_path, (threshold_a, threshold_b), &config, &args, &run, &cancel);
+    }
+    if let cli::Command::Lag(x_field, y_field) = &args.command {
+        return run_lag(file_path, x_field, y_field, &args, &run);
+    }
+    if let cli::Command::Trend(feature) = &args.command {
+        return run_trend(file_path, feature, &args, &run);
+    }
+    if let cli::Command::Trajectory(feature) = &args.command {
+        return run_trajectory(file_path, feature, &config, &args, &run, &cancel);
+    }
+    if args.command == cli::Command::Similar {
+        return run_similar(file_path, &config, &args);
+    }
+    if args.command == cli::Command::Radar {
+        return run_radar(file_path, &config, &args, &run);
+    }
+    if matches!(args.command, cli::Command::Graph | cli::Command::Cluster) {
+        return pipeline::run_graph_stage(file_path, &config, &args, &run, &cancel, None).map(|_| ());
+    }
+
+    let steps = match args.steps.as_deref() {
+        Some(names) => names.split(',').map(pipeline::Step::from_name).collect::<Result<Vec<_>, _>>()?,
+        None => pipeline::Step::ALL.to_vec(),
+    };
+    let pipeline = pipeline::Pipeline::new(file_path, &config, &args, &run)
+        .with_steps(&steps)
+        .fail_fast(!args.keep_going)
+        .cancel_with(&cancel)
+        .reuse_outputs(if args.force { manifest::Manifest::default() } else { manifest::Manifest::load("manifest.json") });
+    if args.dry_run {
+        return dry_run(file_path, &pipeline);
+    }
+    manifest::begin_step("pipeline");
+    let mut results = pipeline.run()?;
+
+    let completed: Vec<&str> = results.completed.iter().map(|step| step.name()).collect();
+    println!("Completed steps: {}", completed.join(", "));
+    if let Some(report) = &results.cleaning {
+        println!(
+            "{} cleaned records ({} imputed values, {} validation issues), {} configured plots",
+            results.records.len(),
+            report.imputed,
+            results.validation_issues,
+            results.produced_plots.len()
+        );
+    }
+    manifest::begin_step("findings");
+    if let Some(artifacts) = results.cached_findings.take() {
+        println!("Findings: cached, their inputs have not changed since the last run");
+        manifest::reuse(artifacts);
+    } else {
+        let findings = findings::findings(&results);
+        if !findings.is_empty() {
+            findings::print_findings(&findings);
+            findings::export_findings_markdown(&findings, "findings.md", &run)?;
+            println!("Findings written to {}", output::display_path("findings.md"));
+        }
+    }
+    manifest::declare_inputs(&results.findings_inputs);
+    let manifest = manifest::finish();
+    let artifacts = &manifest.artifacts;
+    manifest::print_manifest(artifacts, &table::TableOptions::new(args.sort_by.clone()));
+    manifest::export_manifest(&manifest, "manifest.json", &run)?;
+    println!("Manifest written to {}", output::display_path("manifest.json"));
+    #[cfg(feature = "plots")]
+    if let Some(snapshot_file) = args.snapshots.as_deref() {
+        image_hash::check_snapshots(artifacts, snapshot_file, args.update_snapshots)?;
+    }
+    #[cfg(not(feature = "plots"))]
+    if args.snapshots.is_some() {
+        println!("Image snapshots skipped: {}", pipeline::PLOTS_DISABLED);
+    }
+
+    if !results.failures.is_empty() {
+        for (step, reason) in &results.failures {
+            eprintln!("  {}: {}", step.name(), reason);
+        }
+        return Err(format!("{} of {} steps did not complete", results.failures.len(), steps.len()).into());
+    }
+
+    Ok(())
+}
+
+// Generated stand-in for the WHO file, analysed like it by the demo command
+fn write_demo_data(args: &cli::CliArgs, seed: u64) -> Result<&'static str, Box<dyn Error>> {
+    const DEMO_FILE: &str = "demo_data.csv";
+    let mut records = synthetic::generate_synthetic_dataset(60, 2000..=2015, seed);
+    let blanked = synthetic::blank_values(&mut records, args.missing.unwrap_or(0.05), seed);
+    synthetic::write_dataset_csv(&records, DEMO_FILE)?;
+    println!(
+        "Demo data: {} rows with {} blank values (seed {}) written to {}",
+        records.len(),
+        blanked,
+        seed,
+        output::display_path(DEMO_FILE)
+    );
+    Ok(DEMO_FILE)
+}
+
+// Print the validation issues of each step; any error makes the run fail
+fn dry_run(file_path: &str, pipeline: &pipeline::Pipeline) -> Result<(), Box<dyn Error>> {
+    let profile = match pipeline::DatasetProfile::load(file_path) {
+        Ok(profile) => profile,
+        Err(error) => return Err(format!("input: {} cannot be read: {}", file_path, error).into()),
+    };
+    println!("Input: {} rows, {} columns", profile.rows, profile.headers.len());
+
+    let mut errors = 0;
+    for (step, issues) in pipeline.validate(&profile)? {
+        if issues.is_empty() {
+            println!("{}: ok", step.name());
+            continue;
+        }
+        println!("{}:", step.name());
+        for issue in issues {
+            let label = match issue.severity {
+                pipeline::Severity::Error => {
+                    errors += 1;
+                    "error"
+                }
+                pipeline::Severity::Warning => "warning",
+            };
+            println!("  {}: {}", label, issue.message);
+        }
+    }
+
+    if errors > 0 {
+        return Err(format!("Dry run found {} errors", errors).into());
+    }
+    println!("Dry run passed; nothing was written");
+    Ok(())
+}
+
+// Trend slope of one field per country: CSV of all slopes and a plot of the largest
+fn run_trend(file_path: &str, feature: &str, args: &cli::CliArgs, run: &metadata::RunMetadata) -> Result<(), Box<dyn Error>> {
+    let min_years = 3;
+    let policy = load_clean::RowErrorPolicy::from_name(args.row_errors.as_deref().unwrap_or("log"), "rejects.csv")?;
+    let records = load_clean::load_cleaned_data(file_path, &policy)?;
+
+    let (slopes, excluded) = trends::country_trends(&records, feature, min_years)?;
+    if !excluded.is_empty() {
+        println!("Excluded {} countries with fewer than {} years: {}", excluded.len(), min_years, excluded.join(", "));
+    }
+    let improvers: Vec<&trends::TrendSlope> = slopes.iter().take(5).collect();
+    let decliners: Vec<&trends::TrendSlope> = slopes.iter().rev().take(5).collect();
+    for (heading, list) in [("Biggest improvers", improvers), ("Biggest decliners", decliners)] {
+        println!("{}:", heading);
+        for slope in list {
+            println!("  {}: {:+.3} per year over {} years", slope.country, slope.slope, slope.years);
+        }
+    }
+
+    trends::export_trends_csv(&slopes, "trend_slopes.csv", run)?;
+    println!("Slopes written to {}", output::display_path("trend_slopes.csv"));
+    let movers = trends::top_movers(&slopes, 8);
+    #[cfg(feature = "plots")]
+    {
+        trends::plot_top_movers(&records, feature, &movers, "trend_top_movers.png", run)?;
+        println!("Trend plot saved to {}", output::display_path("trend_top_movers.png"));
+    }
+    #[cfg(not(feature = "plots"))]
+    println!("{}", movers_without_plot(&movers));
+    Ok(())
+}
+
+// What run_trend prints in place of the plot a build without plots cannot draw
+#[cfg(not(feature = "plots"))]
+fn movers_without_plot(movers: &[&trends::TrendSlope]) -> String {
+    let countries: Vec<&str> = movers.iter().map(|mover| mover.country.as_str()).collect();
+    format!("Largest movers: {} (plot skipped: {})", countries.join(", "), pipeline::PLOTS_DISABLED)
+}
+
+// Countries clustered by how one field changed over the years rather than by its
+// level: their trajectories compared by --similarity, grouped by average linkage
+// and exported to trajectory_clusters.csv
+fn run_trajectory(
+    file_path: &str,
+    feature: &str,
+    config: &config::Config,
+    args: &cli::CliArgs,
+    run: &metadata::RunMetadata,
+    cancel: &cancel::CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    // Neighbours per country in the trajectory graph
+    const TRAJECTORY_NEIGHBORS: usize = 5;
+    let meta = series::field_meta(feature)?;
+    let similarity = trajectory::TrajectorySimilarity::from_name(args.similarity.as_deref().unwrap_or("cosine"))?;
+    let policy = load_clean::RowErrorPolicy::from_name(args.row_errors.as_deref().unwrap_or("log"), "rejects.csv")?;
+    let mut records = load_clean::load_cleaned_data(file_path, &policy)?;
+    load_clean::EntityFilter::new(&config.exclude_entities).retain_records(&mut records);
+
+    let trajectories = trajectory::country_trajectories(&records, meta)?;
+    println!(
+        "{} trajectories of {} countries over {}-{} ({} missing years interpolated)",
+        meta.name,
+        trajectories.index.len(),
+        trajectories.years.start(),
+        trajectories.years.end(),
+        trajectories.interpolated
+    );
+    if !trajectories.excluded.is_empty() {
+        println!("Excluded {} countries with fewer than 2 years: {}", trajectories.excluded.len(), trajectories.excluded.join(", "));
+    }
+    let index = &trajectories.index;
+    let matrix = trajectory::trajectory_matrix(&trajectories, similarity, cancel)?;
+    let graph = trajectory::trajectory_graph(index, &matrix, TRAJECTORY_NEIGHBORS);
+    println!("Trajectory graph ({:?}): {} countries, {} edges", similarity, graph.node_count(), graph.edge_count());
+
+    let dendrogram = hierarchical::hierarchical_cluster(index.names(), &matrix, hierarchical::Linkage::Average);
+    let count = hierarchical::ClusterCount::from_name(args.clusters.as_deref().unwrap_or(pipeline::DEFAULT_CLUSTER_COUNT))?;
+    let k = match count {
+        hierarchical::ClusterCount::Fixed(k) => k,
+        hierarchical::ClusterCount::Select(criterion) => {
+            let (k, _) = hierarchical::select_k(&dendrogram, index, &matrix, &graph, pipeline::CLUSTER_K_RANGE, criterion);
+            println!("Selected k = {} by {:?}", k, criterion);
+            k
+        }
+    };
+    let clusters = dendrogram.cut(k);
+    let (first, last) = (0, trajectories.years.len() - 1);
+    let mut summary = table::Table::new(&["Cluster", "Countries", "First year", "Last year", "Change", "Examples"]);
+    for cluster in 0..k {
+        let members: Vec<usize> = (0..index.len()).filter(|&i| clusters[i] == cluster).collect();
+        let mean = |year: usize| members.iter().map(|&i| trajectories.values[i][year]).sum::<f64>() / members.len().max(1) as f64;
+        let examples: Vec<&str> = members.iter().take(4).map(|&i| index.name_of(i)).collect();
+        summary = summary.add_row(vec![
+            table::Cell::count(cluster),
+            table::Cell::count(members.len()),
+            table::Cell::number(Some(mean(first)), 2),
+            table::Cell::number(Some(mean(last)), 2),
+            table::Cell::number(Some(mean(last) - mean(first)), 2),
+            table::Cell::text(examples.join(", ")),
+        ]);
+    }
+    println!("Mean {} per trajectory cluster:", meta.name);
+    summary.print(&table::TableOptions::new(args.sort_by.clone()));
+
+    hierarchical::export_clusters_csv(index, &clusters, "trajectory_clusters.csv", run)?;
+    println!("Trajectory clusters exported to {}", output::display_path("trajectory_clusters.csv"));
+    #[cfg(feature = "plots")]
+    {
+        trajectory::plot_cluster_trajectories(&trajectories, &clusters, meta, "trajectory_clusters.png", run)?;
+        println!("Cluster trajectories plotted to {}", output::display_path("trajectory_clusters.png"));
+    }
+    Ok(())
+}
+
+// Whether one field moves ahead of another: pooled correlation of x with y from
+// MAX_LAG years earlier to MAX_LAG years later
+#[cfg_attr(not(feature = "plots"), allow(unused_variables))]
+fn run_lag(file_path: &str, x_field: &str, y_field: &str, args: &cli::CliArgs, run: &metadata::RunMetadata) -> Result<(), Box<dyn Error>> {
+    const MAX_LAG: i32 = 3;
+    let policy = load_clean::RowErrorPolicy::from_name(args.row_errors.as_deref().unwrap_or("log"), "rejects.csv")?;
+    let records = load_clean::load_cleaned_data(file_path, &policy)?;
+
+    let correlations = trends::lagged_correlation(&records, x_field, y_field, -MAX_LAG..=MAX_LAG)?;
+    println!("Correlation of {} at year t with {} at year t + lag:", x_field, y_field);
+    for (lag, correlation, pairs) in &correlations {
+        println!("  lag {:+}: {:+.3} over {} pairs", lag, correlation, pairs);
+    }
+    #[cfg(feature = "plots")]
+    {
+        trends::plot_lagged_correlation(&correlations, x_field, y_field, "lagged_correlation.png", run)?;
+        println!("Lag plot saved to {}", output::display_path("lagged_correlation.png"));
+    }
+    Ok(())
+}
+
+// Similarity graphs at two thresholds over the same features; graph B uses
+// --compare-weights, so raw and standardized features can be compared too
+fn run_graph_diff(
+    file_path: &str,
+    (threshold_a, threshold_b): (f64, f64),
+    config: &config::Config,
+    args: &cli::CliArgs,
+    run: &metadata::RunMetadata,
+    cancel: &cancel::CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    let headers = columns::read_headers(file_path)?;
+    let exclude = load_clean::EntityFilter::new(&config.exclude_entities);
+    let selected = pipeline::select_graph_features(&headers, &exclude, args, |selection| graph::read_feature_cells(file_path, selection))?;
+    let (features, gate) = (&selected.columns, &selected.gate);
+    let (weights_a, weights_b) = (args.feature_weights.as_deref(), args.compare_weights.as_deref());
+    let (weights_a, weights_b) = (weights_a.map(|w| gate.apply(w)), weights_b.map(|w| gate.apply(w)));
+    let (weights_a, weights_b) = (weights_a.as_deref(), weights_b.as_deref());
+    let (a, _) = graph::build_similarity_graph(file_path, features, &exclude, threshold_a, weights_a, None, cancel)?;
+    let (b, _) = graph::build_similarity_graph(file_path, features, &exclude, threshold_b, weights_b, None, cancel)?;
+    println!("A: threshold {}, weights {:?}: {} edges", threshold_a, weights_a, a.edge_count());
+    println!("B: threshold {}, weights {:?}: {} edges", threshold_b, weights_b, b.edge_count());
+
+    let diff = graph_diff::graph_diff(&a, &b, 1e-6);
+    graph_diff::print_graph_diff(&diff);
+    graph_diff::export_graph_diff_csv(&diff, "graph_diff.csv", run)?;
+    println!("Differences written to {}", output::display_path("graph_diff.csv"));
+    Ok(())
+}
+
+// The --n countries most similar to --country over the graph features, averaged
+// per country as for the hierarchical clustering
+fn run_similar(file_path: &str, config: &config::Config, args: &cli::CliArgs) -> Result<(), Box<dyn Error>> {
+    const DEFAULT_NEIGHBORS: usize = 10;
+    let country = args.country.as_deref().ok_or("similar requires --country (e.g. --country Japan)")?;
+    let headers = columns::read_headers(file_path)?;
+    let exclude = load_clean::EntityFilter::new(&config.exclude_entities);
+    let selected = pipeline::select_graph_features(&headers, &exclude, args, |selection| graph::read_feature_cells(file_path, selection))?;
+    let (features, gate) = (&selected.columns, &selected.gate);
+    let (countries, rows) = &selected.rows;
+    // Look the name up among every country first, so one without a complete row
+    // is reported as such rather than as unknown
+    let query = countries[graph::find_country(countries, country)?].clone();
+    let (index, country_rows) = graph::aggregate_by_country(countries, rows, features.len());
+    if index.index_of(&query).is_none() {
+        return Err(format!("{} has no year with every feature of the graph", query).into());
+    }
+
+    let n = args.neighbors.unwrap_or(DEFAULT_NEIGHBORS);
+    let weights = args.feature_weights.as_deref().map(|weights| gate.apply(weights));
+    let neighbors = graph::most_similar(&index, &country_rows, country, n, weights.as_deref())?;
+    println!("Countries most similar to {}:", query);
+    let mut ranking = table::Table::new(&["Rank", "Country", "Similarity"]);
+    for (rank, (other, similarity)) in neighbors.into_iter().enumerate() {
+        ranking = ranking.add_row(vec![table::Cell::count(rank + 1), table::Cell::text(other), table::Cell::number(Some(similarity), 3)]);
+    }
+    ranking.print(&table::TableOptions::new(args.sort_by.clone()));
+    Ok(())
+}
+
+// Percentile of --country among all countries on every RADAR_FEATURES field,
+// drawn as radar_<country>.png
+#[cfg_attr(not(feature = "plots"), allow(unused_variables))]
+fn run_radar(file_path: &str, config: &config::Config, args: &cli::CliArgs, run: &metadata::RunMetadata) -> Result<(), Box<dyn Error>> {
+    let country = args.country.as_deref().ok_or("radar requires --country (e.g. --country Japan)")?;
+    let policy = load_clean::RowErrorPolicy::from_name(args.row_errors.as_deref().unwrap_or("log"), "rejects.csv")?;
+    let mut records = load_clean::load_cleaned_data(file_path, &policy)?;
+    load_clean::EntityFilter::new(&config.exclude_entities).retain_records(&mut records);
+
+    let (name, percentiles) = radar::country_percentiles(&records, country, &radar::RADAR_FEATURES)?;
+    radar::print_percentiles(&name, &percentiles);
+    #[cfg(feature = "plots")]
+    {
+        let output_file = format!("radar_{}.png", load_clean::slug(&name));
+        radar::plot_radar(&percentiles, &format!("{}: percentile among countries", name), &output_file, run)?;
+        println!("Radar chart saved to {}", output::display_path(&output_file));
+    }
+    Ok(())
+}
+
+// Summary of every registry field and derived column, and the mean life
+// expectancy per status, read one record at a time so the file never has to fit
+// in memory
+fn run_describe(file_path: &str, args: &cli::CliArgs) -> Result<(), Box<dyn Error>> {
+    let headers = columns::read_headers(file_path)?;
+    let layout = load_clean::RecordLayout::new(&headers);
+    let extended = layout.extend_headers(&headers);
+    let derived = extended[headers.len()..].iter().map(String::as_str);
+    let present: Vec<(&str, usize)> = fields::FIELDS
+        .iter()
+        .map(|meta| meta.name)
+        .chain(derived)
+        .filter_map(|name| Some((name, columns::find_column(&extended, name)?)))
+        .collect();
+    let indices: Vec<usize> = present.iter().map(|&(_, column)| column).collect();
+
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let summaries: Vec<(String, eda_statistics::SummaryStats)> = present
+        .iter()
+        .zip(streaming::stream_describe(&mut reader, &layout, &indices)?)
+        .filter_map(|(&(name, _), stats)| Some((name.to_string(), stats?)))
+        .collect();
+    println!("Streaming summary (medians are P\u{b2} estimates):");
+    let tables = table::TableOptions::new(args.sort_by.clone());
+    eda_statistics::print_describe(&summaries, &tables);
+
+    let grouping = columns::resolve_columns(file_path, &["Status", "Life expectancy"])?;
+    let (status, life_expectancy) = (grouping[0], grouping[1]);
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let grouped = streaming::stream_grouped_mean(&mut reader, status, life_expectancy)?;
+    let mut means: Vec<(String, (f64, usize))> = grouped.means.into_iter().collect();
+    means.sort_by(|a, b| a.0.cmp(&b.0));
+    println!("Average life expectancy by status:");
+    let mut by_status = table::Table::new(&["Status", "Life expectancy", "Values"]);
+    for (status, (mean, count)) in means {
+        let mean = table::Cell::formatted(Some(mean), format::format_value(mean, format::Unit::Years));
+        by_status = by_status.add_row(vec![table::Cell::text(status), mean, table::Cell::count(count)]);
+    }
+    by_status.print(&tables);
+    Ok(())
+}
+
+// Compare two versions of the dataset and write the differences to dataset_diff.csv
+fn run_diff(file_a: &str, file_b: &str, config_file: &str) -> Result<(), Box<dyn Error>> {
+    let run = metadata::RunMetadata::new(file_a, config_file)?;
+    let (a, b) = (load_clean::Dataset::load(file_a)?, load_clean::Dataset::load(file_b)?);
+    println!("A: {} ({} rows)\nB: {} ({} rows)", file_a, a.rows.len(), file_b, b.rows.len());
+
+    let diff = dataset_diff::compare_datasets(&a, &b, 1e-9)?;
+    dataset_diff::print_diff(&diff);
+    dataset_diff::export_diff_csv(&diff, "dataset_diff.csv", &run)?;
+    println!("Differences written to {}", output::display_path("dataset_diff.csv"));
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "plots"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trend_movers_are_listed_when_the_plot_is_skipped() {
+        let slope = |country: &str, slope: f64| trends::TrendSlope { country: country.to_string(), slope, years: 16 };
+        let slopes = [slope("Haiti", 1.25), slope("Chad", -0.5), slope("Peru", 0.25)];
+        let movers = trends::top_movers(&slopes, 2);
+        assert_eq!(movers_without_plot(&movers), "Largest movers: Haiti, Chad (plot skipped: built without the plots feature)");
+    }
+}
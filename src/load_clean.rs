@@ -1,30 +1,1203 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use csv::Reader;
-use ndarray::Array2;
+use std::fs;
+use std::path::Path;
+use csv::{Reader, ReaderBuilder, StringRecord, Writer};
+use serde::Serialize;
+#[cfg(feature = "plots")]
+use crate::charts;
+use crate::columns;
+use crate::eda_statistics::KahanSum;
+#[cfg(feature = "plots")]
+use crate::fields::FieldMeta;
+use crate::fields::{self, DerivedField, LifeExpectancyRecord, FIELDS};
+use crate::immunization;
+use crate::metadata::RunMetadata;
+use crate::missingness::{self, MissingnessTest};
+use crate::numeric::{parse_numeric, DecimalStyle};
+use crate::output;
+use crate::parallel_csv;
 
 // Load and Clean Data
-pub(crate) fn load_csv_to_array(file_path: &str) -> Result<Array2<f64>, Box<dyn Error>> {
+
+/// Raw CSV contents, with typed access to columns by header name
+pub struct Dataset {
+    pub headers: Vec<String>,
+    pub rows: Vec<StringRecord>,
+}
+
+impl Dataset {
+    /// Reads a CSV file, skipping '#' comment lines and adding the registered
+    /// derived columns.
+    ///
+    /// ```
+    /// use project::load_clean::Dataset;
+    /// use project::synthetic::{generate_synthetic_dataset, write_dataset_csv};
+    ///
+    /// let records = generate_synthetic_dataset(4, 2000..=2002, 7);
+    /// let path = std::env::temp_dir().join(format!("dataset-load-{}.csv", std::process::id()));
+    /// let path = path.to_str().unwrap();
+    /// write_dataset_csv(&records, path)?;
+    ///
+    /// let dataset = Dataset::load(path)?;
+    /// assert_eq!(dataset.rows.len(), 12);
+    /// assert_eq!(dataset.headers[..3], ["Country", "Year", "Status"]);
+    /// let gdp = dataset.numeric_column("GDP")?;
+    /// assert!(gdp.iter().all(Option::is_some));
+    /// # std::fs::remove_file(path)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn load(file_path: &str) -> Result<Self, Box<dyn Error>> {
+        // Generated files start with '#' provenance comments
+        let mut reader = ReaderBuilder::new().comment(Some(b'#')).from_path(file_path)?;
+        let headers = reader.headers()?.iter().map(columns::canonical_header).collect();
+        let rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let mut dataset = Dataset { headers, rows };
+        for field in RecordLayout::new(&dataset.headers).derived {
+            dataset.add_derived(&field.name, &*field.compute)?;
+        }
+        Ok(dataset)
+    }
+
+    // Append a column computed from the record of each row, blank where
+    // `compute` gives None; registry columns missing from the file read as None
+    pub(crate) fn add_derived(
+        &mut self,
+        name: &str,
+        compute: impl Fn(&LifeExpectancyRecord) -> Option<f64>,
+    ) -> Result<(), Box<dyn Error>> {
+        if columns::find_column(&self.headers, name).is_some() {
+            return Err(format!("Cannot add derived column \"{}\": the dataset already has one", name).into());
+        }
+        let layout = RecordLayout::new(&self.headers);
+        for row in &mut self.rows {
+            row.push_field(&derived_cell(compute(&layout.record(row))));
+        }
+        self.headers.push(columns::canonical_header(name));
+        Ok(())
+    }
+
+    pub(crate) fn column_index(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        columns::find_column(&self.headers, name).ok_or_else(|| {
+            format!(
+                "Unknown column \"{}\"; available columns: {}",
+                name,
+                self.headers.iter().map(|h| h.trim()).collect::<Vec<_>>().join(", ")
+            )
+            .into()
+        })
+    }
+
+    /// Parsed values of a column; blank or non-numeric cells are None
+    pub fn numeric_column(&self, name: &str) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+        let index = self.column_index(name)?;
+        Ok(self
+            .rows
+            .iter()
+            .map(|row| row.get(index).and_then(|value| parse_numeric(value, DecimalStyle::Point)).map(|(value, _)| value))
+            .collect())
+    }
+
+    // Parsed Year column; cells that are not a plain year are None
+    pub(crate) fn years(&self) -> Result<Vec<Option<u16>>, Box<dyn Error>> {
+        let index = self.column_index("Year")?;
+        Ok(self
+            .rows
+            .iter()
+            .map(|row| row.get(index).and_then(|value| parse_year(value).ok()))
+            .collect())
+    }
+
+    pub(crate) fn text_column(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let index = self.column_index(name)?;
+        Ok(self
+            .rows
+            .iter()
+            .map(|row| row.get(index).unwrap_or("").trim().to_string())
+            .collect())
+    }
+}
+
+// Cell text of a derived value; blank for None and non-finite values
+fn derived_cell(value: Option<f64>) -> String {
+    value.filter(|value| value.is_finite()).map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Where the record's columns sit in a header row, for computing the registered
+// derived columns of raw rows the same way Dataset::load does
+pub(crate) struct RecordLayout {
+    country: Option<usize>,
+    year: Option<usize>,
+    status: Option<usize>,
+    fields: Vec<Option<usize>>, // per FIELDS entry
+    derived: Vec<DerivedField>, // registered columns the header row does not already have
+}
+
+impl RecordLayout {
+    pub(crate) fn new(headers: &[String]) -> Self {
+        RecordLayout {
+            country: columns::find_column(headers, "Country"),
+            year: columns::find_column(headers, "Year"),
+            status: columns::find_column(headers, "Status"),
+            fields: FIELDS.iter().map(|meta| columns::find_column(headers, meta.name)).collect(),
+            derived: fields::derived_fields()
+                .into_iter()
+                .filter(|field| columns::find_column(headers, &field.name).is_none())
+                .collect(),
+        }
+    }
+
+    // The row as a record; missing or unparsable cells are empty or None, and
+    // the year 0
+    pub(crate) fn record(&self, row: &StringRecord) -> LifeExpectancyRecord {
+        let text = |column: Option<usize>| column.and_then(|column| row.get(column)).unwrap_or("").trim().to_string();
+        let mut record = LifeExpectancyRecord {
+            country: text(self.country),
+            year: parse_year(&text(self.year)).unwrap_or(0),
+            status: text(self.status),
+            ..Default::default()
+        };
+        for (meta, column) in FIELDS.iter().zip(&self.fields) {
+            (meta.setter)(&mut record, column.and_then(|column| get_f64(row, column)));
+        }
+        record
+    }
+
+    // Headers followed by the derived columns `extend` appends
+    pub(crate) fn extend_headers(&self, headers: &[String]) -> Vec<String> {
+        headers.iter().cloned().chain(self.derived.iter().map(|field| field.name.clone())).collect()
+    }
+
+    // Append the derived values of the row, in registration order
+    pub(crate) fn extend(&self, row: &mut StringRecord) {
+        if self.derived.is_empty() {
+            return;
+        }
+        let record = self.record(row);
+        for field in &self.derived {
+            row.push_field(&derived_cell((field.compute)(&record)));
+        }
+    }
+}
+
+/// What to do with rows that fail to deserialize
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowErrorPolicy {
+    Fail,               // abort on the first bad row
+    Skip,               // drop bad rows silently
+    SkipAndLog(String), // drop bad rows and write them to this rejects file
+}
+
+impl RowErrorPolicy {
+    // Policy from its command-line name ("fail", "skip" or "log")
+    pub(crate) fn from_name(name: &str, rejects_file: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "fail" => Ok(RowErrorPolicy::Fail),
+            "skip" => Ok(RowErrorPolicy::Skip),
+            "log" => Ok(RowErrorPolicy::SkipAndLog(rejects_file.to_string())),
+            other => Err(format!("Unknown row error policy \"{}\" (expected fail, skip or log)", other).into()),
+        }
+    }
+}
+
+impl RowErrorPolicy {
+    // Apply the policy to a bad row outside of cleaning: Fail aborts, the skipping
+    // policies drop the row (the rejects file is only written by clean_data, so
+    // SkipAndLog reports the row on stderr instead)
+    pub(crate) fn reject(&self, file_path: &str, row_number: usize, error: &str) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowErrorPolicy::Fail => Err(format!("{} row {}: {}", file_path, row_number, error).into()),
+            RowErrorPolicy::Skip => Ok(()),
+            RowErrorPolicy::SkipAndLog(_) => {
+                eprintln!("Skipping {} row {}: {}", file_path, row_number, error);
+                Ok(())
+            }
+        }
+    }
+}
+
+// A raw CSV row with its Year already parsed
+pub(crate) type YearRow = (u16, StringRecord);
+
+// Parse a Year cell; surrounding whitespace is allowed, anything else is an error
+pub(crate) fn parse_year(value: &str) -> Result<u16, String> {
+    value.trim().parse::<u16>().map_err(|_| format!("invalid year \"{}\"", value))
+}
+
+// Numeric cell of a raw record; None when the column is missing or the cell is
+// blank, malformed or not finite, so callers skip it instead of reading a zero
+pub(crate) fn get_f64(record: &StringRecord, column: usize) -> Option<f64> {
+    parse_numeric(record.get(column)?, DecimalStyle::Point).map(|(value, _)| value).filter(|value| value.is_finite())
+}
+
+// Read a raw CSV keeping each row with its numeric year. Rows whose year does not
+// parse go through the error policy instead of becoming odd string keys later.
+pub(crate) fn read_year_rows(file_path: &str, year_column: usize, policy: &RowErrorPolicy) -> Result<Vec<YearRow>, Box<dyn Error>> {
     let mut reader = Reader::from_path(file_path)?;
-    let mut data = Vec::new();
+    let mut rows = Vec::new();
+    for (i, result) in reader.records().enumerate() {
+        let record = result?;
+        match parse_year(record.get(year_column).unwrap_or("")) {
+            Ok(year) => rows.push((year, record)),
+            // Row 1 is the header line
+            Err(error) => policy.reject(file_path, i + 2, &error)?,
+        }
+    }
+    Ok(rows)
+}
 
-    let mut max_cols = 0;
-    for record in reader.records() {
-        let record = record?;
-        let row: Vec<f64> = record
+/// Counts collected while cleaning
+#[derive(Debug, Clone, Default)]
+pub struct CleaningReport {
+    pub rows_read: usize,
+    pub rows_written: usize,
+    pub rejected: usize,
+    pub imputed: usize, // missing cells filled with the country's mean
+    pub(crate) expenditure_issues: usize,    // rows in the consistency report
+    pub(crate) expenditure_corrected: usize, // of those, recomputed from GDP
+    pub(crate) status_changes: usize, // countries whose Status differs between years
+    pub(crate) status_unified: usize, // rows relabelled by the status policy
+    pub(crate) excluded: usize,       // rows of excluded aggregate entities
+    pub(crate) single_row: Vec<String>, // countries with one year, in name order
+    pub(crate) missingness: Vec<MissingnessTest>, // on the values before imputation
+}
+
+/// Optional work done by clean_data besides writing the cleaned file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CleaningOptions<'a> {
+    pub(crate) comparison_dir: Option<&'a str>,    // one before/after histogram per imputed field
+    pub(crate) consistency_report: Option<&'a str>, // CSV of inconsistent percentage expenditure rows
+    pub(crate) correct_expenditure: bool,           // recompute flagged values from GDP
+    pub(crate) unify_status: StatusPolicy,          // one Status per country
+    pub(crate) exclude: Option<&'a EntityFilter>,   // aggregate rows to drop
+}
+
+// Aggregates that merged datasets carry next to the countries
+pub(crate) const DEFAULT_EXCLUDED_ENTITIES: [&str; 12] = [
+    "World",
+    "European Union",
+    "Euro area",
+    "Sub-Saharan Africa",
+    "Middle East & North Africa",
+    "Latin America & Caribbean",
+    "East Asia & Pacific",
+    "South Asia",
+    "North America",
+    "High income",
+    "Low income",
+    "Middle income",
+];
+
+// Entities whose rows are left out of the analysis. Names match after folding case,
+// accents and punctuation, so "european-union" excludes "European Union".
+#[derive(Debug, Clone)]
+pub(crate) struct EntityFilter {
+    names: HashSet<String>,
+}
+
+impl EntityFilter {
+    // The default aggregates plus `extra`
+    pub(crate) fn new(extra: &[String]) -> Self {
+        let names = DEFAULT_EXCLUDED_ENTITIES
             .iter()
-            .filter_map(|value| value.parse::<f64>().ok())
+            .copied()
+            .chain(extra.iter().map(String::as_str))
+            .map(slug)
             .collect();
-        max_cols = max_cols.max(row.len());
-        data.push(row);
+        EntityFilter { names }
+    }
+
+    pub(crate) fn excludes(&self, name: &str) -> bool {
+        self.names.contains(&slug(name))
+    }
+
+    // Drop the raw rows of excluded entities; returns how many were dropped
+    pub(crate) fn retain_year_rows(&self, rows: &mut Vec<YearRow>, country_column: usize) -> usize {
+        let before = rows.len();
+        rows.retain(|(_, row)| !self.excludes(row.get(country_column).unwrap_or("")));
+        before - rows.len()
+    }
+
+    pub(crate) fn retain_records(&self, records: &mut Vec<LifeExpectancyRecord>) -> usize {
+        let before = records.len();
+        records.retain(|record| !self.excludes(&record.country));
+        before - records.len()
+    }
+}
+
+// How to label a country whose Status differs between years
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum StatusPolicy {
+    Latest,   // the status of its latest year
+    Majority, // the status of most of its years; ties go to the later status
+    #[default]
+    Keep,     // leave every row as it is
+}
+
+impl StatusPolicy {
+    // Policy from its command-line name ("latest", "majority" or "keep")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "latest" => Ok(StatusPolicy::Latest),
+            "majority" => Ok(StatusPolicy::Majority),
+            "keep" => Ok(StatusPolicy::Keep),
+            other => Err(format!("Unknown status policy \"{}\" (expected latest, majority or keep)", other).into()),
+        }
+    }
+}
+
+// Deserialize every row, applying the error policy to the ones that fail.
+// Rejected rows go to the rejects file as: row number, error, original fields.
+fn read_records(
+    file_path: &str,
+    policy: &RowErrorPolicy,
+    run: Option<&RunMetadata>,
+) -> Result<(Vec<LifeExpectancyRecord>, usize), Box<dyn Error>> {
+    // Flexible so rows with a wrong field count reach the policy instead of aborting the reader
+    // Lines starting with '#' are provenance comments written by output::stamped_writer
+    let bytes = fs::read(file_path)?;
+    let mut reader = ReaderBuilder::new().flexible(true).comment(Some(b'#')).from_reader(bytes.as_slice());
+    let headers = columns::canonical_headers(reader.headers()?);
+    let body = &bytes[reader.position().byte() as usize..];
+    let mut rejects = match policy {
+        RowErrorPolicy::SkipAndLog(path) => Some(Writer::from_writer(output::stamped_writer(path, run)?)),
+        _ => None,
+    };
+
+    // Rows are parsed in chunks on several threads for large files; the policy
+    // still sees them one at a time, in file order
+    let parsed = parallel_csv::parse_chunks(body, |mut chunk| {
+        chunk
+            .records()
+            .map(|result| match result {
+                Ok(raw) if raw.len() != headers.len() => {
+                    Err((format!("expected {} fields, found {}", headers.len(), raw.len()), raw))
+                }
+                Ok(raw) => raw.deserialize::<LifeExpectancyRecord>(Some(&headers)).map_err(|e| (row_error(e), raw)),
+                Err(e) => Err((row_error(e), StringRecord::new())),
+            })
+            .collect()
+    });
+
+    let mut records = Vec::new();
+    let mut rejected = 0;
+    for (i, result) in parsed.into_iter().enumerate() {
+        // Row 1 is the header line
+        let row_number = i + 2;
+        let (error, raw) = match result {
+            Ok(record) => {
+                records.push(record);
+                continue;
+            }
+            Err(rejection) => rejection,
+        };
+
+        rejected += 1;
+        match policy {
+            RowErrorPolicy::Fail => return Err(format!("Row {}: {}", row_number, error).into()),
+            RowErrorPolicy::Skip => {}
+            RowErrorPolicy::SkipAndLog(_) => {
+                if let Some(writer) = rejects.as_mut() {
+                    let mut fields = vec![row_number.to_string(), error];
+                    fields.extend(raw.iter().map(String::from));
+                    writer.write_record(&fields)?;
+                }
+            }
+        }
+    }
+
+    if let Some(writer) = rejects {
+        writer.into_inner()?.finish()?;
+    }
+    Ok((records, rejected))
+}
+
+// Error of one row without the reader's position, which counts from the start of
+// the row's chunk; the rejects file gives the row number instead
+fn row_error(error: csv::Error) -> String {
+    match error.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+        csv::ErrorKind::Utf8 { err, .. } => err.to_string(),
+        _ => error.to_string(),
+    }
+}
+
+/// Read the raw dataset, tidy text fields and write the typed records to
+/// output_file, each missing value filled with its country's mean.
+///
+/// ```
+/// use project::load_clean::{clean_data, CleaningOptions, RowErrorPolicy};
+/// use project::metadata::RunMetadata;
+/// use project::synthetic::{generate_synthetic_dataset, write_dataset_csv};
+///
+/// let dir = std::env::temp_dir();
+/// let raw = dir.join(format!("clean-raw-{}.csv", std::process::id()));
+/// let cleaned = dir.join(format!("clean-out-{}.csv", std::process::id()));
+/// let (raw, cleaned) = (raw.to_str().unwrap(), cleaned.to_str().unwrap());
+/// let mut records = generate_synthetic_dataset(3, 2000..=2004, 11);
+/// records[1].gdp = None;
+/// records[7].schooling = None;
+/// write_dataset_csv(&records, raw)?;
+///
+/// let run = RunMetadata::new(raw, "no-config.toml")?;
+/// let report = clean_data(raw, cleaned, &RowErrorPolicy::Skip, &CleaningOptions::default(), &run)?;
+/// assert_eq!((report.rows_read, report.rows_written, report.rejected), (15, 15, 0));
+/// assert_eq!(report.imputed, 2);
+/// # std::fs::remove_file(raw)?;
+/// # std::fs::remove_file(cleaned)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn clean_data(
+    file_path: &str,
+    output_file: &str,
+    policy: &RowErrorPolicy,
+    options: &CleaningOptions,
+    run: &RunMetadata,
+) -> Result<CleaningReport, Box<dyn Error>> {
+    let (mut records, rejected) = read_records(file_path, policy, Some(run))?;
+
+    for record in &mut records {
+        record.country = record.country.trim().to_string();
+        record.status = record.status.trim().to_string();
+    }
+    let excluded = options.exclude.map_or(0, |filter| filter.retain_records(&mut records));
+
+    // Such countries are split between the Developed and Developing averages
+    let status_changes = detect_status_changes(&records);
+    if !status_changes.is_empty() {
+        println!("Warning: {} countries change Status between years:", status_changes.len());
+        for (country, labels) in &status_changes {
+            println!("  {}: {}", country, describe_status_runs(labels));
+        }
+    }
+    let status_unified = unify_status(&mut records, options.unify_status);
+
+    // Checked on the raw values, before imputation can spread a bad value
+    let expenditure_issues = check_expenditure_consistency(&records);
+    let expenditure_corrected = if options.correct_expenditure {
+        correct_percentage_expenditure(&mut records, &expenditure_issues)
+    } else {
+        0
+    };
+    if let Some(report_file) = options.consistency_report {
+        export_consistency_csv(&expenditure_issues, options.correct_expenditure, report_file, run)?;
+    }
+
+    #[cfg(feature = "plots")]
+    let raw: Vec<Vec<f64>> = FIELDS.iter().map(|meta| field_values(&records, meta)).collect();
+    let missingness = missingness::missingness_tests(&records)?;
+    let imputed = impute_country_means(&mut records);
+
+    #[cfg(not(feature = "plots"))]
+    if options.comparison_dir.is_some() {
+        println!("Cleaning plots skipped: {}", crate::pipeline::PLOTS_DISABLED);
+    }
+    #[cfg(feature = "plots")]
+    if let Some(dir) = options.comparison_dir {
+        for (meta, raw) in FIELDS.iter().zip(&raw) {
+            let cleaned = field_values(&records, meta);
+            if cleaned.len() > raw.len() {
+                let path = Path::new(dir).join(format!("cleaning_{}.png", slug(meta.name)));
+                let output_file = path.to_str().ok_or("Output path is not valid UTF-8")?;
+                charts::plot_cleaning_comparison(raw, &cleaned, meta.name, output_file, run)?;
+            }
+        }
+    }
+
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for record in &records {
+        writer.serialize(record)?;
+    }
+    writer.into_inner()?.finish()?;
+
+    Ok(CleaningReport {
+        rows_read: records.len() + rejected + excluded,
+        rows_written: records.len(),
+        rejected,
+        imputed,
+        expenditure_issues: expenditure_issues.len(),
+        expenditure_corrected,
+        status_changes: status_changes.len(),
+        status_unified,
+        excluded,
+        single_row: single_row_countries(&records),
+        missingness,
+    })
+}
+
+// Countries with a single row, in name order. They have no trend slope and no
+// spread over the years, and their per-country average is that one row.
+pub(crate) fn single_row_countries(records: &[LifeExpectancyRecord]) -> Vec<String> {
+    let mut rows: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in records {
+        *rows.entry(&record.country).or_default() += 1;
+    }
+    rows.into_iter().filter(|&(_, count)| count == 1).map(|(country, _)| country.to_string()).collect()
+}
+
+// Countries whose Status is not the same in every year, in name order, each with
+// its status per year in year order
+pub(crate) fn detect_status_changes(records: &[LifeExpectancyRecord]) -> Vec<(String, Vec<(u16, String)>)> {
+    let mut by_country: BTreeMap<&str, Vec<(u16, String)>> = BTreeMap::new();
+    for record in records {
+        by_country.entry(&record.country).or_default().push((record.year, record.status.clone()));
+    }
+    by_country
+        .into_iter()
+        .filter(|(_, labels)| labels.iter().any(|(_, status)| *status != labels[0].1))
+        .map(|(country, mut labels)| {
+            labels.sort();
+            (country.to_string(), labels)
+        })
+        .collect()
+}
+
+// "Developing 2000-2007, Developed 2008-2015" for year-ordered labels
+fn describe_status_runs(labels: &[(u16, String)]) -> String {
+    let mut runs: Vec<(&str, u16, u16)> = Vec::new();
+    for (year, status) in labels {
+        match runs.last_mut() {
+            Some((last, _, end)) if *last == status.as_str() => *end = *year,
+            _ => runs.push((status, *year, *year)),
+        }
+    }
+    let parts: Vec<String> = runs
+        .iter()
+        .map(|(status, start, end)| if start == end { format!("{} {}", status, start) } else { format!("{} {}-{}", status, start, end) })
+        .collect();
+    parts.join(", ")
+}
+
+// Give every country that changes Status a single one under `policy`; returns the
+// number of rows relabelled
+pub(crate) fn unify_status(records: &mut [LifeExpectancyRecord], policy: StatusPolicy) -> usize {
+    let unified: HashMap<String, String> = detect_status_changes(records)
+        .into_iter()
+        .filter_map(|(country, labels)| {
+            let status = match policy {
+                StatusPolicy::Keep => return None,
+                StatusPolicy::Latest => labels.last()?.1.clone(),
+                StatusPolicy::Majority => {
+                    // Count and latest year of every status
+                    let mut counts: HashMap<&str, (usize, u16)> = HashMap::new();
+                    for (year, status) in &labels {
+                        let entry = counts.entry(status).or_insert((0, *year));
+                        *entry = (entry.0 + 1, *year);
+                    }
+                    counts.into_iter().max_by_key(|&(_, count_and_year)| count_and_year)?.0.to_string()
+                }
+            };
+            Some((country, status))
+        })
+        .collect();
+
+    let mut relabelled = 0;
+    for record in records.iter_mut() {
+        if let Some(status) = unified.get(&record.country) {
+            if record.status != *status {
+                record.status = status.clone();
+                relabelled += 1;
+            }
+        }
     }
+    relabelled
+}
 
-    // Pad rows to the same length
-    for row in &mut data {
-        row.resize(max_cols, f64::NAN); // Fills in missing values
+// Overwrite the status cell of raw rows with the (unified) status of the same
+// country in `records`, so raw-row analyses group countries the same way
+pub(crate) fn apply_statuses(rows: &mut [YearRow], country_column: usize, status_column: usize, records: &[LifeExpectancyRecord]) {
+    let statuses: HashMap<&str, &str> = records.iter().map(|record| (record.country.as_str(), record.status.as_str())).collect();
+    for (_, row) in rows.iter_mut() {
+        let Some(&status) = row.get(country_column).and_then(|country| statuses.get(country.trim())) else {
+            continue;
+        };
+        if row.get(status_column).is_some_and(|current| current.trim() != status) {
+            *row = row.iter().enumerate().map(|(i, field)| if i == status_column { status } else { field }).collect();
+        }
     }
+}
 
-    let rows = data.len();
-    let cols = max_cols;
-    let flat_data: Vec<f64> = data.into_iter().flatten().collect();
-    Ok(Array2::from_shape_vec((rows, cols), flat_data)?)
-}
\ No newline at end of file
+// Present values of one field
+#[cfg(feature = "plots")]
+fn field_values(records: &[LifeExpectancyRecord], meta: &FieldMeta) -> Vec<f64> {
+    records.iter().filter_map(|record| (meta.getter)(record)).collect()
+}
+
+// File-name form of a field or country name, e.g. "HIV/AIDS" -> "hiv_aids",
+// "Côte d'Ivoire" -> "cote_d_ivoire"; accented Latin letters lose their accent
+pub(crate) fn slug(name: &str) -> String {
+    let lower: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            c if c.is_ascii_alphanumeric() => c,
+            _ => '_',
+        })
+        .collect();
+    lower.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_")
+}
+
+// Fill missing values with the mean of the same country's other years.
+// Countries with no value at all for a field keep it missing.
+fn impute_country_means(records: &mut [LifeExpectancyRecord]) -> usize {
+    let mut imputed = 0;
+    for field in FIELDS {
+        let mut totals: HashMap<String, (KahanSum, usize)> = HashMap::new();
+        for record in records.iter() {
+            if let Some(value) = (field.getter)(record) {
+                let entry = totals.entry(record.country.clone()).or_default();
+                entry.0.add(value);
+                entry.1 += 1;
+            }
+        }
+
+        for record in records.iter_mut() {
+            if (field.getter)(record).is_none() {
+                if let Some(&(total, count)) = totals.get(&record.country) {
+                    (field.setter)(record, Some(total.value() / count as f64));
+                    imputed += 1;
+                }
+            }
+        }
+    }
+    imputed
+}
+
+// Why a value was flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IssueKind {
+    OutOfRange,
+    // Immunization coverage under 10%. The WHO data holds many of these among
+    // neighbouring years in the 90s, most likely 90-something with a digit lost.
+    SingleDigitCoverage,
+}
+
+// A value outside its field's plausible range, or a suspicious coverage value
+#[derive(Debug, Clone)]
+pub(crate) struct ValidationIssue {
+    pub(crate) country: String,
+    pub(crate) year: u16,
+    pub(crate) field: &'static str,
+    pub(crate) value: f64,
+    pub(crate) kind: IssueKind,
+}
+
+// Check every present value against the registry's valid range, and flag
+// vaccine coverage recorded in single digits
+pub(crate) fn validate_records(records: &[LifeExpectancyRecord]) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for record in records {
+        for field in FIELDS {
+            if let Some(value) = (field.getter)(record) {
+                let (low, high) = field.valid_range;
+                let kind = if value < low || value > high {
+                    IssueKind::OutOfRange
+                } else if immunization::VACCINES.contains(&field.name) && value < 10.0 {
+                    IssueKind::SingleDigitCoverage
+                } else {
+                    continue;
+                };
+                issues.push(ValidationIssue { country: record.country.clone(), year: record.year, field: field.name, value, kind });
+            }
+        }
+    }
+    issues
+}
+
+// Ratio beyond which percentage expenditure counts as inconsistent with its implied
+// value (either way); most rows of the WHO data sit within a factor of 5
+const EXPENDITURE_TOLERANCE: f64 = 10.0;
+
+// A row whose percentage expenditure is implausible: above 100, or far from the
+// value implied by GDP and Total expenditure
+#[derive(Debug, Clone)]
+pub(crate) struct ExpenditureIssue {
+    pub(crate) row: usize, // index into the checked records
+    pub(crate) country: String,
+    pub(crate) year: u16,
+    pub(crate) percentage_expenditure: f64,
+    pub(crate) gdp: Option<f64>,
+    pub(crate) total_expenditure: Option<f64>,
+    pub(crate) implied: Option<f64>, // GDP x Total expenditure / 100, when both are present
+    pub(crate) reason: &'static str,
+}
+
+// Flag rows with percentage expenditure above 100 or more than a factor
+// EXPENDITURE_TOLERANCE away from GDP x Total expenditure / 100
+pub(crate) fn check_expenditure_consistency(records: &[LifeExpectancyRecord]) -> Vec<ExpenditureIssue> {
+    let mut issues = Vec::new();
+    for (row, record) in records.iter().enumerate() {
+        let Some(value) = record.percentage_expenditure else { continue };
+        let implied = record.gdp.zip(record.total_expenditure).map(|(gdp, total)| gdp * total / 100.0);
+        let inconsistent = implied
+            .filter(|&implied| implied > 0.0 && value > 0.0)
+            .is_some_and(|implied| !(1.0 / EXPENDITURE_TOLERANCE..=EXPENDITURE_TOLERANCE).contains(&(value / implied)));
+        let reason = match (value > 100.0, inconsistent) {
+            (true, true) => "above 100 and inconsistent with GDP",
+            (true, false) => "above 100",
+            (false, true) => "inconsistent with GDP",
+            (false, false) => continue,
+        };
+        issues.push(ExpenditureIssue {
+            row,
+            country: record.country.clone(),
+            year: record.year,
+            percentage_expenditure: value,
+            gdp: record.gdp,
+            total_expenditure: record.total_expenditure,
+            implied,
+            reason,
+        });
+    }
+    issues
+}
+
+// Replace flagged percentage expenditure values by their implied value where GDP
+// and Total expenditure are both present; returns how many were replaced
+pub(crate) fn correct_percentage_expenditure(records: &mut [LifeExpectancyRecord], issues: &[ExpenditureIssue]) -> usize {
+    let mut corrected = 0;
+    for issue in issues {
+        if let Some(implied) = issue.implied {
+            records[issue.row].percentage_expenditure = Some(implied);
+            corrected += 1;
+        }
+    }
+    corrected
+}
+
+// One line of consistency_report.csv
+#[derive(Serialize)]
+struct ConsistencyRow<'a> {
+    #[serde(rename = "Country")]
+    country: &'a str,
+    #[serde(rename = "Year")]
+    year: u16,
+    #[serde(rename = "percentage expenditure")]
+    percentage_expenditure: f64,
+    #[serde(rename = "GDP")]
+    gdp: Option<f64>,
+    #[serde(rename = "Total expenditure")]
+    total_expenditure: Option<f64>,
+    #[serde(rename = "Implied")]
+    implied: Option<f64>,
+    #[serde(rename = "Reason")]
+    reason: &'a str,
+    #[serde(rename = "Corrected")]
+    corrected: bool,
+}
+
+// Offending rows with the values they were flagged on; Corrected marks the ones
+// replaced by their implied value
+fn export_consistency_csv(
+    issues: &[ExpenditureIssue],
+    corrected: bool,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for issue in issues {
+        writer.serialize(ConsistencyRow {
+            country: &issue.country,
+            year: issue.year,
+            percentage_expenditure: issue.percentage_expenditure,
+            gdp: issue.gdp,
+            total_expenditure: issue.total_expenditure,
+            implied: issue.implied,
+            reason: issue.reason,
+            corrected: corrected && issue.implied.is_some(),
+        })?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+// One (country, year, field) observation in long format
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LongRow {
+    #[serde(rename = "Country")]
+    pub(crate) country: String,
+    #[serde(rename = "Year")]
+    pub(crate) year: u16,
+    #[serde(rename = "Status")]
+    pub(crate) status: String,
+    #[serde(rename = "Field")]
+    pub(crate) field: &'static str,
+    #[serde(rename = "Value")]
+    pub(crate) value: f64,
+}
+
+// Reshape records into one row per present field value
+pub(crate) fn to_long(records: &[LifeExpectancyRecord]) -> Vec<LongRow> {
+    records
+        .iter()
+        .flat_map(|record| {
+            FIELDS.iter().filter_map(move |field| {
+                Some(LongRow {
+                    country: record.country.clone(),
+                    year: record.year,
+                    status: record.status.clone(),
+                    field: field.name,
+                    value: (field.getter)(record)?,
+                })
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn export_long_csv(rows: &[LongRow], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+// What export_partitioned splits the records by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PartitionKey {
+    Country,
+    Year,
+}
+
+impl PartitionKey {
+    // Key from its command-line name ("country" or "year")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "country" => Ok(PartitionKey::Country),
+            "year" => Ok(PartitionKey::Year),
+            other => Err(format!("Unknown partition \"{}\" (expected country or year)", other).into()),
+        }
+    }
+}
+
+// One line of the partition manifest
+#[derive(Debug, Serialize)]
+pub(crate) struct Partition {
+    #[serde(rename = "File")]
+    pub(crate) file: String,
+    #[serde(rename = "Rows")]
+    pub(crate) rows: usize,
+    #[serde(rename = "Key")]
+    pub(crate) key: String,
+}
+
+pub(crate) const PARTITION_MANIFEST: &str = "manifest.csv";
+
+// Write the records as one CSV per year (2005.csv) or per country (slug of the
+// name, e.g. cote_d_ivoire.csv) in `out_dir`, each with the full header, plus a
+// manifest listing every file with its row count and key. Partitions are in key
+// order; names that slug alike get a numeric suffix so no file is overwritten.
+pub(crate) fn export_partitioned(
+    records: &[LifeExpectancyRecord],
+    by: PartitionKey,
+    out_dir: &str,
+    run: &RunMetadata,
+) -> Result<Vec<Partition>, Box<dyn Error>> {
+    let mut groups: BTreeMap<String, Vec<&LifeExpectancyRecord>> = BTreeMap::new();
+    for record in records {
+        let key = match by {
+            PartitionKey::Country => record.country.clone(),
+            PartitionKey::Year => record.year.to_string(),
+        };
+        groups.entry(key).or_default().push(record);
+    }
+
+    let mut used: HashSet<String> = HashSet::new();
+    let mut partitions = Vec::new();
+    for (key, rows) in groups {
+        let stem = match by {
+            PartitionKey::Country => Some(slug(&key)).filter(|stem| !stem.is_empty()).unwrap_or_else(|| "country".to_string()),
+            PartitionKey::Year => key.clone(),
+        };
+        let mut file = format!("{}.csv", stem);
+        for suffix in 2.. {
+            if used.insert(file.clone()) {
+                break;
+            }
+            file = format!("{}_{}.csv", stem, suffix);
+        }
+
+        let path = Path::new(out_dir).join(&file);
+        let mut writer = Writer::from_writer(output::stamped_writer(path.to_str().ok_or("Output path is not valid UTF-8")?, Some(run))?);
+        for record in &rows {
+            writer.serialize(record)?;
+        }
+        writer.into_inner()?.finish()?;
+        partitions.push(Partition { file, rows: rows.len(), key });
+    }
+
+    let manifest = Path::new(out_dir).join(PARTITION_MANIFEST);
+    let mut writer = Writer::from_writer(output::stamped_writer(manifest.to_str().ok_or("Output path is not valid UTF-8")?, Some(run))?);
+    for partition in &partitions {
+        writer.serialize(partition)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(partitions)
+}
+
+// Load a cleaned file back into records
+pub(crate) fn load_cleaned_data(
+    file_path: &str,
+    policy: &RowErrorPolicy,
+) -> Result<Vec<LifeExpectancyRecord>, Box<dyn Error>> {
+    let (records, rejected) = read_records(file_path, policy, None)?;
+    if rejected > 0 {
+        println!("Skipped {} malformed rows in {}", rejected, file_path);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(country: &str, population: Option<f64>) -> LifeExpectancyRecord {
+        LifeExpectancyRecord { country: country.to_string(), population, ..Default::default() }
+    }
+
+    #[test]
+    fn impute_country_means_fills_gaps_with_the_compensated_mean() {
+        // 1e8 followed by many 0.1s loses most of them under naive summation
+        let mut records: Vec<LifeExpectancyRecord> = std::iter::once(record("A", Some(1e8)))
+            .chain((0..10_000).map(|_| record("A", Some(0.1))))
+            .chain([record("A", None), record("B", None), record("B", Some(7.0))])
+            .collect();
+        let imputed = impute_country_means(&mut records);
+
+        let expected = (1e8 + 1_000.0) / 10_001.0;
+        assert_eq!(imputed, 2);
+        assert_eq!(records[10_001].population, Some(expected));
+        assert_eq!(records[10_002].population, Some(7.0));
+    }
+
+    #[test]
+    fn parse_year_accepts_padded_years_only() {
+        assert_eq!(parse_year(" 2015 "), Ok(2015));
+        assert_eq!(parse_year("200 5"), Err("invalid year \"200 5\"".to_string()));
+        assert!(parse_year("2015.0").is_err());
+        assert!(parse_year("").is_err());
+    }
+
+    #[test]
+    fn read_year_rows_applies_the_policy_to_bad_years() {
+        let dir = crate::test_support::scratch_dir("year-rows");
+        let path = dir.join("years.csv");
+        std::fs::write(&path, "Country,Year\nA,2014\nA,200 5\nB, 2015\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let rows = read_year_rows(path, 1, &RowErrorPolicy::Skip).unwrap();
+        let years: Vec<u16> = rows.iter().map(|(year, _)| *year).collect();
+        assert_eq!(years, vec![2014, 2015]);
+        assert_eq!(&rows[1].1[0], "B");
+
+        let error = read_year_rows(path, 1, &RowErrorPolicy::Fail).unwrap_err().to_string();
+        assert_eq!(error, format!("{} row 3: invalid year \"200 5\"", path));
+    }
+
+    #[test]
+    fn the_header_row_of_the_who_file_deserializes() {
+        // Copied byte for byte from "Life Expectancy Data.csv", padding and CRLF included
+        let header = "Country,Year,Status,Life expectancy ,Adult Mortality,infant deaths,Alcohol,percentage expenditure,Hepatitis B,Measles , BMI ,under-five deaths ,Polio,Total expenditure,Diphtheria , HIV/AIDS,GDP,Population, thinness  1-19 years, thinness 5-9 years,Income composition of resources,Schooling";
+        let row = "Afghanistan,2015,Developing,65,263,62,0.01,71.27962362,65,1154,19.1,83,6,8.16,65,0.1,584.25921,33736494,17.2,17.3,0.479,10.1";
+        let dir = crate::test_support::scratch_dir("who-header");
+        let path = dir.join("who.csv");
+        std::fs::write(&path, format!("{}\r\n{}\r\n", header, row)).unwrap();
+        let path = path.to_str().unwrap();
+
+        let records = load_cleaned_data(path, &RowErrorPolicy::Fail).unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!((record.country.as_str(), record.year, record.status.as_str()), ("Afghanistan", 2015, "Developing"));
+        assert_eq!((record.life_expectancy, record.measles, record.bmi), (Some(65.0), Some(1154.0), Some(19.1)));
+        assert_eq!((record.hiv_aids, record.thinness_1_19_years, record.schooling), (Some(0.1), Some(17.2), Some(10.1)));
+        assert_eq!(columns::resolve_columns(path, &["Life expectancy", "thinness 1-19 years", "BMI"]).unwrap(), vec![3, 18, 10]);
+    }
+
+    #[test]
+    fn expenditure_checks_flag_real_problem_rows() {
+        let row = |country: &str, year: u16, percentage: f64, total: Option<f64>, gdp: Option<f64>| LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            percentage_expenditure: Some(percentage),
+            total_expenditure: total,
+            gdp,
+            ..Default::default()
+        };
+        // Rows of the WHO file; only Afghanistan 2015 is plausible
+        let mut records = vec![
+            row("Afghanistan", 2015, 71.27962362, Some(8.16), Some(584.25921)),
+            row("Albania", 2015, 364.9752287, Some(6.0), Some(3954.22783)),
+            row("Austria", 2006, 69.92899358, Some(1.35), Some(443.99361)),
+            row("Albania", 2014, 428.7490668, Some(5.88), None),
+        ];
+        let issues = check_expenditure_consistency(&records);
+        let flagged: Vec<(&str, u16, &str)> = issues.iter().map(|issue| (issue.country.as_str(), issue.year, issue.reason)).collect();
+        assert_eq!(flagged, vec![("Albania", 2015, "above 100"), ("Austria", 2006, "inconsistent with GDP"), ("Albania", 2014, "above 100")]);
+        assert!((issues[1].implied.unwrap() - 5.993913735).abs() < 1e-9);
+        assert_eq!(issues[2].implied, None);
+
+        // Only rows with both GDP and Total expenditure can be recomputed
+        assert_eq!(correct_percentage_expenditure(&mut records, &issues), 2);
+        assert!((records[1].percentage_expenditure.unwrap() - 237.2536698).abs() < 1e-6);
+        assert_eq!(records[3].percentage_expenditure, Some(428.7490668));
+        assert_eq!(records[0].percentage_expenditure, Some(71.27962362));
+    }
+
+    #[test]
+    fn partitions_by_year_add_up_to_the_whole_file() {
+        let records = load_cleaned_data("Life Expectancy Data.csv", &RowErrorPolicy::Skip).unwrap();
+        let dir = crate::test_support::scratch_dir("partition-years");
+        let run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        let partitions = export_partitioned(&records, PartitionKey::Year, dir.to_str().unwrap(), &run).unwrap();
+
+        assert_eq!(partitions.len(), 16);
+        assert_eq!((partitions[0].file.as_str(), partitions[0].key.as_str()), ("2000.csv", "2000"));
+        assert_eq!(partitions.iter().map(|partition| partition.rows).sum::<usize>(), records.len());
+        for partition in &partitions {
+            let path = dir.join(&partition.file);
+            let written = load_cleaned_data(path.to_str().unwrap(), &RowErrorPolicy::Fail).unwrap();
+            assert_eq!(written.len(), partition.rows);
+            assert!(written.iter().all(|record| record.year.to_string() == partition.key));
+        }
+        let manifest = std::fs::read_to_string(dir.join(PARTITION_MANIFEST)).unwrap();
+        assert_eq!(manifest.lines().filter(|line| !line.starts_with('#')).count(), 17);
+    }
+
+    #[test]
+    fn country_partitions_get_distinct_file_names() {
+        let records: Vec<LifeExpectancyRecord> =
+            ["Côte d'Ivoire", "Korea, Republic of", "Korea Republic of", "Korea, Republic of"].iter().map(|country| record(country, None)).collect();
+        let dir = crate::test_support::scratch_dir("partition-countries");
+        let run = RunMetadata::new("none.csv", "none").unwrap();
+        let partitions = export_partitioned(&records, PartitionKey::Country, dir.to_str().unwrap(), &run).unwrap();
+        let files: Vec<(&str, usize)> = partitions.iter().map(|partition| (partition.file.as_str(), partition.rows)).collect();
+        assert_eq!(files, vec![("cote_d_ivoire.csv", 1), ("korea_republic_of.csv", 1), ("korea_republic_of_2.csv", 2)]);
+        assert!(dir.join("korea_republic_of_2.csv").exists());
+        assert_eq!(PartitionKey::from_name("region").unwrap_err().to_string(), "Unknown partition \"region\" (expected country or year)");
+    }
+
+    // X flips halfway through 2000-2007, Z after five years, Y never
+    fn status_records() -> Vec<LifeExpectancyRecord> {
+        let row = |country: &str, year: u16, status: &str| LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            status: status.to_string(),
+            ..Default::default()
+        };
+        (2000..=2007)
+            .rev()
+            .flat_map(|year| {
+                [
+                    row("X", year, if year < 2004 { "Developing" } else { "Developed" }),
+                    row("Y", year, "Developed"),
+                    row("Z", year, if year < 2005 { "Developing" } else { "Developed" }),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn status_changes_are_listed_year_by_year() {
+        let changes = detect_status_changes(&status_records());
+        let countries: Vec<&str> = changes.iter().map(|(country, _)| country.as_str()).collect();
+        assert_eq!(countries, vec!["X", "Z"]);
+        assert_eq!(changes[0].1[0], (2000, "Developing".to_string()));
+        assert_eq!(describe_status_runs(&changes[0].1), "Developing 2000-2003, Developed 2004-2007");
+        assert_eq!(describe_status_runs(&[(2001, "Developed".to_string())]), "Developed 2001");
+    }
+
+    #[test]
+    fn status_policies_relabel_the_flipping_countries() {
+        let status_of = |records: &[LifeExpectancyRecord], country: &str| -> HashSet<String> {
+            records.iter().filter(|record| record.country == country).map(|record| record.status.clone()).collect()
+        };
+        let mut latest = status_records();
+        assert_eq!(unify_status(&mut latest, StatusPolicy::Latest), 9);
+        assert_eq!(status_of(&latest, "X"), HashSet::from(["Developed".to_string()]));
+        assert_eq!(status_of(&latest, "Z"), HashSet::from(["Developed".to_string()]));
+
+        // The 4-4 tie of X goes to its later status
+        let mut majority = status_records();
+        assert_eq!(unify_status(&mut majority, StatusPolicy::Majority), 7);
+        assert_eq!(status_of(&majority, "X"), HashSet::from(["Developed".to_string()]));
+        assert_eq!(status_of(&majority, "Z"), HashSet::from(["Developing".to_string()]));
+
+        let mut kept = status_records();
+        assert_eq!(unify_status(&mut kept, StatusPolicy::Keep), 0);
+        assert_eq!(status_of(&kept, "X").len(), 2);
+    }
+
+    #[test]
+    fn aggregates_are_left_out_of_rankings_and_the_graph() {
+        let exclude = EntityFilter::new(&["Arab World".to_string()]);
+        assert!(exclude.excludes("World") && exclude.excludes("european-union") && exclude.excludes("ARAB WORLD"));
+        assert!(!exclude.excludes("Albania"));
+
+        let dir = crate::test_support::scratch_dir("exclude-world");
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "Country,Year,Life expectancy,GDP\nWorld,2015,90,5\nA,2015,70,1\nB,2015,60,2\nWorld,2014,89,4\n").unwrap();
+        let input = input.to_str().unwrap();
+
+        let mut rows = read_year_rows(input, 1, &RowErrorPolicy::Fail).unwrap();
+        assert_eq!(exclude.retain_year_rows(&mut rows, 0), 2);
+        let top = crate::eda_statistics::find_top_countries(&rows, 0, 2, 1, crate::eda_statistics::RankOrder::Highest).unwrap();
+        assert_eq!(top[&2015], vec![("A".to_string(), 70.0)]);
+
+        let cancel = crate::cancel::CancellationToken::new();
+        let (graph, _) = crate::graph::build_similarity_graph(input, &[2, 3], &exclude, -1.0, None, None, &cancel).unwrap();
+        let nodes: Vec<&str> = graph.node_weights().map(String::as_str).collect();
+        assert_eq!(nodes, vec!["A", "B"]);
+
+        let mut records = vec![record("World", None), record("A", None)];
+        assert_eq!(exclude.retain_records(&mut records), 1);
+    }
+
+    #[test]
+    fn countries_with_one_row_are_listed_by_name() {
+        let row = |country: &str, year: u16| LifeExpectancyRecord { country: country.to_string(), year, ..Default::default() };
+        let records = [row("Tuvalu", 2013), row("Chad", 2000), row("Chad", 2001), row("Niue", 2013)];
+        assert_eq!(single_row_countries(&records), vec!["Niue", "Tuvalu"]);
+        assert!(single_row_countries(&records[1..3]).is_empty());
+    }
+
+    #[test]
+    fn a_corrupt_row_goes_to_the_rejects_file() {
+        let dir = crate::test_support::scratch_dir("rejects");
+        let (input, cleaned, rejects) = (dir.join("input.csv"), dir.join("cleaned.csv"), dir.join("rejects.csv"));
+        let raw = input.to_str().unwrap();
+        // Row 4 (the third data row) gets a year that does not parse
+        let mut lines: Vec<String> = std::fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().take(7).map(String::from).collect();
+        let mut fields: Vec<String> = lines[3].split(',').map(String::from).collect();
+        fields[1] = "20x1".to_string();
+        lines[3] = fields.join(",");
+        std::fs::write(&input, lines.join("\n") + "\n").unwrap();
+
+        let run = RunMetadata::new(raw, "none").unwrap();
+        let policy = RowErrorPolicy::SkipAndLog(rejects.to_str().unwrap().to_string());
+        let report = clean_data(raw, cleaned.to_str().unwrap(), &policy, &CleaningOptions::default(), &run).unwrap();
+        assert_eq!((report.rows_read, report.rows_written, report.rejected), (6, 5, 1));
+        assert_eq!(load_cleaned_data(cleaned.to_str().unwrap(), &RowErrorPolicy::Fail).unwrap().len(), 5);
+
+        let logged: Vec<StringRecord> = ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .from_path(&rejects)
+            .unwrap()
+            .records()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(logged.len(), 1);
+        assert_eq!(&logged[0][0], "4");
+        assert!(logged[0][1].contains("invalid digit"), "{}", &logged[0][1]);
+        assert_eq!(logged[0].iter().skip(2).collect::<Vec<_>>(), fields);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::format::group_thousands;
+use crate::metadata::RunMetadata;
+use crate::output;
+use crate::table::{Cell, Table, TableOptions};
+
+// One file a run produced, recorded when its writer committed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Artifact {
+    pub(crate) path: String, // as written, relative to the working directory
+    pub(crate) kind: String,
+    pub(crate) bytes: u64,
+    pub(crate) sha256: String,
+    pub(crate) step: String,
+    pub(crate) elapsed_seconds: f64, // since the producing step started
+    // Kept from an earlier run whose step had the same inputs, not written again
+    #[serde(default)]
+    pub(crate) cached: bool,
+}
+
+// What a run recorded: its artifacts and the input key of each step (see
+// declare_inputs), which a later run compares its own keys with
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Manifest {
+    pub(crate) artifacts: Vec<Artifact>,
+    #[serde(default)]
+    pub(crate) inputs: BTreeMap<String, String>,
+}
+
+// Artifacts of the run so far; None while nothing is being recorded, so the
+// subcommands keep writing without a manifest
+struct Recorder {
+    step: String,
+    started: Instant,
+    artifacts: Vec<Artifact>,
+    inputs: BTreeMap<String, String>,
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+// SHA-256 of some bytes as lowercase hex
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Key of everything a step's outputs are made from, one entry per line
+pub(crate) fn input_key(inputs: &[String]) -> String {
+    sha256_hex(inputs.join("\n").as_bytes())
+}
+
+// Kind of an artifact from its file name
+fn kind(path: &Path) -> &'static str {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.ends_with(".chart.json") {
+        return "chart-json";
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => "image",
+        Some("csv") => "csv",
+        Some("json") => "json",
+        Some("md") => "markdown",
+        Some("dot") => "graphviz",
+        _ => "file",
+    }
+}
+
+// Record every committed output from now on, under `step` until the next call
+pub(crate) fn begin_step(step: &str) {
+    if let Ok(mut recorder) = RECORDER.lock() {
+        let (artifacts, inputs) = recorder.take().map(|recorder| (recorder.artifacts, recorder.inputs)).unwrap_or_default();
+        *recorder = Some(Recorder { step: step.to_string(), started: Instant::now(), artifacts, inputs });
+    }
+}
+
+// Store the input key of the current step in the manifest
+pub(crate) fn declare_inputs(key: &str) {
+    if let Ok(mut guard) = RECORDER.lock() {
+        if let Some(recorder) = guard.as_mut() {
+            recorder.inputs.insert(recorder.step.clone(), key.to_string());
+        }
+    }
+}
+
+// List the outputs an earlier run left for the current step as this run's
+pub(crate) fn reuse(artifacts: Vec<Artifact>) {
+    if let Ok(mut guard) = RECORDER.lock() {
+        if let Some(recorder) = guard.as_mut() {
+            recorder.artifacts.retain(|kept| artifacts.iter().all(|artifact| artifact.path != kept.path));
+            recorder.artifacts.extend(artifacts);
+        }
+    }
+}
+
+// List a file that was just committed at `path`; a file written again replaces
+// its earlier entry. Does nothing while not recording.
+pub(crate) fn record(path: &Path) -> io::Result<()> {
+    let Ok(mut guard) = RECORDER.lock() else {
+        return Ok(());
+    };
+    let Some(recorder) = guard.as_mut() else {
+        return Ok(());
+    };
+    let bytes = fs::read(path)?;
+    let display = path.display().to_string();
+    recorder.artifacts.retain(|artifact| artifact.path != display);
+    recorder.artifacts.push(Artifact {
+        path: display,
+        kind: kind(path).to_string(),
+        bytes: bytes.len() as u64,
+        sha256: sha256_hex(&bytes),
+        step: recorder.step.clone(),
+        elapsed_seconds: (recorder.started.elapsed().as_secs_f64() * 1000.0).round() / 1000.0,
+        cached: false,
+    });
+    Ok(())
+}
+
+// Stop recording and return what was recorded, artifacts in the order they were written
+pub(crate) fn finish() -> Manifest {
+    RECORDER
+        .lock()
+        .ok()
+        .and_then(|mut recorder| recorder.take())
+        .map(|recorder| Manifest { artifacts: recorder.artifacts, inputs: recorder.inputs })
+        .unwrap_or_default()
+}
+
+impl Manifest {
+    // Manifest an earlier run wrote to `path`; empty when there is none or it
+    // cannot be read, so every step runs
+    pub(crate) fn load(path: &str) -> Manifest {
+        fs::read_to_string(path).ok().and_then(|text| serde_json::from_str(&text).ok()).unwrap_or_default()
+    }
+
+    // Outputs `step` wrote when its input key was `key`, marked cached; None when
+    // the key differs or one of them is gone or no longer holds what was written
+    pub(crate) fn cached(&self, step: &str, key: &str) -> Option<Vec<Artifact>> {
+        if self.inputs.get(step).map(String::as_str) != Some(key) {
+            return None;
+        }
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.step == step)
+            .map(|artifact| {
+                let bytes = fs::read(&artifact.path).ok()?;
+                (sha256_hex(&bytes) == artifact.sha256).then(|| Artifact { cached: true, ..artifact.clone() })
+            })
+            .collect()
+    }
+}
+
+// Files and bytes per step, in the order the steps first wrote; a step whose
+// files all come from an earlier run is marked cached
+pub(crate) fn print_manifest(artifacts: &[Artifact], options: &TableOptions) {
+    let mut steps: Vec<&str> = Vec::new();
+    let mut totals: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for artifact in artifacts {
+        if !steps.contains(&artifact.step.as_str()) {
+            steps.push(&artifact.step);
+        }
+        let total = totals.entry(&artifact.step).or_default();
+        (total.0, total.1) = (total.0 + 1, total.1 + artifact.bytes);
+    }
+    let bytes: u64 = artifacts.iter().map(|artifact| artifact.bytes).sum();
+    let cached = artifacts.iter().filter(|artifact| artifact.cached).count();
+    println!(
+        "Run produced {} files ({} bytes, {} cached):",
+        artifacts.len(),
+        group_thousands(&bytes.to_string()),
+        cached
+    );
+    let mut table = Table::new(&["Step", "Files", "Bytes"]);
+    for step in steps {
+        let (files, bytes) = totals[step];
+        let all_cached = artifacts.iter().filter(|artifact| artifact.step == step).all(|artifact| artifact.cached);
+        let label = if all_cached { format!("{} (cached)", step) } else { step.to_string() };
+        table = table.add_row(vec![
+            Cell::text(label),
+            Cell::count(files),
+            Cell::formatted(Some(bytes as f64), group_thousands(&bytes.to_string())),
+        ]);
+    }
+    table.print(options);
+}
+
+#[derive(Serialize)]
+struct ManifestJson<'a> {
+    run: &'a RunMetadata,
+    artifacts: &'a [Artifact],
+    inputs: &'a BTreeMap<String, String>,
+}
+
+// The artifacts and step inputs with the run's provenance as JSON; the manifest
+// does not list itself
+pub(crate) fn export_manifest(manifest: &Manifest, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let (artifacts, inputs) = (&manifest.artifacts, &manifest.inputs);
+    let json = serde_json::to_vec_pretty(&ManifestJson { run, artifacts, inputs })?;
+    output::atomic_write(output_file, json)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::AtomicTarget;
+
+    // Files under `dir`, as the paths they were written with
+    fn files_in(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path().display().to_string()).collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn the_manifest_lists_exactly_the_files_written() {
+        let _recording = crate::test_support::RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = crate::test_support::scratch_dir("manifest");
+        let path = |name: &str| dir.join(name).display().to_string();
+        finish();
+
+        begin_step("clean");
+        output::atomic_write(path("cleaned_data.csv"), "Country,Year\nA,2000\n").unwrap();
+        output::cache_write(path("cache.json"), "{}").unwrap();
+        declare_inputs("clean-key");
+        begin_step("graph");
+        output::atomic_write(path("graph.dot"), "graph {}\n").unwrap();
+        output::atomic_write(path("plot.chart.json"), "{}").unwrap();
+        // A file written again keeps one entry; an uncommitted one is never listed
+        output::atomic_write(path("cleaned_data.csv"), "Country,Year\nA,2000\nB,2001\n").unwrap();
+        let abandoned = AtomicTarget::new(path("abandoned.png")).unwrap();
+        fs::write(abandoned.tmp_path(), "partial").unwrap();
+        drop(abandoned);
+        let manifest = finish();
+
+        let prefix = dir.display().to_string();
+        let mut listed: Vec<&Artifact> = manifest.artifacts.iter().filter(|artifact| artifact.path.starts_with(&prefix)).collect();
+        let mut unlisted = vec![path("cache.json")];
+        let mut expected = files_in(&dir);
+        expected.retain(|file| !unlisted.contains(file));
+        listed.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(listed.iter().map(|artifact| artifact.path.clone()).collect::<Vec<_>>(), expected);
+        for artifact in &listed {
+            let bytes = fs::read(&artifact.path).unwrap();
+            assert_eq!((artifact.bytes, artifact.sha256.as_str()), (bytes.len() as u64, sha256_hex(&bytes).as_str()));
+            assert!(!artifact.cached);
+        }
+        let kinds: Vec<(&str, &str)> = listed.iter().map(|artifact| (artifact.kind.as_str(), artifact.step.as_str())).collect();
+        assert_eq!(kinds, vec![("csv", "graph"), ("graphviz", "graph"), ("chart-json", "graph")]);
+        assert_eq!(manifest.inputs.get("clean").map(String::as_str), Some("clean-key"));
+
+        // The manifest file itself is written last and is not listed
+        let run = RunMetadata::new("Life Expectancy Data.csv", "none").unwrap();
+        export_manifest(&manifest, &path("manifest.json"), &run).unwrap();
+        let loaded = Manifest::load(&path("manifest.json"));
+        assert_eq!(loaded.artifacts.len(), manifest.artifacts.len());
+        unlisted.push(path("manifest.json"));
+        assert_eq!(files_in(&dir).len(), listed.len() + unlisted.len());
+    }
+
+    #[test]
+    fn cached_outputs_need_the_same_key_and_unchanged_files() {
+        let dir = crate::test_support::scratch_dir("manifest-cached");
+        let file = dir.join("findings.md");
+        fs::write(&file, "# Findings\n").unwrap();
+        let artifact = Artifact {
+            path: file.display().to_string(),
+            kind: "markdown".into(),
+            bytes: 11,
+            sha256: sha256_hex(b"# Findings\n"),
+            step: "findings".into(),
+            elapsed_seconds: 0.0,
+            cached: false,
+        };
+        let manifest = Manifest { artifacts: vec![artifact], inputs: BTreeMap::from([("findings".to_string(), "key".to_string())]) };
+
+        let cached = manifest.cached("findings", "key").unwrap();
+        assert!(cached.len() == 1 && cached[0].cached);
+        assert!(manifest.cached("findings", "other").is_none());
+        assert!(manifest.cached("report", "key").is_none());
+        fs::write(&file, "# Edited\n").unwrap();
+        assert!(manifest.cached("findings", "key").is_none());
+        assert_eq!(input_key(&["a".into(), "b".into()]), sha256_hex(b"a\nb"));
+        assert_eq!(kind(Path::new("out/plot.png")), "image");
+    }
+}
@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "plots")]
+use plotters::prelude::*;
+use serde::Serialize;
+use crate::config::RandomConfig;
+use crate::workspace::file_hash;
+
+/// Provenance of one run, stamped into every chart, CSV and report it produces
+#[derive(Debug, Clone, Serialize)]
+pub struct RunMetadata {
+    pub(crate) input_hash: String,    // SHA-256 of the input CSV
+    pub(crate) timestamp: String,     // run start, UTC, ISO 8601
+    pub(crate) crate_version: String, // version of this program
+    pub(crate) config: String,        // config file and its hash, or "defaults"
+    pub(crate) seed: u64,             // seed of the random steps (see config::RandomConfig)
+    #[serde(skip)]
+    pub(crate) chart_json: bool, // write chart.json sidecars next to charts (see chart_json)
+}
+
+impl RunMetadata {
+    /// Metadata of a run over `input_file` with the config in `config_file`;
+    /// either may be missing
+    pub fn new(input_file: &str, config_file: &str) -> Result<Self, Box<dyn Error>> {
+        let input_hash = if Path::new(input_file).exists() { file_hash(input_file)? } else { "missing".to_string() };
+        let config = if Path::new(config_file).exists() {
+            format!("{}@{}", config_file, &file_hash(config_file)?[..12])
+        } else {
+            "defaults".to_string()
+        };
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(RunMetadata {
+            input_hash,
+            timestamp: iso_timestamp(seconds),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            config,
+            seed: RandomConfig::default().seed,
+            chart_json: false,
+        })
+    }
+
+    // Short single-line summary for chart footers
+    #[cfg(feature = "plots")]
+    pub(crate) fn footer(&self) -> String {
+        format!(
+            "input={} | v{} | config={} | {}",
+            &self.input_hash[..self.input_hash.len().min(12)],
+            self.crate_version,
+            self.config,
+            self.timestamp
+        )
+    }
+
+    // Comment line written above the header of generated CSV files
+    pub(crate) fn csv_comment(&self) -> String {
+        format!(
+            "# input={} version={} config={} timestamp={}\n",
+            self.input_hash, self.crate_version, self.config, self.timestamp
+        )
+    }
+}
+
+// UTC "YYYY-MM-DDTHH:MM:SSZ" for seconds since the Unix epoch
+fn iso_timestamp(seconds: u64) -> String {
+    let (days, rem) = (seconds / 86_400, seconds % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+// Small grey provenance line along the bottom edge of a chart
+#[cfg(feature = "plots")]
+pub(crate) fn draw_footer<DB: DrawingBackend>(
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let (_, height) = area.dim_in_pixel();
+    let style = ("sans-serif", 11).into_font().color(&RGBColor(140, 140, 140));
+    area.draw(&Text::new(run.footer(), (6, height as i32 - 14), style))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn identical_inputs_get_identical_hashes() {
+        let dir = scratch_dir("metadata-hash");
+        let (a, b) = (dir.join("a.csv"), dir.join("b.csv"));
+        std::fs::write(&a, "Country,Year\nA,2000\n").unwrap();
+        std::fs::write(&b, "Country,Year\nA,2000\n").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let first = RunMetadata::new(a, "no-config.toml").unwrap();
+        let second = RunMetadata::new(b, "no-config.toml").unwrap();
+        assert_eq!(first.input_hash, second.input_hash);
+        assert_eq!(first.input_hash.len(), 64);
+        assert_eq!(first.config, "defaults");
+
+        std::fs::write(b, "Country,Year\nA,2001\n").unwrap();
+        assert_ne!(RunMetadata::new(b, "no-config.toml").unwrap().input_hash, first.input_hash);
+        assert_eq!(RunMetadata::new(&dir.join("missing.csv").to_string_lossy(), "x").unwrap().input_hash, "missing");
+    }
+
+    #[test]
+    fn csv_comment_carries_the_provenance() {
+        let run = RunMetadata {
+            input_hash: "abc".to_string(),
+            timestamp: iso_timestamp(0),
+            crate_version: "0.1.0".to_string(),
+            config: "defaults".to_string(),
+            seed: 1,
+            chart_json: false,
+        };
+        assert_eq!(run.csv_comment(), "# input=abc version=0.1.0 config=defaults timestamp=1970-01-01T00:00:00Z\n");
+    }
+
+    #[test]
+    fn timestamps_are_utc_civil_dates() {
+        assert_eq!(iso_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+}
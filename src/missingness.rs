@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use statrs::distribution::{ChiSquared, ContinuousCDF};
+use crate::fields::LifeExpectancyRecord;
+use crate::series::field_meta;
+
+// Group of a record, e.g. its Status
+pub(crate) type GroupKey = fn(&LifeExpectancyRecord) -> String;
+// Name of a grouping and its key
+pub(crate) type Grouping = (&'static str, GroupKey);
+// Group with its (missing, present) counts
+type GroupCounts = (String, [usize; 2]);
+
+// Fields and groupings checked for values missing not at random, before imputation
+pub(crate) const MISSINGNESS_FIELDS: [&str; 3] = ["GDP", "Population", "Hepatitis B"];
+pub(crate) const MISSINGNESS_GROUPINGS: [Grouping; 2] =
+    [("Status", |record| record.status.clone()), ("Year", |record| record.year.to_string())];
+
+// Whether one field's values go missing more often in some groups than others
+#[derive(Debug, Clone)]
+pub(crate) struct MissingnessTest {
+    pub(crate) field: &'static str,
+    pub(crate) group_by: &'static str,
+    pub(crate) groups: Vec<(String, f64)>, // missing fraction per group, in group order
+    pub(crate) missing: usize,
+    pub(crate) total: usize,
+    pub(crate) chi_squared: Option<ChiSquaredTest>, // None when nothing or everything is missing
+}
+
+// Pearson's chi-squared test of independence on a contingency table
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChiSquaredTest {
+    pub(crate) statistic: f64,
+    pub(crate) df: usize,
+    pub(crate) p_value: f64,
+}
+
+// Missing and present counts of `field` per group, in group order
+fn missing_counts(records: &[LifeExpectancyRecord], field: &str, group_by: GroupKey) -> Result<Vec<GroupCounts>, Box<dyn Error>> {
+    let meta = field_meta(field)?;
+    let mut counts: BTreeMap<String, [usize; 2]> = BTreeMap::new();
+    for record in records {
+        let cell = counts.entry(group_by(record)).or_default();
+        cell[usize::from((meta.getter)(record).is_some())] += 1;
+    }
+    Ok(counts.into_iter().collect())
+}
+
+fn missing_fraction([missing, present]: [usize; 2]) -> f64 {
+    missing as f64 / (missing + present) as f64
+}
+
+// Share of records per group with no value for `field`, in group order
+pub(crate) fn missingness_by_group(
+    records: &[LifeExpectancyRecord],
+    field: &str,
+    group_by: GroupKey,
+) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    Ok(missing_counts(records, field, group_by)?
+        .into_iter()
+        .map(|(group, cell)| (group, missing_fraction(cell)))
+        .collect())
+}
+
+// Chi-squared test on rows of (missing, present) counts. Rows and columns that
+// are all zero are left out; None when fewer than two of either remain.
+pub(crate) fn chi_squared_independence(table: &[[usize; 2]]) -> Option<ChiSquaredTest> {
+    let rows: Vec<[f64; 2]> = table.iter().filter(|row| row[0] + row[1] > 0).map(|row| [row[0] as f64, row[1] as f64]).collect();
+    let column_totals = rows.iter().fold([0.0, 0.0], |totals, row| [totals[0] + row[0], totals[1] + row[1]]);
+    let total = column_totals[0] + column_totals[1];
+    if rows.len() < 2 || column_totals.contains(&0.0) {
+        return None;
+    }
+
+    let statistic = rows
+        .iter()
+        .flat_map(|row| {
+            let row_total = row[0] + row[1];
+            (0..2).map(move |j| {
+                let expected = row_total * column_totals[j] / total;
+                (row[j] - expected).powi(2) / expected
+            })
+        })
+        .sum();
+    let df = rows.len() - 1;
+    let p_value = ChiSquared::new(df as f64).ok()?.sf(statistic);
+    Some(ChiSquaredTest { statistic, df, p_value })
+}
+
+// Missing fractions of `field` per group with the chi-squared test of missingness
+// against the grouping
+pub(crate) fn missingness_test(
+    records: &[LifeExpectancyRecord],
+    field: &'static str,
+    (group_by, group): Grouping,
+) -> Result<MissingnessTest, Box<dyn Error>> {
+    let table: Vec<[usize; 2]> = missing_counts(records, field, group)?.into_iter().map(|(_, cell)| cell).collect();
+    let missing = table.iter().map(|cell| cell[0]).sum();
+    Ok(MissingnessTest {
+        field,
+        group_by,
+        groups: missingness_by_group(records, field, group)?,
+        missing,
+        total: records.len(),
+        chi_squared: chi_squared_independence(&table),
+    })
+}
+
+// Every field in MISSINGNESS_FIELDS against every grouping
+pub(crate) fn missingness_tests(records: &[LifeExpectancyRecord]) -> Result<Vec<MissingnessTest>, Box<dyn Error>> {
+    MISSINGNESS_GROUPINGS
+        .iter()
+        .flat_map(|&grouping| MISSINGNESS_FIELDS.iter().map(move |&field| missingness_test(records, field, grouping)))
+        .collect()
+}
+
+pub(crate) fn print_missingness(tests: &[MissingnessTest]) {
+    println!("Missing values by group (before imputation):");
+    for test in tests {
+        let verdict = match test.chi_squared {
+            Some(chi) => format!(
+                "chi2 = {:.1}, df = {}, p = {:.2e}{}",
+                chi.statistic,
+                chi.df,
+                chi.p_value,
+                if chi.p_value < 0.05 { " (depends on group)" } else { "" }
+            ),
+            None => "no test".to_string(),
+        };
+        println!("  {} by {}: {} of {} missing; {}", test.field, test.group_by, test.missing, test.total, verdict);
+        // The most affected groups, for the reader to see where
+        let mut worst: Vec<&(String, f64)> = test.groups.iter().filter(|(_, fraction)| *fraction > 0.0).collect();
+        worst.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let shown: Vec<String> = worst.iter().take(3).map(|(group, fraction)| format!("{} {:.1}%", group, fraction * 100.0)).collect();
+        if !shown.is_empty() {
+            println!("    most missing: {}", shown.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chi_squared_matches_a_small_table() {
+        // Expected counts 12/18 and 28/42, so chi2 = 4 * (1/12 + 1/18 + 1/28 + 1/42)
+        let test = chi_squared_independence(&[[10, 20], [30, 40]]).unwrap();
+        assert!((test.statistic - 4.0 * (1.0 / 12.0 + 1.0 / 18.0 + 1.0 / 28.0 + 1.0 / 42.0)).abs() < 1e-12);
+        assert_eq!(test.df, 1);
+        assert!((test.p_value - 0.372998).abs() < 1e-5, "{}", test.p_value);
+
+        // Empty rows are dropped; a column of zeros leaves nothing to test
+        assert_eq!(chi_squared_independence(&[[10, 20], [0, 0], [30, 40]]).unwrap().df, 1);
+        assert!(chi_squared_independence(&[[0, 20], [0, 40]]).is_none());
+        assert!(chi_squared_independence(&[[10, 20]]).is_none());
+    }
+
+    #[test]
+    fn missing_fractions_follow_the_grouping() {
+        let record = |status: &str, gdp: Option<f64>| LifeExpectancyRecord { status: status.to_string(), gdp, ..Default::default() };
+        let records = [record("Developing", None), record("Developing", None), record("Developing", Some(1.0)), record("Developed", Some(2.0))];
+        let test = missingness_test(&records, "GDP", MISSINGNESS_GROUPINGS[0]).unwrap();
+        assert_eq!(test.groups, vec![("Developed".to_string(), 0.0), ("Developing".to_string(), 2.0 / 3.0)]);
+        assert_eq!((test.missing, test.total), (2, 4));
+        assert!(test.chi_squared.is_some());
+        assert!(missingness_test(&records, "Happiness", MISSINGNESS_GROUPINGS[0]).is_err());
+    }
+}
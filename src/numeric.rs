@@ -0,0 +1,128 @@
+use std::error::Error;
+
+// Decimal separator of the numbers in an input file: a point as in the WHO data
+// ("72.3", "1,234.5"), a comma as in European exports ("72,3", "1.234,5"), or
+// Auto, chosen cell by cell from the separators it holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DecimalStyle {
+    #[default]
+    Point,
+    Comma,
+    Auto,
+}
+
+impl DecimalStyle {
+    // From its command-line name ("point", "comma" or "auto")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "point" => Ok(DecimalStyle::Point),
+            "comma" => Ok(DecimalStyle::Comma),
+            "auto" => Ok(DecimalStyle::Auto),
+            other => Err(format!("Unknown decimal style \"{}\" (expected point, comma or auto)", other).into()),
+        }
+    }
+}
+
+// Number written with `decimal` as its decimal separator; `grouping` may only
+// split the integer part into groups of three digits after a first group of one
+// to three without a leading zero
+fn parse_with(text: &str, decimal: char, grouping: char) -> Option<f64> {
+    let (integer, fraction) = match text.find(decimal) {
+        Some(at) => (&text[..at], Some(&text[at + decimal.len_utf8()..])),
+        None => (text, None),
+    };
+    let mut plain = integer.to_string();
+    if integer.contains(grouping) {
+        let groups: Vec<&str> = integer.trim_start_matches(['-', '+']).split(grouping).collect();
+        let grouped = groups.iter().enumerate().all(|(i, group)| {
+            let sizes = if i == 0 { 1..=3 } else { 3..=3 };
+            let leading_zero = i == 0 && group.starts_with('0');
+            sizes.contains(&group.len()) && !leading_zero && group.bytes().all(|b| b.is_ascii_digit())
+        });
+        if !grouped {
+            return None;
+        }
+        plain = integer.replace(grouping, "");
+    }
+    if let Some(fraction) = fraction {
+        if fraction.contains(grouping) {
+            return None;
+        }
+        plain = format!("{}.{}", plain, fraction);
+    }
+    plain.parse().ok()
+}
+
+// Number in `text` under `style`, with surrounding whitespace allowed. The flag
+// is set when Auto had to guess, on a single separator followed by exactly three
+// digits: "1,234" is read as 1234 and "1.234" as 1.234, though each may mean the
+// other. Otherwise Auto takes the last of two different separators as the decimal
+// one, a lone separator followed by any other number of digits as decimal, and a
+// repeated one as grouping.
+pub(crate) fn parse_numeric(text: &str, style: DecimalStyle) -> Option<(f64, bool)> {
+    let text = text.trim();
+    match style {
+        DecimalStyle::Point => parse_with(text, '.', ',').map(|value| (value, false)),
+        DecimalStyle::Comma => parse_with(text, ',', '.').map(|value| (value, false)),
+        DecimalStyle::Auto => match (text.rfind('.'), text.rfind(',')) {
+            (Some(point), Some(comma)) if comma > point => parse_with(text, ',', '.').map(|value| (value, false)),
+            (None, Some(comma)) if text.matches(',').count() == 1 => {
+                let (integer, digits) = (&text[..comma], &text[comma + 1..]);
+                let unsigned = integer.trim_start_matches(['-', '+']);
+                // "0,123" cannot be grouping
+                if digits.len() == 3 && unsigned != "0" {
+                    parse_with(text, '.', ',').map(|value| (value, true))
+                } else {
+                    parse_with(text, ',', '.').map(|value| (value, false))
+                }
+            }
+            // "1.234.567" can only be grouping
+            (Some(_), None) if text.matches('.').count() > 1 => parse_with(text, ',', '.').map(|value| (value, false)),
+            (Some(point), None) => {
+                let (integer, digits) = (&text[..point], &text[point + 1..]);
+                let guess = digits.len() == 3 && integer.trim_start_matches(['-', '+']) != "0";
+                parse_with(text, '.', ',').map(|value| (value, guess))
+            }
+            _ => parse_with(text, '.', ',').map(|value| (value, false)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cell and its reading as Point, as Comma, and as Auto (value, guessed)
+    type Case = (&'static str, Option<f64>, Option<f64>, Option<(f64, bool)>);
+
+    #[test]
+    fn each_style_reads_the_same_cells_its_own_way() {
+        let cases: [Case; 8] = [
+            ("1.234,56", None, Some(1234.56), Some((1234.56, false))),
+            ("72,3", None, Some(72.3), Some((72.3, false))),
+            ("1,234", Some(1234.0), Some(1.234), Some((1234.0, true))),
+            ("1.234", Some(1.234), Some(1234.0), Some((1.234, true))),
+            ("1,234.5", Some(1234.5), None, Some((1234.5, false))),
+            ("0,123", None, Some(0.123), Some((0.123, false))),
+            ("1.234.567", None, Some(1234567.0), Some((1234567.0, false))),
+            (" -72,25 ", None, Some(-72.25), Some((-72.25, false))),
+        ];
+        for (cell, point, comma, auto) in cases {
+            let value = |style| parse_numeric(cell, style).map(|(value, _)| value);
+            assert_eq!(value(DecimalStyle::Point), point, "{} as Point", cell);
+            assert_eq!(value(DecimalStyle::Comma), comma, "{} as Comma", cell);
+            assert_eq!(parse_numeric(cell, DecimalStyle::Auto), auto, "{} as Auto", cell);
+        }
+    }
+
+    #[test]
+    fn grouping_must_be_in_threes() {
+        assert_eq!(parse_numeric("12,34,567", DecimalStyle::Point), None);
+        assert_eq!(parse_numeric("012,345", DecimalStyle::Point), None);
+        assert_eq!(parse_numeric("1,234,567", DecimalStyle::Point), Some((1234567.0, false)));
+        assert_eq!(parse_numeric("", DecimalStyle::Auto), None);
+        assert_eq!(parse_numeric("n/a", DecimalStyle::Comma), None);
+        assert_eq!(DecimalStyle::from_name("auto").unwrap(), DecimalStyle::Auto);
+        assert!(DecimalStyle::from_name("dot").unwrap_err().to_string().contains("expected point, comma or auto"));
+    }
+}
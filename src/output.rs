@@ -0,0 +1,519 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::format::{format_float, group_thousands};
+use crate::manifest;
+use crate::metadata::RunMetadata;
+
+// Failure to prepare or write an output file, with the absolute path involved
+#[derive(Debug)]
+pub(crate) struct OutputError {
+    pub(crate) path: PathBuf,
+    pub(crate) source: io::Error,
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source.kind() {
+            io::ErrorKind::PermissionDenied => write!(f, "Permission denied writing {}", self.path.display()),
+            _ => write!(f, "Cannot write {}: {}", self.path.display(), self.source),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Absolute form of a path, without requiring it to exist
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Creates the missing parent directories of an output file
+pub(crate) fn ensure_parent_dir(path: impl AsRef<Path>) -> Result<(), OutputError> {
+    let path = path.as_ref();
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent).map_err(|source| OutputError {
+            path: absolute(parent),
+            source,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Canonical path of a written file for success messages
+pub(crate) fn display_path(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    fs::canonicalize(path).unwrap_or_else(|_| absolute(path)).display().to_string()
+}
+
+// Temporary sibling of an output file that replaces the real file only on commit.
+// Dropping it uncommitted (an early `?` return, a panic) deletes the temporary, so
+// an interrupted run never leaves a truncated artifact at the real path.
+pub(crate) struct AtomicTarget {
+    path: PathBuf,
+    tmp: PathBuf,
+    committed: Cell<bool>,
+    listed: bool, // whether the committed file goes into the run manifest
+}
+
+impl AtomicTarget {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Result<Self, OutputError> {
+        let path = path.as_ref().to_path_buf();
+        ensure_parent_dir(&path)?;
+        // "name.tmp.ext" rather than "name.ext.tmp": plotters picks the image format
+        // from the extension
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let tmp_name = match path.extension() {
+            Some(ext) => format!("{}.tmp.{}", stem, ext.to_string_lossy()),
+            None => format!("{}.tmp", stem),
+        };
+        let tmp = path.with_file_name(tmp_name);
+        Ok(AtomicTarget { path, tmp, committed: Cell::new(false), listed: true })
+    }
+
+    // Target left out of the run manifest, for cache files that are not outputs
+    pub(crate) fn unlisted(path: impl AsRef<Path>) -> Result<Self, OutputError> {
+        let mut target = AtomicTarget::new(path)?;
+        target.listed = false;
+        Ok(target)
+    }
+
+    // Where the content should be written before commit
+    pub(crate) fn tmp_path(&self) -> &Path {
+        &self.tmp
+    }
+
+    // Moves the finished temporary into place. Takes &self so a plot backend can
+    // still borrow tmp_path() when the target is committed.
+    pub(crate) fn commit(&self) -> Result<(), OutputError> {
+        fs::rename(&self.tmp, &self.path).map_err(|source| OutputError {
+            path: absolute(&self.path),
+            source,
+        })?;
+        self.committed.set(true);
+        if self.listed {
+            manifest::record(&self.path).map_err(|source| OutputError { path: absolute(&self.path), source })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicTarget {
+    fn drop(&mut self) {
+        if !self.committed.get() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+// Writes bytes to path through a temporary sibling
+pub(crate) fn atomic_write(path: impl AsRef<Path>, bytes: impl AsRef<[u8]>) -> Result<(), OutputError> {
+    write_target(AtomicTarget::new(path)?, bytes)
+}
+
+// atomic_write for a cache file, which the run manifest does not list
+pub(crate) fn cache_write(path: impl AsRef<Path>, bytes: impl AsRef<[u8]>) -> Result<(), OutputError> {
+    write_target(AtomicTarget::unlisted(path)?, bytes)
+}
+
+fn write_target(target: AtomicTarget, bytes: impl AsRef<[u8]>) -> Result<(), OutputError> {
+    fs::write(target.tmp_path(), bytes).map_err(|source| OutputError {
+        path: absolute(target.tmp_path()),
+        source,
+    })?;
+    target.commit()
+}
+
+// Buffered writer into a temporary sibling; `finish` publishes the file, dropping
+// the writer without finishing discards it
+pub(crate) struct AtomicWriter {
+    file: BufWriter<File>,
+    target: AtomicTarget,
+}
+
+pub(crate) fn atomic_writer(path: impl AsRef<Path>) -> Result<AtomicWriter, OutputError> {
+    let target = AtomicTarget::new(path)?;
+    let file = File::create(target.tmp_path()).map_err(|source| OutputError {
+        path: absolute(target.tmp_path()),
+        source,
+    })?;
+    Ok(AtomicWriter { file: BufWriter::new(file), target })
+}
+
+impl AtomicWriter {
+    pub(crate) fn finish(mut self) -> Result<(), OutputError> {
+        self.file.flush().map_err(|source| OutputError {
+            path: absolute(self.target.tmp_path()),
+            source,
+        })?;
+        self.target.commit()
+    }
+}
+
+impl Write for AtomicWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Atomic writer for a generated CSV, starting with the run's provenance comment
+pub(crate) fn stamped_writer(path: &str, run: Option<&RunMetadata>) -> Result<AtomicWriter, Box<dyn Error>> {
+    let mut writer = atomic_writer(path)?;
+    if let Some(run) = run {
+        writer.write_all(run.csv_comment().as_bytes())?;
+    }
+    Ok(writer)
+}
+
+// Whether a CSV export replaces the file or adds rows to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ExportMode {
+    #[default]
+    Overwrite,
+    Append, // keep existing rows; the header is written only when creating the file
+}
+
+// How many rows an export writes and how often they reach the disk
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RowLimit {
+    pub(crate) max_rows: Option<usize>, // rows past it are counted but not written
+    pub(crate) flush_every: Option<usize>, // None: whenever the write buffer fills
+}
+
+// How a CSV export is written: mode, constant columns (e.g. Year=2005) added to
+// every row after the exporter's own columns, decimals of computed values, and
+// the row limit
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ExportOptions<'a> {
+    pub(crate) mode: ExportMode,
+    pub(crate) extra_columns: &'a [(String, String)],
+    pub(crate) precision: Option<usize>, // None: shortest exact representation
+    pub(crate) limit: RowLimit,
+}
+
+impl ExportOptions<'_> {
+    // Exporter's own columns followed by the extra column names
+    pub(crate) fn header(&self, columns: &[&str]) -> Vec<String> {
+        let mut header: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+        header.extend(self.extra_columns.iter().map(|(name, _)| name.clone()));
+        header
+    }
+
+    pub(crate) fn extra_values(&self) -> impl Iterator<Item = &str> {
+        self.extra_columns.iter().map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn format_float(&self, value: f64) -> String {
+        format_float(value, self.precision)
+    }
+}
+
+// Fields joined into one CSV line without its terminator, quoted where needed
+fn csv_line(fields: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new().terminator(csv::Terminator::Any(b'\n')).from_writer(Vec::new());
+    writer.write_record(fields)?;
+    let mut line = String::from_utf8(writer.into_inner()?)?;
+    line.pop();
+    Ok(line)
+}
+
+// CSV writer for the rows of an export opened with `export_writer`
+pub(crate) fn row_writer(writer: AtomicWriter) -> csv::Writer<AtomicWriter> {
+    csv::WriterBuilder::new().has_headers(false).from_writer(writer)
+}
+
+// Rows an export wrote, and those it left out past RowLimit::max_rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExportSummary {
+    pub(crate) written: usize,
+    pub(crate) omitted: usize,
+}
+
+impl ExportSummary {
+    // Warning for a truncated export of `path`; None when every row was written
+    pub(crate) fn warning(&self, path: impl AsRef<Path>) -> Option<String> {
+        (self.omitted > 0).then(|| {
+            format!(
+                "Warning: {} truncated to {} of {} rows (--max-output-rows)",
+                display_path(path),
+                group_thousands(&self.written.to_string()),
+                group_thousands(&(self.written + self.omitted).to_string())
+            )
+        })
+    }
+}
+
+// Streams the rows of an export one at a time, so no export holds more than the
+// write buffer in memory. Rows past the limit are only counted; with `marked`, a
+// truncated file ends with a '#' comment saying how many rows it left out.
+pub(crate) struct RowSink {
+    writer: csv::Writer<AtomicWriter>,
+    limit: RowLimit,
+    marked: bool,
+    written: usize,
+    omitted: usize,
+}
+
+impl RowSink {
+    pub(crate) fn new(writer: AtomicWriter, limit: RowLimit, marked: bool) -> Self {
+        RowSink { writer: row_writer(writer), limit, marked, written: 0, omitted: 0 }
+    }
+
+    pub(crate) fn write<I, T>(&mut self, row: I) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        if self.limit.max_rows.is_some_and(|max| self.written >= max) {
+            self.omitted += 1;
+            return Ok(());
+        }
+        self.writer.write_record(row)?;
+        self.written += 1;
+        if self.limit.flush_every.is_some_and(|every| self.written.is_multiple_of(every)) {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Publish the file, with the truncation comment when rows were left out
+    pub(crate) fn finish(self) -> Result<ExportSummary, Box<dyn Error>> {
+        let summary = ExportSummary { written: self.written, omitted: self.omitted };
+        let mut file = self.writer.into_inner()?;
+        if self.marked && summary.omitted > 0 {
+            writeln!(
+                file,
+                "{} {} of {} rows written, max_output_rows={}",
+                TRUNCATION_MARKER,
+                summary.written,
+                summary.written + summary.omitted,
+                self.limit.max_rows.unwrap_or_default()
+            )?;
+        }
+        file.finish()?;
+        Ok(summary)
+    }
+}
+
+impl ExportOptions<'_> {
+    // Row sink for an export opened with `export_writer`, under these options' limit
+    pub(crate) fn rows(&self, writer: AtomicWriter) -> RowSink {
+        RowSink::new(writer, self.limit, true)
+    }
+}
+
+// Header line of an existing CSV, skipping the leading '#' comment lines; only
+// the lines up to it are read
+fn existing_header(path: &Path) -> io::Result<Option<String>> {
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.starts_with('#') {
+            return Ok(Some(line.trim_end().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+// Start of the comment RowSink::finish ends a truncated export with
+const TRUNCATION_MARKER: &str = "# truncated:";
+
+// Whether an existing CSV ends with the truncation comment of a cut-short export
+fn ends_truncated(path: &Path) -> io::Result<bool> {
+    let mut last = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    Ok(last.is_some_and(|line| line.starts_with(TRUNCATION_MARKER)))
+}
+
+// Whether a non-empty file's last byte is a newline
+fn ends_with_newline(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    if file.seek(SeekFrom::End(0))? == 0 {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0];
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
+
+// Atomic writer for a CSV export positioned after its header. A new file (or
+// Overwrite) starts with the run's provenance comment, `comments` and the header.
+// Append to an existing file copies its contents over as a stream, and fails if
+// its header is not `header`, so rows with a different layout never land in the
+// same file. It also fails on a file cut short by an earlier --max-output-rows,
+// whose missing rows the appended ones would hide.
+pub(crate) fn export_writer(
+    path: &str,
+    header: &[String],
+    comments: &[String],
+    mode: ExportMode,
+    run: Option<&RunMetadata>,
+) -> Result<AtomicWriter, Box<dyn Error>> {
+    let header_line = csv_line(header)?;
+    if mode == ExportMode::Append && Path::new(path).exists() {
+        let read_error = |source| OutputError { path: absolute(Path::new(path)), source };
+        let found = existing_header(Path::new(path)).map_err(read_error)?.unwrap_or_default();
+        if found != header_line {
+            return Err(format!(
+                "Cannot append to {}: its header \"{}\" does not match \"{}\"",
+                display_path(path),
+                found,
+                header_line
+            )
+            .into());
+        }
+        if ends_truncated(Path::new(path)).map_err(read_error)? {
+            return Err(format!(
+                "Cannot append to {}: it was truncated by --max-output-rows; overwrite it instead",
+                display_path(path)
+            )
+            .into());
+        }
+        let mut writer = atomic_writer(path)?;
+        io::copy(&mut File::open(path).map_err(read_error)?, &mut writer)?;
+        if !ends_with_newline(Path::new(path)).map_err(read_error)? {
+            writer.write_all(b"\n")?;
+        }
+        return Ok(writer);
+    }
+
+    let mut writer = stamped_writer(path, run)?;
+    for comment in comments {
+        writeln!(writer, "# {}", comment)?;
+    }
+    writeln!(writer, "{}", header_line)?;
+    Ok(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn header() -> Vec<String> {
+        vec!["Country".to_string(), "Value".to_string()]
+    }
+
+    // One export of `rows` to `path` under `mode` and `limit`
+    fn export(path: &str, rows: &[[&str; 2]], mode: ExportMode, limit: RowLimit) -> Result<ExportSummary, Box<dyn Error>> {
+        let writer = export_writer(path, &header(), &[], mode, None)?;
+        let mut sink = RowSink::new(writer, limit, true);
+        for row in rows {
+            sink.write(row)?;
+        }
+        sink.finish()
+    }
+
+    #[test]
+    fn writes_create_missing_parent_directories() {
+        let path = scratch_dir("nested-output").join("a/b/c/out.csv");
+        assert!(!path.parent().unwrap().exists());
+        atomic_write(&path, "x\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x\n");
+    }
+
+    #[test]
+    fn a_blocked_parent_directory_is_reported_with_its_path() {
+        let dir = scratch_dir("blocked-output");
+        fs::write(dir.join("file"), "").unwrap();
+        let error = atomic_write(dir.join("file/out.csv"), "x").unwrap_err();
+        assert!(error.path.ends_with("file"), "{}", error);
+        assert!(error.to_string().starts_with("Cannot write "));
+    }
+
+    #[test]
+    fn an_interrupted_write_leaves_the_old_file() {
+        let path = scratch_dir("interrupted-write").join("out.csv");
+        atomic_write(&path, "old\n").unwrap();
+
+        let failing = || -> Result<(), Box<dyn Error>> {
+            let mut writer = atomic_writer(&path)?;
+            writer.write_all(b"new, partial")?;
+            Err("failed halfway".into())
+        };
+        assert!(failing().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        // Nor is the temporary left behind
+        let entries: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, vec![std::ffi::OsString::from("out.csv")]);
+
+        let mut writer = atomic_writer(&path).unwrap();
+        writer.write_all(b"new\n").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn temporaries_keep_the_extension() {
+        let target = AtomicTarget::new(scratch_dir("tmp-name").join("chart.png")).unwrap();
+        assert_eq!(target.tmp_path().file_name().unwrap(), "chart.tmp.png");
+    }
+
+    #[test]
+    fn append_adds_rows_after_the_existing_ones() {
+        let path = scratch_dir("append-rows").join("rows.csv");
+        let path = path.to_str().unwrap();
+        export(path, &[["A", "1"]], ExportMode::Append, RowLimit::default()).unwrap();
+        export(path, &[["B", "2"]], ExportMode::Append, RowLimit::default()).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "Country,Value\nA,1\nB,2\n");
+    }
+
+    #[test]
+    fn append_refuses_another_header() {
+        let path = scratch_dir("append-header").join("rows.csv");
+        let path = path.to_str().unwrap();
+        fs::write(path, "Country,Other\nA,1\n").unwrap();
+        let error = export(path, &[["B", "2"]], ExportMode::Append, RowLimit::default()).unwrap_err();
+        assert!(error.to_string().contains("does not match"));
+        assert_eq!(fs::read_to_string(path).unwrap(), "Country,Other\nA,1\n");
+    }
+
+    #[test]
+    fn append_refuses_a_truncated_export() {
+        let path = scratch_dir("append-truncated").join("rows.csv");
+        let path = path.to_str().unwrap();
+        let limit = RowLimit { max_rows: Some(1), flush_every: None };
+        let summary = export(path, &[["A", "1"], ["B", "2"]], ExportMode::Overwrite, limit).unwrap();
+        assert_eq!(summary, ExportSummary { written: 1, omitted: 1 });
+        let truncated = fs::read_to_string(path).unwrap();
+        assert_eq!(truncated, "Country,Value\nA,1\n# truncated: 1 of 2 rows written, max_output_rows=1\n");
+
+        let error = export(path, &[["C", "3"]], ExportMode::Append, RowLimit::default()).unwrap_err();
+        assert!(error.to_string().contains("truncated"));
+        assert_eq!(fs::read_to_string(path).unwrap(), truncated);
+        // Overwriting starts a complete file again
+        export(path, &[["C", "3"]], ExportMode::Overwrite, RowLimit::default()).unwrap();
+        export(path, &[["D", "4"]], ExportMode::Append, RowLimit::default()).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "Country,Value\nC,3\nD,4\n");
+    }
+
+    #[test]
+    fn names_with_commas_and_quotes_are_quoted() {
+        let path = scratch_dir("quoted-names").join("rows.csv");
+        let path = path.to_str().unwrap();
+        export(path, &[["Korea, Republic of", "1"], ["Cote d\"Ivoire", "2"]], ExportMode::Overwrite, RowLimit::default()).unwrap();
+        let written = fs::read_to_string(path).unwrap();
+        assert_eq!(written, "Country,Value\n\"Korea, Republic of\",1\n\"Cote d\"\"Ivoire\",2\n");
+
+        let mut reader = csv::Reader::from_path(path).unwrap();
+        let countries: Vec<String> = reader.records().map(|record| record.unwrap()[0].to_string()).collect();
+        assert_eq!(countries, vec!["Korea, Republic of", "Cote d\"Ivoire"]);
+        assert_eq!(csv_line(&["a,b".to_string(), "c".to_string()]).unwrap(), "\"a,b\",c");
+    }
+}
@@ -0,0 +1,166 @@
+use std::ops::Range;
+use std::thread;
+use csv::{Reader, ReaderBuilder};
+
+// Files smaller than this are parsed on one thread; below it the threads cost
+// more than they save
+pub(crate) const PARALLEL_MIN_BYTES: usize = 4 << 20;
+
+// Byte ranges of `body` split into about `parts` pieces of whole records: every
+// cut lies just after a newline outside a quoted field. Quoting follows the csv
+// crate: a quote opens a quoted field only at the start of a field, and "" inside
+// one is an escaped quote, so a newline inside quotes never becomes a cut.
+pub(crate) fn record_chunks(body: &[u8], parts: usize) -> Vec<Range<usize>> {
+    let step = (body.len() / parts.max(1)).max(1);
+    let mut cuts = vec![0];
+    let (mut quoted, mut field_start) = (false, true);
+    let mut i = 0;
+    while i < body.len() {
+        let byte = body[i];
+        if quoted {
+            if byte == b'"' {
+                if body.get(i + 1) == Some(&b'"') {
+                    i += 1;
+                } else {
+                    quoted = false;
+                }
+            }
+        } else {
+            match byte {
+                b'"' if field_start => quoted = true,
+                b',' => {
+                    field_start = true;
+                    i += 1;
+                    continue;
+                }
+                b'\n' => {
+                    field_start = true;
+                    if i + 1 - cuts[cuts.len() - 1] >= step && i + 1 < body.len() {
+                        cuts.push(i + 1);
+                    }
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            field_start = false;
+        }
+        i += 1;
+    }
+    cuts.push(body.len());
+    cuts.windows(2).filter(|cut| cut[0] < cut[1]).map(|cut| cut[0]..cut[1]).collect()
+}
+
+// Parse the records of `body` (the file after its header) with `parse`, one
+// headerless csv reader per chunk on its own thread, and concatenate the results
+// in file order. Small inputs or a single core run `parse` over the whole body.
+pub(crate) fn parse_chunks<T, F>(body: &[u8], parse: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Reader<&[u8]>) -> Vec<T> + Sync,
+{
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if threads < 2 || body.len() < PARALLEL_MIN_BYTES {
+        return parse(chunk_reader(body));
+    }
+    parse_on_threads(body, threads, parse)
+}
+
+fn chunk_reader(bytes: &[u8]) -> Reader<&[u8]> {
+    ReaderBuilder::new().has_headers(false).flexible(true).comment(Some(b'#')).from_reader(bytes)
+}
+
+// parse_chunks on `threads` chunks, whatever the size of the input
+fn parse_on_threads<T, F>(body: &[u8], threads: usize, parse: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Reader<&[u8]>) -> Vec<T> + Sync,
+{
+    let parse = &parse;
+    thread::scope(|scope| {
+        let handles: Vec<_> = record_chunks(body, threads)
+            .into_iter()
+            .map(|range| scope.spawn(move || parse(chunk_reader(&body[range]))))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("CSV parser thread panicked")).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use csv::StringRecord;
+
+    // Rows with quoted newlines, commas and escaped quotes, like free-text columns
+    fn body(rows: usize) -> Vec<u8> {
+        let mut body = String::new();
+        for i in 0..rows {
+            let note = if i % 3 == 0 { "first line\nsecond, \"\"quoted\"\" line\n" } else { "plain" };
+            body.push_str(&format!("Country {},{},\"{}\",{}.5\n", i, 2000 + i % 16, note, i));
+        }
+        body.into_bytes()
+    }
+
+    fn all_records(mut reader: Reader<&[u8]>) -> Vec<StringRecord> {
+        reader.records().map(Result::unwrap).collect()
+    }
+
+    fn sequential(body: &[u8]) -> Vec<StringRecord> {
+        all_records(chunk_reader(body))
+    }
+
+    #[test]
+    fn chunks_never_cut_a_quoted_field() {
+        let body = body(200);
+        let expected = sequential(&body);
+        assert_eq!(expected.len(), 200);
+        for parts in [1, 2, 3, 7, 64, 1000] {
+            let chunks = record_chunks(&body, parts);
+            assert_eq!(chunks.first().unwrap().start, 0);
+            assert_eq!(chunks.last().unwrap().end, body.len());
+            assert!(chunks.windows(2).all(|pair| pair[0].end == pair[1].start && body[pair[1].start - 1] == b'\n'));
+            let parsed: Vec<StringRecord> = chunks.into_iter().flat_map(|range| sequential(&body[range])).collect();
+            assert_eq!(parsed, expected, "{} parts", parts);
+        }
+        assert!(record_chunks(b"", 4).is_empty());
+    }
+
+    #[test]
+    fn parallel_parsing_matches_sequential() {
+        let small = body(5_000);
+        let expected = sequential(&small);
+        for threads in [2, 4, 9] {
+            assert_eq!(parse_on_threads(&small, threads, all_records), expected, "{} threads", threads);
+        }
+        assert_eq!(&expected[3][2], "first line\nsecond, \"quoted\" line\n");
+        // Above the size limit, on however many cores there are
+        let large = body(120_000);
+        assert!(large.len() > PARALLEL_MIN_BYTES);
+        assert_eq!(parse_chunks(&large, all_records), sequential(&large));
+    }
+
+    // cargo test --release parallel_parsing_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn parallel_parsing_benchmark() {
+        let body = body(1_200_000);
+        let start = Instant::now();
+        let one_thread = sequential(&body);
+        let sequential_time = start.elapsed();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).max(2);
+        let start = Instant::now();
+        let chunked = parse_on_threads(&body, threads, all_records);
+        let parallel_time = start.elapsed();
+        assert_eq!(chunked, one_thread);
+        println!(
+            "{} MB, {} rows: sequential {:?}, parallel {:?} on {} threads ({:.1}x)",
+            body.len() >> 20,
+            one_thread.len(),
+            sequential_time,
+            parallel_time,
+            threads,
+            sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,1446 @@
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use petgraph::Graph;
+use crate::analysis_error::AnalysisError;
+use crate::cancel::CancellationToken;
+use crate::cli::{self, CliArgs};
+use crate::config::Config;
+use crate::fields::{self, FieldMeta, LifeExpectancyRecord, FIELDS};
+use crate::format::group_thousands;
+use crate::load_clean::{self, CleaningReport, Dataset, EntityFilter, IssueKind, PartitionKey, RowErrorPolicy, StatusPolicy, YearRow};
+use crate::manifest::{Artifact, Manifest};
+use crate::metadata::RunMetadata;
+use crate::correlation::{self, CorrelationAnalysis, CorrelationMethod};
+use crate::eda_statistics::RankOrder;
+use crate::{banding, cluster_profiles, columns, eda_statistics, graph, graph_bundle, hierarchical, immunization, manifest, missingness, output, region_purity, workspace};
+#[cfg(feature = "plots")]
+use crate::{charts, dashboard, eda_plots, radar, series, spec_plots, status_gap, tile_map};
+#[cfg(feature = "plots")]
+use crate::missingness::MissingnessTest;
+use crate::output::{ExportMode, ExportOptions, ExportSummary, RowLimit};
+use crate::table::{Cell, Table, TableOptions};
+
+// One stage of the analysis. Declaration order is also the run order, so every
+// step comes after the steps it depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Step {
+    Clean,   // clean the raw file, validate and reload the cleaned records
+    Eda,     // summaries, top countries, scatter plots and status comparisons
+    Heatmap, // correlation heatmap of the raw file
+    Graph,   // similarity graph, clustering and node metrics
+    Charts,  // feature grid, stacked areas, slopegraph, country-year heatmaps
+    Specs,   // extra plots described in the config file
+    Dashboard, // one image combining the headline charts
+}
+
+impl Step {
+    pub(crate) const ALL: [Step; 7] =
+        [Step::Clean, Step::Eda, Step::Heatmap, Step::Graph, Step::Charts, Step::Specs, Step::Dashboard];
+
+    pub(crate) fn from_name(name: &str) -> Result<Step, Box<dyn Error>> {
+        Step::ALL
+            .into_iter()
+            .find(|step| step.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Step::ALL.iter().map(|step| step.name()).collect();
+                format!("Unknown step: {} (expected one of {})", name.trim(), names.join(", ")).into()
+            })
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Step::Clean => "clean",
+            Step::Eda => "eda",
+            Step::Heatmap => "heatmap",
+            Step::Graph => "graph",
+            Step::Charts => "charts",
+            Step::Specs => "specs",
+            Step::Dashboard => "dashboard",
+        }
+    }
+
+    // Steps whose results this step reads
+    pub(crate) fn dependencies(self) -> &'static [Step] {
+        match self {
+            Step::Eda | Step::Graph | Step::Charts => &[Step::Clean],
+            Step::Dashboard => &[Step::Heatmap, Step::Graph],
+            Step::Clean | Step::Heatmap | Step::Specs => &[],
+        }
+    }
+
+    // Config sections this step's outputs depend on, as text for its input key.
+    // Excluded entities and derived columns change the rows every step reads.
+    fn config_inputs(self, config: &Config) -> String {
+        let rows = format!("exclude_entities = {:?}\nderived = {:?}", config.exclude_entities, config.derived);
+        match self {
+            Step::Clean | Step::Heatmap => rows,
+            Step::Graph => format!("{}\n{:?}\n{:?}", rows, config.random, config.graph),
+            Step::Eda | Step::Charts => format!("{}\n{:?}\n{:?}", rows, config.theme, config.random),
+            Step::Specs => format!("{}\n{:?}\n{:?}", rows, config.theme, config.plots),
+            Step::Dashboard => format!("{}\n{:?}", rows, config.theme),
+        }
+    }
+}
+
+// Steps whose results the findings are drawn from, which run again whenever the
+// findings do
+pub(crate) const FINDINGS_READS: [Step; 3] = [Step::Clean, Step::Heatmap, Step::Graph];
+
+// Settings of a run that change its outputs, as text for the input keys: every
+// option but the ones choosing what runs or how the console output looks
+fn output_settings(args: &CliArgs) -> String {
+    let settings = CliArgs {
+        steps: None,
+        workspace: None,
+        keep_going: false,
+        dry_run: false,
+        force: false,
+        snapshots: None,
+        update_snapshots: false,
+        sort_by: None,
+        ..args.clone()
+    };
+    format!("{:?}", settings)
+}
+
+// Similarity graph features when --features is not given
+pub(crate) const GRAPH_FEATURES: &str = "Life expectancy, GDP, Population";
+
+// Graph features after the missing-value check, with the rows of those kept
+pub(crate) struct GraphFeatures {
+    pub(crate) columns: Vec<usize>, // kept columns, in selection order
+    pub(crate) gate: graph::FeatureGate,
+    pub(crate) rows: graph::FeatureRows, // without the rows of excluded entities
+    pub(crate) excluded: usize,          // rows left out as excluded entities
+}
+
+// The graph features (--features or GRAPH_FEATURES) that pass the missing-value
+// check of --missing-features and --max-missing, with their rows. `load` reads
+// the cells of the selected columns, which the check counts the missing ones of.
+pub(crate) fn select_graph_features(
+    headers: &[String],
+    exclude: &EntityFilter,
+    args: &CliArgs,
+    load: impl FnOnce(&[usize]) -> Result<graph::FeatureCells, Box<dyn Error>>,
+) -> Result<GraphFeatures, Box<dyn Error>> {
+    // Derived columns follow those of the file, as read_feature_cells appends them
+    let headers = &load_clean::RecordLayout::new(headers).extend_headers(headers);
+    let selection = columns::parse_selection(headers, args.features.as_deref().unwrap_or(GRAPH_FEATURES))?;
+    let policy = match args.missing_features.as_deref() {
+        Some(name) => graph::MissingFeaturePolicy::from_name(name)?,
+        None => graph::MissingFeaturePolicy::default(),
+    };
+    let limit = args.max_missing.unwrap_or(graph::MAX_MISSING_FRACTION);
+    let cells = load(&selection)?;
+    let names: Vec<String> = selection.iter().map(|&column| headers[column].clone()).collect();
+    let gate = graph::gate_missing_features(&cells, &names, exclude, limit, policy)?;
+
+    let (countries, rows) = graph::gated_feature_rows(cells, &gate);
+    let read = countries.len();
+    let rows: graph::FeatureRows = countries.into_iter().zip(rows).filter(|(country, _)| !exclude.excludes(country)).unzip();
+    Ok(GraphFeatures { columns: gate.apply(&selection), gate, excluded: read - rows.0.len(), rows })
+}
+
+// Cluster count when --clusters is not given, and the k tried when selecting it
+pub(crate) const DEFAULT_CLUSTER_COUNT: &str = "silhouette";
+pub(crate) const CLUSTER_K_RANGE: std::ops::RangeInclusive<usize> = 2..=10;
+// Quantile bands of Schooling when comparing life expectancy between statuses
+const SCHOOLING_BANDS: usize = 5;
+// Countries listed per year at each end of the life expectancy ranking
+const TOP_COUNTRIES: usize = 5;
+// Bootstrap resamples behind the status gap's interval
+#[cfg(feature = "plots")]
+const GAP_RESAMPLES: usize = 1000;
+// Correlation cells based on fewer row pairs are masked in the heatmap
+pub(crate) const HEATMAP_MIN_OBSERVATIONS: usize = 30;
+// Directory the configured plots are written to
+const PLOTS_DIR: &str = "plots";
+// Columns of the correlation heatmap
+const HEATMAP_COLUMNS: &str = "!Country, !Year, !Status";
+// Why image outputs are skipped in a build without the `plots` feature
+#[cfg(not(feature = "plots"))]
+pub(crate) const PLOTS_DISABLED: &str = "built without the plots feature";
+
+// Headers and row-level parse results of the input, gathered once for validation
+#[derive(Debug)]
+pub(crate) struct DatasetProfile {
+    pub(crate) headers: Vec<String>,
+    pub(crate) rows: usize,
+    pub(crate) bad_years: usize, // rows whose Year does not parse
+}
+
+impl DatasetProfile {
+    pub(crate) fn load(file_path: &str) -> Result<DatasetProfile, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new().comment(Some(b'#')).from_path(file_path)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(columns::canonical_header).collect();
+        let headers = load_clean::RecordLayout::new(&headers).extend_headers(&headers);
+        let year_column = columns::find_column(&headers, "Year");
+        let (mut rows, mut bad_years) = (0, 0);
+        for record in reader.records() {
+            let record = record?;
+            rows += 1;
+            let year = year_column.and_then(|column| record.get(column)).unwrap_or("");
+            if load_clean::parse_year(year).is_err() {
+                bad_years += 1;
+            }
+        }
+        Ok(DatasetProfile { headers, rows, bad_years })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+// A problem found by a dry run, before anything is rendered or written
+#[derive(Debug, Clone)]
+pub(crate) struct ValidationIssue {
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+impl ValidationIssue {
+    fn error(message: impl Into<String>) -> Self {
+        ValidationIssue { severity: Severity::Error, message: message.into() }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        ValidationIssue { severity: Severity::Warning, message: message.into() }
+    }
+}
+
+// Issues reported by one step
+pub(crate) type StepIssues = (Step, Vec<ValidationIssue>);
+
+// Whether files could be created in `dir`: the nearest existing ancestor must be
+// a directory that is not read-only
+fn writable_dir(dir: &Path) -> bool {
+    let absolute = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    absolute
+        .ancestors()
+        .find_map(|ancestor| fs::metadata(ancestor).ok())
+        .is_some_and(|meta| meta.is_dir() && !meta.permissions().readonly())
+}
+
+// Error for every name that is not a header of the input
+fn missing_columns(profile: &DatasetProfile, names: &[&str], context: &str) -> Vec<ValidationIssue> {
+    names
+        .iter()
+        .filter(|name| columns::find_column(&profile.headers, name).is_none())
+        .map(|name| ValidationIssue::error(format!("{}: column \"{}\" not found in the input", context, name)))
+        .collect()
+}
+
+// Columns the EDA step reads from the raw rows by name
+const EDA_COLUMNS: [&str; 6] = ["Country", "Year", "Status", "Life expectancy", "Adult Mortality", "infant deaths"];
+// Features whose means per status the comparison bar plot shows
+const COMPARISON_FEATURES: [&str; 6] = ["Measles", "Polio", "BMI", "Diphtheria", "Hepatitis B", "HIV/AIDS"];
+
+// Features drawn by the charts step
+const CHART_FEATURES: [&str; 11] = [
+    "Life expectancy",
+    "Adult Mortality",
+    "infant deaths",
+    "under-five deaths",
+    "Alcohol",
+    "BMI",
+    "HIV/AIDS",
+    "GDP",
+    "Income composition of resources",
+    "Schooling",
+    "Status",
+];
+
+impl Step {
+    // Problems that would make this step fail or misbehave, found without running it
+    pub(crate) fn validate(self, config: &Config, profile: &DatasetProfile, args: &CliArgs) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        match self {
+            Step::Clean => {
+                let names: Vec<&str> = ["Country", "Year", "Status"]
+                    .into_iter()
+                    .chain(FIELDS.iter().map(|meta| meta.name))
+                    .collect();
+                issues.extend(missing_columns(profile, &names, "cleaning"));
+                if profile.rows == 0 {
+                    issues.push(ValidationIssue::error("the input has no data rows"));
+                }
+                if profile.bad_years > 0 {
+                    let policy = args.row_errors.as_deref().unwrap_or("log");
+                    let message = format!("{} rows have a Year that does not parse (row policy: {})", profile.bad_years, policy);
+                    issues.push(if policy == "fail" { ValidationIssue::error(message) } else { ValidationIssue::warning(message) });
+                }
+                if let Err(error) = RowErrorPolicy::from_name(args.row_errors.as_deref().unwrap_or("log"), "rejects.csv") {
+                    issues.push(ValidationIssue::error(error.to_string()));
+                }
+                if !writable_dir(Path::new(".")) {
+                    issues.push(ValidationIssue::error("the output directory is not writable"));
+                }
+                if args.cleaning_plots && !writable_dir(Path::new("cleaning")) {
+                    issues.push(ValidationIssue::error("the cleaning plots directory is not writable"));
+                }
+                if let Some(Err(error)) = args.unify_status.as_deref().map(StatusPolicy::from_name) {
+                    issues.push(ValidationIssue::error(error.to_string()));
+                }
+                if let Some(name) = args.partition.as_deref() {
+                    if let Err(error) = PartitionKey::from_name(name) {
+                        issues.push(ValidationIssue::error(error.to_string()));
+                    } else if !writable_dir(Path::new("cleaned")) {
+                        issues.push(ValidationIssue::error("the partition directory is not writable"));
+                    }
+                }
+                #[cfg(not(feature = "plots"))]
+                if args.cleaning_plots {
+                    issues.push(ValidationIssue::warning(format!("--cleaning-plots has no effect: {}", PLOTS_DISABLED)));
+                }
+            }
+            Step::Eda => {
+                issues.extend(missing_columns(profile, &EDA_COLUMNS, "EDA"));
+                issues.extend(missing_columns(profile, &COMPARISON_FEATURES, "EDA"));
+            }
+            Step::Heatmap => {
+                if let Err(error) = columns::parse_selection(&profile.headers, HEATMAP_COLUMNS) {
+                    issues.push(ValidationIssue::error(error.to_string()));
+                }
+            }
+            Step::Graph => {
+                let selection = args.features.as_deref().unwrap_or(GRAPH_FEATURES);
+                match columns::parse_selection(&profile.headers, selection) {
+                    Ok(columns) if columns.is_empty() => issues.push(ValidationIssue::error("the feature selection is empty")),
+                    Ok(_) => {}
+                    Err(error) => issues.push(ValidationIssue::error(error.to_string())),
+                }
+                let threshold = config.graph.threshold;
+                if !(0.0..=1.0).contains(&threshold) {
+                    issues.push(ValidationIssue::error(format!("similarity threshold {} is outside [0, 1]", threshold)));
+                }
+                if let Some(Err(error)) = args.missing_features.as_deref().map(graph::MissingFeaturePolicy::from_name) {
+                    issues.push(ValidationIssue::error(error.to_string()));
+                }
+                if let Err(error) = hierarchical::ClusterCount::from_name(args.clusters.as_deref().unwrap_or(DEFAULT_CLUSTER_COUNT)) {
+                    issues.push(ValidationIssue::error(error.to_string()));
+                }
+                if let (Some(weights), Ok(columns)) = (&args.feature_weights, columns::parse_selection(&profile.headers, selection)) {
+                    if weights.len() != columns.len() {
+                        issues.push(ValidationIssue::error(format!(
+                            "{} feature weights given for {} selected features",
+                            weights.len(),
+                            columns.len()
+                        )));
+                    }
+                }
+                if args.permutations == Some(0) {
+                    issues.push(ValidationIssue::error("--permutations must be at least 1"));
+                }
+                if args.null_percentile.is_some() && args.permutations.is_none() {
+                    issues.push(ValidationIssue::warning("--null-percentile has no effect without --permutations"));
+                }
+            }
+            Step::Charts => {
+                issues.extend(missing_columns(profile, &CHART_FEATURES, "charts"));
+                #[cfg(not(feature = "plots"))]
+                issues.push(ValidationIssue::warning(format!("no charts are drawn: {}", PLOTS_DISABLED)));
+            }
+            Step::Specs => {
+                #[cfg(not(feature = "plots"))]
+                if !config.plots.is_empty() {
+                    issues.push(ValidationIssue::warning(format!("configured plots are not rendered: {}", PLOTS_DISABLED)));
+                }
+                for (i, spec) in config.plots.iter().enumerate() {
+                    issues.extend(missing_columns(profile, &spec.columns(), &format!("plot {}", i + 1)));
+                }
+                if !config.plots.is_empty() && !writable_dir(Path::new(PLOTS_DIR)) {
+                    issues.push(ValidationIssue::error("the plots directory is not writable"));
+                }
+            }
+            Step::Dashboard => {
+                issues.extend(missing_columns(profile, &["Country", "Year", "Status", "Life expectancy", "GDP"], "dashboard"));
+                #[cfg(not(feature = "plots"))]
+                issues.push(ValidationIssue::warning(format!("no dashboard is drawn: {}", PLOTS_DISABLED)));
+            }
+        }
+        issues
+    }
+}
+
+// Results shared between steps and returned to the caller
+#[derive(Debug, Default)]
+pub(crate) struct AnalysisResults {
+    pub(crate) cleaning: Option<CleaningReport>,
+    pub(crate) records: Vec<LifeExpectancyRecord>,
+    pub(crate) validation_issues: usize,
+    pub(crate) produced_plots: Vec<PathBuf>, // plots rendered from the config file
+    pub(crate) correlations: Option<CorrelationAnalysis>, // from the heatmap step
+    pub(crate) clusters: HashMap<String, usize>, // country -> average-linkage cluster, from the graph step
+    pub(crate) completed: Vec<Step>,
+    pub(crate) cached: Vec<Step>, // completed steps whose outputs an earlier run left
+    pub(crate) failures: Vec<(Step, String)>, // failed or skipped steps with the reason
+    pub(crate) findings_inputs: String, // input key of the findings
+    pub(crate) cached_findings: Option<Vec<Artifact>>, // findings files kept from the last run
+}
+
+pub(crate) struct Pipeline<'a> {
+    file_path: &'a str,
+    config: &'a Config,
+    args: &'a CliArgs,
+    run: &'a RunMetadata,
+    steps: Vec<Step>,
+    fail_fast: bool,
+    cancel: CancellationToken,
+    previous: Manifest, // of the last run, whose outputs are kept when their inputs did not change
+    #[cfg(feature = "plots")]
+    plots_dir: &'a str,
+    // Plain and winsorized correlations of the heatmap columns, built on first use
+    correlations: OnceCell<(CorrelationAnalysis, CorrelationAnalysis)>,
+}
+
+impl<'a> Pipeline<'a> {
+    // All steps, stopping at the first failure
+    pub(crate) fn new(file_path: &'a str, config: &'a Config, args: &'a CliArgs, run: &'a RunMetadata) -> Self {
+        Pipeline {
+            file_path,
+            config,
+            args,
+            run,
+            steps: Step::ALL.to_vec(),
+            fail_fast: true,
+            cancel: CancellationToken::new(),
+            previous: Manifest::default(),
+            #[cfg(feature = "plots")]
+            plots_dir: PLOTS_DIR,
+            correlations: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn with_steps(mut self, steps: &[Step]) -> Self {
+        self.steps = steps.to_vec();
+        self
+    }
+
+    // Keep running the remaining steps after a failure instead of returning it
+    pub(crate) fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    // Token that stops the run between steps and inside the graph step's pairwise
+    // loops; a cancelled run fails even when it keeps going after failures
+    pub(crate) fn cancel_with(mut self, cancel: &CancellationToken) -> Self {
+        self.cancel = cancel.clone();
+        self
+    }
+
+    // Skip the steps whose input keys match those of `previous` and whose outputs
+    // are still as it recorded them
+    pub(crate) fn reuse_outputs(mut self, previous: Manifest) -> Self {
+        self.previous = previous;
+        self
+    }
+
+    // Write the configured plots to `dir` instead of PLOTS_DIR, for tests that
+    // must not write to the working directory
+    #[cfg(all(test, feature = "plots"))]
+    pub(crate) fn plots_dir(mut self, dir: &'a str) -> Self {
+        self.plots_dir = dir;
+        self
+    }
+
+    // Input key of every step: the input file, the program (its version and the
+    // hash of its executable, so a rebuild after a code change runs every step),
+    // the step's config sections, the output settings and the keys of the steps
+    // it reads
+    fn input_keys(&self, steps: &[Step]) -> BTreeMap<Step, String> {
+        let settings = output_settings(self.args);
+        let executable = std::env::current_exe().ok().and_then(|path| workspace::file_hash(path.to_str()?).ok());
+        let mut keys = BTreeMap::new();
+        for &step in steps {
+            let mut inputs = vec![
+                format!("input = {}", self.run.input_hash),
+                format!("program = {} {}", self.run.crate_version, executable.as_deref().unwrap_or("unknown")),
+                step.config_inputs(self.config),
+                settings.clone(),
+            ];
+            inputs.extend(step.dependencies().iter().filter_map(|dependency| keys.get(dependency).cloned()));
+            keys.insert(step, manifest::input_key(&inputs));
+        }
+        keys
+    }
+
+    // Outputs of the steps that can be skipped, with the findings' own when they
+    // can be too. A step still runs when a step reading its results runs, and the
+    // steps the findings read run when the findings do.
+    fn cache_plan(
+        &self,
+        steps: &[Step],
+        keys: &BTreeMap<Step, String>,
+        findings_key: &str,
+    ) -> (BTreeMap<Step, Vec<Artifact>>, Option<Vec<Artifact>>) {
+        let mut cached: BTreeMap<Step, Vec<Artifact>> =
+            steps.iter().filter_map(|&step| Some((step, self.previous.cached(step.name(), &keys[&step])?))).collect();
+        let findings = self.previous.cached("findings", findings_key);
+        if findings.is_none() {
+            for step in FINDINGS_READS {
+                cached.remove(&step);
+            }
+        }
+        // Later steps come first, so a dependency sees its dependents' decision
+        for step in steps.iter().rev() {
+            if !cached.contains_key(step) {
+                for dependency in step.dependencies() {
+                    cached.remove(dependency);
+                }
+            }
+        }
+        (cached, findings)
+    }
+
+    // Selected steps in run order; every dependency must be selected as well
+    fn ordered_steps(&self) -> Result<Vec<Step>, Box<dyn Error>> {
+        let selected: BTreeSet<Step> = self.steps.iter().copied().collect();
+        for step in &selected {
+            for dependency in step.dependencies() {
+                if !selected.contains(dependency) {
+                    return Err(format!("Step {} needs step {}", step.name(), dependency.name()).into());
+                }
+            }
+        }
+        Ok(selected.into_iter().collect())
+    }
+
+    // Issues of every selected step in run order, without producing any output
+    pub(crate) fn validate(&self, profile: &DatasetProfile) -> Result<Vec<StepIssues>, Box<dyn Error>> {
+        Ok(self
+            .ordered_steps()?
+            .into_iter()
+            .map(|step| (step, step.validate(self.config, profile, self.args)))
+            .collect())
+    }
+
+    pub(crate) fn run(&self) -> Result<AnalysisResults, Box<dyn Error>> {
+        let steps = self.ordered_steps()?;
+        let policy = RowErrorPolicy::from_name(self.args.row_errors.as_deref().unwrap_or("log"), "rejects.csv")?;
+        let mut results = AnalysisResults::default();
+        let keys = self.input_keys(&steps);
+        results.findings_inputs = manifest::input_key(&keys.values().cloned().collect::<Vec<_>>());
+        let (mut cached, findings) = self.cache_plan(&steps, &keys, &results.findings_inputs);
+        results.cached_findings = findings;
+
+        for step in steps {
+            self.cancel.check()?;
+            if let Some(failed) = step.dependencies().iter().find(|dep| !results.completed.contains(dep)) {
+                results.failures.push((step, format!("skipped because {} did not complete", failed.name())));
+                continue;
+            }
+            manifest::begin_step(step.name());
+            if let Some(artifacts) = cached.remove(&step) {
+                println!("Step {}: cached, its inputs have not changed since the last run", step.name());
+                manifest::reuse(artifacts);
+                manifest::declare_inputs(&keys[&step]);
+                results.completed.push(step);
+                results.cached.push(step);
+                continue;
+            }
+            match self.run_step(step, &policy, &mut results) {
+                // Only a step that completed can be reused by the next run
+                Ok(()) => {
+                    manifest::declare_inputs(&keys[&step]);
+                    results.completed.push(step);
+                }
+                Err(error) if self.cancel.is_cancelled() => return Err(error),
+                Err(error) if self.fail_fast => return Err(format!("Step {} failed: {}", step.name(), error).into()),
+                Err(error) => {
+                    eprintln!("Step {} failed: {}", step.name(), error);
+                    results.failures.push((step, error.to_string()));
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn run_step(&self, step: Step, policy: &RowErrorPolicy, results: &mut AnalysisResults) -> Result<(), Box<dyn Error>> {
+        match step {
+            Step::Clean => self.clean(policy, results),
+            Step::Eda => self.eda(policy, &results.records),
+            Step::Heatmap => self.heatmap(results),
+            Step::Graph => {
+                results.clusters = run_graph_stage(self.file_path, self.config, self.args, self.run, &self.cancel, Some(&results.records))?;
+                Ok(())
+            }
+            Step::Charts => self.charts(&results.records),
+            #[cfg(not(feature = "plots"))]
+            Step::Specs => {
+                if !self.config.plots.is_empty() {
+                    println!("Skipped {} configured plots: {}", self.config.plots.len(), PLOTS_DISABLED);
+                }
+                Ok(())
+            }
+            #[cfg(feature = "plots")]
+            Step::Specs => {
+                if !self.config.plots.is_empty() {
+                    let dataset = load_clean::Dataset::load(self.file_path)?;
+                    let produced =
+                        spec_plots::run_plot_specs(&dataset, &self.config.plots, &self.config.theme, self.plots_dir, self.run)?;
+                    println!("Rendered {} configured plots:", produced.len());
+                    for path in &produced {
+                        println!("  {}", path.display());
+                    }
+                    results.produced_plots = produced;
+                }
+                Ok(())
+            }
+            #[cfg(not(feature = "plots"))]
+            Step::Dashboard => {
+                println!("Dashboard skipped: {}", PLOTS_DISABLED);
+                Ok(())
+            }
+            #[cfg(feature = "plots")]
+            Step::Dashboard => {
+                let dataset = load_clean::Dataset::load(self.file_path)?;
+                dashboard::render_dashboard(&dataset, results, "dashboard.png", &self.config.theme, self.run)?;
+                println!("Dashboard saved to {}", output::display_path("dashboard.png"));
+                Ok(())
+            }
+        }
+    }
+
+    fn clean(&self, policy: &RowErrorPolicy, results: &mut AnalysisResults) -> Result<(), Box<dyn Error>> {
+        let (file_path, run) = (self.file_path, self.run);
+
+        let exclude = EntityFilter::new(&self.config.exclude_entities);
+
+        // Clean the raw file, logging malformed rows instead of aborting
+        let options = load_clean::CleaningOptions {
+            comparison_dir: self.args.cleaning_plots.then_some("cleaning"),
+            consistency_report: Some("consistency_report.csv"),
+            correct_expenditure: self.args.correct_expenditure,
+            unify_status: self.args.unify_status.as_deref().map_or(Ok(StatusPolicy::Keep), StatusPolicy::from_name)?,
+            exclude: Some(&exclude),
+        };
+        let report = load_clean::clean_data(file_path, "cleaned_data.csv", policy, &options, run)?;
+        println!(
+            "Cleaning: {} rows read, {} written, {} rejected, {} values imputed",
+            report.rows_read, report.rows_written, report.rejected, report.imputed
+        );
+        println!(
+            "Percentage expenditure: {} inconsistent rows ({} corrected from GDP) in {}",
+            report.expenditure_issues,
+            report.expenditure_corrected,
+            output::display_path("consistency_report.csv")
+        );
+        if report.excluded > 0 {
+            println!("Excluded {} rows of aggregate entities (such as World)", report.excluded);
+        }
+        if report.status_unified > 0 {
+            println!("Status: {} rows of {} countries relabelled", report.status_unified, report.status_changes);
+        }
+        if !report.single_row.is_empty() {
+            println!(
+                "{} countries have a single row and no trend or spread over the years: {}",
+                report.single_row.len(),
+                report.single_row.join(", ")
+            );
+        }
+        // Where values were missing before imputation filled them in
+        missingness::print_missingness(&report.missingness);
+        #[cfg(feature = "plots")]
+        for (group_by, _) in missingness::MISSINGNESS_GROUPINGS {
+            let tests: Vec<&MissingnessTest> = report.missingness.iter().filter(|test| test.group_by == group_by).collect();
+            let output_file = format!("missingness_by_{}.png", group_by.to_lowercase());
+            charts::plot_missingness(&tests, &output_file, run)?;
+            println!("Missing values by {} plotted to {}", group_by, output::display_path(&output_file));
+        }
+        let records = load_clean::load_cleaned_data("cleaned_data.csv", &RowErrorPolicy::Fail)?;
+        println!("Loaded {} cleaned records", records.len());
+        if let Some(name) = self.args.partition.as_deref() {
+            let partitions = load_clean::export_partitioned(&records, PartitionKey::from_name(name)?, "cleaned", run)?;
+            println!(
+                "Partitioned into {} files, listed in {}",
+                partitions.len(),
+                output::display_path(format!("cleaned/{}", load_clean::PARTITION_MANIFEST))
+            );
+        }
+
+        let issues = load_clean::validate_records(&records);
+        let single_digit = issues.iter().filter(|issue| issue.kind == IssueKind::SingleDigitCoverage).count();
+        println!(
+            "Validation: {} values outside their plausible range, {} vaccine coverage values in single digits",
+            issues.len() - single_digit,
+            single_digit
+        );
+        for issue in issues.iter().take(5) {
+            let note = if issue.kind == IssueKind::SingleDigitCoverage { " (single-digit coverage)" } else { "" };
+            println!("  {} {}: {} = {}{}", issue.country, issue.year, issue.field, issue.value, note);
+        }
+        load_clean::export_long_csv(&load_clean::to_long(&records), "cleaned_long.csv", run)?;
+
+        results.cleaning = Some(report);
+        results.validation_issues = issues.len();
+        results.records = records;
+        Ok(())
+    }
+
+    fn eda(&self, policy: &RowErrorPolicy, records: &[LifeExpectancyRecord]) -> Result<(), Box<dyn Error>> {
+        let file_path = self.file_path;
+
+        // Summary statistics of every numeric field, overall and per status
+        let tables = TableOptions::new(self.args.sort_by.clone());
+        eda_statistics::print_describe(&eda_statistics::describe(records), &tables);
+        for (status, summaries) in eda_statistics::describe_by_status(records) {
+            println!("{} countries:", status);
+            eda_statistics::print_describe(&summaries, &tables);
+        }
+
+        // Which relationships with life expectancy are driven by outliers
+        let winsor = self.args.winsor.unwrap_or(eda_statistics::WINSOR_LIMITS);
+        let (plain, robust) = self.correlation_analyses()?;
+        let correlations = correlation::target_correlations(plain, robust, "Life expectancy").ok_or("No Life expectancy column")?;
+        eda_statistics::print_target_correlations("Life expectancy", &correlations, winsor, &tables);
+
+        // How far apart the two statuses are on every field
+        let effects = eda_statistics::effect_sizes_by_status(records);
+        eda_statistics::print_effect_sizes(&effects, &tables);
+        eda_statistics::export_effect_sizes_csv(&effects, "effect_sizes.csv", self.run)?;
+        println!("Effect sizes written to {}", output::display_path("effect_sizes.csv"));
+
+        // Which country-year holds the smallest and largest value of each field
+        let extremes = eda_statistics::extremes_table(records);
+        eda_statistics::print_extremes(&extremes, &tables);
+        eda_statistics::export_extremes_csv(&extremes, "extremes.csv", self.run)?;
+        println!("Extreme values written to {}", output::display_path("extremes.csv"));
+
+        // Whether schooling goes with life expectancy differently per status
+        let bands = banding::banded_interaction(records, "Schooling", "Life expectancy", |record| record.status.as_str(), SCHOOLING_BANDS)?;
+        banding::print_banded_interaction(&bands, "Schooling", "Life expectancy");
+        banding::export_banded_interaction_csv(&bands, "schooling_bands.csv", self.run)?;
+        println!("Schooling bands written to {}", output::display_path("schooling_bands.csv"));
+
+        // Vaccine coverage and alcohol consumption over the years, per status
+        let immunization = immunization::immunization_analysis(records)?;
+        immunization::print_immunization(&immunization, &tables);
+        immunization::export_immunization_csv(&immunization, "immunization.csv", self.run)?;
+        println!("Immunization coverage written to {}", output::display_path("immunization.csv"));
+        #[cfg(feature = "plots")]
+        {
+            charts::plot_coverage_by_status(&immunization, "immunization_coverage.png", self.run)?;
+            println!("Coverage by status plotted to {}", output::display_path("immunization_coverage.png"));
+        }
+
+        // Top and bottom 5 countries
+        let eda_columns = columns::resolve_columns(file_path, &EDA_COLUMNS)?;
+        let (country_column, year_column, status_column, life_expectancy_column) = (eda_columns[0], eda_columns[1], eda_columns[2], eda_columns[3]);
+
+        let mut year_rows: Vec<YearRow> = load_clean::read_year_rows(file_path, year_column, policy)?;
+        EntityFilter::new(&self.config.exclude_entities).retain_year_rows(&mut year_rows, country_column);
+        // Group raw rows by the statuses the cleaned records ended up with
+        if self.args.unify_status.as_deref().is_some_and(|name| name != "keep") {
+            load_clean::apply_statuses(&mut year_rows, country_column, status_column, records);
+        }
+        for order in [RankOrder::Highest, RankOrder::Lowest] {
+            let top = eda_statistics::find_top_countries(&year_rows, country_column, life_expectancy_column, TOP_COUNTRIES, order)?;
+            eda_statistics::print_top_countries(&top, TOP_COUNTRIES, order);
+        }
+
+        // average life_expectancy vs status
+        eda_statistics::calculate_average_life_expectancy(&year_rows, country_column, status_column, life_expectancy_column)?;
+
+        #[cfg(feature = "plots")]
+        self.eda_plots(&year_rows)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "plots")]
+    fn eda_plots(&self, year_rows: &[YearRow]) -> Result<(), Box<dyn Error>> {
+        let (file_path, run, theme) = (self.file_path, self.run, &self.config.theme);
+        let eda_columns = columns::resolve_columns(file_path, &EDA_COLUMNS)?;
+        let (status_column, adult_mortality_column, infant_deaths_column) = (eda_columns[2], eda_columns[4], eda_columns[5]);
+        let dataset = load_clean::Dataset::load(file_path)?;
+        let (income_column, schooling_column) = ("Income composition of resources", "Schooling");
+
+        let linear = |column| eda_plots::MarginalAxis { column, log: false };
+        // Inputs converted from the Kaggle v2 layout have no income column
+        if dataset.numeric_column(income_column)?.iter().all(Option::is_none) {
+            println!("Skipped the income vs. schooling scatter plots: {} has no values", income_column);
+        } else {
+            let scatter_options = eda_plots::ScatterOptions {
+                annotate_extremes: 5,
+                jitter: Some(eda_plots::Jitter { fraction: 0.005, seed: self.config.random.seed_for("scatter_jitter") }),
+                ..Default::default()
+            };
+            eda_plots::create_scatter_plot(&dataset, "scatter_plot.png", income_column, schooling_column, &scatter_options, theme, run)?;
+            // One marker per country, so countries with more years do not weigh more
+            let country_options = eda_plots::ScatterOptions {
+                annotate_extremes: 5,
+                aggregate_per_country: true,
+                ..Default::default()
+            };
+            eda_plots::create_scatter_plot(&dataset, "scatter_plot_countries.png", income_column, schooling_column, &country_options, theme, run)?;
+            let binned_options = eda_plots::ScatterOptions {
+                hexbin: true,
+                ..Default::default()
+            };
+            eda_plots::create_scatter_plot(&dataset, "scatter_plot_binned.png", income_column, schooling_column, &binned_options, theme, run)?;
+            let text = eda_plots::ChartText { caption: "Income vs. Schooling Rates", x_desc: "Income", y_desc: "Schooling Rates" };
+            eda_plots::plot_scatter_with_marginals(&dataset, (linear(income_column), linear(schooling_column)), &text, "scatter_marginals_income_schooling.png", theme, run)?;
+        }
+        let gdp = eda_plots::MarginalAxis { column: "GDP", log: true };
+        let text = eda_plots::ChartText { caption: "GDP vs. Life Expectancy", x_desc: "GDP (USD, log scale)", y_desc: "Life expectancy (years)" };
+        eda_plots::plot_scatter_with_marginals(&dataset, (gdp, linear("Life expectancy")), &text, "scatter_marginals_gdp_life_expectancy.png", theme, run)?;
+
+        // plot developed vs developing across Adult Mortality
+        eda_plots::create_developed_vs_developing_plot(
+            year_rows,
+            "developed_vs_developing_plot_adult_mortality.png",
+            adult_mortality_column,
+            status_column,
+            theme,
+            run,
+        )?;
+
+        // plot developed vs developing across Infant Mortality
+        eda_plots::create_developed_vs_developing_plot_infant(
+            year_rows,
+            "developed_vs_developing_plot_infant_mortality.png",
+            infant_deaths_column,
+            status_column,
+            theme,
+            run,
+        )?;
+
+        eda_plots::create_features_comparison_bar_plot(file_path, "comparison_bar_plot.png", &COMPARISON_FEATURES, run)?;
+        Ok(())
+    }
+
+    // Built from the raw file on the first call and shared by every later step
+    fn correlation_analyses(&self) -> Result<&(CorrelationAnalysis, CorrelationAnalysis), Box<dyn Error>> {
+        if let Some(analyses) = self.correlations.get() {
+            return Ok(analyses);
+        }
+        let mut dataset = Dataset::load(self.file_path)?;
+        let (exclude, country) = (EntityFilter::new(&self.config.exclude_entities), dataset.column_index("Country")?);
+        dataset.rows.retain(|row| !exclude.excludes(row.get(country).unwrap_or("")));
+        let selection = columns::parse_selection(&dataset.headers, HEATMAP_COLUMNS)?;
+        let features: Vec<&str> = selection.iter().map(|&i| dataset.headers[i].as_str()).collect();
+        let (low, high) = self.args.winsor.unwrap_or(eda_statistics::WINSOR_LIMITS);
+        let plain = CorrelationAnalysis::compute(&dataset, &features, CorrelationMethod::Pearson, HEATMAP_MIN_OBSERVATIONS)?;
+        let robust = CorrelationAnalysis::compute(&dataset, &features, CorrelationMethod::Winsorized(low, high), HEATMAP_MIN_OBSERVATIONS)?;
+        Ok(self.correlations.get_or_init(|| (plain, robust)))
+    }
+
+    fn heatmap(&self, results: &mut AnalysisResults) -> Result<(), Box<dyn Error>> {
+        let (plain, robust) = self.correlation_analyses()?;
+        let analysis = if self.args.robust { robust } else { plain };
+        if let CorrelationMethod::Winsorized(low, high) = analysis.method {
+            println!("Heatmap: columns winsorized at the {}% and {}% quantiles", low * 100.0, high * 100.0);
+        }
+        #[cfg(feature = "plots")]
+        analysis.heatmap("correlation_heatmap.png", self.run)?;
+        let masked = analysis.matrix.iter().filter(|value| value.is_nan()).count();
+        if masked > 0 {
+            println!("Heatmap: {} cells with fewer than {} observations masked", masked, HEATMAP_MIN_OBSERVATIONS);
+        }
+
+        println!("Most strongly correlated pairs:");
+        let mut table = Table::new(&["Column", "With", "r"]);
+        for (a, b, correlation) in analysis.top_pairs(5) {
+            table = table.add_row(vec![Cell::text(a), Cell::text(b), Cell::signed(Some(correlation), 3)]);
+        }
+        table.print(&TableOptions::new(self.args.sort_by.clone()));
+        let order: Vec<&str> = analysis.feature_order_by_clustering().into_iter().map(|i| analysis.labels[i].as_str()).collect();
+        println!("Columns grouped by correlation: {}", order.join(", "));
+        results.correlations = Some(analysis.clone());
+        Ok(())
+    }
+
+    #[cfg(not(feature = "plots"))]
+    fn charts(&self, _records: &[LifeExpectancyRecord]) -> Result<(), Box<dyn Error>> {
+        println!("Charts skipped: {}", PLOTS_DISABLED);
+        Ok(())
+    }
+
+    #[cfg(feature = "plots")]
+    fn charts(&self, records: &[LifeExpectancyRecord]) -> Result<(), Box<dyn Error>> {
+        let run = self.run;
+
+        // The same comparison for several features at once, one panel each
+        charts::plot_feature_grid(
+            records,
+            &[
+                "Life expectancy",
+                "Adult Mortality",
+                "infant deaths",
+                "Alcohol",
+                "BMI",
+                "HIV/AIDS",
+                "GDP",
+                "Income composition of resources",
+                "Schooling",
+            ],
+            "feature_grid.png",
+            run,
+        )?;
+        let deaths = ["infant deaths", "under-five deaths", "Adult Mortality"];
+        charts::plot_stacked_area(records, &deaths, false, "stacked_deaths.png", run)?;
+        charts::plot_stacked_area(records, &deaths, true, "stacked_deaths_percent.png", run)?;
+        charts::plot_slopegraph(records, 2000, 2015, 12, "slopegraph_life_expectancy.png", run)?;
+        charts::plot_country_year_heatmap(records, "Life expectancy", None, "country_year_life_expectancy.png", charts::SortBy::Mean, run)?;
+        // Left out for data without these countries, such as the demo dataset
+        let hiv_countries = ["Botswana", "Kenya", "Malawi", "South Africa", "Uganda", "Zambia", "Zimbabwe"];
+        if hiv_countries.iter().all(|country| records.iter().any(|record| record.country == *country)) {
+            charts::plot_country_year_heatmap(
+                records,
+                "HIV/AIDS",
+                Some(&hiv_countries),
+                "country_year_hiv_aids.png",
+                charts::SortBy::Alphabetical,
+                run,
+            )?;
+        } else {
+            println!("country_year_hiv_aids.png skipped: the data lacks some of {}", hiv_countries.join(", "));
+        }
+        charts::plot_dual_axis(records, "Adult Mortality", "GDP", "Developing", "dual_axis_mortality_gdp.png", run)?;
+        charts::plot_binned_means(records, "GDP", "Life expectancy", 5, "life_expectancy_by_gdp_quintile.png", run)?;
+        // Whole countries are resampled unless asked otherwise: a country's years move together
+        let unit = status_gap::ResampleUnit::from_name(self.args.bootstrap_unit.as_deref().unwrap_or("country"))?;
+        let meta = series::field_meta("Life expectancy")?;
+        let band = status_gap::bootstrap_gap(records, meta, unit, GAP_RESAMPLES, self.config.random.seed_for("gap_bootstrap"))?;
+        let (average, lower, upper) = band.average;
+        println!(
+            "{} gap averaged over {} years: {:.2} (95% interval {:.2} to {:.2}, resampling {:?})",
+            meta.name,
+            band.years.len(),
+            average,
+            lower,
+            upper,
+            unit
+        );
+        charts::plot_status_gap(&band, meta, "life_expectancy_gap.png", run)?;
+        let by_status: fn(&LifeExpectancyRecord) -> &str = |record| record.status.as_str();
+        charts::plot_faceted_scatter(records, "Schooling", "Life expectancy", by_status, "scatter_schooling_by_status.png", &self.config.theme, run)?;
+        let bands = banding::banded_interaction(records, "Schooling", "Life expectancy", by_status, SCHOOLING_BANDS)?;
+        charts::plot_banded_interaction(&bands, "Schooling", "Life expectancy", "life_expectancy_by_schooling_band.png", run)?;
+
+        let life_expectancy_2015: HashMap<String, f64> = records
+            .iter()
+            .filter(|record| record.year == 2015)
+            .filter_map(|record| Some((record.country.clone(), record.life_expectancy?)))
+            .collect();
+        tile_map::plot_tile_map(
+            tile_map::TileValues::Values(&life_expectancy_2015),
+            "Life expectancy (years), 2015",
+            "tile_map_life_expectancy_2015.png",
+            run,
+        )
+    }
+}
+
+// Print the warning of an export cut short by --max-output-rows
+fn report_truncation(summary: &ExportSummary, path: impl AsRef<Path>) {
+    if let Some(warning) = summary.warning(path) {
+        println!("{}", warning);
+    }
+}
+
+// The values whose flag in `keep` is set
+fn retain_rows<T: Clone>(values: &[T], keep: &[bool]) -> Vec<T> {
+    values.iter().zip(keep).filter(|(_, &kept)| kept).map(|(value, _)| value.clone()).collect()
+}
+
+// Build, export and cluster the similarity graph, optionally for one status only.
+// Statuses and cluster profiles come from `records`, the cleaned records when the
+// pipeline cleaned the file first. Everything read from the file is kept in the
+// workspace, so `cluster` and later runs reuse it without reading the file again.
+// Returns the average-linkage cluster of every country.
+pub(crate) fn run_graph_stage(
+    file_path: &str,
+    config: &Config,
+    args: &CliArgs,
+    run: &RunMetadata,
+    cancel: &CancellationToken,
+    records: Option<&[LifeExpectancyRecord]>,
+) -> Result<HashMap<String, usize>, Box<dyn Error>> {
+    let threshold = config.graph.threshold;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("Similarity threshold {} is outside [0, 1]", threshold).into());
+    }
+    let cluster_only = args.command == cli::Command::Cluster;
+
+    let mut workspace = workspace::Workspace::open_in(args.workspace_dir(), file_path, args.no_cache)?;
+    let headers: Vec<String> = workspace.cached("headers.json", "headers=canonical", || columns::read_headers(file_path))?;
+    let exclude = EntityFilter::new(&config.exclude_entities);
+    // The records as read, when the subcommands run the stage without cleaning
+    let mut records = match records {
+        Some(records) => records.to_vec(),
+        None => workspace.cached("records.json", "records=typed", || load_clean::load_cleaned_data(file_path, &RowErrorPolicy::Skip))?,
+    };
+    exclude.retain_records(&mut records);
+    let statuses = graph::record_statuses(&records);
+    // The cells are cached before the check, so changing its limit or policy
+    // needs no new pass over the file
+    let selected = select_graph_features(&headers, &exclude, args, |selection| {
+        let cells_key = format!("selection={:?};{}", selection, fields::derived_key());
+        let cells: workspace::SerializedCells = workspace.cached("features.json", &cells_key, || {
+            graph::read_feature_cells(file_path, selection).map(workspace::SerializedCells::from)
+        })?;
+        Ok(cells.into())
+    })?;
+    let (features, gate) = (&selected.columns, &selected.gate);
+    let features_key = format!("features={:?};{}", features, fields::derived_key());
+    // Aggregate rows are dropped after the cache, so changing the list needs no rebuild
+    if selected.excluded > 0 {
+        println!("Similarity graph: excluded {} rows of aggregate entities", selected.excluded);
+    }
+    let (all_countries, all_rows) = &selected.rows;
+    if all_rows.is_empty() {
+        return Err(AnalysisError::empty("Similarity graph").into());
+    }
+    let weights = args.feature_weights.as_deref().map(|weights| gate.apply(weights));
+    let weights = weights.as_deref();
+    if let Some(weights) = weights {
+        println!("Feature weights (standardized features): {:?}", weights);
+    }
+    let all_similarity_rows = match weights {
+        Some(weights) => graph::weight_features(all_rows, weights)?,
+        None => all_rows.clone(),
+    };
+
+    // With --status every step below sees only the rows and records of that
+    // status; the graph is built over all rows once and restricted the same way
+    let in_status = |country: &str| {
+        args.status.as_deref().is_none_or(|status| statuses.get(country).is_some_and(|s| s.eq_ignore_ascii_case(status)))
+    };
+    let keep: Vec<bool> = all_countries.iter().map(|country| in_status(country)).collect();
+    let (rows_countries, rows, similarity_rows) = (retain_rows(all_countries, &keep), retain_rows(all_rows, &keep), retain_rows(&all_similarity_rows, &keep));
+    records.retain(|record| in_status(&record.country));
+    if let Some(status) = args.status.as_deref() {
+        println!("Restricted to {} countries: {} rows", status, rows.len());
+        if rows.is_empty() {
+            return Err(AnalysisError::empty(format!("Graph restricted to {} countries", status)).into());
+        }
+    }
+
+    if !cluster_only {
+        // Pairwise similarity distribution to inform the threshold choice
+        let histogram = graph::similarity_distribution(&similarity_rows, cancel)?;
+        println!(
+            "Similarity over {} pairs: min {:.3}, mean {:.3}, max {:.3}",
+            histogram.total, histogram.min, histogram.mean, histogram.max
+        );
+        println!(
+            "Similarity percentiles: p50 {:.3}, p90 {:.3}, p99 {:.3}",
+            histogram.percentile(50.0),
+            histogram.percentile(90.0),
+            histogram.percentile(99.0)
+        );
+        #[cfg(feature = "plots")]
+        graph::plot_similarity_distribution(&histogram, threshold, "similarity_distribution.png", run)?;
+    }
+
+    let significance = args.permutations.map(|permutations| graph::EdgeSignificance {
+        permutations,
+        percentile: args.null_percentile.unwrap_or(95.0),
+        seed: config.random.seed_for("edge_significance"),
+    });
+    let graph_key = format!(
+        "{};threshold={};weights={:?};significance={:?};exclude={:?}",
+        features_key, threshold, weights, significance, config.exclude_entities
+    );
+    let serialized: workspace::SerializedGraph = workspace.cached("graph.json", &graph_key, || {
+        let (graph, pruning) = graph::build_similarity_graph_from_rows(all_countries, &all_similarity_rows, threshold, significance.as_ref(), cancel)?;
+        if let Some(pruning) = pruning {
+            println!(
+                "Significance pruning (null p{} = {:.4}) removed {} of {} edges above the threshold",
+                args.null_percentile.unwrap_or(95.0),
+                pruning.null_cutoff,
+                pruning.removed,
+                pruning.threshold_edges
+            );
+        }
+        Ok(workspace::SerializedGraph::from(&graph))
+    })?;
+    let mut graph: Graph<String, f64> = serialized.into();
+
+    if let Some(status) = args.status.as_deref() {
+        graph = graph::filter_graph_by(&graph, in_status);
+        println!("Graph restricted to {} countries: {} nodes", status, graph.node_count());
+    }
+
+    let export = ExportOptions {
+        mode: if args.append { ExportMode::Append } else { ExportMode::Overwrite },
+        extra_columns: &args.tags,
+        precision: args.precision,
+        limit: RowLimit { max_rows: args.max_output_rows, flush_every: args.flush_every },
+    };
+    if !cluster_only {
+        // visualize clusters
+        let output_file = "graph_edge_list.csv";
+        let summary = graph::export_graph_to_csv(&graph, output_file, weights, gate, &export, run)?;
+
+        println!("Edge list exported to {} ({} rows)", output_file, group_thousands(&summary.written.to_string()));
+        report_truncation(&summary, output_file);
+    }
+
+    // Number of clusters: given, or the best of a sweep over average-linkage cuts
+    // Every per-country result below is keyed by this one index
+    let similarity_key = format!("{};status={:?}", graph_key, args.status);
+    let similarity: workspace::SerializedMatrix = workspace.cached("similarity.json", &similarity_key, || {
+        let (index, country_rows) = graph::aggregate_by_country(&rows_countries, &rows, features.len());
+        let matrix = graph::similarity_matrix(&index, &country_rows, weights)?;
+        Ok(workspace::SerializedMatrix::from((&index, &matrix)))
+    })?;
+    let (index, matrix) = similarity.into();
+    let count = hierarchical::ClusterCount::from_name(args.clusters.as_deref().unwrap_or(DEFAULT_CLUSTER_COUNT))?;
+    let k = match count {
+        hierarchical::ClusterCount::Fixed(k) => k,
+        hierarchical::ClusterCount::Select(criterion) => {
+            let dendrogram = hierarchical::hierarchical_cluster(index.names(), &matrix, hierarchical::Linkage::Average);
+            let (k, scores) = hierarchical::select_k(&dendrogram, &index, &matrix, &graph, CLUSTER_K_RANGE, criterion);
+            let curve: Vec<String> = CLUSTER_K_RANGE.zip(&scores).map(|(k, score)| format!("{}: {:.3}", k, score)).collect();
+            println!("Selected k = {} by {:?} ({})", k, criterion, curve.join(", "));
+            #[cfg(feature = "plots")]
+            if !cluster_only {
+                charts::plot_k_scores(CLUSTER_K_RANGE, &scores, k, criterion, "cluster_k_scores.png", run)?;
+                println!("Cluster count scores saved to {}", output::display_path("cluster_k_scores.png"));
+            }
+            k
+        }
+    };
+
+    // Node scores the cluster representatives maximise
+    let centrality = graph::Centrality::from_name(args.centrality.as_deref().unwrap_or("degree"))?;
+    let scores = centrality.scores(&graph, config.random.seed_for("betweenness_pivots"), cancel)?;
+
+    // Compare the clustering against degree-preserving rewired graphs
+    let labels = graph::component_labels(&graph);
+    let null_model = graph::compare_to_null_model(&graph, &labels, 10, config.random.seed_for("null_model"), cancel)?;
+    println!(
+        "Modularity {:.2} vs null {:.2} ± {:.2} (p = {:.3})",
+        null_model.modularity, null_model.null_mean, null_model.null_std, null_model.p_value
+    );
+
+    // How well the clusters line up with the known development status
+    graph::print_label_table(&graph::cluster_label_table(&graph, &labels, &statuses), 10);
+
+    // Whether similar countries are simply neighbours in the same region (or the
+    // same status when no region file is given)
+    let (regions, label_name) = match args.regions.as_deref() {
+        Some(path) => (region_purity::load_regions(path)?, "region"),
+        None => (statuses.clone(), "status"),
+    };
+    let purity = region_purity::neighbor_region_purity(&graph, &regions);
+    if let Some(test) = region_purity::region_purity_test(&graph, &regions, 100, config.random.seed_for("region_purity"), cancel)? {
+        region_purity::print_region_purity(&purity, &test, label_name, 5);
+    }
+
+    // Hierarchical clustering of country-averaged features
+    let mut clusters = Vec::new();
+    for linkage in [hierarchical::Linkage::Single, hierarchical::Linkage::Complete, hierarchical::Linkage::Average] {
+        let dendrogram = hierarchical::hierarchical_cluster(index.names(), &matrix, linkage);
+        let assignments = dendrogram.cut(k);
+        if linkage == hierarchical::Linkage::Average {
+            clusters = assignments.clone();
+        }
+        let mut sizes = vec![0; k];
+        for &cluster in &assignments {
+            sizes[cluster] += 1;
+        }
+        println!("{:?} linkage, {} clusters with sizes {:?}", linkage, k, sizes);
+        dendrogram.print_ascii(3);
+    }
+    let memberships: HashMap<String, usize> = index.names().iter().cloned().zip(clusters.iter().copied()).collect();
+
+    // Representatives of the average-linkage clusters on the graph
+    let per_status = if args.per_status { Some(&statuses) } else { None };
+    let node_clusters: Vec<Option<usize>> = index.node_countries(&graph).into_iter().map(|country| country.map(|i| clusters[i])).collect();
+    let representatives = graph::cluster_graph(&graph, &node_clusters, per_status, &scores)?;
+
+    println!("Representatives of {} clusters:", representatives.len());
+    for (cluster_id, chosen) in representatives {
+        println!("Cluster {}: {}", cluster_id, graph::describe_representatives(&chosen));
+    }
+
+    // What sets the average-linkage clusters apart, over every registry field
+    let fields: Vec<&FieldMeta> = FIELDS.iter().collect();
+    let profiles = cluster_profiles::cluster_feature_profiles(&memberships, &records, &fields);
+    cluster_profiles::print_cluster_profiles(&profiles, &TableOptions::new(args.sort_by.clone()));
+    #[cfg(feature = "plots")]
+    if !cluster_only {
+        charts::plot_cluster_profiles(&profiles, "cluster_profiles.png", run)?;
+        println!("Cluster profile heatmap saved to {}", output::display_path("cluster_profiles.png"));
+        tile_map::plot_tile_map(tile_map::TileValues::Clusters(&memberships), "Average-linkage clusters", "tile_map_clusters.png", run)?;
+        println!("Cluster tile map saved to {}", output::display_path("tile_map_clusters.png"));
+        for cluster in 0..k {
+            let percentiles = radar::cluster_percentiles(&records, &memberships, cluster, &radar::RADAR_FEATURES)?;
+            let output_file = format!("radar_cluster_{}.png", cluster);
+            radar::plot_radar(&percentiles, &format!("Cluster {} centroid: percentile among countries", cluster), &output_file, run)?;
+            println!("Cluster {} radar chart saved to {}", cluster, output::display_path(&output_file));
+        }
+    }
+
+    if !cluster_only {
+        let cluster_ids: Vec<f64> = clusters.iter().map(|&cluster| cluster as f64).collect();
+        let mut metrics = graph::basic_node_metrics(&graph);
+        if centrality != graph::Centrality::Degree {
+            metrics.insert("Betweenness", scores.iter().map(|&score| Some(score)).collect());
+        }
+        metrics.insert_by_country(&index.node_countries(&graph), "Cluster", &cluster_ids);
+        let summary = graph::export_node_metrics_csv(&graph, &index, &metrics, "node_metrics.csv", &export, run)?;
+        println!("Node metrics exported to {} ({} rows)", output::display_path("node_metrics.csv"), group_thousands(&summary.written.to_string()));
+        report_truncation(&summary, "node_metrics.csv");
+        hierarchical::export_clusters_csv(&index, &clusters, "clusters.csv", run)?;
+        println!("Clusters exported to {}", output::display_path("clusters.csv"));
+    }
+    if let Some(dir) = args.gephi.as_deref() {
+        let summary = graph::export_gephi_csv(&graph, &index, &clusters, &statuses, dir, &export)?;
+        println!("Gephi edges and nodes exported to {} ({} edges)", output::display_path(dir), group_thousands(&summary.written.to_string()));
+        report_truncation(&summary, Path::new(dir).join("edges.csv"));
+    }
+    if let Some(output_file) = args.dot.as_deref() {
+        let bundles = graph_bundle::bundle_edges(&graph, &index, &clusters);
+        graph_bundle::print_edge_bundles(&bundles, &TableOptions::new(args.sort_by.clone()));
+        graph_bundle::export_bundled_dot(&bundles, &index, &clusters, output_file, run)?;
+        println!("Cluster-bundled graph written to {}", output::display_path(output_file));
+    }
+
+    Ok(memberships)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> CliArgs {
+        cli::parse_args(values.iter().map(|value| value.to_string())).unwrap()
+    }
+
+    #[test]
+    fn steps_run_in_declaration_order_and_need_their_dependencies() {
+        let (config, args) = (Config::default(), args(&[]));
+        let run = RunMetadata::new("missing.csv", "none").unwrap();
+        let pipeline = Pipeline::new("missing.csv", &config, &args, &run);
+
+        let ordered = pipeline.with_steps(&[Step::Graph, Step::Specs, Step::Clean]).ordered_steps().unwrap();
+        assert_eq!(ordered, vec![Step::Clean, Step::Graph, Step::Specs]);
+        let pipeline = Pipeline::new("missing.csv", &config, &args, &run).with_steps(&[Step::Eda, Step::Heatmap]);
+        assert_eq!(pipeline.ordered_steps().unwrap_err().to_string(), "Step eda needs step clean");
+        assert_eq!(Step::from_name(" Graph ").unwrap(), Step::Graph);
+        assert!(Step::from_name("plots").unwrap_err().to_string().contains("expected one of clean, eda"));
+    }
+
+    #[test]
+    fn a_failed_step_stops_the_run_or_skips_its_dependents() {
+        let _recording = crate::test_support::RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = crate::test_support::scratch_dir("pipeline-steps");
+        let input = dir.join("missing.csv");
+        let input = input.to_str().unwrap();
+        let (config, args) = (Config::default(), args(&[]));
+        let run = RunMetadata::new(input, "none").unwrap();
+        let steps = [Step::Clean, Step::Graph, Step::Specs];
+
+        let error = Pipeline::new(input, &config, &args, &run).with_steps(&steps).run().unwrap_err().to_string();
+        assert!(error.starts_with("Step clean failed"), "{}", error);
+
+        let results = Pipeline::new(input, &config, &args, &run).with_steps(&steps).fail_fast(false).run().unwrap();
+        let failed: Vec<Step> = results.failures.iter().map(|(step, _)| *step).collect();
+        assert_eq!(failed, vec![Step::Clean, Step::Graph]);
+        assert_eq!(results.failures[1].1, "skipped because clean did not complete");
+        // Without configured plots the specs step has nothing to read or write
+        assert_eq!(results.completed, vec![Step::Specs]);
+    }
+
+    // Whether a returned error is AnalysisError::EmptyInput
+    fn is_empty_input(error: &(dyn Error + 'static)) -> bool {
+        matches!(error.downcast_ref::<AnalysisError>(), Some(AnalysisError::EmptyInput { .. }))
+    }
+
+    #[test]
+    fn a_header_only_file_is_empty_input_everywhere() {
+        let dir = crate::test_support::scratch_dir("header-only");
+        let input = dir.join("input.csv");
+        let header = std::fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().next().unwrap().to_string();
+        std::fs::write(&input, format!("{}\n", header)).unwrap();
+        let input = input.to_str().unwrap();
+
+        let records = load_clean::load_cleaned_data(input, &RowErrorPolicy::Fail).unwrap();
+        assert!(records.is_empty());
+        let rows = load_clean::read_year_rows(input, 1, &RowErrorPolicy::Fail).unwrap();
+        assert!(rows.is_empty());
+        let dataset = Dataset::load(input).unwrap();
+        assert!(dataset.rows.is_empty());
+
+        assert!(is_empty_input(&eda_statistics::find_top_countries(&rows, 0, 3, 5, RankOrder::Highest).unwrap_err()));
+        assert!(is_empty_input(&*eda_statistics::calculate_average_life_expectancy(&rows, 0, 2, 3).unwrap_err()));
+        assert!(eda_statistics::summary_statistics(&[]).is_none());
+        let correlations = CorrelationAnalysis::compute(&dataset, &["GDP", "BMI"], CorrelationMethod::Pearson, 5);
+        assert!(is_empty_input(&*correlations.unwrap_err()));
+        let exclude = EntityFilter::new(&[]);
+        let graph = graph::build_similarity_graph(input, &[16, 20], &exclude, 0.9, None, None, &CancellationToken::new());
+        assert!(is_empty_input(&*graph.unwrap_err()));
+        assert!(is_empty_input(&*crate::trends::country_trends(&records, "GDP", 2).unwrap_err()));
+        assert!(graph::component_labels(&Graph::new()).is_empty());
+    }
+
+    #[test]
+    fn a_dry_run_reports_a_missing_column_under_its_step() {
+        let dir = crate::test_support::scratch_dir("dry-run");
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "Country,Year,Status,Life expectancy,GDP\nA,2015,Developing,70,1000\nA,20 15,Developing,71,1100\n").unwrap();
+        let profile = DatasetProfile::load(input.to_str().unwrap()).unwrap();
+        assert_eq!((profile.rows, profile.bad_years), (2, 1));
+
+        let config = Config::default();
+        let args = args(&["--features", "GDP, Happiness"]);
+        let run = RunMetadata::new(input.to_str().unwrap(), "none").unwrap();
+        let pipeline = Pipeline::new(input.to_str().unwrap(), &config, &args, &run).with_steps(&[Step::Clean, Step::Graph]);
+        let issues = pipeline.validate(&profile).unwrap();
+
+        assert_eq!(issues.iter().map(|(step, _)| *step).collect::<Vec<_>>(), vec![Step::Clean, Step::Graph]);
+        let graph_errors: Vec<&str> = issues[1].1.iter().filter(|issue| issue.severity == Severity::Error).map(|issue| issue.message.as_str()).collect();
+        assert_eq!(graph_errors.len(), 1);
+        assert!(graph_errors[0].contains("unknown column \"Happiness\""), "{}", graph_errors[0]);
+        let clean = &issues[0].1;
+        assert!(clean.iter().any(|issue| issue.severity == Severity::Error && issue.message == "cleaning: column \"Schooling\" not found in the input"));
+        assert!(clean.iter().any(|issue| issue.severity == Severity::Warning && issue.message.starts_with("1 rows have a Year")));
+        assert!(!dir.join("cleaned_data.csv").exists());
+    }
+
+    #[test]
+    fn a_dry_run_rejects_a_threshold_outside_zero_to_one() {
+        let dir = crate::test_support::scratch_dir("dry-run-threshold");
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "Country,Year,Status,Life expectancy,GDP,Population\nA,2015,Developing,70,1000,5000\n").unwrap();
+        let input = input.to_str().unwrap();
+        let profile = DatasetProfile::load(input).unwrap();
+        let (args, run) = (args(&[]), RunMetadata::new(input, "none").unwrap());
+        let graph_errors = |config: &Config| -> Vec<String> {
+            let issues = Pipeline::new(input, config, &args, &run).with_steps(&[Step::Clean, Step::Graph]).validate(&profile).unwrap();
+            issues[1].1.iter().filter(|issue| issue.severity == Severity::Error).map(|issue| issue.message.clone()).collect()
+        };
+
+        assert!(graph_errors(&Config::default()).is_empty());
+        let config: Config = toml::from_str("[graph]\nthreshold = 1.5\n").unwrap();
+        assert_eq!(graph_errors(&config), vec!["similarity threshold 1.5 is outside [0, 1]"]);
+        let error = run_graph_stage(input, &config, &args, &run, &CancellationToken::new(), None).unwrap_err();
+        assert_eq!(error.to_string(), "Similarity threshold 1.5 is outside [0, 1]");
+    }
+
+    #[test]
+    fn the_cluster_command_runs_from_the_workspace_after_the_input_is_deleted() {
+        let dir = crate::test_support::scratch_dir("cluster-deleted-input");
+        let (input, workspace) = (dir.join("input.csv"), dir.join("workspace"));
+        let input = input.to_str().unwrap();
+        let records = crate::synthetic::generate_synthetic_dataset(12, 2000..=2003, 6);
+        crate::synthetic::write_dataset_csv(&records, input).unwrap();
+        let config = Config::default();
+        let cluster = |extra: &[&str]| {
+            let mut values = vec!["cluster", "--workspace", workspace.to_str().unwrap()];
+            values.extend(extra);
+            args(&values)
+        };
+        let run = RunMetadata::new(input, "none").unwrap();
+        let cancel = CancellationToken::new();
+
+        let first = run_graph_stage(input, &config, &cluster(&[]), &run, &cancel, None).unwrap();
+        for name in ["headers.json", "records.json", "features.json", "graph.json", "similarity.json"] {
+            assert!(workspace.join(name).exists(), "{}", name);
+        }
+        std::fs::remove_file(input).unwrap();
+
+        let second = run_graph_stage(input, &config, &cluster(&[]), &run, &cancel, None).unwrap();
+        assert_eq!(second, first);
+        assert_eq!(second.len(), 12);
+        // Rebuilding needs the file, so the second run read only the workspace
+        assert!(run_graph_stage(input, &config, &cluster(&["--no-cache"]), &run, &cancel, None).is_err());
+    }
+
+    // Modification time of every file under `dir`, by name
+    #[cfg(feature = "plots")]
+    fn modified_times(dir: &Path) -> BTreeMap<String, std::time::SystemTime> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry.metadata().unwrap().modified().unwrap()))
+            .collect()
+    }
+
+    #[cfg(feature = "plots")]
+    #[test]
+    fn a_second_run_with_the_same_inputs_writes_nothing() {
+        let _recording = crate::test_support::RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = crate::test_support::scratch_dir("stale-outputs");
+        let plots = dir.join("plots");
+        let plots_dir = plots.to_str().unwrap();
+        let input = dir.join("input.csv");
+        let rows: Vec<String> = std::fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().take(200).map(String::from).collect();
+        std::fs::write(&input, rows.join("\n")).unwrap();
+        let input = input.to_str().unwrap();
+        let config: Config = toml::from_str("[[plots]]\nkind = \"histogram\"\nfeature = \"Life expectancy\"\nbins = 10\n").unwrap();
+        let args = args(&[]);
+        let run = RunMetadata::new(input, "none").unwrap();
+        // Runs the specs step after `previous`, returning what it recorded
+        let run_specs = |config: &Config, previous: Manifest| {
+            manifest::finish();
+            let results = Pipeline::new(input, config, &args, &run).with_steps(&[Step::Specs]).plots_dir(plots_dir).reuse_outputs(previous).run().unwrap();
+            (results.cached, manifest::finish())
+        };
+
+        let (cached, first) = run_specs(&config, Manifest::default());
+        assert!(cached.is_empty());
+        assert_eq!(first.artifacts.len(), 1);
+        let written = modified_times(&plots);
+        assert_eq!(written.keys().collect::<Vec<_>>(), ["01_histogram_life_expectancy_10.png"]);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let (cached, second) = run_specs(&config, Manifest { artifacts: first.artifacts.clone(), inputs: first.inputs.clone() });
+        assert_eq!(cached, vec![Step::Specs]);
+        assert_eq!(modified_times(&plots), written);
+        assert!(second.artifacts.iter().all(|artifact| artifact.cached));
+        assert_eq!(second.artifacts[0].sha256, first.artifacts[0].sha256);
+
+        // A changed spec, or forcing with an empty manifest, writes the plot again
+        let rebinned: Config = toml::from_str("[[plots]]\nkind = \"histogram\"\nfeature = \"Life expectancy\"\nbins = 12\n").unwrap();
+        let (cached, _) = run_specs(&rebinned, second);
+        assert!(cached.is_empty());
+        assert_ne!(modified_times(&plots), written);
+        let (cached, _) = run_specs(&config, Manifest::default());
+        assert!(cached.is_empty());
+    }
+
+    #[cfg(not(feature = "plots"))]
+    #[test]
+    fn without_plots_the_chart_steps_warn_and_draw_nothing() {
+        let _recording = crate::test_support::RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = crate::test_support::scratch_dir("no-plots");
+        let input = dir.join("input.csv");
+        let rows: Vec<String> = std::fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().take(50).map(String::from).collect();
+        std::fs::write(&input, rows.join("\n")).unwrap();
+        let input = input.to_str().unwrap();
+        let profile = DatasetProfile::load(input).unwrap();
+        let config: Config = toml::from_str("[[plots]]\nkind = \"histogram\"\nfeature = \"Life expectancy\"\n").unwrap();
+        let (args, run) = (args(&[]), RunMetadata::new(input, "none").unwrap());
+        let pipeline = |steps| Pipeline::new(input, &config, &args, &run).with_steps(steps);
+
+        let issues = pipeline(&[Step::Clean, Step::Charts, Step::Specs]).validate(&profile).unwrap();
+        let messages: Vec<(Step, Severity, &str)> = issues
+            .iter()
+            .flat_map(|(step, issues)| issues.iter().map(move |issue| (*step, issue.severity, issue.message.as_str())))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (Step::Charts, Severity::Warning, "no charts are drawn: built without the plots feature"),
+                (Step::Specs, Severity::Warning, "configured plots are not rendered: built without the plots feature"),
+            ]
+        );
+
+        manifest::finish();
+        let results = pipeline(&[Step::Specs]).run().unwrap();
+        assert!(results.failures.is_empty() && results.produced_plots.is_empty());
+        assert!(manifest::finish().artifacts.is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+#[cfg(feature = "plots")]
+use std::error::Error;
+#[cfg(feature = "plots")]
+use std::ops::Range;
+use serde::Deserialize;
+#[cfg(feature = "plots")]
+use crate::eda_statistics::quantile;
+#[cfg(feature = "plots")]
+use crate::load_clean::Dataset;
+
+// One chart described in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum PlotSpec {
+    Scatter {
+        x: String,
+        y: String,
+        group_by: Option<String>,
+        #[serde(default)]
+        #[cfg(feature = "plots")]
+        annotate_extremes: usize, // label the points farthest from the trend line
+        #[serde(default)]
+        #[cfg(feature = "plots")]
+        x_limits: AxisLimits,
+        #[serde(default)]
+        #[cfg(feature = "plots")]
+        y_limits: AxisLimits,
+    },
+    LineByGroup {
+        feature: String,
+        group: String,
+        #[serde(default)]
+        #[cfg(feature = "plots")]
+        y_limits: AxisLimits,
+    },
+    Histogram {
+        feature: String,
+        #[cfg(feature = "plots")]
+        bins: usize,
+    },
+    Heatmap { features: Vec<String> },
+}
+
+impl PlotSpec {
+    // Every column name the spec refers to
+    pub(crate) fn columns(&self) -> Vec<&str> {
+        match self {
+            PlotSpec::Scatter { x, y, group_by, .. } => {
+                let mut columns = vec![x.as_str(), y.as_str()];
+                columns.extend(group_by.as_deref());
+                columns
+            }
+            PlotSpec::LineByGroup { feature, group, .. } => vec![feature, group, "Year"],
+            PlotSpec::Histogram { feature, .. } => vec![feature],
+            PlotSpec::Heatmap { features } => features.iter().map(String::as_str).collect(),
+        }
+    }
+
+    // Deterministic output file name, e.g. 01_scatter_gdp_vs_life_expectancy.png
+    #[cfg(feature = "plots")]
+    pub(crate) fn file_name(&self, index: usize) -> String {
+        let name = match self {
+            PlotSpec::Scatter { x, y, .. } => format!("scatter_{}_vs_{}", y, x),
+            PlotSpec::LineByGroup { feature, group, .. } => format!("line_{}_by_{}", feature, group),
+            PlotSpec::Histogram { feature, bins } => format!("histogram_{}_{}", feature, bins),
+            PlotSpec::Heatmap { features } => format!("heatmap_{}", features.len()),
+        };
+        let slug: String = name
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let slug = slug.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_");
+        format!("{:02}_{}.png", index + 1, slug)
+    }
+}
+
+// How the range of a chart axis is chosen. In the config: "full",
+// { percentile = [1, 99] } or { fixed = { start = 0, end = 100 } }.
+#[cfg(feature = "plots")]
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AxisLimits {
+    #[default]
+    Full,                 // every value; the chart picks its usual range
+    Percentile(f64, f64), // between two percentiles of the values, e.g. 1 and 99
+    Fixed(Range<f64>),
+}
+
+#[cfg(feature = "plots")]
+impl AxisLimits {
+    // Bounds of the axis over `values`; None for Full, or when there are no finite values
+    pub(crate) fn bounds(&self, values: &[f64]) -> Option<(f64, f64)> {
+        match self {
+            AxisLimits::Full => None,
+            AxisLimits::Fixed(range) => Some((range.start, range.end)),
+            AxisLimits::Percentile(low, high) => {
+                let mut sorted: Vec<f64> = values.iter().copied().filter(|value| value.is_finite()).collect();
+                if sorted.is_empty() {
+                    return None;
+                }
+                sorted.sort_by(f64::total_cmp);
+                Some((quantile(&sorted, low / 100.0), quantile(&sorted, high / 100.0)))
+            }
+        }
+    }
+}
+
+// A value moved onto the nearest bound, and whether it had to move
+#[cfg(feature = "plots")]
+pub(crate) fn pin(value: f64, (low, high): (f64, f64)) -> (f64, bool) {
+    let pinned = value.clamp(low, high);
+    (pinned, pinned != value)
+}
+
+// Every point pinned into the x and y bounds, with whether it was beyond either
+#[cfg(feature = "plots")]
+pub(crate) fn pin_points(points: &[(f64, f64)], x: (f64, f64), y: (f64, f64)) -> Vec<((f64, f64), bool)> {
+    points
+        .iter()
+        .map(|&(px, py)| {
+            let ((px, x_moved), (py, y_moved)) = (pin(px, x), pin(py, y));
+            ((px, py), x_moved || y_moved)
+        })
+        .collect()
+}
+
+// Caption of the pinned-point count, e.g. "3 points beyond range"
+#[cfg(feature = "plots")]
+pub(crate) fn beyond_range_label(count: usize) -> String {
+    format!("{} point{} beyond range", count, if count == 1 { "" } else { "s" })
+}
+
+// Equal-width histogram bins spanning a set of values, so several series can share them
+#[cfg(feature = "plots")]
+pub(crate) struct Bins {
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) width: f64,
+    bins: usize,
+}
+
+#[cfg(feature = "plots")]
+impl Bins {
+    // `bins` bins over the range of `values`; [0, 1] when the range is empty
+    pub(crate) fn covering(values: &[f64], bins: usize) -> Bins {
+        let bins = bins.max(1);
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let (min, max) = if min.is_finite() && max > min { (min, max) } else { (0.0, 1.0) };
+        Bins::spanning((min, max), bins)
+    }
+
+    // `bins` bins from `min` to `max` exactly, e.g. the range of a chart axis;
+    // `max` must be above `min`
+    pub(crate) fn spanning((min, max): (f64, f64), bins: usize) -> Bins {
+        let bins = bins.max(1);
+        Bins { min, max, width: (max - min) / bins as f64, bins }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.bins
+    }
+
+    pub(crate) fn counts(&self, values: &[f64]) -> Vec<usize> {
+        let mut counts = vec![0usize; self.bins];
+        for value in values {
+            let bin = (((value - self.min) / self.width).max(0.0) as usize).min(self.bins - 1);
+            counts[bin] += 1;
+        }
+        counts
+    }
+
+    // Lower edge of bin i
+    pub(crate) fn start(&self, i: usize) -> f64 {
+        self.min + i as f64 * self.width
+    }
+}
+
+// Fails naming every spec column that is not in the dataset, so a typo fails the
+// batch before any rendering
+#[cfg(feature = "plots")]
+pub(crate) fn check_columns(dataset: &Dataset, specs: &[PlotSpec]) -> Result<(), Box<dyn Error>> {
+    let unknown: Vec<String> = specs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, spec)| {
+            spec.columns()
+                .into_iter()
+                .filter(|column| dataset.column_index(column).is_err())
+                .map(move |column| format!("plot {}: \"{}\"", i + 1, column))
+        })
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Unknown feature names in plot specs: {}; available columns: {}",
+            unknown.join(", "),
+            dataset.headers.iter().map(|h| h.trim()).collect::<Vec<_>>().join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "plots")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_and_cleaned_values_share_their_bins() {
+        let raw = [1.0, 2.0, 9.0];
+        let cleaned = [1.0, 2.0, 9.0, 5.0, 5.0];
+        let all: Vec<f64> = raw.iter().chain(&cleaned).copied().collect();
+        let bins = Bins::covering(&all, 4);
+        assert_eq!((bins.min, bins.max, bins.width, bins.bins), (1.0, 9.0, 2.0, 4));
+        // The maximum belongs to the last bin
+        assert_eq!(bins.counts(&raw), vec![2, 0, 0, 1]);
+        assert_eq!(bins.counts(&cleaned), vec![2, 0, 2, 1]);
+        assert_eq!(bins.start(3), 7.0);
+    }
+
+    #[test]
+    fn bins_of_a_single_value_fall_back_to_the_unit_range() {
+        let bins = Bins::covering(&[3.0, 3.0], 0);
+        assert_eq!((bins.min, bins.max, bins.bins), (0.0, 1.0, 1));
+        assert_eq!(bins.counts(&[3.0, -2.0]), vec![2]);
+    }
+
+    #[test]
+    fn points_beyond_the_percentile_limits_are_pinned_and_counted() {
+        let values: Vec<f64> = (0..=100).map(f64::from).chain([1e6]).collect();
+        let bounds = AxisLimits::Percentile(1.0, 99.0).bounds(&values).unwrap();
+        assert!((bounds.0 - 1.01).abs() < 1e-9 && (bounds.1 - 99.99).abs() < 1e-9, "{:?}", bounds);
+        assert_eq!(AxisLimits::Full.bounds(&values), None);
+        assert_eq!(AxisLimits::Fixed(0.0..10.0).bounds(&values), Some((0.0, 10.0)));
+        assert_eq!(AxisLimits::Percentile(1.0, 99.0).bounds(&[f64::NAN]), None);
+
+        let points = [(5.0, 5.0), (-1.0, 5.0), (5.0, 20.0), (11.0, -3.0), (10.0, 0.0)];
+        let pinned = pin_points(&points, (0.0, 10.0), (0.0, 10.0));
+        let moved = pinned.iter().filter(|(_, moved)| *moved).count();
+        assert_eq!(moved, 3);
+        assert_eq!(pinned[3], ((10.0, 0.0), true));
+        assert_eq!(pinned[4], ((10.0, 0.0), false));
+        assert_eq!(beyond_range_label(moved), "3 points beyond range");
+        assert_eq!(beyond_range_label(1), "1 point beyond range");
+    }
+}
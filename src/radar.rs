@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "plots")]
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(feature = "plots")]
+use std::f64::consts::PI;
+#[cfg(feature = "plots")]
+use plotters::prelude::*;
+#[cfg(feature = "plots")]
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+#[cfg(feature = "plots")]
+use crate::analysis_error::AnalysisError;
+#[cfg(feature = "plots")]
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+#[cfg(feature = "plots")]
+use crate::colormap::PALETTE;
+use crate::eda_statistics::{percentile_rank, summary_statistics};
+use crate::fields::LifeExpectancyRecord;
+use crate::graph::find_country;
+#[cfg(feature = "plots")]
+use crate::metadata::{self, RunMetadata};
+#[cfg(feature = "plots")]
+use crate::output;
+use crate::series::field_meta;
+
+// Spokes of a profile radar: health, wealth and education
+pub(crate) const RADAR_FEATURES: [&str; 8] = [
+    "Life expectancy",
+    "Adult Mortality",
+    "HIV/AIDS",
+    "BMI",
+    "Alcohol",
+    "GDP",
+    "Income composition of resources",
+    "Schooling",
+];
+
+// Mean of each feature per country, None where the country has no value
+type CountryMeans = BTreeMap<String, Vec<Option<f64>>>;
+// Percentile (0 to 100) of each feature with a value, in feature order
+pub(crate) type Percentiles = Vec<(String, f64)>;
+
+// Mean of each feature per country over its years with a value, by country name
+fn country_means(records: &[LifeExpectancyRecord], features: &[&str]) -> Result<CountryMeans, Box<dyn Error>> {
+    let metas = features.iter().map(|name| field_meta(name)).collect::<Result<Vec<_>, _>>()?;
+    let mut by_country: BTreeMap<&str, Vec<&LifeExpectancyRecord>> = BTreeMap::new();
+    for record in records {
+        by_country.entry(record.country.as_str()).or_default().push(record);
+    }
+    Ok(by_country
+        .into_iter()
+        .map(|(country, rows)| {
+            let means = metas
+                .iter()
+                .map(|meta| {
+                    let values: Vec<Option<f64>> = rows.iter().map(|row| (meta.getter)(row)).collect();
+                    summary_statistics(&values).map(|stats| stats.mean)
+                })
+                .collect();
+            (country.to_string(), means)
+        })
+        .collect())
+}
+
+// Percentile rank of each value of `profile` among the country means of that
+// feature; features where the profile has no value are left out
+fn percentiles(means: &CountryMeans, profile: &[Option<f64>], features: &[&str]) -> Percentiles {
+    features
+        .iter()
+        .enumerate()
+        .filter_map(|(i, feature)| {
+            let column: Vec<f64> = means.values().filter_map(|row| row[i]).collect();
+            Some((feature.to_string(), percentile_rank(&column, profile[i]?)?))
+        })
+        .collect()
+}
+
+// Percentile of the country's mean of every feature among all countries' means.
+// Returns the country's name as found in the data with the percentiles.
+pub(crate) fn country_percentiles(
+    records: &[LifeExpectancyRecord],
+    country: &str,
+    features: &[&str],
+) -> Result<(String, Percentiles), Box<dyn Error>> {
+    let means = country_means(records, features)?;
+    let countries: Vec<String> = means.keys().cloned().collect();
+    let name = countries[find_country(&countries, country)?].clone();
+    Ok((name.clone(), percentiles(&means, &means[&name], features)))
+}
+
+// Percentiles of a cluster's centroid, the mean over its countries of their
+// feature means, among all countries' means
+#[cfg(feature = "plots")]
+pub(crate) fn cluster_percentiles(
+    records: &[LifeExpectancyRecord],
+    memberships: &HashMap<String, usize>,
+    cluster: usize,
+    features: &[&str],
+) -> Result<Percentiles, Box<dyn Error>> {
+    let means = country_means(records, features)?;
+    let members: Vec<&Vec<Option<f64>>> =
+        means.iter().filter(|(country, _)| memberships.get(*country) == Some(&cluster)).map(|(_, row)| row).collect();
+    if members.is_empty() {
+        return Err(AnalysisError::empty(format!("Cluster {}", cluster)).into());
+    }
+    let centroid: Vec<Option<f64>> = (0..features.len())
+        .map(|i| summary_statistics(&members.iter().map(|row| row[i]).collect::<Vec<_>>()).map(|stats| stats.mean))
+        .collect();
+    Ok(percentiles(&means, &centroid, features))
+}
+
+pub(crate) fn print_percentiles(subject: &str, percentiles: &[(String, f64)]) {
+    println!("Percentile among countries for {}:", subject);
+    for (feature, percentile) in percentiles {
+        println!("  {:<32} {:>5.1}", feature, percentile);
+    }
+}
+
+// Angle of spoke `i` of `n` in screen coordinates (y down): the first spoke
+// points straight up and the others follow clockwise
+#[cfg(feature = "plots")]
+pub(crate) fn spoke_angle(i: usize, n: usize) -> f64 {
+    -PI / 2.0 + 2.0 * PI * i as f64 / n as f64
+}
+
+// Pixel at `fraction` of `radius` along the spoke at `angle`
+#[cfg(feature = "plots")]
+pub(crate) fn spoke_point(center: (i32, i32), radius: f64, angle: f64, fraction: f64) -> (i32, i32) {
+    let r = radius * fraction;
+    (center.0 + (r * angle.cos()).round() as i32, center.1 + (r * angle.sin()).round() as i32)
+}
+
+// Anchor of a label at the end of a spoke, so the text extends away from the
+// chart: spokes on the right start their label there, spokes on the left end it
+#[cfg(feature = "plots")]
+pub(crate) fn label_anchor(angle: f64) -> Pos {
+    let (x, y) = (angle.cos(), angle.sin());
+    let h = if x > 0.2 { HPos::Left } else if x < -0.2 { HPos::Right } else { HPos::Center };
+    let v = if y > 0.2 { VPos::Top } else if y < -0.2 { VPos::Bottom } else { VPos::Center };
+    Pos::new(h, v)
+}
+
+// Radar chart of percentiles (0 to 100), one spoke per feature with rings at 25,
+// 50, 75 and 100 and the profile as a filled translucent polygon
+#[cfg(feature = "plots")]
+pub(crate) fn plot_radar(percentiles: &[(String, f64)], title: &str, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let n = percentiles.len();
+    if n < 3 {
+        return Err(AnalysisError::empty(format!("{} (fewer than 3 features with a value)", title)).into());
+    }
+    let (width, height) = (1200u32, 860u32);
+    let center = (width as i32 / 2, height as i32 / 2 + 20);
+    let radius = 280.0;
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (width, height)).into_drawing_area();
+    root.fill(&WHITE)?;
+    root.draw(&Text::new(
+        title.to_string(),
+        (width as i32 / 2, 24),
+        TextStyle::from(("sans-serif", 28).into_font()).pos(Pos::new(HPos::Center, VPos::Top)),
+    ))?;
+
+    let grid = RGBColor(200, 200, 200);
+    let ring_label = ("sans-serif", 13).into_font().color(&RGBColor(120, 120, 120));
+    for ring in [0.25, 0.5, 0.75, 1.0] {
+        let mut points: Vec<(i32, i32)> = (0..n).map(|i| spoke_point(center, radius, spoke_angle(i, n), ring)).collect();
+        points.push(points[0]);
+        root.draw(&PathElement::new(points, grid.stroke_width(1)))?;
+        let (x, y) = spoke_point(center, radius, spoke_angle(0, n), ring);
+        root.draw(&Text::new(format!("{}", ring * 100.0), (x + 4, y), ring_label.clone()))?;
+    }
+    let label = TextStyle::from(("sans-serif", 16).into_font());
+    for (i, (feature, percentile)) in percentiles.iter().enumerate() {
+        let angle = spoke_angle(i, n);
+        root.draw(&PathElement::new(vec![center, spoke_point(center, radius, angle, 1.0)], grid.stroke_width(1)))?;
+        let text = format!("{} ({:.0})", feature, percentile);
+        root.draw(&Text::new(text, spoke_point(center, radius, angle, 1.06), label.clone().pos(label_anchor(angle))))?;
+    }
+
+    let color = PALETTE[0];
+    let outline: Vec<(i32, i32)> =
+        percentiles.iter().enumerate().map(|(i, (_, p))| spoke_point(center, radius, spoke_angle(i, n), p / 100.0)).collect();
+    root.draw(&Polygon::new(outline.clone(), color.mix(0.3).filled()))?;
+    let mut closed = outline.clone();
+    closed.push(outline[0]);
+    root.draw(&PathElement::new(closed, color.stroke_width(2)))?;
+    for &point in &outline {
+        root.draw(&Circle::new(point, 4, color.filled()))?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // Spoke index against percentile, in spoke order clockwise from the top
+    chart_json::write_sidecar(output_file, run, || {
+        let features = percentiles.iter().map(|(feature, _)| feature.clone()).collect();
+        let points = percentiles.iter().enumerate().map(|(i, &(_, p))| (i as f64, p)).collect();
+        ChartSpecJson::new("radar", title, AxisJson::categories("Feature", features), AxisJson::new("Percentile", (0.0, 100.0)))
+            .series(SeriesJson::new(title, Some(color), points))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_rank_country_means() {
+        let record = |country: &str, gdp: f64, schooling: Option<f64>| LifeExpectancyRecord { country: country.to_string(), gdp: Some(gdp), schooling, ..Default::default() };
+        // Chad averages 300 over two years; it has no schooling value at all
+        let records = [
+            record("Austria", 400.0, Some(15.0)),
+            record("Benin", 100.0, Some(8.0)),
+            record("Chad", 250.0, None),
+            record("Chad", 350.0, None),
+            record("Denmark", 200.0, Some(12.0)),
+        ];
+        let (name, percentiles) = country_percentiles(&records, "chad", &["GDP", "Schooling"]).unwrap();
+        assert_eq!(name, "Chad");
+        assert_eq!(percentiles, vec![("GDP".to_string(), 62.5)]);
+        let (_, austria) = country_percentiles(&records, "Austria", &["GDP", "Schooling"]).unwrap();
+        assert_eq!(austria, vec![("GDP".to_string(), 87.5), ("Schooling".to_string(), 100.0 * 2.5 / 3.0)]);
+        assert!(country_percentiles(&records, "Atlantis", &["GDP"]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn spokes_start_at_the_top_and_go_clockwise() {
+        let angles: Vec<f64> = (0..4).map(|i| spoke_angle(i, 4)).collect();
+        let ends: Vec<(i32, i32)> = angles.iter().map(|&angle| spoke_point((100, 100), 50.0, angle, 1.0)).collect();
+        // Screen y grows downwards: up, right, down, left
+        assert_eq!(ends, vec![(100, 50), (150, 100), (100, 150), (50, 100)]);
+        assert_eq!(spoke_point((100, 100), 50.0, angles[1], 0.5), (125, 100));
+        assert_eq!(spoke_point((100, 100), 50.0, angles[3], 0.0), (100, 100));
+        assert!((spoke_angle(7, 8) - spoke_angle(0, 8) - 7.0 * PI / 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn labels_extend_away_from_the_chart() {
+        let anchor = |i: usize, n: usize| {
+            let pos = label_anchor(spoke_angle(i, n));
+            (pos.h_pos, pos.v_pos)
+        };
+        assert!(matches!(anchor(0, 4), (HPos::Center, VPos::Bottom)));
+        assert!(matches!(anchor(1, 4), (HPos::Left, VPos::Center)));
+        assert!(matches!(anchor(2, 4), (HPos::Center, VPos::Top)));
+        assert!(matches!(anchor(3, 4), (HPos::Right, VPos::Center)));
+        // Diagonal spokes of an 8-spoke chart lean both ways
+        assert!(matches!(anchor(1, 8), (HPos::Left, VPos::Bottom)));
+        assert!(matches!(anchor(5, 8), (HPos::Right, VPos::Top)));
+    }
+}
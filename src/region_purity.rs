@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::error::Error;
+use petgraph::Graph;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use crate::analysis_error::AnalysisError;
+use crate::cancel::CancellationToken;
+use crate::columns;
+use crate::graph::load_country_status;
+
+// Neighbor counts between countries, with the nodes of one country (one per
+// year) merged. Edges between two nodes of the same country are left out, since
+// a country trivially shares its own region.
+struct CountryNeighbors<'a> {
+    countries: Vec<&'a str>,
+    counts: HashMap<(usize, usize), usize>, // both directions
+}
+
+impl<'a> CountryNeighbors<'a> {
+    fn new(graph: &'a Graph<String, f64>) -> Self {
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        let mut countries = Vec::new();
+        let node_ids: Vec<usize> = graph
+            .node_indices()
+            .map(|node| {
+                *ids.entry(graph[node].as_str()).or_insert_with(|| {
+                    countries.push(graph[node].as_str());
+                    countries.len() - 1
+                })
+            })
+            .collect();
+
+        let mut counts = HashMap::new();
+        for edge in graph.edge_indices() {
+            let (a, b) = graph.edge_endpoints(edge).unwrap();
+            let (a, b) = (node_ids[a.index()], node_ids[b.index()]);
+            if a != b {
+                *counts.entry((a, b)).or_insert(0) += 1;
+                *counts.entry((b, a)).or_insert(0) += 1;
+            }
+        }
+        CountryNeighbors { countries, counts }
+    }
+
+    // Share of each country's neighbors (among those with a label) carrying its own
+    // label; None for countries without a label or without labelled neighbors
+    fn purity(&self, labels: &[Option<&str>]) -> Vec<Option<f64>> {
+        let mut totals = vec![(0, 0); self.countries.len()]; // (same label, labelled)
+        for (&(country, neighbor), &count) in &self.counts {
+            let (Some(own), Some(other)) = (labels[country], labels[neighbor]) else {
+                continue;
+            };
+            totals[country].1 += count;
+            if own == other {
+                totals[country].0 += count;
+            }
+        }
+        totals
+            .into_iter()
+            .zip(labels)
+            .map(|((same, labelled), label)| (label.is_some() && labelled > 0).then(|| same as f64 / labelled as f64))
+            .collect()
+    }
+}
+
+fn mean(values: &[Option<f64>]) -> Option<f64> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    (!present.is_empty()).then(|| present.iter().sum::<f64>() / present.len() as f64)
+}
+
+// Fraction of each country's graph neighbors that share its region, most
+// regionally pure first. Countries without a region, or whose neighbors all lack
+// one, are left out.
+pub(crate) fn neighbor_region_purity(graph: &Graph<String, f64>, regions: &HashMap<String, String>) -> Vec<(String, f64)> {
+    let neighbors = CountryNeighbors::new(graph);
+    let labels: Vec<Option<&str>> = neighbors.countries.iter().map(|country| regions.get(*country).map(String::as_str)).collect();
+    let mut purity: Vec<(String, f64)> = neighbors
+        .countries
+        .iter()
+        .zip(neighbors.purity(&labels))
+        .filter_map(|(country, purity)| Some((country.to_string(), purity?)))
+        .collect();
+    purity.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    purity
+}
+
+// Mean purity against region labels shuffled among the labelled countries
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PurityTest {
+    pub(crate) mean: f64,
+    pub(crate) null_mean: f64,
+    pub(crate) p_value: f64, // share of shuffles at least as pure, with the +1 correction
+}
+
+pub(crate) fn region_purity_test(
+    graph: &Graph<String, f64>,
+    regions: &HashMap<String, String>,
+    permutations: usize,
+    seed: u64,
+    cancel: &CancellationToken,
+) -> Result<Option<PurityTest>, AnalysisError> {
+    let neighbors = CountryNeighbors::new(graph);
+    let mut labels: Vec<Option<&str>> =
+        neighbors.countries.iter().map(|country| regions.get(*country).map(String::as_str)).collect();
+    let Some(observed) = mean(&neighbors.purity(&labels)) else {
+        return Ok(None);
+    };
+
+    // Permute the labels only among the countries that have one
+    let labelled: Vec<usize> = (0..labels.len()).filter(|&i| labels[i].is_some()).collect();
+    let mut pool: Vec<Option<&str>> = labelled.iter().map(|&i| labels[i]).collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut null = Vec::with_capacity(permutations);
+    for _ in 0..permutations {
+        cancel.check()?;
+        pool.shuffle(&mut rng);
+        for (&i, &label) in labelled.iter().zip(&pool) {
+            labels[i] = label;
+        }
+        null.extend(mean(&neighbors.purity(&labels)));
+    }
+
+    let null_mean = null.iter().sum::<f64>() / null.len().max(1) as f64;
+    let at_least = null.iter().filter(|&&purity| purity >= observed).count();
+    Ok(Some(PurityTest {
+        mean: observed,
+        null_mean,
+        p_value: (at_least + 1) as f64 / (null.len() + 1) as f64,
+    }))
+}
+
+// Country -> region from a CSV with "Country" and "Region" columns
+pub(crate) fn load_regions(file_path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let region_columns = columns::resolve_columns(file_path, &["Country", "Region"])?;
+    load_country_status(file_path, region_columns[0], region_columns[1])
+}
+
+// Mean purity with its permutation test, and the most and least regionally pure countries
+pub(crate) fn print_region_purity(purity: &[(String, f64)], test: &PurityTest, labels: &str, limit: usize) {
+    println!(
+        "Neighbors sharing the country's {}: mean {:.3} vs {:.3} with shuffled labels (p = {:.3})",
+        labels, test.mean, test.null_mean, test.p_value
+    );
+    println!("Most regionally pure:");
+    for (country, share) in purity.iter().take(limit) {
+        println!("  {}: {:.3}", country, share);
+    }
+    println!("Least regionally pure:");
+    for (country, share) in purity.iter().rev().take(limit) {
+        println!("  {}: {:.3}", country, share);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two cliques of four countries, one per region, joined by the edge D-E, with
+    // a second node of A and an unlabelled country X next to it
+    fn two_regions() -> (Graph<String, f64>, HashMap<String, String>) {
+        let mut graph = Graph::new();
+        let countries = ["A", "B", "C", "D", "E", "F", "G", "H", "A", "X"];
+        let nodes: Vec<_> = countries.iter().map(|country| graph.add_node(country.to_string())).collect();
+        for group in [0..4, 4..8] {
+            for i in group.clone() {
+                for j in (i + 1)..group.end {
+                    graph.add_edge(nodes[i], nodes[j], 0.9);
+                }
+            }
+        }
+        graph.add_edge(nodes[3], nodes[4], 0.9);
+        graph.add_edge(nodes[0], nodes[8], 0.9);
+        graph.add_edge(nodes[8], nodes[9], 0.9);
+        let regions = countries[..8]
+            .iter()
+            .enumerate()
+            .map(|(i, country)| (country.to_string(), if i < 4 { "Europe" } else { "Asia" }.to_string()))
+            .collect();
+        (graph, regions)
+    }
+
+    #[test]
+    fn purity_is_the_share_of_neighbors_in_the_same_region() {
+        let (graph, regions) = two_regions();
+        let purity = neighbor_region_purity(&graph, &regions);
+        let expected: Vec<(String, f64)> = ["A", "B", "C", "F", "G", "H"]
+            .iter()
+            .map(|country| (country.to_string(), 1.0))
+            .chain([("D".to_string(), 0.75), ("E".to_string(), 0.75)])
+            .collect();
+        assert_eq!(purity, expected);
+    }
+
+    #[test]
+    fn the_permutation_test_is_seeded() {
+        let (graph, regions) = two_regions();
+        let cancel = CancellationToken::new();
+        let test = region_purity_test(&graph, &regions, 200, 42, &cancel).unwrap().unwrap();
+        assert!((test.mean - 15.0 / 16.0).abs() < 1e-12);
+        assert!(test.null_mean < test.mean);
+        // Only the two perfect splits of 70 labellings are as pure
+        assert!(test.p_value < 0.1, "{}", test.p_value);
+        let again = region_purity_test(&graph, &regions, 200, 42, &cancel).unwrap().unwrap();
+        assert_eq!((again.null_mean, again.p_value), (test.null_mean, test.p_value));
+
+        assert!(region_purity_test(&graph, &HashMap::new(), 10, 42, &cancel).unwrap().is_none());
+    }
+}
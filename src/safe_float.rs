@@ -0,0 +1,102 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// f64 for JSON outputs and caches. JSON has no NaN or infinity, so those are
+// written as null, and null reads back as NaN: a value that was not finite stays
+// not finite, and a cached file holding one still loads.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub(crate) struct SafeF64(pub(crate) f64);
+
+impl From<f64> for SafeF64 {
+    fn from(value: f64) -> Self {
+        SafeF64(value)
+    }
+}
+
+impl From<SafeF64> for f64 {
+    fn from(value: SafeF64) -> Self {
+        value.0
+    }
+}
+
+impl Serialize for SafeF64 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f64(self.0)
+        } else {
+            serializer.serialize_none()
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SafeF64 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(SafeF64(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN)))
+    }
+}
+
+#[cfg(feature = "plots")]
+pub(crate) fn safe_pair((x, y): (f64, f64)) -> (SafeF64, SafeF64) {
+    (SafeF64(x), SafeF64(y))
+}
+
+// Why some of `values` are written as null, e.g. "2 values written as null: 1
+// not a number, 1 infinite"; None when all are finite
+#[cfg(feature = "plots")]
+pub(crate) fn null_reason(values: impl IntoIterator<Item = f64>) -> Option<String> {
+    let (mut nan, mut infinite) = (0, 0);
+    for value in values {
+        if value.is_nan() {
+            nan += 1;
+        } else if value.is_infinite() {
+            infinite += 1;
+        }
+    }
+    let parts: Vec<String> = [(nan, "not a number"), (infinite, "infinite")]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| format!("{} {}", count, kind))
+        .collect();
+    let total = nan + infinite;
+    (total > 0).then(|| format!("{} {} written as null: {}", total, if total == 1 { "value" } else { "values" }, parts.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Fit {
+        slope: SafeF64,
+        r: SafeF64,
+        range: (SafeF64, SafeF64),
+    }
+
+    #[derive(Deserialize)]
+    struct PlainFit {
+        slope: Option<f64>,
+        r: Option<f64>,
+        range: (Option<f64>, Option<f64>),
+    }
+
+    #[test]
+    fn values_that_are_not_finite_are_written_as_null() {
+        let fit = Fit { slope: SafeF64(f64::NAN), r: SafeF64(0.5), range: (SafeF64(f64::NEG_INFINITY), SafeF64(f64::INFINITY)) };
+        let json = serde_json::to_string(&fit).unwrap();
+        assert_eq!(json, r#"{"slope":null,"r":0.5,"range":[null,null]}"#);
+
+        let plain: PlainFit = serde_json::from_str(&json).unwrap();
+        assert_eq!((plain.slope, plain.r, plain.range), (None, Some(0.5), (None, None)));
+        let read: Fit = serde_json::from_str(&json).unwrap();
+        assert!(read.slope.0.is_nan() && read.range.1.0.is_nan());
+        assert_eq!(read.r, SafeF64(0.5));
+    }
+
+    #[cfg(feature = "plots")]
+    #[test]
+    fn the_null_reason_counts_each_kind() {
+        assert_eq!(null_reason([1.0, 2.0]), None);
+        assert_eq!(null_reason([f64::NAN, 1.0]).unwrap(), "1 value written as null: 1 not a number");
+        let reason = null_reason([f64::NAN, f64::INFINITY, f64::NEG_INFINITY]).unwrap();
+        assert_eq!(reason, "3 values written as null: 1 not a number, 2 infinite");
+    }
+}
@@ -0,0 +1,273 @@
+use std::error::Error;
+use std::path::Path;
+use csv::{Reader, StringRecord, Writer};
+use crate::columns;
+use crate::fields::FIELDS;
+use crate::format::group_thousands;
+use crate::numeric::{parse_numeric, DecimalStyle};
+use crate::output;
+
+// Layout of the input file: the WHO dataset the crate was written for, or the
+// updated Kaggle version ("kaggle v2") with underscored names, a 0/1
+// Economy_status_Developed flag instead of Status, and no missing values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SchemaVariant {
+    Who,
+    KaggleV2,
+}
+
+impl SchemaVariant {
+    // Variant from its command-line name ("who" or "v2")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "who" => Ok(SchemaVariant::Who),
+            "v2" => Ok(SchemaVariant::KaggleV2),
+            other => Err(format!("Unknown schema \"{}\" (expected who or v2)", other).into()),
+        }
+    }
+
+    // The variant a header row belongs to: v2 when it has v2's status flag or
+    // underscored life expectancy column
+    pub(crate) fn detect(headers: &[String]) -> Self {
+        let v2 = ["Economy_status_Developed", "Life_expectancy"].iter().any(|name| columns::find_column(headers, name).is_some());
+        if v2 { SchemaVariant::KaggleV2 } else { SchemaVariant::Who }
+    }
+}
+
+// v2 column, the registry field it holds, and the factor converting it to that
+// field's unit
+const V2_COLUMNS: &[(&str, &str, f64)] = &[
+    ("Life_expectancy", "Life expectancy", 1.0),
+    ("Adult_mortality", "Adult Mortality", 1.0),
+    ("Infant_deaths", "infant deaths", 1.0),
+    ("Alcohol_consumption", "Alcohol", 1.0),
+    ("Hepatitis_B", "Hepatitis B", 1.0),
+    ("BMI", "BMI", 1.0),
+    ("Under_five_deaths", "under-five deaths", 1.0),
+    ("Polio", "Polio", 1.0),
+    ("Diphtheria", "Diphtheria", 1.0),
+    ("Incidents_HIV", "HIV/AIDS", 1.0),
+    ("GDP_per_capita", "GDP", 1.0),
+    ("Population_mln", "Population", 1e6),
+    ("Thinness_ten_nineteen_years", "thinness 1-19 years", 1.0),
+    ("Thinness_five_nine_years", "thinness 5-9 years", 1.0),
+    ("Schooling", "Schooling", 1.0),
+];
+// v2 columns read for the record's text columns
+const V2_COUNTRY: &str = "Country";
+const V2_YEAR: &str = "Year";
+const V2_DEVELOPED: &str = "Economy_status_Developed";
+// v2 columns left out on purpose, with the reason reported
+const V2_SKIPPED: &[(&str, &str)] = &[
+    ("Measles", "vaccine coverage in v2, reported cases in the WHO data"),
+    ("Economy_status_Developing", "the complement of Economy_status_Developed"),
+];
+
+// Status of a v2 row from its Economy_status_Developed flag; blank when the flag
+// is neither 0 nor 1, as for a missing status in the WHO data
+fn v2_status(flag: &str) -> &'static str {
+    match flag.trim() {
+        "1" | "1.0" => "Developed",
+        "0" | "0.0" => "Developing",
+        _ => "",
+    }
+}
+
+// Header row of the WHO layout: Country, Year, Status and the registry fields
+fn who_headers() -> Vec<&'static str> {
+    ["Country", "Year", "Status"].into_iter().chain(FIELDS.iter().map(|meta| meta.name)).collect()
+}
+
+// Where each WHO column of a converted row comes from in a v2 header row
+struct V2Mapping {
+    country: usize,
+    year: usize,
+    developed: Option<usize>,
+    fields: Vec<Option<(usize, f64)>>, // per FIELDS entry
+}
+
+impl V2Mapping {
+    fn new(headers: &[String]) -> Result<Self, Box<dyn Error>> {
+        let find = |name: &str| columns::find_column(headers, name);
+        let required = |name: &str| find(name).ok_or_else(|| format!("Kaggle v2 input has no \"{}\" column", name));
+        let fields = FIELDS
+            .iter()
+            .map(|meta| {
+                let (column, _, factor) = V2_COLUMNS.iter().find(|(_, field, _)| *field == meta.name)?;
+                Some((find(column)?, *factor))
+            })
+            .collect();
+        Ok(V2Mapping { country: required(V2_COUNTRY)?, year: required(V2_YEAR)?, developed: find(V2_DEVELOPED), fields })
+    }
+
+    // The v2 row in the WHO layout; cells that do not parse stay blank
+    fn convert(&self, row: &StringRecord) -> Vec<String> {
+        let text = |column: usize| row.get(column).unwrap_or("").trim().to_string();
+        let status = self.developed.map_or("", |column| v2_status(row.get(column).unwrap_or("")));
+        let values = self.fields.iter().map(|source| {
+            source
+                .and_then(|(column, factor)| row.get(column)?.trim().parse::<f64>().ok().map(|value| value * factor))
+                .map_or(String::new(), |value| value.to_string())
+        });
+        [text(self.country), text(self.year), status.to_string()].into_iter().chain(values).collect()
+    }
+
+    // v2 columns nothing reads, and registry fields nothing fills
+    fn report(&self, headers: &[String]) {
+        let used: Vec<&str> =
+            V2_COLUMNS.iter().map(|(column, _, _)| *column).chain([V2_COUNTRY, V2_YEAR, V2_DEVELOPED]).collect();
+        let unmapped: Vec<String> = headers
+            .iter()
+            .filter(|header| !used.iter().any(|name| name.eq_ignore_ascii_case(header)))
+            .map(|header| match V2_SKIPPED.iter().find(|(name, _)| name.eq_ignore_ascii_case(header)) {
+                Some((_, reason)) => format!("{} ({})", header, reason),
+                None => header.clone(),
+            })
+            .collect();
+        let unfilled: Vec<&str> =
+            FIELDS.iter().zip(&self.fields).filter(|(_, source)| source.is_none()).map(|(meta, _)| meta.name).collect();
+        if self.developed.is_none() {
+            println!("Warning: no {} column; every Status is blank", V2_DEVELOPED);
+        }
+        if !unmapped.is_empty() {
+            println!("Unmapped v2 columns: {}", unmapped.join(", "));
+        }
+        if !unfilled.is_empty() {
+            println!("Fields without a v2 column (left blank): {}", unfilled.join(", "));
+        }
+    }
+}
+
+// Path of the input the pipeline should read. WHO input is read as it is; v2
+// input is converted once into the WHO layout under the workspace directory, so
+// every reader, including those that take Country and Status by position, runs
+// unchanged. `variant` overrides the detection.
+pub(crate) fn normalize_input(file_path: &str, variant: Option<SchemaVariant>, workspace_dir: &Path) -> Result<String, Box<dyn Error>> {
+    if !Path::new(file_path).exists() {
+        return Ok(file_path.to_string());
+    }
+    let mut reader = Reader::from_path(file_path)?;
+    let headers: Vec<String> = reader.headers()?.iter().map(columns::canonical_header).collect();
+    let variant = variant.unwrap_or_else(|| SchemaVariant::detect(&headers));
+    if variant == SchemaVariant::Who {
+        return Ok(file_path.to_string());
+    }
+
+    let mapping = V2Mapping::new(&headers)?;
+    println!("Reading {} as the Kaggle v2 layout", file_path);
+    mapping.report(&headers);
+    let converted = workspace_dir.join("kaggle_v2_as_who.csv");
+    let mut writer = Writer::from_writer(Vec::new());
+    writer.write_record(who_headers())?;
+    for row in reader.records() {
+        writer.write_record(mapping.convert(&row?))?;
+    }
+    output::cache_write(&converted, writer.into_inner()?)?;
+    Ok(converted.display().to_string())
+}
+
+// Path of the input with its numbers in the decimal-point form every reader
+// parses. Point input is read as it is; Comma or Auto input is converted once
+// under the workspace directory, copying cells that are not numbers (names,
+// Status, blanks) unchanged. Under Auto the cells whose reading was a guess are
+// counted per column and reported.
+pub(crate) fn normalize_decimals(file_path: &str, style: DecimalStyle, workspace_dir: &Path) -> Result<String, Box<dyn Error>> {
+    if style == DecimalStyle::Point || !Path::new(file_path).exists() {
+        return Ok(file_path.to_string());
+    }
+    let mut reader = Reader::from_path(file_path)?;
+    let headers = reader.headers()?.clone();
+    let mut guessed = vec![0usize; headers.len()];
+    let mut writer = Writer::from_writer(Vec::new());
+    writer.write_record(&headers)?;
+    for row in reader.records() {
+        let row = row?;
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| match parse_numeric(cell, style) {
+                Some((value, ambiguous)) => {
+                    if ambiguous && column < guessed.len() {
+                        guessed[column] += 1;
+                    }
+                    value.to_string()
+                }
+                None => cell.to_string(),
+            })
+            .collect();
+        writer.write_record(&cells)?;
+    }
+    println!("Reading {} with {:?} decimals", file_path, style);
+    let total: usize = guessed.iter().sum();
+    if total > 0 {
+        let columns: Vec<String> = headers
+            .iter()
+            .zip(&guessed)
+            .filter(|(_, count)| **count > 0)
+            .map(|(header, count)| format!("{} ({})", header.trim(), group_thousands(&count.to_string())))
+            .collect();
+        println!(
+            "Warning: {} cells like \"1,234\" (read as 1234) or \"1.234\" (read as 1.234) could mean either; \
+             --decimal point or comma decides: {}",
+            group_thousands(&total.to_string()),
+            columns.join(", ")
+        );
+    }
+    let converted = workspace_dir.join("decimal_point.csv");
+    output::cache_write(&converted, writer.into_inner()?)?;
+    Ok(converted.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::LifeExpectancyRecord;
+
+    const WHO_HEADER: &str = "Country,Year,Status,Life expectancy ,Adult Mortality,infant deaths,Alcohol,percentage expenditure,Hepatitis B,Measles , BMI ,under-five deaths ,Polio,Total expenditure,Diphtheria , HIV/AIDS,GDP,Population, thinness  1-19 years, thinness 5-9 years,Income composition of resources,Schooling";
+    const V2_HEADER: &str = "Country,Region,Year,Infant_deaths,Under_five_deaths,Adult_mortality,Alcohol_consumption,Hepatitis_B,Measles,BMI,Polio,Diphtheria,Incidents_HIV,GDP_per_capita,Population_mln,Thinness_ten_nineteen_years,Thinness_five_nine_years,Schooling,Economy_status_Developed,Economy_status_Developing,Life_expectancy";
+
+    fn header_names(header: &str) -> Vec<String> {
+        header.split(',').map(columns::canonical_header).collect()
+    }
+
+    fn record(headers: &[String], cells: Vec<String>) -> LifeExpectancyRecord {
+        let headers: StringRecord = headers.iter().collect();
+        StringRecord::from(cells).deserialize(Some(&headers)).unwrap()
+    }
+
+    #[test]
+    fn both_variants_read_into_the_same_record() {
+        // The same country-year; v2 has no expenditure or income columns, and its
+        // Measles column is left out, so the WHO row leaves those blank
+        let who_row = "Afghanistan,2015,Developing,65,263,62,0.01,,65,,19.1,83,6,,65,0.1,584.25921,33500000,17.2,17.3,,10.1";
+        let v2_row = "Afghanistan,Asia,2015,62,83,263,0.01,65,65,19.1,6,65,0.1,584.25921,33.5,17.2,17.3,10.1,0,1,65";
+
+        let headers = header_names(WHO_HEADER);
+        assert_eq!(SchemaVariant::detect(&headers), SchemaVariant::Who);
+        let who = record(&headers, who_row.split(',').map(String::from).collect());
+
+        let v2_headers = header_names(V2_HEADER);
+        assert_eq!(SchemaVariant::detect(&v2_headers), SchemaVariant::KaggleV2);
+        let mapping = V2Mapping::new(&v2_headers).unwrap();
+        let converted = mapping.convert(&v2_row.split(',').collect());
+        let v2 = record(&who_headers().into_iter().map(String::from).collect::<Vec<_>>(), converted);
+
+        assert_eq!(v2, who);
+        assert_eq!((v2.status.as_str(), v2.population, v2.measles), ("Developing", Some(33_500_000.0), None));
+    }
+
+    #[test]
+    fn the_status_flag_and_missing_columns() {
+        assert_eq!((v2_status("1"), v2_status(" 0.0 "), v2_status("")), ("Developed", "Developing", ""));
+        assert_eq!(SchemaVariant::from_name("v2").unwrap(), SchemaVariant::KaggleV2);
+        assert!(SchemaVariant::from_name("kaggle").unwrap_err().to_string().contains("expected who or v2"));
+
+        let headers = header_names("Year,Life_expectancy");
+        let error = V2Mapping::new(&headers).err().unwrap().to_string();
+        assert_eq!(error, "Kaggle v2 input has no \"Country\" column");
+        let mapping = V2Mapping::new(&header_names("country,YEAR,life_expectancy")).unwrap();
+        assert_eq!(mapping.developed, None);
+        let converted = mapping.convert(&StringRecord::from(vec!["A", "2000", "n/a"]));
+        assert_eq!(&converted[..4], ["A", "2000", "", ""]);
+    }
+}
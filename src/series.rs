@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use crate::fields::{self, FieldMeta, LifeExpectancyRecord};
+
+// Per-group, per-year mean of one field, skipping missing values
+pub(crate) type YearlyMeans = BTreeMap<String, BTreeMap<u16, f64>>;
+
+// Registry entry for a feature name, or an error naming it
+pub(crate) fn field_meta(feature: &str) -> Result<&'static FieldMeta, Box<dyn Error>> {
+    fields::field(feature).ok_or_else(|| format!("Unknown feature \"{}\"", feature).into())
+}
+
+// Averages a field per (status, year)
+pub(crate) fn yearly_means_by_status(records: &[LifeExpectancyRecord], meta: &FieldMeta) -> YearlyMeans {
+    let mut sums: BTreeMap<String, BTreeMap<u16, (f64, usize)>> = BTreeMap::new();
+    for record in records {
+        if let Some(value) = (meta.getter)(record) {
+            let entry = sums.entry(record.status.clone()).or_default().entry(record.year).or_insert((0.0, 0));
+            entry.0 += value;
+            entry.1 += 1;
+        }
+    }
+
+    sums.into_iter()
+        .map(|(group, years)| {
+            let means = years.into_iter().map(|(year, (sum, n))| (year, sum / n as f64)).collect();
+            (group, means)
+        })
+        .collect()
+}
+
+// Axis description for a field, e.g. "GDP (USD)"
+#[cfg(feature = "plots")]
+pub(crate) fn axis_label(meta: &FieldMeta) -> String {
+    match meta.unit.label() {
+        "" => meta.name.to_string(),
+        unit => format!("{} ({})", meta.name, unit),
+    }
+}
+
+// Per-year totals of each feature (in feature order), erroring on negative values
+#[cfg(feature = "plots")]
+pub(crate) fn yearly_totals(records: &[LifeExpectancyRecord], metas: &[&FieldMeta]) -> Result<BTreeMap<u16, Vec<f64>>, Box<dyn Error>> {
+    let mut totals: BTreeMap<u16, Vec<f64>> = BTreeMap::new();
+    for record in records {
+        let row = totals.entry(record.year).or_insert_with(|| vec![0.0; metas.len()]);
+        for (total, meta) in row.iter_mut().zip(metas) {
+            match (meta.getter)(record) {
+                Some(value) if value < 0.0 => {
+                    return Err(format!("Cannot stack {}: negative value {} for {} {}", meta.name, value, record.country, record.year).into());
+                }
+                Some(value) => *total += value,
+                None => {}
+            }
+        }
+    }
+    Ok(totals)
+}
+
+// Cumulative upper edges of each layer per year, in the given layer order; with
+// `percent` each year is scaled so the top layer ends at 100
+#[cfg(feature = "plots")]
+pub(crate) fn stack_layers(totals: &BTreeMap<u16, Vec<f64>>, percent: bool) -> BTreeMap<u16, Vec<f64>> {
+    totals
+        .iter()
+        .map(|(&year, values)| {
+            let sum: f64 = values.iter().sum();
+            let scale = if percent && sum > 0.0 { 100.0 / sum } else { 1.0 };
+            let edges = values
+                .iter()
+                .scan(0.0, |acc, value| {
+                    *acc += value * scale;
+                    Some(*acc)
+                })
+                .collect();
+            (year, edges)
+        })
+        .collect()
+}
+
+// Mean value per (country, year); duplicate rows for a country-year are averaged
+pub(crate) fn country_year_pivot(records: &[LifeExpectancyRecord], meta: &FieldMeta) -> BTreeMap<String, BTreeMap<u16, f64>> {
+    let mut sums: BTreeMap<String, BTreeMap<u16, (f64, usize)>> = BTreeMap::new();
+    for record in records {
+        let years = sums.entry(record.country.clone()).or_default();
+        let entry = years.entry(record.year).or_insert((0.0, 0));
+        if let Some(value) = (meta.getter)(record) {
+            entry.0 += value;
+            entry.1 += 1;
+        }
+    }
+
+    sums.into_iter()
+        .map(|(country, years)| {
+            let means = years.into_iter().filter(|(_, (_, n))| *n > 0).map(|(year, (sum, n))| (year, sum / n as f64)).collect();
+            (country, means)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(country: &str, year: u16, status: &str, measles: Option<f64>, hiv_aids: Option<f64>) -> LifeExpectancyRecord {
+        LifeExpectancyRecord { country: country.to_string(), year, status: status.to_string(), measles, hiv_aids, ..Default::default() }
+    }
+
+    #[test]
+    fn yearly_means_skip_missing_values() {
+        let records = [
+            record("A", 2000, "Developing", Some(10.0), None),
+            record("B", 2000, "Developing", None, None),
+            record("C", 2000, "Developing", Some(20.0), None),
+            record("D", 2001, "Developed", Some(4.0), None),
+        ];
+        let means = yearly_means_by_status(&records, field_meta("Measles").unwrap());
+        assert_eq!(means["Developing"][&2000], 15.0);
+        assert_eq!(means["Developed"][&2001], 4.0);
+        assert!(field_meta("Happiness").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn layers_stack_in_feature_order() {
+        let records = [
+            record("A", 2000, "Developing", Some(3.0), Some(1.0)),
+            record("B", 2000, "Developing", Some(1.0), None),
+            record("A", 2001, "Developing", Some(2.0), Some(6.0)),
+        ];
+        let metas = [field_meta("Measles").unwrap(), field_meta("HIV/AIDS").unwrap()];
+        let totals = yearly_totals(&records, &metas).unwrap();
+        assert_eq!(totals[&2000], vec![4.0, 1.0]);
+
+        let stacked = stack_layers(&totals, false);
+        assert_eq!(stacked[&2000], vec![4.0, 5.0]);
+        assert_eq!(stacked[&2001], vec![2.0, 8.0]);
+        let percent = stack_layers(&totals, true);
+        assert_eq!(percent[&2000], vec![80.0, 100.0]);
+        assert_eq!(percent[&2001], vec![25.0, 100.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "plots")]
+    fn negative_values_cannot_be_stacked() {
+        let records = [record("A", 2000, "Developing", Some(-1.0), None)];
+        let error = yearly_totals(&records, &[field_meta("Measles").unwrap()]).unwrap_err();
+        assert_eq!(error.to_string(), "Cannot stack Measles: negative value -1 for A 2000");
+    }
+}
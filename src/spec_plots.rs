@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ndarray::Array1;
+use plotters::prelude::*;
+use crate::analysis_error::AnalysisError;
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+use crate::colormap::{self, PALETTE};
+use crate::eda_statistics::{calculate_correlation, extreme_annotations};
+use crate::format::format_tick;
+use crate::load_clean::Dataset;
+use crate::metadata::{self, RunMetadata};
+use crate::output;
+use crate::eda_plots::{correlation_color, draw_pinned};
+use crate::plot_specs::{check_columns, pin, pin_points, AxisLimits, Bins, PlotSpec};
+use crate::theme::PlotTheme;
+
+// Render every spec into out_dir and return the produced paths
+pub(crate) fn run_plot_specs(
+    dataset: &Dataset,
+    specs: &[PlotSpec],
+    theme: &PlotTheme,
+    out_dir: &str,
+    run: &RunMetadata,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    check_columns(dataset, specs)?;
+
+    fs::create_dir_all(out_dir)?;
+    let mut produced = Vec::new();
+    for (i, spec) in specs.iter().enumerate() {
+        let path = Path::new(out_dir).join(spec.file_name(i));
+        let output_file = path.to_str().ok_or("Output path is not valid UTF-8")?;
+        let canvas = Canvas { output_file, theme, run };
+        match spec {
+            PlotSpec::Scatter { x, y, group_by, annotate_extremes, x_limits, y_limits } => {
+                draw_scatter(dataset, x, y, group_by.as_deref(), *annotate_extremes, (x_limits, y_limits), &canvas)?
+            }
+            PlotSpec::LineByGroup { feature, group, y_limits } => {
+                draw_line_by_group(dataset, feature, group, y_limits, &canvas)?
+            }
+            PlotSpec::Histogram { feature, bins } => draw_histogram(dataset, feature, *bins, &canvas)?,
+            PlotSpec::Heatmap { features } => draw_heatmap(dataset, features, &canvas)?,
+        }
+        produced.push(path);
+    }
+
+    Ok(produced)
+}
+
+// Where and how a single spec is drawn
+#[derive(Clone, Copy)]
+struct Canvas<'a> {
+    output_file: &'a str,
+    theme: &'a PlotTheme,
+    run: &'a RunMetadata,
+}
+
+// Range covering all values with a little padding on both sides
+fn padded_range(values: impl Iterator<Item = f64>) -> std::ops::Range<f64> {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if !min.is_finite() {
+        return 0.0..1.0;
+    }
+    let pad = if max > min { (max - min) * 0.05 } else { 1.0 };
+    (min - pad)..(max + pad)
+}
+
+fn draw_scatter(
+    dataset: &Dataset,
+    x: &str,
+    y: &str,
+    group_by: Option<&str>,
+    annotate_extremes: usize,
+    (x_limits, y_limits): (&AxisLimits, &AxisLimits),
+    canvas: &Canvas,
+) -> Result<(), Box<dyn Error>> {
+    let Canvas { output_file, theme, run } = *canvas;
+    let xs = dataset.numeric_column(x)?;
+    let ys = dataset.numeric_column(y)?;
+    let countries = dataset.text_column("Country")?;
+    let groups = match group_by {
+        Some(column) => dataset.text_column(column)?,
+        None => vec![String::new(); xs.len()],
+    };
+
+    // Points with both values present, grouped by label, plus their countries
+    let mut points: BTreeMap<&str, Vec<(f64, f64)>> = BTreeMap::new();
+    let mut all_points = Vec::new();
+    let mut labels = Vec::new();
+    for (((x, y), group), country) in xs.iter().zip(&ys).zip(&groups).zip(&countries) {
+        if let (Some(x), Some(y)) = (x, y) {
+            points.entry(group.as_str()).or_default().push((*x, *y));
+            all_points.push((*x, *y));
+            labels.push(country.clone());
+        }
+    }
+
+    if all_points.is_empty() {
+        return Err(AnalysisError::empty(format!("{} vs. {}", y.trim(), x.trim())).into());
+    }
+
+    // Axis limits cut the range down; points beyond it are pinned to the edge
+    let axis_range = |limits: &AxisLimits, values: Vec<f64>| {
+        limits.bounds(&values).map_or_else(|| padded_range(values.iter().copied()), |(low, high)| low..high)
+    };
+    let x_range = axis_range(x_limits, all_points.iter().map(|p| p.0).collect());
+    let y_range = axis_range(y_limits, all_points.iter().map(|p| p.1).collect());
+    let bounds = ((x_range.start, x_range.end), (y_range.start, y_range.end));
+    let caption = format!("{} vs. {}", y.trim(), x.trim());
+    let mut pinned = Vec::new();
+    let mut inside_labels = Vec::new();
+    let mut inside_points = Vec::new();
+    for ((point, beyond), label) in pin_points(&all_points, bounds.0, bounds.1).into_iter().zip(labels) {
+        if beyond {
+            pinned.push(point);
+        } else {
+            inside_points.push(point);
+            inside_labels.push(label);
+        }
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 30))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_range, y_range)?;
+
+    chart
+        .configure_mesh()
+        .x_desc(x.trim())
+        .y_desc(y.trim())
+        .x_label_formatter(&|x| format_tick(*x, theme.x_ticks))
+        .y_label_formatter(&|y| format_tick(*y, theme.y_ticks))
+        .draw()?;
+
+    for (i, (group, group_points)) in points.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let series = chart.draw_series(
+            pin_points(group_points, bounds.0, bounds.1)
+                .into_iter()
+                .filter(|&(_, beyond)| !beyond)
+                .map(move |((x, y), _)| Circle::new((x, y), 3, color.mix(0.5).filled())),
+        )?;
+        if group_by.is_some() {
+            series
+                .label(group.to_string())
+                .legend(move |(x, y)| Circle::new((x + 10, y), 4, color.filled()));
+        }
+    }
+
+    draw_pinned(&mut chart, &pinned)?;
+
+    let annotations = extreme_annotations(&inside_points, &inside_labels, annotate_extremes);
+    chart.draw_series(annotations.into_iter().map(|(point, label, offset)| {
+        EmptyElement::at(point)
+            + Circle::new((0, 0), 4, BLACK.stroke_width(1))
+            + Text::new(label, offset, ("sans-serif", 13))
+    }))?;
+
+    if group_by.is_some() {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE)
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // Points at their values, also those pinned to the edge
+    chart_json::write_sidecar(output_file, run, || {
+        let mut spec = ChartSpecJson::new("scatter", caption, AxisJson::new(x.trim(), bounds.0), AxisJson::new(y.trim(), bounds.1));
+        for (i, (group, group_points)) in points.iter().enumerate() {
+            spec = spec.series(SeriesJson::new(*group, Some(PALETTE[i % PALETTE.len()]), group_points.clone()));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+fn draw_line_by_group(
+    dataset: &Dataset,
+    feature: &str,
+    group: &str,
+    y_limits: &AxisLimits,
+    canvas: &Canvas,
+) -> Result<(), Box<dyn Error>> {
+    let Canvas { output_file, theme, run } = *canvas;
+    let values = dataset.numeric_column(feature)?;
+    let years = dataset.years()?;
+    let groups = dataset.text_column(group)?;
+
+    // (sum, count) per group and year, skipping missing values
+    let mut totals: BTreeMap<&str, BTreeMap<i64, (f64, usize)>> = BTreeMap::new();
+    for ((value, year), group) in values.iter().zip(&years).zip(&groups) {
+        if let (Some(value), Some(year)) = (value, year) {
+            let entry = totals.entry(group.as_str()).or_default().entry(*year as i64).or_insert((0.0, 0));
+            entry.0 += value;
+            entry.1 += 1;
+        }
+    }
+
+    let series: Vec<(&str, Vec<(i64, f64)>)> = totals
+        .iter()
+        .map(|(group, by_year)| {
+            let points = by_year.iter().map(|(&year, &(sum, count))| (year, sum / count as f64)).collect();
+            (*group, points)
+        })
+        .collect();
+
+    if series.is_empty() {
+        return Err(AnalysisError::empty(format!("{} by {}", feature.trim(), group.trim())).into());
+    }
+
+    let all_years: BTreeSet<i64> = series.iter().flat_map(|(_, points)| points.iter().map(|p| p.0)).collect();
+    let first_year = all_years.iter().next().copied().unwrap_or(0);
+    let last_year = all_years.iter().last().copied().unwrap_or(1).max(first_year + 1);
+
+    // Means beyond the axis limits are drawn on the edge and marked
+    let means: Vec<f64> = series.iter().flat_map(|(_, points)| points.iter().map(|p| p.1)).collect();
+    let y_range = y_limits.bounds(&means).map_or_else(|| padded_range(means.iter().copied()), |(low, high)| low..high);
+    let bounds = (y_range.start, y_range.end);
+    let mut pinned = Vec::new();
+    let series: Vec<(&str, Vec<(i64, f64)>)> = series
+        .into_iter()
+        .map(|(group, points)| {
+            let points = points
+                .into_iter()
+                .map(|(year, mean)| {
+                    let (mean, beyond) = pin(mean, bounds);
+                    if beyond {
+                        pinned.push((year, mean));
+                    }
+                    (year, mean)
+                })
+                .collect();
+            (group, points)
+        })
+        .collect();
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{} Averages per Year by {}", feature.trim(), group.trim());
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(60)
+        .build_cartesian_2d(first_year..last_year, y_range)?;
+
+    chart
+        .configure_mesh()
+        .y_desc(format!("{} Averages", feature.trim()))
+        .x_desc("Years")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .y_label_formatter(&|y| format_tick(*y, theme.y_ticks))
+        .draw()?;
+
+    for (i, (group, points)) in series.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), color.stroke_width(2)))?
+            .label(group.to_string())
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color));
+    }
+    draw_pinned(&mut chart, &pinned)?;
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE)
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // The means as drawn, pinned ones on the edge
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new("Years", (first_year as f64, last_year as f64));
+        let mut spec = ChartSpecJson::new("line", caption, x_axis, AxisJson::new(format!("{} Averages", feature.trim()), bounds));
+        for (i, (group, points)) in series.iter().enumerate() {
+            let points = points.iter().map(|&(year, mean)| (year as f64, mean)).collect();
+            spec = spec.series(SeriesJson::new(*group, Some(PALETTE[i % PALETTE.len()]), points));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+fn draw_histogram(
+    dataset: &Dataset,
+    feature: &str,
+    bins: usize,
+    canvas: &Canvas,
+) -> Result<(), Box<dyn Error>> {
+    let Canvas { output_file, theme, run } = *canvas;
+    let values: Vec<f64> = dataset.numeric_column(feature)?.into_iter().flatten().collect();
+    if values.is_empty() {
+        return Err(AnalysisError::empty(format!("Distribution of {}", feature.trim())).into());
+    }
+    let binning = Bins::covering(&values, bins);
+    let counts = binning.counts(&values);
+    let (min, max) = (binning.min, binning.max);
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+    let caption = format!("Distribution of {}", feature.trim());
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(60)
+        .build_cartesian_2d(min..max, 0.0..(max_count as f64 * 1.1))?;
+
+    chart
+        .configure_mesh()
+        .x_desc(feature.trim())
+        .y_desc("Count")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| format_tick(*x, theme.x_ticks))
+        .draw()?;
+
+    chart.draw_series(counts.iter().enumerate().map(|(i, &count)| {
+        let start = binning.start(i);
+        Rectangle::new([(start, 0.0), (start + binning.width, count as f64)], PALETTE[0].filled())
+    }))?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // (bin start, count) per bin; every bin is binning.width wide
+    chart_json::write_sidecar(output_file, run, || {
+        let bars = counts.iter().enumerate().map(|(i, &count)| (binning.start(i), count as f64)).collect();
+        ChartSpecJson::new("histogram", caption, AxisJson::new(feature.trim(), (min, max)), AxisJson::new("Count", (0.0, max_count as f64 * 1.1)))
+            .series(SeriesJson::new(feature.trim(), Some(PALETTE[0]), bars))
+    })?;
+    Ok(())
+}
+
+fn draw_heatmap(dataset: &Dataset, features: &[String], canvas: &Canvas) -> Result<(), Box<dyn Error>> {
+    let Canvas { output_file, run, .. } = *canvas;
+    let columns = features
+        .iter()
+        .map(|feature| dataset.numeric_column(feature))
+        .collect::<Result<Vec<_>, _>>()?;
+    let n = columns.len();
+    if n == 0 || columns[0].is_empty() {
+        return Err(AnalysisError::empty("Correlation heatmap").into());
+    }
+
+    // Pearson correlation over the rows where both columns are present; NaN when
+    // it is undefined (too few rows, or a constant column)
+    let mut matrix = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..n {
+            let (x, y): (Vec<f64>, Vec<f64>) = columns[i]
+                .iter()
+                .zip(&columns[j])
+                .filter_map(|(a, b)| Some(((*a)?, (*b)?)))
+                .unzip();
+            matrix[i][j] = calculate_correlation(&Array1::from(x).view(), &Array1::from(y).view()).unwrap_or(f64::NAN);
+        }
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1124, 1024)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (plot_area, legend_area) = root.split_horizontally(1024);
+
+    let mut chart = ChartBuilder::on(&plot_area)
+        .caption("Feature Correlation Heatmap", ("sans-serif", 30))
+        .margin(5)
+        .x_label_area_size(60)
+        .y_label_area_size(120)
+        .build_cartesian_2d((0..n as u32 - 1).into_segmented(), (0..n as u32 - 1).into_segmented())?;
+
+    // Labels sit at cell centers; row 0 is drawn at the top
+    let label = |index: u32| features.get(index as usize).map(|f| f.trim().to_string()).unwrap_or_default();
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(n)
+        .y_labels(n)
+        .x_label_formatter(&|x| match x {
+            SegmentValue::CenterOf(x) => label(*x),
+            _ => String::new(),
+        })
+        .y_label_formatter(&|y| match y {
+            SegmentValue::CenterOf(y) if (*y as usize) < n => label(n as u32 - 1 - *y),
+            _ => String::new(),
+        })
+        .label_style(("sans-serif", 15))
+        .draw()?;
+
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, &value) in row.iter().enumerate() {
+            let color = correlation_color(value);
+            chart.draw_series(std::iter::once(Rectangle::new(
+                [
+                    (SegmentValue::Exact(j as u32), SegmentValue::Exact((n - i - 1) as u32)),
+                    (SegmentValue::Exact((j + 1) as u32), SegmentValue::Exact((n - i) as u32)),
+                ],
+                color.filled(),
+            )))?;
+        }
+    }
+
+    colormap::draw_color_bar(&legend_area, colormap::Colormap::Diverging, (-1.0, 1.0), "Correlation")?;
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    // One series per row with (column, correlation) points
+    chart_json::write_sidecar(output_file, run, || {
+        let names: Vec<String> = features.iter().map(|feature| feature.trim().to_string()).collect();
+        let axis = |label: &str| AxisJson::categories(label, names.clone());
+        let mut spec = ChartSpecJson::new("heatmap", "Feature Correlation Heatmap", axis("Features"), axis("Features"));
+        for (name, row) in names.iter().zip(&matrix) {
+            spec = spec.series(SeriesJson::new(name.as_str(), None, row.iter().enumerate().map(|(j, &value)| (j as f64, value)).collect()));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn an_unknown_feature_fails_the_batch_before_any_plot_is_drawn() {
+        let dir = crate::test_support::scratch_dir("unknown-spec-feature");
+        let input = dir.join("input.csv");
+        let rows: Vec<String> = fs::read_to_string("Life Expectancy Data.csv").unwrap().lines().take(50).map(String::from).collect();
+        fs::write(&input, rows.join("\n")).unwrap();
+        let input = input.to_str().unwrap();
+        let dataset = Dataset::load(input).unwrap();
+        let run = RunMetadata::new(input, "none").unwrap();
+        let config: Config = toml::from_str(
+            "[[plots]]\nkind = \"histogram\"\nfeature = \"Life expectancy\"\nbins = 10\n\n\
+             [[plots]]\nkind = \"scatter\"\nx = \"GDP\"\ny = \"Happiness\"\n",
+        )
+        .unwrap();
+        let out_dir = dir.join("plots");
+
+        let err = run_plot_specs(&dataset, &config.plots, &config.theme, out_dir.to_str().unwrap(), &run).unwrap_err();
+        assert!(err.to_string().contains("plot 2: \"Happiness\""), "{}", err);
+        // Not even the valid histogram was rendered
+        assert!(!out_dir.exists());
+    }
+}
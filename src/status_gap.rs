@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::analysis_error::AnalysisError;
+use crate::eda_statistics::quantile;
+use crate::fields::{FieldMeta, LifeExpectancyRecord};
+
+// The two groups whose difference the gap is: first minus second
+pub(crate) const GAP_GROUPS: (&str, &str) = ("Developed", "Developing");
+
+// What a bootstrap resample draws: single country-year rows, as if every row
+// were independent, or whole countries with all their years. A country's yearly
+// values move together, so rows understate how much the gap could vary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResampleUnit {
+    Row,
+    Country,
+}
+
+impl ResampleUnit {
+    // From its command-line name ("row" or "country")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "row" => Ok(ResampleUnit::Row),
+            "country" => Ok(ResampleUnit::Country),
+            other => Err(format!("Unknown resampling unit \"{}\" (expected row or country)", other).into()),
+        }
+    }
+}
+
+// Per-year gap between the GAP_GROUPS means with its bootstrap 95% interval;
+// years where either group has no value are left out
+#[derive(Debug, Clone)]
+pub(crate) struct GapBand {
+    pub(crate) years: Vec<u16>,
+    pub(crate) gap: Vec<f64>,
+    pub(crate) lower: Vec<f64>,
+    pub(crate) upper: Vec<f64>,
+    // Mean of the yearly gaps, with its interval; the years of one country being
+    // alike shows most here, where rows from every year are pooled
+    pub(crate) average: (f64, f64, f64),
+    pub(crate) unit: ResampleUnit,
+    pub(crate) resamples: usize,
+}
+
+// Resampling units of one group: each country's (year, value) pairs, or each row
+// on its own
+type Units = Vec<Vec<(u16, f64)>>;
+
+// The units of each GAP_GROUPS group holding a value of `meta`, countries in name order
+fn group_units(records: &[LifeExpectancyRecord], meta: &FieldMeta, unit: ResampleUnit) -> (Units, Units) {
+    let mut groups: [BTreeMap<&str, Vec<(u16, f64)>>; 2] = Default::default();
+    for record in records {
+        let Some(value) = (meta.getter)(record).filter(|value| value.is_finite()) else {
+            continue;
+        };
+        let group = if record.status == GAP_GROUPS.0 {
+            0
+        } else if record.status == GAP_GROUPS.1 {
+            1
+        } else {
+            continue;
+        };
+        groups[group].entry(record.country.as_str()).or_default().push((record.year, value));
+    }
+    let [first, second] = groups.map(|countries| match unit {
+        ResampleUnit::Country => countries.into_values().collect(),
+        ResampleUnit::Row => countries.into_values().flatten().map(|row| vec![row]).collect(),
+    });
+    (first, second)
+}
+
+// Per-year sum and count of the values of a group's units
+fn yearly_sums<'a>(units: impl Iterator<Item = &'a Vec<(u16, f64)>>) -> BTreeMap<u16, (f64, usize)> {
+    let mut sums = BTreeMap::new();
+    for &(year, value) in units.flatten() {
+        let entry: &mut (f64, usize) = sums.entry(year).or_default();
+        (entry.0, entry.1) = (entry.0 + value, entry.1 + 1);
+    }
+    sums
+}
+
+// First minus second group mean, for the years both have
+fn yearly_gap(first: BTreeMap<u16, (f64, usize)>, second: BTreeMap<u16, (f64, usize)>) -> BTreeMap<u16, f64> {
+    first
+        .into_iter()
+        .filter_map(|(year, (sum, n))| {
+            let (other_sum, other_n) = second.get(&year)?;
+            Some((year, sum / n as f64 - other_sum / *other_n as f64))
+        })
+        .collect()
+}
+
+// Units drawn with replacement, as many as there are
+fn resample<'a>(units: &'a Units, rng: &mut StdRng) -> impl Iterator<Item = &'a Vec<(u16, f64)>> + 'a {
+    let draws: Vec<usize> = (0..units.len()).map(|_| rng.gen_range(0..units.len())).collect();
+    draws.into_iter().map(move |i| &units[i])
+}
+
+// Percentile 95% interval of bootstrap draws; NaN without any
+fn interval(mut draws: Vec<f64>) -> (f64, f64) {
+    if draws.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+    draws.sort_by(f64::total_cmp);
+    (quantile(&draws, 0.025), quantile(&draws, 0.975))
+}
+
+// The GAP_GROUPS gap in `meta` per year with a percentile bootstrap 95% interval
+// over `resamples` resamples. Each group is resampled on its own, by `unit`, so
+// every resample keeps both groups at their sizes; a year a resample leaves one
+// group without is skipped in that resample.
+pub(crate) fn bootstrap_gap(
+    records: &[LifeExpectancyRecord],
+    meta: &FieldMeta,
+    unit: ResampleUnit,
+    resamples: usize,
+    seed: u64,
+) -> Result<GapBand, AnalysisError> {
+    let (first, second) = group_units(records, meta, unit);
+    let observed = yearly_gap(yearly_sums(first.iter()), yearly_sums(second.iter()));
+    if observed.is_empty() {
+        return Err(AnalysisError::empty(format!("{} {} vs. {} gap", meta.name, GAP_GROUPS.0, GAP_GROUPS.1)));
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut draws: BTreeMap<u16, Vec<f64>> = BTreeMap::new();
+    let mut averages = Vec::with_capacity(resamples);
+    for _ in 0..resamples {
+        let first_sums = yearly_sums(resample(&first, &mut rng));
+        let gap = yearly_gap(first_sums, yearly_sums(resample(&second, &mut rng)));
+        if !gap.is_empty() {
+            averages.push(gap.values().sum::<f64>() / gap.len() as f64);
+        }
+        for (year, value) in gap {
+            draws.entry(year).or_default().push(value);
+        }
+    }
+
+    let average = observed.values().sum::<f64>() / observed.len() as f64;
+    let (lower, upper) = interval(averages);
+    let mut band = GapBand {
+        years: Vec::new(),
+        gap: Vec::new(),
+        lower: Vec::new(),
+        upper: Vec::new(),
+        average: (average, lower, upper),
+        unit,
+        resamples,
+    };
+    for (year, gap) in observed {
+        let (lower, upper) = interval(draws.remove(&year).unwrap_or_default());
+        band.years.push(year);
+        band.gap.push(gap);
+        band.lower.push(lower);
+        band.upper.push(upper);
+    }
+    Ok(band)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::FIELDS;
+
+    // Countries whose rows share a persistent offset from their group's mean,
+    // Developed 10 years above Developing, so the gap of the population is 10
+    fn correlated_dataset(countries: usize, seed: u64) -> Vec<LifeExpectancyRecord> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut spread = |scale: f64| (0..3).map(|_| rng.gen::<f64>()).sum::<f64>().mul_add(scale, -1.5 * scale);
+        let mut records = Vec::new();
+        for country in 0..countries {
+            let (status, mean) = if country % 2 == 0 { ("Developed", 75.0) } else { ("Developing", 65.0) };
+            let offset = spread(4.0);
+            for year in 2000..2016 {
+                records.push(LifeExpectancyRecord {
+                    country: format!("C{}", country),
+                    year,
+                    status: status.to_string(),
+                    life_expectancy: Some(mean + offset + spread(0.5)),
+                    ..Default::default()
+                });
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn resampling_countries_covers_the_true_gap() {
+        let meta = FIELDS.iter().find(|meta| meta.name == "Life expectancy").unwrap();
+        let coverage = |unit: ResampleUnit| {
+            let covered = (0..40)
+                .filter(|&trial| {
+                    let band = bootstrap_gap(&correlated_dataset(20, trial), meta, unit, 200, trial).unwrap();
+                    let (_, lower, upper) = band.average;
+                    (lower..=upper).contains(&10.0)
+                })
+                .count();
+            covered as f64 / 40.0
+        };
+        let (by_country, by_row) = (coverage(ResampleUnit::Country), coverage(ResampleUnit::Row));
+        assert!(by_country >= 0.85, "country intervals cover the gap {:.0}% of the time", by_country * 100.0);
+        assert!(by_row < by_country - 0.3, "row intervals cover it {:.0}% of the time", by_row * 100.0);
+    }
+
+    #[test]
+    fn countries_are_drawn_with_all_their_years() {
+        let meta = FIELDS.iter().find(|meta| meta.name == "Life expectancy").unwrap();
+        let mut records = correlated_dataset(4, 1);
+        records[0].status.clear();
+        let (developed, developing) = group_units(&records, meta, ResampleUnit::Country);
+        assert_eq!((developed.len(), developing.len()), (2, 2));
+        // C0 lost the status of its first year only
+        assert_eq!((developed[0].len(), developed[1].len()), (15, 16));
+        let (rows, _) = group_units(&records, meta, ResampleUnit::Row);
+        assert!(rows.len() == 31 && rows.iter().all(|unit| unit.len() == 1));
+
+        let by_country = bootstrap_gap(&records, meta, ResampleUnit::Country, 50, 3).unwrap();
+        let by_row = bootstrap_gap(&records, meta, ResampleUnit::Row, 50, 3).unwrap();
+        assert_eq!((by_country.years.len(), &by_country.gap), (16, &by_row.gap));
+        assert!(ResampleUnit::from_name("year").unwrap_err().to_string().contains("expected row or country"));
+    }
+}
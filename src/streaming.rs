@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::io::Read;
+use csv::{Reader, StringRecord};
+use crate::eda_statistics::{grouped_mean, GroupedMeans, SummaryStats};
+use crate::load_clean::{get_f64, RecordLayout};
+
+// Count, mean, variance (Welford's online update), min and max of a stream of values
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64, // sum of squared deviations from the running mean
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub(crate) fn push(&mut self, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    // Sample standard deviation, as statrs computes it; None below two values
+    pub(crate) fn std_dev(&self) -> Option<f64> {
+        (self.count > 1).then(|| (self.m2 / (self.count - 1) as f64).sqrt())
+    }
+}
+
+// P² estimate of one quantile (Jain & Chlamtac, 1985): five markers whose heights
+// are adjusted with a piecewise-parabolic fit as values arrive, so memory stays
+// constant. Exact for the first five values.
+#[derive(Debug, Clone)]
+pub(crate) struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5], // actual marker positions, 1-based
+    desired: [f64; 5],   // desired marker positions
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    pub(crate) fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub(crate) fn push(&mut self, value: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Cell the value falls into, extending the extreme markers if needed
+        let h = &mut self.heights;
+        let cell = if value < h[0] {
+            h[0] = value;
+            0
+        } else if value >= h[4] {
+            h[4] = value;
+            3
+        } else {
+            (0..4).find(|&i| value < h[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        // Move the three middle markers towards their desired positions
+        for i in 1..4 {
+            let offset = self.desired[i] - self.positions[i];
+            let (n, h) = (&self.positions, &self.heights);
+            if (offset >= 1.0 && n[i + 1] - n[i] > 1.0) || (offset <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = offset.signum();
+                let parabolic = h[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                let height = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                self.heights[i] = height;
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    // Current estimate; interpolated between order statistics while fewer than
+    // five values were seen. None before any value.
+    pub(crate) fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=4 => {
+                let mut seen = self.heights[..self.count].to_vec();
+                seen.sort_by(f64::total_cmp);
+                let position = (self.count - 1) as f64 * self.p;
+                let (low, high) = (position.floor() as usize, position.ceil() as usize);
+                Some(seen[low] + (seen[high] - seen[low]) * (position - low as f64))
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+// Summary of one column computed record by record
+#[derive(Debug, Clone)]
+struct ColumnAccumulator {
+    stats: RunningStats,
+    median: P2Quantile,
+    missing: usize,
+}
+
+impl ColumnAccumulator {
+    fn summary(&self) -> Option<SummaryStats> {
+        Some(SummaryStats {
+            count: self.stats.count,
+            missing: self.missing,
+            mean: (self.stats.count > 0).then_some(self.stats.mean)?,
+            std_dev: self.stats.std_dev(),
+            min: self.stats.min,
+            median: self.median.estimate()?,
+            max: self.stats.max,
+        })
+    }
+}
+
+// Summary statistics of the given columns without holding the rows in memory:
+// mean and standard deviation are exact, the median is a P² estimate. None for a
+// column without any value. The estimate is within 0.1% of the exact median on
+// 100k shuffled values, but drifts (by 10-50% on skewed columns) when rows arrive
+// in an order correlated with the value, e.g. grouped by country. Each record is
+// extended by `layout` first, so columns past the file's are its derived columns.
+pub(crate) fn stream_describe<R: Read>(
+    reader: &mut Reader<R>,
+    layout: &RecordLayout,
+    columns: &[usize],
+) -> Result<Vec<Option<SummaryStats>>, Box<dyn Error>> {
+    let mut accumulators = vec![
+        ColumnAccumulator { stats: RunningStats::default(), median: P2Quantile::new(0.5), missing: 0 };
+        columns.len()
+    ];
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        layout.extend(&mut record);
+        for (accumulator, &column) in accumulators.iter_mut().zip(columns) {
+            match get_f64(&record, column) {
+                Some(value) => {
+                    accumulator.stats.push(value);
+                    accumulator.median.push(value);
+                }
+                None => accumulator.missing += 1,
+            }
+        }
+    }
+    Ok(accumulators.iter().map(ColumnAccumulator::summary).collect())
+}
+
+// grouped_mean over the reader's records as they are read, keyed by the text of
+// `key_column`; rows with an empty key are left out
+pub(crate) fn stream_grouped_mean<R: Read>(
+    reader: &mut Reader<R>,
+    key_column: usize,
+    value_column: usize,
+) -> Result<GroupedMeans<String>, Box<dyn Error>> {
+    let mut failure = None;
+    let records = reader.records().map_while(|record| record.map_err(|error| failure = Some(error)).ok());
+    let grouped = grouped_mean(
+        records,
+        |record| record.get(key_column).map(str::trim).filter(|key| !key.is_empty()).map(str::to_string),
+        |record| get_f64(record, value_column),
+    );
+    match failure {
+        Some(error) => Err(error.into()),
+        None => Ok(grouped),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use crate::eda_statistics::quantile;
+
+    #[test]
+    fn p2_estimates_are_close_to_the_exact_quantiles() {
+        // Skewed values (exp of uniform) in random order
+        let mut rng = StdRng::seed_from_u64(7);
+        let values: Vec<f64> = (0..100_000).map(|_| rng.gen_range(0.0..5.0_f64).exp()).collect();
+        let mut sorted = values.clone();
+        sorted.sort_by(f64::total_cmp);
+
+        // The median within 0.1% as stream_describe documents it, other quantiles within 0.5%
+        for (p, tolerance) in [(0.5, 1e-3), (0.25, 5e-3), (0.9, 5e-3)] {
+            let mut estimator = P2Quantile::new(p);
+            values.iter().for_each(|&value| estimator.push(value));
+            let (estimate, exact) = (estimator.estimate().unwrap(), quantile(&sorted, p));
+            assert!(((estimate - exact) / exact).abs() < tolerance, "p={}: {} vs {}", p, estimate, exact);
+        }
+    }
+
+    #[test]
+    fn p2_is_exact_for_the_first_values() {
+        let mut estimator = P2Quantile::new(0.5);
+        assert_eq!(estimator.estimate(), None);
+        for value in [4.0, 1.0, 3.0, 2.0] {
+            estimator.push(value);
+        }
+        assert_eq!(estimator.estimate(), Some(2.5));
+        estimator.push(5.0);
+        assert_eq!(estimator.estimate(), Some(3.0));
+    }
+
+    #[test]
+    fn running_stats_match_the_two_pass_values() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let mut stats = RunningStats::default();
+        values.iter().for_each(|&value| stats.push(value));
+        assert_eq!((stats.count, stats.mean, stats.min, stats.max), (8, 5.0, 2.0, 9.0));
+        assert!((stats.std_dev().unwrap() - (32.0_f64 / 7.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn streamed_summaries_and_group_means_skip_blanks() {
+        let data = "Status,Value\nDeveloped,80\nDeveloped,\nDeveloping,60\nDeveloping,64\n,99\n";
+        let layout = RecordLayout::new(&["Status".to_string(), "Value".to_string()]);
+        let summaries = stream_describe(&mut Reader::from_reader(data.as_bytes()), &layout, &[0, 1]).unwrap();
+        assert!(summaries[0].is_none());
+        let value = summaries[1].as_ref().unwrap();
+        assert_eq!((value.count, value.missing, value.min, value.max), (4, 1, 60.0, 99.0));
+        assert_eq!(value.mean, 75.75);
+
+        let grouped = stream_grouped_mean(&mut Reader::from_reader(data.as_bytes()), 0, 1).unwrap();
+        assert_eq!(grouped.means["Developed"], (80.0, 1));
+        assert_eq!(grouped.means["Developing"], (62.0, 2));
+        assert_eq!((grouped.means.len(), grouped.skipped), (2, 1));
+    }
+}
@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::ops::RangeInclusive;
+use csv::Writer;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use statrs::distribution::{LogNormal, Normal};
+use crate::fields::{LifeExpectancyRecord, FIELDS};
+use crate::output;
+
+// Share of generated countries that are Developed, close to the WHO data's 32 of 193
+const DEVELOPED_SHARE: f64 = 0.2;
+
+/// Plausible WHO-like rows for `n_countries` countries over `years`, identical for
+/// the same seed. GDP is log-normal and grows a little each year; life expectancy
+/// is a noisy function of log GDP and schooling, and the other fields follow from
+/// those two. No value is missing; see blank_values.
+pub fn generate_synthetic_dataset(n_countries: usize, years: RangeInclusive<u16>, seed: u64) -> Vec<LifeExpectancyRecord> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let normal = |mean: f64, std_dev: f64| Normal::new(mean, std_dev).unwrap();
+    let noise = normal(0.0, 1.0);
+
+    let mut records = Vec::with_capacity(n_countries * years.len());
+    for i in 0..n_countries {
+        let developed = rng.gen_bool(DEVELOPED_SHARE);
+        let status = if developed { "Developed" } else { "Developing" };
+        let gdp_start = rng.sample(if developed { LogNormal::new(10.2, 0.5) } else { LogNormal::new(7.5, 1.0) }.unwrap());
+        let growth = rng.sample(normal(0.02, 0.015));
+        let schooling_start = rng.sample(if developed { normal(15.5, 1.5) } else { normal(10.0, 2.5) });
+        let population_start = rng.sample(LogNormal::new(15.5, 1.8).unwrap());
+        let alcohol = rng.sample(if developed { normal(9.0, 2.0) } else { normal(3.5, 2.5) }).clamp(0.01, 17.0);
+        let total_expenditure = rng.sample(if developed { normal(8.5, 1.5) } else { normal(5.5, 2.0) }).clamp(1.0, 17.0);
+        let hiv_aids = if developed { 0.1 } else { rng.sample(LogNormal::new(-1.0, 1.3).unwrap()).clamp(0.1, 40.0) };
+        let thinness = if developed { rng.sample(normal(1.5, 0.7)) } else { rng.sample(normal(7.5, 3.5)) }.clamp(0.1, 27.0);
+
+        for (t, year) in years.clone().enumerate() {
+            let t = t as f64;
+            let gdp = gdp_start * (growth * t).exp() * (0.05 * rng.sample(noise)).exp();
+            let schooling = (schooling_start + 0.08 * t + 0.3 * rng.sample(noise)).clamp(0.0, 20.5);
+            let life_expectancy =
+                (33.0 + 3.0 * gdp.ln() + schooling - 0.6 * hiv_aids + 2.5 * rng.sample(noise)).clamp(36.0, 89.0);
+            // Deaths and coverage scale with the gap to a long life
+            let gap = (85.0 - life_expectancy).max(0.0);
+            let adult_mortality = (20.0 + 8.0 * gap + 15.0 * rng.sample(noise)).clamp(1.0, 720.0).round();
+            let infant_deaths = (0.4 * gap * (1.0 + 0.2 * rng.sample(noise))).max(0.0).round();
+            let coverage = |rng: &mut StdRng| (99.0 - 1.5 * gap + 5.0 * rng.sample(noise)).clamp(5.0, 99.0).round();
+            let (hepatitis_b, polio, diphtheria) = (coverage(&mut rng), coverage(&mut rng), coverage(&mut rng));
+            let measles = (rng.sample(LogNormal::new(0.15 * gap, 1.0).unwrap()) - 1.0).max(0.0).round();
+            let total = (total_expenditure + 0.3 * rng.sample(noise)).clamp(1.0, 17.0);
+            let year_thinness = (thinness * (1.0 + 0.05 * rng.sample(noise))).max(0.1);
+
+            records.push(LifeExpectancyRecord {
+                country: format!("Country {:03}", i + 1),
+                year,
+                status: status.to_string(),
+                life_expectancy: Some(round_to(life_expectancy, 1)),
+                adult_mortality: Some(adult_mortality),
+                infant_deaths: Some(infant_deaths),
+                alcohol: Some(round_to((alcohol + 0.3 * rng.sample(noise)).max(0.01), 2)),
+                percentage_expenditure: Some(round_to(gdp * total / 100.0 * (0.2 * rng.sample(noise)).exp(), 2)),
+                hepatitis_b: Some(hepatitis_b),
+                measles: Some(measles),
+                bmi: Some(round_to((22.0 + 0.6 * schooling + 2.0 * rng.sample(noise)).clamp(12.0, 60.0), 1)),
+                under_five_deaths: Some((infant_deaths * 1.3).round()),
+                polio: Some(polio),
+                total_expenditure: Some(round_to(total, 2)),
+                diphtheria: Some(diphtheria),
+                hiv_aids: Some(round_to(hiv_aids * (1.0 + 0.1 * rng.sample(noise)).max(0.5), 1)),
+                gdp: Some(round_to(gdp, 2)),
+                population: Some((population_start * (0.012 * t).exp()).round()),
+                thinness_1_19_years: Some(round_to(year_thinness, 1)),
+                thinness_5_9_years: Some(round_to(year_thinness * (1.0 + 0.05 * rng.sample(noise)), 1)),
+                income_composition_of_resources: Some(round_to(
+                    (0.075 * (gdp.ln() - 5.0) + 0.035 * schooling).clamp(0.0, 0.95),
+                    3,
+                )),
+                schooling: Some(round_to(schooling, 1)),
+            });
+        }
+    }
+    records
+}
+
+fn round_to(value: f64, decimals: i32) -> f64 {
+    let scale = 10f64.powi(decimals);
+    (value * scale).round() / scale
+}
+
+// Blank each numeric value with probability `fraction`, deterministically per
+// seed; returns how many were blanked
+pub(crate) fn blank_values(records: &mut [LifeExpectancyRecord], fraction: f64, seed: u64) -> usize {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut blanked = 0;
+    for record in records.iter_mut() {
+        for meta in FIELDS {
+            if (meta.getter)(record).is_some() && rng.gen_bool(fraction) {
+                (meta.setter)(record, None);
+                blanked += 1;
+            }
+        }
+    }
+    blanked
+}
+
+/// Write records in the layout of the WHO file, so every loader reads them
+pub fn write_dataset_csv(records: &[LifeExpectancyRecord], output_file: &str) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, None)?);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_clean::{load_cleaned_data, RowErrorPolicy};
+
+    #[test]
+    fn the_same_seed_gives_the_same_dataset() {
+        let records = generate_synthetic_dataset(12, 2000..=2009, 5);
+        assert_eq!(records, generate_synthetic_dataset(12, 2000..=2009, 5));
+        assert_ne!(records, generate_synthetic_dataset(12, 2000..=2009, 6));
+        assert_eq!(records.len(), 120);
+        assert_eq!((records[0].country.as_str(), records[0].year), ("Country 001", 2000));
+        // Every value is present; like the WHO data, percentage expenditure may pass 100
+        for meta in FIELDS {
+            let (low, high) = meta.valid_range;
+            let high = if meta.name == "percentage expenditure" { f64::INFINITY } else { high };
+            assert!(records.iter().all(|record| (meta.getter)(record).is_some_and(|value| (low..=high).contains(&value))), "{}", meta.name);
+        }
+    }
+
+    #[test]
+    fn blanking_is_seeded_and_the_csv_reads_back() {
+        let mut a = generate_synthetic_dataset(10, 2000..=2004, 1);
+        let mut b = a.clone();
+        let blanked = blank_values(&mut a, 0.1, 3);
+        assert_eq!(blank_values(&mut b, 0.1, 3), blanked);
+        assert_eq!(a, b);
+        let cells = 50 * FIELDS.len();
+        assert!(blanked > cells / 20 && blanked < cells / 5, "{} of {}", blanked, cells);
+
+        let path = crate::test_support::scratch_dir("synthetic-csv").join("data.csv");
+        write_dataset_csv(&a, path.to_str().unwrap()).unwrap();
+        assert_eq!(load_cleaned_data(path.to_str().unwrap(), &RowErrorPolicy::Fail).unwrap(), a);
+    }
+}
@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::io::IsTerminal;
+
+// One table cell. Numbers keep their value for sorting and are right-aligned;
+// signed numbers are also colored by sign when color is on.
+#[derive(Debug, Clone)]
+pub(crate) enum Cell {
+    Text(String),
+    Number { value: Option<f64>, text: String, signed: bool },
+}
+
+impl Cell {
+    pub(crate) fn text(text: impl Into<String>) -> Self {
+        Cell::Text(text.into())
+    }
+
+    // A number printed with `precision` decimals; None shows as "-"
+    pub(crate) fn number(value: Option<f64>, precision: usize) -> Self {
+        let text = value.map_or("-".to_string(), |v| format!("{:.*}", precision, v));
+        Cell::Number { value, text, signed: false }
+    }
+
+    // A number with its sign always shown, e.g. a correlation or an effect size
+    pub(crate) fn signed(value: Option<f64>, precision: usize) -> Self {
+        let text = value.map_or("-".to_string(), |v| format!("{:+.*}", precision, v));
+        Cell::Number { value, text, signed: true }
+    }
+
+    pub(crate) fn count(count: usize) -> Self {
+        Cell::Number { value: Some(count as f64), text: count.to_string(), signed: false }
+    }
+
+    // A number already formatted by the caller (e.g. with format_value and a unit)
+    pub(crate) fn formatted(value: Option<f64>, text: impl Into<String>) -> Self {
+        Cell::Number { value, text: text.into(), signed: false }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Cell::Text(text) | Cell::Number { text, .. } => text,
+        }
+    }
+
+    fn is_missing(&self) -> bool {
+        matches!(self, Cell::Number { value: None, .. })
+    }
+
+    // Numbers before text, missing numbers last
+    fn compare(&self, other: &Cell) -> Ordering {
+        match (self, other) {
+            (Cell::Number { value: a, .. }, Cell::Number { value: b, .. }) => match (a, b) {
+                (Some(a), Some(b)) => a.total_cmp(b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            },
+            (a, b) => a.as_str().cmp(b.as_str()),
+        }
+    }
+}
+
+// How console tables are printed: the --sort-by column and direction, whether
+// to color signed values, and the width text columns are shrunk to
+#[derive(Debug, Clone)]
+pub(crate) struct TableOptions {
+    pub(crate) sort_by: Option<(String, bool)>, // column name, descending
+    pub(crate) color: bool,
+    pub(crate) max_width: usize,
+}
+
+impl TableOptions {
+    // Color and width follow the terminal: both are off when stdout is not one
+    // (or NO_COLOR is set), unless COLUMNS gives a width
+    pub(crate) fn new(sort_by: Option<(String, bool)>) -> Self {
+        let terminal = std::io::stdout().is_terminal();
+        let columns = std::env::var("COLUMNS").ok().and_then(|value| value.parse().ok());
+        TableOptions {
+            sort_by,
+            color: terminal && std::env::var_os("NO_COLOR").is_none(),
+            max_width: columns.unwrap_or(if terminal { 120 } else { usize::MAX }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    color: bool,
+}
+
+impl Table {
+    pub(crate) fn new(headers: &[&str]) -> Self {
+        Table { headers: headers.iter().map(|h| h.to_string()).collect(), rows: Vec::new(), color: false }
+    }
+
+    pub(crate) fn add_row(mut self, row: Vec<Cell>) -> Self {
+        self.rows.push(row);
+        self
+    }
+
+    // Stable sort on the named column (ignoring case), missing numbers last in
+    // either direction; a table without that column keeps its order
+    pub(crate) fn sort_by(mut self, column: &str, descending: bool) -> Self {
+        if let Some(index) = self.headers.iter().position(|header| header.eq_ignore_ascii_case(column)) {
+            self.rows.sort_by(|a, b| {
+                let (a, b) = (&a[index], &b[index]);
+                a.is_missing().cmp(&b.is_missing()).then_with(|| {
+                    let ordering = a.compare(b);
+                    if descending { ordering.reverse() } else { ordering }
+                })
+            });
+        }
+        self
+    }
+
+    pub(crate) fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // Aligned columns, two spaces apart. When wider than `max_width`, the widest
+    // text column loses characters first; cut cells end in "…".
+    pub(crate) fn render(&self, max_width: usize) -> String {
+        let n = self.headers.len();
+        let numeric: Vec<bool> =
+            (0..n).map(|i| !self.rows.is_empty() && self.rows.iter().all(|row| matches!(row.get(i), Some(Cell::Number { .. })))).collect();
+        let mut widths: Vec<usize> = (0..n)
+            .map(|i| {
+                let cells = self.rows.iter().filter_map(|row| row.get(i)).map(|cell| cell.as_str().chars().count());
+                cells.chain([self.headers[i].chars().count()]).max().unwrap_or(0)
+            })
+            .collect();
+        let total = |widths: &[usize]| widths.iter().sum::<usize>() + 2 * n.saturating_sub(1);
+        while total(&widths) > max_width {
+            let widest = (0..n).filter(|&i| !numeric[i] && widths[i] > 4).max_by_key(|&i| widths[i]);
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+        }
+
+        let line = |cells: Vec<(&str, Option<&Cell>)>| {
+            let parts: Vec<String> = cells
+                .into_iter()
+                .enumerate()
+                .map(|(i, (text, cell))| {
+                    let text = truncate(text, widths[i]);
+                    let padded = if numeric[i] { format!("{:>w$}", text, w = widths[i]) } else { format!("{:<w$}", text, w = widths[i]) };
+                    match cell {
+                        Some(&Cell::Number { value: Some(value), signed: true, .. }) if self.color && value != 0.0 => {
+                            format!("\x1b[{}m{}\x1b[0m", if value > 0.0 { 32 } else { 31 }, padded)
+                        }
+                        _ => padded,
+                    }
+                })
+                .collect();
+            parts.join("  ").trim_end().to_string()
+        };
+
+        let mut lines = vec![line(self.headers.iter().map(|h| (h.as_str(), None)).collect())];
+        for row in &self.rows {
+            lines.push(line((0..n).map(|i| row.get(i).map_or(("", None), |cell| (cell.as_str(), Some(cell)))).collect()));
+        }
+        lines.join("\n")
+    }
+
+    // Sort and color as the options say, then print
+    pub(crate) fn print(self, options: &TableOptions) {
+        let mut table = self.color(options.color);
+        if let Some((column, descending)) = &options.sort_by {
+            table = table.sort_by(column, *descending);
+        }
+        println!("{}", table.render(options.max_width));
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rankings() -> Table {
+        Table::new(&["Country", "r", "n"])
+            .add_row(vec![Cell::text("Japan"), Cell::signed(Some(0.5), 3), Cell::count(10)])
+            .add_row(vec![Cell::text("Chad"), Cell::signed(Some(-0.25), 3), Cell::count(3)])
+            .add_row(vec![Cell::text("Peru"), Cell::signed(None, 3), Cell::count(7)])
+    }
+
+    #[test]
+    fn numbers_are_right_aligned() {
+        let expected = ["Country       r   n", "Japan    +0.500  10", "Chad     -0.250   3", "Peru          -   7"];
+        assert_eq!(rankings().render(usize::MAX), expected.join("\n"));
+        assert_eq!(Cell::number(Some(2.0 / 3.0), 2).as_str(), "0.67");
+    }
+
+    #[test]
+    fn sorting_keeps_missing_numbers_last() {
+        let order = |table: Table| table.render(usize::MAX).lines().skip(1).map(|line| line[..4].to_string()).collect::<Vec<_>>();
+        assert_eq!(order(rankings().sort_by("R", false)), ["Chad", "Japa", "Peru"]);
+        assert_eq!(order(rankings().sort_by("r", true)), ["Japa", "Chad", "Peru"]);
+        assert_eq!(order(rankings().sort_by("Country", false)), ["Chad", "Japa", "Peru"]);
+        assert_eq!(order(rankings().sort_by("Happiness", true)), ["Japa", "Chad", "Peru"]);
+    }
+
+    #[test]
+    fn narrow_tables_cut_text_and_color_is_optional() {
+        let narrow = rankings().render(16);
+        assert_eq!(narrow.lines().next().unwrap(), "Cou…       r   n");
+        assert_eq!(narrow.lines().nth(1).unwrap(), "Jap…  +0.500  10");
+        let colored = rankings().color(true).render(usize::MAX);
+        assert!(colored.contains("\x1b[32m+0.500\x1b[0m") && colored.contains("\x1b[31m-0.250\x1b[0m"));
+        assert!(!rankings().render(usize::MAX).contains('\x1b'));
+    }
+}
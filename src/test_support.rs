@@ -0,0 +1,13 @@
+use std::fs;
+use std::path::PathBuf;
+
+// Empty directory under the system temp dir, unique to the test and the process
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ds210-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Held by tests that record a run manifest, which is global to the process
+pub(crate) static RECORDING: std::sync::Mutex<()> = std::sync::Mutex::new(());
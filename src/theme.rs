@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use crate::format::TickStyle;
+
+// Presentation settings shared by the chart functions
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct PlotTheme {
+    pub(crate) x_ticks: TickStyle,
+    pub(crate) y_ticks: TickStyle,
+}
@@ -0,0 +1,348 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use crate::analysis_error::AnalysisError;
+use crate::colormap::{self, PALETTE};
+use crate::metadata::{self, RunMetadata};
+use crate::output;
+
+// Grid cell of one country on the tile map. Positions follow the countries'
+// rough geographic layout (north at the top), nudged apart so every country
+// gets a cell of its own; the code is its ISO 3166 alpha-3 code.
+pub(crate) struct TilePosition {
+    pub(crate) country: &'static str,
+    pub(crate) code: &'static str,
+    pub(crate) col: u32,
+    pub(crate) row: u32,
+}
+
+// Every country of the dataset, by its name in the dataset
+pub(crate) const TILE_POSITIONS: &[TilePosition] = &[
+    TilePosition { country: "Afghanistan", code: "AFG", col: 19, row: 6 },
+    TilePosition { country: "Albania", code: "ALB", col: 13, row: 4 },
+    TilePosition { country: "Algeria", code: "DZA", col: 11, row: 6 },
+    TilePosition { country: "Angola", code: "AGO", col: 13, row: 14 },
+    TilePosition { country: "Antigua and Barbuda", code: "ATG", col: 6, row: 8 },
+    TilePosition { country: "Argentina", code: "ARG", col: 5, row: 18 },
+    TilePosition { country: "Armenia", code: "ARM", col: 18, row: 4 },
+    TilePosition { country: "Australia", code: "AUS", col: 29, row: 16 },
+    TilePosition { country: "Austria", code: "AUT", col: 11, row: 0 },
+    TilePosition { country: "Azerbaijan", code: "AZE", col: 17, row: 4 },
+    TilePosition { country: "Bahamas", code: "BHS", col: 4, row: 7 },
+    TilePosition { country: "Bahrain", code: "BHR", col: 17, row: 8 },
+    TilePosition { country: "Bangladesh", code: "BGD", col: 23, row: 7 },
+    TilePosition { country: "Barbados", code: "BRB", col: 6, row: 9 },
+    TilePosition { country: "Belarus", code: "BLR", col: 15, row: 2 },
+    TilePosition { country: "Belgium", code: "BEL", col: 11, row: 2 },
+    TilePosition { country: "Belize", code: "BLZ", col: 2, row: 8 },
+    TilePosition { country: "Benin", code: "BEN", col: 12, row: 9 },
+    TilePosition { country: "Bhutan", code: "BTN", col: 23, row: 6 },
+    TilePosition { country: "Bolivia (Plurinational State of)", code: "BOL", col: 5, row: 15 },
+    TilePosition { country: "Bosnia and Herzegovina", code: "BIH", col: 12, row: 6 },
+    TilePosition { country: "Botswana", code: "BWA", col: 14, row: 16 },
+    TilePosition { country: "Brazil", code: "BRA", col: 7, row: 13 },
+    TilePosition { country: "Brunei Darussalam", code: "BRN", col: 26, row: 11 },
+    TilePosition { country: "Bulgaria", code: "BGR", col: 17, row: 3 },
+    TilePosition { country: "Burkina Faso", code: "BFA", col: 11, row: 9 },
+    TilePosition { country: "Burundi", code: "BDI", col: 14, row: 13 },
+    TilePosition { country: "Cabo Verde", code: "CPV", col: 8, row: 9 },
+    TilePosition { country: "Cambodia", code: "KHM", col: 25, row: 10 },
+    TilePosition { country: "Cameroon", code: "CMR", col: 13, row: 11 },
+    TilePosition { country: "Canada", code: "CAN", col: 0, row: 1 },
+    TilePosition { country: "Central African Republic", code: "CAF", col: 14, row: 10 },
+    TilePosition { country: "Chad", code: "TCD", col: 13, row: 9 },
+    TilePosition { country: "Chile", code: "CHL", col: 4, row: 18 },
+    TilePosition { country: "China", code: "CHN", col: 25, row: 5 },
+    TilePosition { country: "Colombia", code: "COL", col: 4, row: 11 },
+    TilePosition { country: "Comoros", code: "COM", col: 17, row: 14 },
+    TilePosition { country: "Congo", code: "COG", col: 13, row: 12 },
+    TilePosition { country: "Cook Islands", code: "COK", col: 37, row: 16 },
+    TilePosition { country: "Costa Rica", code: "CRI", col: 2, row: 10 },
+    TilePosition { country: "Croatia", code: "HRV", col: 10, row: 5 },
+    TilePosition { country: "Cuba", code: "CUB", col: 3, row: 8 },
+    TilePosition { country: "Cyprus", code: "CYP", col: 15, row: 5 },
+    TilePosition { country: "Czechia", code: "CZE", col: 13, row: 1 },
+    TilePosition { country: "Côte d'Ivoire", code: "CIV", col: 10, row: 10 },
+    TilePosition { country: "Democratic People's Republic of Korea", code: "PRK", col: 28, row: 4 },
+    TilePosition { country: "Democratic Republic of the Congo", code: "COD", col: 14, row: 12 },
+    TilePosition { country: "Denmark", code: "DNK", col: 12, row: 1 },
+    TilePosition { country: "Djibouti", code: "DJI", col: 17, row: 10 },
+    TilePosition { country: "Dominica", code: "DMA", col: 5, row: 9 },
+    TilePosition { country: "Dominican Republic", code: "DOM", col: 5, row: 8 },
+    TilePosition { country: "Ecuador", code: "ECU", col: 3, row: 12 },
+    TilePosition { country: "Egypt", code: "EGY", col: 15, row: 7 },
+    TilePosition { country: "El Salvador", code: "SLV", col: 2, row: 9 },
+    TilePosition { country: "Equatorial Guinea", code: "GNQ", col: 12, row: 11 },
+    TilePosition { country: "Eritrea", code: "ERI", col: 16, row: 9 },
+    TilePosition { country: "Estonia", code: "EST", col: 15, row: 1 },
+    TilePosition { country: "Ethiopia", code: "ETH", col: 16, row: 10 },
+    TilePosition { country: "Fiji", code: "FJI", col: 35, row: 15 },
+    TilePosition { country: "Finland", code: "FIN", col: 14, row: 0 },
+    TilePosition { country: "France", code: "FRA", col: 11, row: 3 },
+    TilePosition { country: "Gabon", code: "GAB", col: 12, row: 12 },
+    TilePosition { country: "Gambia", code: "GMB", col: 8, row: 8 },
+    TilePosition { country: "Georgia", code: "GEO", col: 16, row: 4 },
+    TilePosition { country: "Germany", code: "DEU", col: 12, row: 2 },
+    TilePosition { country: "Ghana", code: "GHA", col: 11, row: 11 },
+    TilePosition { country: "Greece", code: "GRC", col: 14, row: 5 },
+    TilePosition { country: "Grenada", code: "GRD", col: 7, row: 10 },
+    TilePosition { country: "Guatemala", code: "GTM", col: 1, row: 9 },
+    TilePosition { country: "Guinea", code: "GIN", col: 10, row: 9 },
+    TilePosition { country: "Guinea-Bissau", code: "GNB", col: 8, row: 10 },
+    TilePosition { country: "Guyana", code: "GUY", col: 6, row: 11 },
+    TilePosition { country: "Haiti", code: "HTI", col: 4, row: 8 },
+    TilePosition { country: "Honduras", code: "HND", col: 1, row: 8 },
+    TilePosition { country: "Hungary", code: "HUN", col: 16, row: 1 },
+    TilePosition { country: "Iceland", code: "ISL", col: 8, row: 0 },
+    TilePosition { country: "India", code: "IND", col: 21, row: 8 },
+    TilePosition { country: "Indonesia", code: "IDN", col: 27, row: 12 },
+    TilePosition { country: "Iran (Islamic Republic of)", code: "IRN", col: 18, row: 6 },
+    TilePosition { country: "Iraq", code: "IRQ", col: 17, row: 5 },
+    TilePosition { country: "Ireland", code: "IRL", col: 10, row: 2 },
+    TilePosition { country: "Israel", code: "ISR", col: 15, row: 6 },
+    TilePosition { country: "Italy", code: "ITA", col: 12, row: 4 },
+    TilePosition { country: "Jamaica", code: "JAM", col: 4, row: 9 },
+    TilePosition { country: "Japan", code: "JPN", col: 29, row: 5 },
+    TilePosition { country: "Jordan", code: "JOR", col: 16, row: 6 },
+    TilePosition { country: "Kazakhstan", code: "KAZ", col: 20, row: 3 },
+    TilePosition { country: "Kenya", code: "KEN", col: 16, row: 12 },
+    TilePosition { country: "Kiribati", code: "KIR", col: 38, row: 11 },
+    TilePosition { country: "Kuwait", code: "KWT", col: 17, row: 6 },
+    TilePosition { country: "Kyrgyzstan", code: "KGZ", col: 21, row: 4 },
+    TilePosition { country: "Lao People's Democratic Republic", code: "LAO", col: 24, row: 8 },
+    TilePosition { country: "Latvia", code: "LVA", col: 14, row: 1 },
+    TilePosition { country: "Lebanon", code: "LBN", col: 14, row: 6 },
+    TilePosition { country: "Lesotho", code: "LSO", col: 15, row: 17 },
+    TilePosition { country: "Liberia", code: "LBR", col: 10, row: 11 },
+    TilePosition { country: "Libya", code: "LBY", col: 13, row: 7 },
+    TilePosition { country: "Lithuania", code: "LTU", col: 14, row: 2 },
+    TilePosition { country: "Luxembourg", code: "LUX", col: 10, row: 3 },
+    TilePosition { country: "Madagascar", code: "MDG", col: 17, row: 15 },
+    TilePosition { country: "Malawi", code: "MWI", col: 15, row: 14 },
+    TilePosition { country: "Malaysia", code: "MYS", col: 24, row: 11 },
+    TilePosition { country: "Maldives", code: "MDV", col: 21, row: 11 },
+    TilePosition { country: "Mali", code: "MLI", col: 10, row: 8 },
+    TilePosition { country: "Malta", code: "MLT", col: 13, row: 5 },
+    TilePosition { country: "Marshall Islands", code: "MHL", col: 34, row: 10 },
+    TilePosition { country: "Mauritania", code: "MRT", col: 9, row: 8 },
+    TilePosition { country: "Mauritius", code: "MUS", col: 18, row: 15 },
+    TilePosition { country: "Mexico", code: "MEX", col: 0, row: 7 },
+    TilePosition { country: "Micronesia (Federated States of)", code: "FSM", col: 31, row: 10 },
+    TilePosition { country: "Monaco", code: "MCO", col: 11, row: 4 },
+    TilePosition { country: "Mongolia", code: "MNG", col: 25, row: 3 },
+    TilePosition { country: "Montenegro", code: "MNE", col: 13, row: 6 },
+    TilePosition { country: "Morocco", code: "MAR", col: 10, row: 6 },
+    TilePosition { country: "Mozambique", code: "MOZ", col: 16, row: 15 },
+    TilePosition { country: "Myanmar", code: "MMR", col: 23, row: 8 },
+    TilePosition { country: "Namibia", code: "NAM", col: 13, row: 16 },
+    TilePosition { country: "Nauru", code: "NRU", col: 33, row: 12 },
+    TilePosition { country: "Nepal", code: "NPL", col: 22, row: 6 },
+    TilePosition { country: "Netherlands", code: "NLD", col: 10, row: 1 },
+    TilePosition { country: "New Zealand", code: "NZL", col: 34, row: 19 },
+    TilePosition { country: "Nicaragua", code: "NIC", col: 3, row: 9 },
+    TilePosition { country: "Niger", code: "NER", col: 12, row: 8 },
+    TilePosition { country: "Nigeria", code: "NGA", col: 12, row: 10 },
+    TilePosition { country: "Niue", code: "NIU", col: 36, row: 15 },
+    TilePosition { country: "Norway", code: "NOR", col: 12, row: 0 },
+    TilePosition { country: "Oman", code: "OMN", col: 18, row: 8 },
+    TilePosition { country: "Pakistan", code: "PAK", col: 20, row: 6 },
+    TilePosition { country: "Palau", code: "PLW", col: 29, row: 10 },
+    TilePosition { country: "Panama", code: "PAN", col: 3, row: 10 },
+    TilePosition { country: "Papua New Guinea", code: "PNG", col: 30, row: 13 },
+    TilePosition { country: "Paraguay", code: "PRY", col: 6, row: 16 },
+    TilePosition { country: "Peru", code: "PER", col: 4, row: 13 },
+    TilePosition { country: "Philippines", code: "PHL", col: 27, row: 9 },
+    TilePosition { country: "Poland", code: "POL", col: 13, row: 2 },
+    TilePosition { country: "Portugal", code: "PRT", col: 9, row: 4 },
+    TilePosition { country: "Qatar", code: "QAT", col: 19, row: 7 },
+    TilePosition { country: "Republic of Korea", code: "KOR", col: 28, row: 5 },
+    TilePosition { country: "Republic of Moldova", code: "MDA", col: 16, row: 3 },
+    TilePosition { country: "Romania", code: "ROU", col: 14, row: 3 },
+    TilePosition { country: "Russian Federation", code: "RUS", col: 23, row: 0 },
+    TilePosition { country: "Rwanda", code: "RWA", col: 15, row: 12 },
+    TilePosition { country: "Saint Kitts and Nevis", code: "KNA", col: 7, row: 8 },
+    TilePosition { country: "Saint Lucia", code: "LCA", col: 7, row: 9 },
+    TilePosition { country: "Saint Vincent and the Grenadines", code: "VCT", col: 4, row: 10 },
+    TilePosition { country: "Samoa", code: "WSM", col: 36, row: 14 },
+    TilePosition { country: "San Marino", code: "SMR", col: 11, row: 5 },
+    TilePosition { country: "Sao Tome and Principe", code: "STP", col: 11, row: 12 },
+    TilePosition { country: "Saudi Arabia", code: "SAU", col: 17, row: 7 },
+    TilePosition { country: "Senegal", code: "SEN", col: 9, row: 9 },
+    TilePosition { country: "Serbia", code: "SRB", col: 17, row: 2 },
+    TilePosition { country: "Seychelles", code: "SYC", col: 18, row: 13 },
+    TilePosition { country: "Sierra Leone", code: "SLE", col: 9, row: 10 },
+    TilePosition { country: "Singapore", code: "SGP", col: 25, row: 11 },
+    TilePosition { country: "Slovakia", code: "SVK", col: 16, row: 2 },
+    TilePosition { country: "Slovenia", code: "SVN", col: 13, row: 3 },
+    TilePosition { country: "Solomon Islands", code: "SLB", col: 32, row: 13 },
+    TilePosition { country: "Somalia", code: "SOM", col: 17, row: 11 },
+    TilePosition { country: "South Africa", code: "ZAF", col: 14, row: 17 },
+    TilePosition { country: "South Sudan", code: "SSD", col: 15, row: 10 },
+    TilePosition { country: "Spain", code: "ESP", col: 10, row: 4 },
+    TilePosition { country: "Sri Lanka", code: "LKA", col: 22, row: 10 },
+    TilePosition { country: "Sudan", code: "SDN", col: 15, row: 9 },
+    TilePosition { country: "Suriname", code: "SUR", col: 7, row: 11 },
+    TilePosition { country: "Swaziland", code: "SWZ", col: 15, row: 16 },
+    TilePosition { country: "Sweden", code: "SWE", col: 13, row: 0 },
+    TilePosition { country: "Switzerland", code: "CHE", col: 12, row: 3 },
+    TilePosition { country: "Syrian Arab Republic", code: "SYR", col: 16, row: 5 },
+    TilePosition { country: "Tajikistan", code: "TJK", col: 20, row: 4 },
+    TilePosition { country: "Thailand", code: "THA", col: 24, row: 9 },
+    TilePosition { country: "The former Yugoslav republic of Macedonia", code: "MKD", col: 14, row: 4 },
+    TilePosition { country: "Timor-Leste", code: "TLS", col: 28, row: 13 },
+    TilePosition { country: "Togo", code: "TGO", col: 11, row: 10 },
+    TilePosition { country: "Tonga", code: "TON", col: 35, row: 16 },
+    TilePosition { country: "Trinidad and Tobago", code: "TTO", col: 6, row: 10 },
+    TilePosition { country: "Tunisia", code: "TUN", col: 12, row: 5 },
+    TilePosition { country: "Turkey", code: "TUR", col: 15, row: 4 },
+    TilePosition { country: "Turkmenistan", code: "TKM", col: 19, row: 5 },
+    TilePosition { country: "Tuvalu", code: "TUV", col: 34, row: 13 },
+    TilePosition { country: "Uganda", code: "UGA", col: 15, row: 11 },
+    TilePosition { country: "Ukraine", code: "UKR", col: 15, row: 3 },
+    TilePosition { country: "United Arab Emirates", code: "ARE", col: 18, row: 7 },
+    TilePosition { country: "United Kingdom of Great Britain and Northern Ireland", code: "GBR", col: 11, row: 1 },
+    TilePosition { country: "United Republic of Tanzania", code: "TZA", col: 15, row: 13 },
+    TilePosition { country: "United States of America", code: "USA", col: 1, row: 4 },
+    TilePosition { country: "Uruguay", code: "URY", col: 6, row: 18 },
+    TilePosition { country: "Uzbekistan", code: "UZB", col: 19, row: 4 },
+    TilePosition { country: "Vanuatu", code: "VUT", col: 33, row: 14 },
+    TilePosition { country: "Venezuela (Bolivarian Republic of)", code: "VEN", col: 5, row: 10 },
+    TilePosition { country: "Viet Nam", code: "VNM", col: 25, row: 9 },
+    TilePosition { country: "Yemen", code: "YEM", col: 17, row: 9 },
+    TilePosition { country: "Zambia", code: "ZMB", col: 14, row: 14 },
+    TilePosition { country: "Zimbabwe", code: "ZWE", col: 15, row: 15 },
+];
+
+pub(crate) fn tile_position(country: &str) -> Option<&'static TilePosition> {
+    TILE_POSITIONS.iter().find(|tile| tile.country == country)
+}
+
+// What the tiles are colored by
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TileValues<'a> {
+    Values(&'a HashMap<String, f64>),     // sequential colormap with a color bar
+    Clusters(&'a HashMap<String, usize>), // one palette color per cluster
+}
+
+// One labelled square per country at its tile position. Countries of the table
+// without a value are drawn as grey outlines; countries missing from the table
+// are left off and listed in a warning.
+pub(crate) fn plot_tile_map(values: TileValues, title: &str, output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let countries: Vec<&String> = match values {
+        TileValues::Values(values) => values.keys().collect(),
+        TileValues::Clusters(clusters) => clusters.keys().collect(),
+    };
+    if countries.is_empty() {
+        return Err(AnalysisError::empty(format!("{} tile map", title)).into());
+    }
+    let unknown: BTreeSet<&str> =
+        countries.iter().filter(|country| tile_position(country).is_none()).map(|country| country.as_str()).collect();
+    if !unknown.is_empty() {
+        println!(
+            "{}: no tile position for {} countries, left off: {}",
+            output_file,
+            unknown.len(),
+            unknown.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let cols = TILE_POSITIONS.iter().map(|tile| tile.col).max().unwrap_or(0) + 1;
+    let rows = TILE_POSITIONS.iter().map(|tile| tile.row).max().unwrap_or(0) + 1;
+    let cell = 40;
+    let (left, top) = (20, 70);
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (left as u32 * 2 + cols * cell as u32 + 160, top as u32 + rows * cell as u32 + 50))
+        .into_drawing_area();
+    root.fill(&WHITE)?;
+    root.draw(&Text::new(title.to_string(), (left, 20), ("sans-serif", 28)))?;
+
+    let range = match values {
+        TileValues::Values(values) => {
+            let (min, max) = values.values().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            Some((min, max))
+        }
+        TileValues::Clusters(_) => None,
+    };
+    let color_of = |country: &str| -> Option<RGBColor> {
+        match values {
+            TileValues::Values(values) => {
+                let (min, max) = range?;
+                let value = values.get(country)?;
+                Some(colormap::sample(if max > min { (value - min) / (max - min) } else { 0.5 }))
+            }
+            TileValues::Clusters(clusters) => clusters.get(country).map(|&cluster| PALETTE[cluster % PALETTE.len()]),
+        }
+    };
+
+    let label = TextStyle::from(("sans-serif", 11).into_font()).pos(Pos::new(HPos::Center, VPos::Center));
+    for tile in TILE_POSITIONS {
+        let (x, y) = (left + tile.col as i32 * cell, top + tile.row as i32 * cell);
+        let square = [(x + 1, y + 1), (x + cell - 1, y + cell - 1)];
+        match color_of(tile.country) {
+            Some(color) => {
+                root.draw(&Rectangle::new(square, color.filled()))?;
+                // Dark label on light tiles, white on dark ones
+                let luminance = 0.299 * color.0 as f64 + 0.587 * color.1 as f64 + 0.114 * color.2 as f64;
+                let text = if luminance > 140.0 { BLACK } else { WHITE };
+                root.draw(&Text::new(tile.code, (x + cell / 2, y + cell / 2), label.color(&text)))?;
+            }
+            None => {
+                root.draw(&Rectangle::new(square, RGBColor(200, 200, 200).stroke_width(1)))?;
+                root.draw(&Text::new(tile.code, (x + cell / 2, y + cell / 2), label.color(&RGBColor(160, 160, 160))))?;
+            }
+        }
+    }
+
+    let legend_area = root.margin(0, 0, left as u32 + cols * cell as u32 + 30, 0);
+    match (values, range) {
+        (TileValues::Values(_), Some(range)) => colormap::draw_color_bar(&legend_area, colormap::Colormap::Sequential, range, "")?,
+        (TileValues::Clusters(clusters), _) => {
+            let ids: BTreeSet<usize> = clusters.values().copied().collect();
+            for (i, cluster) in ids.into_iter().enumerate() {
+                let y = top + i as i32 * 24;
+                legend_area.draw(&Rectangle::new([(10, y), (28, y + 18)], PALETTE[cluster % PALETTE.len()].filled()))?;
+                legend_area.draw(&Text::new(format!("Cluster {}", cluster), (36, y + 2), ("sans-serif", 15)))?;
+            }
+        }
+        _ => {}
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_country_of_the_dataset_has_a_cell_of_its_own() {
+        let mut cells = BTreeSet::new();
+        let mut codes = BTreeSet::new();
+        for tile in TILE_POSITIONS {
+            assert!(cells.insert((tile.col, tile.row)), "{} shares its cell", tile.country);
+            assert!(tile.code.len() == 3 && codes.insert(tile.code), "{} has code {}", tile.country, tile.code);
+        }
+        let mut reader = csv::Reader::from_path("Life Expectancy Data.csv").unwrap();
+        let countries: BTreeSet<String> = reader.records().map(|record| record.unwrap()[0].to_string()).collect();
+        let missing: Vec<&String> = countries.iter().filter(|country| tile_position(country).is_none()).collect();
+        assert!(missing.is_empty(), "no tile for {:?}", missing);
+        assert_eq!(tile_position("Viet Nam").unwrap().code, "VNM");
+    }
+
+    #[test]
+    fn unknown_countries_are_left_off_the_map() {
+        let dir = crate::test_support::scratch_dir("tile-map");
+        let run = RunMetadata::new(&dir.join("input.csv").to_string_lossy(), "none").unwrap();
+        let values: HashMap<String, f64> = [("Albania", 78.0), ("Atlantis", 90.0)].iter().map(|&(c, v)| (c.to_string(), v)).collect();
+        let output_file = dir.join("tiles.png");
+        plot_tile_map(TileValues::Values(&values), "Life expectancy", output_file.to_str().unwrap(), &run).unwrap();
+        assert!(output_file.exists());
+
+        let empty = HashMap::new();
+        assert!(plot_tile_map(TileValues::Clusters(&empty), "Clusters", output_file.to_str().unwrap(), &run).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::ops::RangeInclusive;
+use ndarray::Array2;
+use petgraph::Graph;
+#[cfg(feature = "plots")]
+use plotters::prelude::*;
+use crate::analysis_error::AnalysisError;
+use crate::cancel::CancellationToken;
+#[cfg(feature = "plots")]
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+use crate::country_index::CountryIndex;
+use crate::fields::{FieldMeta, LifeExpectancyRecord};
+use crate::graph;
+#[cfg(feature = "plots")]
+use crate::metadata;
+#[cfg(feature = "plots")]
+use crate::metadata::RunMetadata;
+#[cfg(feature = "plots")]
+use crate::output;
+use crate::series::country_year_pivot;
+
+// How two trajectories are compared: the cosine of their standardized values,
+// or the negated dynamic time warping distance, which also matches trajectories
+// whose changes came a few years apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrajectorySimilarity {
+    Cosine,
+    Dtw,
+}
+
+impl TrajectorySimilarity {
+    // From its command-line name ("cosine" or "dtw")
+    pub(crate) fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "cosine" => Ok(TrajectorySimilarity::Cosine),
+            "dtw" => Ok(TrajectorySimilarity::Dtw),
+            other => Err(format!("Unknown trajectory similarity \"{}\" (expected cosine or dtw)", other).into()),
+        }
+    }
+
+    pub(crate) fn compare(self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            TrajectorySimilarity::Cosine => graph::calculate_similarity(a, b),
+            TrajectorySimilarity::Dtw => -dtw_distance(a, b),
+        }
+    }
+}
+
+// One field of every country over the same years, one row per country of the index
+pub(crate) struct Trajectories {
+    pub(crate) years: RangeInclusive<u16>,
+    pub(crate) index: CountryIndex,
+    pub(crate) values: Vec<Vec<f64>>, // with the missing years interpolated
+    pub(crate) standardized: Vec<Vec<f64>>, // each row z-scored over its years
+    pub(crate) interpolated: usize, // country-years filled in
+    pub(crate) excluded: Vec<String>, // countries with fewer than two years with a value
+}
+
+// Missing years filled in linearly between the nearest known years on either
+// side; years before the first or after the last known one take its value. None
+// with fewer than two known values, which give no trajectory.
+pub(crate) fn interpolate_missing(values: &[Option<f64>]) -> Option<Vec<f64>> {
+    let known: Vec<(usize, f64)> = values.iter().enumerate().filter_map(|(i, value)| Some((i, (*value)?))).collect();
+    if known.len() < 2 {
+        return None;
+    }
+    let filled = (0..values.len())
+        .map(|i| {
+            // First known year after i (or at i); the one before it is the left neighbour
+            let next = known.partition_point(|&(year, _)| year < i);
+            match (next.checked_sub(1).map(|before| known[before]), known.get(next)) {
+                (_, Some(&(year, value))) if year == i => value,
+                (Some((x0, y0)), Some(&(x1, y1))) => y0 + (y1 - y0) * (i - x0) as f64 / (x1 - x0) as f64,
+                (None, Some(&(_, value))) | (Some((_, value)), None) => value,
+                (None, None) => unreachable!("at least two known values"),
+            }
+        })
+        .collect();
+    Some(filled)
+}
+
+// Values z-scored over the years; all zero when the values do not change
+fn standardize(values: &[f64]) -> Vec<f64> {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std = (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n).sqrt();
+    values.iter().map(|value| if std > 0.0 { (value - mean) / std } else { 0.0 }).collect()
+}
+
+// Trajectory of `meta` for every country over the years of the records. Each is
+// standardized over its own years, so two countries at different levels that
+// moved the same way have the same standardized trajectory.
+pub(crate) fn country_trajectories(records: &[LifeExpectancyRecord], meta: &FieldMeta) -> Result<Trajectories, AnalysisError> {
+    let (first, last) = records.iter().fold((u16::MAX, u16::MIN), |(lo, hi), record| (lo.min(record.year), hi.max(record.year)));
+    if first > last {
+        return Err(AnalysisError::empty(format!("{} trajectories", meta.name)));
+    }
+    let mut countries = Vec::new();
+    let mut values = Vec::new();
+    let mut interpolated = 0;
+    let mut excluded = Vec::new();
+    // The pivot is in name order, so the rows line up with the index
+    for (country, years) in country_year_pivot(records, meta) {
+        let row: Vec<Option<f64>> = (first..=last).map(|year| years.get(&year).copied()).collect();
+        match interpolate_missing(&row) {
+            Some(filled) => {
+                interpolated += row.iter().filter(|value| value.is_none()).count();
+                countries.push(country);
+                values.push(filled);
+            }
+            None => excluded.push(country),
+        }
+    }
+    if countries.is_empty() {
+        return Err(AnalysisError::empty(format!("{} trajectories", meta.name)));
+    }
+    let standardized = values.iter().map(|row| standardize(row)).collect();
+    Ok(Trajectories { years: first..=last, index: CountryIndex::new(&countries), values, standardized, interpolated, excluded })
+}
+
+// Dynamic time warping distance: the least total |a_i - b_j| over the monotone
+// alignments of the two series that match every point of each, first with first
+// and last with last. O(len(a) * len(b)).
+pub(crate) fn dtw_distance(a: &[f64], b: &[f64]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return if a.len() == b.len() { 0.0 } else { f64::INFINITY };
+    }
+    // costs[j] holds the distance of a[..=i] and b[..=j] for the current i
+    let mut previous = vec![f64::INFINITY; b.len()];
+    let mut costs = vec![0.0; b.len()];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            let best = match (i, j) {
+                (0, 0) => 0.0,
+                (0, _) => costs[j - 1],
+                (_, 0) => previous[0],
+                _ => previous[j].min(previous[j - 1]).min(costs[j - 1]),
+            };
+            costs[j] = (x - y).abs() + best;
+        }
+        std::mem::swap(&mut previous, &mut costs);
+    }
+    previous[b.len() - 1]
+}
+
+// Pairwise similarity of the standardized trajectories, in index order
+pub(crate) fn trajectory_matrix(
+    trajectories: &Trajectories,
+    similarity: TrajectorySimilarity,
+    cancel: &CancellationToken,
+) -> Result<Array2<f64>, AnalysisError> {
+    let rows = &trajectories.standardized;
+    let n = rows.len();
+    let mut matrix = Array2::zeros((n, n));
+    for i in 0..n {
+        cancel.check()?;
+        matrix[(i, i)] = similarity.compare(&rows[i], &rows[i]);
+        for j in (i + 1)..n {
+            let value = similarity.compare(&rows[i], &rows[j]);
+            matrix[(i, j)] = value;
+            matrix[(j, i)] = value;
+        }
+    }
+    Ok(matrix)
+}
+
+// Graph with one node per country of the index, joined to its `neighbors` most
+// similar countries (ties by name). A fixed threshold would mean different things
+// for cosine similarities and DTW distances, so the graph keeps the nearest instead.
+pub(crate) fn trajectory_graph(index: &CountryIndex, matrix: &Array2<f64>, neighbors: usize) -> Graph<String, f64> {
+    let mut graph = Graph::<String, f64>::new();
+    let nodes: Vec<_> = index.names().iter().map(|country| graph.add_node(country.clone())).collect();
+    let mut linked = HashSet::new();
+    for i in 0..index.len() {
+        let mut others: Vec<usize> = (0..index.len()).filter(|&j| j != i).collect();
+        others.sort_by(|&a, &b| matrix[(i, b)].total_cmp(&matrix[(i, a)]).then(a.cmp(&b)));
+        for &j in others.iter().take(neighbors) {
+            if linked.insert((i.min(j), i.max(j))) {
+                graph.add_edge(nodes[i], nodes[j], matrix[(i, j)]);
+            }
+        }
+    }
+    graph
+}
+
+// Mean standardized trajectory of each cluster, by cluster id
+#[cfg(feature = "plots")]
+pub(crate) fn cluster_mean_trajectories(trajectories: &Trajectories, clusters: &[usize]) -> Vec<Vec<f64>> {
+    let k = clusters.iter().max().map_or(0, |k| k + 1);
+    let years = trajectories.years.len();
+    let mut sums = vec![(vec![0.0; years], 0usize); k];
+    for (row, &cluster) in trajectories.standardized.iter().zip(clusters) {
+        for (total, value) in sums[cluster].0.iter_mut().zip(row) {
+            *total += value;
+        }
+        sums[cluster].1 += 1;
+    }
+    sums.into_iter().map(|(totals, count)| totals.iter().map(|total| total / count.max(1) as f64).collect()).collect()
+}
+
+// One line per cluster: its mean standardized trajectory
+#[cfg(feature = "plots")]
+pub(crate) fn plot_cluster_trajectories(
+    trajectories: &Trajectories,
+    clusters: &[usize],
+    meta: &FieldMeta,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let means = cluster_mean_trajectories(trajectories, clusters);
+    let mut sizes = vec![0; means.len()];
+    for &cluster in clusters {
+        sizes[cluster] += 1;
+    }
+    let (first, last) = (*trajectories.years.start() as f64, *trajectories.years.end() as f64);
+    let values = means.iter().flatten().copied();
+    let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let pad = ((high - low) * 0.05).max(0.1);
+    let series: Vec<(String, Vec<(f64, f64)>)> = means
+        .iter()
+        .enumerate()
+        .map(|(cluster, mean)| {
+            let points = mean.iter().enumerate().map(|(i, &value)| (first + i as f64, value)).collect();
+            let noun = if sizes[cluster] == 1 { "country" } else { "countries" };
+            (format!("Cluster {} ({} {})", cluster, sizes[cluster], noun), points)
+        })
+        .collect();
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("{} trajectory clusters", meta.name);
+    let y_label = format!("{} (standardized per country)", meta.name);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(first..last.max(first + 1.0), (low - pad)..(high + pad))?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Year")
+        .y_desc(&y_label)
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .draw()?;
+
+    for (i, (name, points)) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), color.stroke_width(2)))?
+            .label(name)
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerRight)
+        .background_style(WHITE.mix(0.9))
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new("Year", (first, last));
+        let mut spec = ChartSpecJson::new("line", caption, x_axis, AxisJson::new(y_label, (low - pad, high + pad)));
+        for (i, (name, points)) in series.into_iter().enumerate() {
+            let (r, g, b) = Palette99::pick(i).rgb();
+            spec = spec.series(SeriesJson::new(name, Some(RGBColor(r, g, b)), points));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::FIELDS;
+
+    #[test]
+    fn missing_years_are_interpolated_between_neighbours() {
+        let filled = interpolate_missing(&[None, Some(1.0), None, None, Some(7.0), None]).unwrap();
+        assert_eq!(filled, vec![1.0, 1.0, 3.0, 5.0, 7.0, 7.0]);
+        assert_eq!(interpolate_missing(&[Some(2.0), Some(4.0)]).unwrap(), vec![2.0, 4.0]);
+        assert_eq!(interpolate_missing(&[None, Some(2.0), None]), None);
+    }
+
+    #[test]
+    fn dtw_matches_hand_computed_alignments() {
+        // Cumulative costs of [1, 2, 3] against [2, 2, 2]: rows 1 2 3, 1 1 1, 2 2 2
+        assert_eq!(dtw_distance(&[1.0, 2.0, 3.0], &[2.0, 2.0, 2.0]), 2.0);
+        // The same step a year later costs nothing once warped, 5 point by point
+        assert_eq!(dtw_distance(&[0.0, 0.0, 5.0], &[0.0, 5.0, 5.0]), 0.0);
+        assert_eq!(dtw_distance(&[0.0, 1.0, 2.0], &[0.0, 0.0, 1.0, 2.0]), 0.0);
+        assert_eq!(dtw_distance(&[1.0], &[4.0, 6.0]), 8.0);
+        assert_eq!((dtw_distance(&[], &[]), dtw_distance(&[], &[1.0])), (0.0, f64::INFINITY));
+        assert_eq!(TrajectorySimilarity::Dtw.compare(&[1.0, 2.0, 3.0], &[2.0, 2.0, 2.0]), -2.0);
+        assert!(TrajectorySimilarity::from_name("euclid").unwrap_err().to_string().contains("expected cosine or dtw"));
+    }
+
+    #[test]
+    fn countries_that_moved_alike_get_the_same_trajectory() {
+        let meta = FIELDS.iter().find(|meta| meta.name == "Life expectancy").unwrap();
+        let record = |country: &str, year: u16, value: f64| LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            life_expectancy: Some(value),
+            ..Default::default()
+        };
+        // A and B rise the same way at different levels (B's 2001 is missing), C falls, D has one year
+        let records = vec![
+            record("A", 2000, 50.0), record("A", 2001, 52.0), record("A", 2002, 54.0),
+            record("B", 2000, 70.0), record("B", 2002, 74.0),
+            record("C", 2000, 60.0), record("C", 2001, 58.0), record("C", 2002, 56.0),
+            record("D", 2001, 65.0),
+        ];
+        let trajectories = country_trajectories(&records, meta).unwrap();
+        assert_eq!(trajectories.index.names(), ["A", "B", "C"]);
+        assert_eq!((trajectories.interpolated, trajectories.excluded.clone()), (1, vec!["D".to_string()]));
+        assert_eq!(trajectories.values[1], vec![70.0, 72.0, 74.0]);
+        assert_eq!(trajectories.standardized[0], trajectories.standardized[1]);
+
+        let matrix = trajectory_matrix(&trajectories, TrajectorySimilarity::Cosine, &CancellationToken::new()).unwrap();
+        assert!((matrix[(0, 1)] - 1.0).abs() < 1e-12 && (matrix[(0, 2)] + 1.0).abs() < 1e-12);
+        let graph = trajectory_graph(&trajectories.index, &matrix, 1);
+        let edges: Vec<(&str, &str)> = graph
+            .edge_indices()
+            .map(|edge| graph.edge_endpoints(edge).unwrap())
+            .map(|(a, b)| (graph[a].as_str(), graph[b].as_str()))
+            .collect();
+        // C's nearest is A, ahead of B by name, at similarity -1
+        assert_eq!(edges, vec![("A", "B"), ("C", "A")]);
+    }
+}
@@ -0,0 +1,330 @@
+#[cfg(feature = "plots")]
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ops::RangeInclusive;
+use csv::Writer;
+use ndarray::Array1;
+#[cfg(feature = "plots")]
+use plotters::prelude::*;
+use serde::Serialize;
+use crate::analysis_error::AnalysisError;
+#[cfg(feature = "plots")]
+use crate::chart_json::{self, AxisJson, ChartSpecJson, SeriesJson};
+#[cfg(feature = "plots")]
+use crate::series::axis_label;
+use crate::series::{country_year_pivot, field_meta};
+use crate::eda_statistics::{calculate_correlation, linear_fit, PairedColumns};
+use crate::fields::LifeExpectancyRecord;
+#[cfg(feature = "plots")]
+use crate::format::{format_tick, TickStyle};
+#[cfg(feature = "plots")]
+use crate::metadata;
+use crate::metadata::RunMetadata;
+use crate::output;
+
+// Least-squares change per year of one country's values
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TrendSlope {
+    #[serde(rename = "Country")]
+    pub(crate) country: String,
+    #[serde(rename = "Slope")]
+    pub(crate) slope: f64, // units per year
+    #[serde(rename = "Years")]
+    pub(crate) years: usize, // years with a value
+}
+
+// Per-country trend slopes (steepest increase first) and the countries left out
+// for having fewer than `min_years` years with a value
+pub(crate) type Trends = (Vec<TrendSlope>, Vec<String>);
+
+// Trend slope of `feature` for every country with at least `min_years` values.
+// A slope needs two years, so a country with one is left out whatever `min_years` is.
+pub(crate) fn country_trends(
+    records: &[LifeExpectancyRecord],
+    feature: &str,
+    min_years: usize,
+) -> Result<Trends, Box<dyn Error>> {
+    let meta = field_meta(feature)?;
+    if records.is_empty() {
+        return Err(AnalysisError::empty(format!("{} trends", meta.name)).into());
+    }
+    let mut slopes = Vec::new();
+    let mut excluded = Vec::new();
+    for (country, years) in country_year_pivot(records, meta) {
+        let points: Vec<(f64, f64)> = years.iter().map(|(&year, &value)| (year as f64, value)).collect();
+        match linear_fit(&points) {
+            Some((slope, _)) if points.len() >= min_years => {
+                slopes.push(TrendSlope { country, slope, years: points.len() })
+            }
+            _ => excluded.push(country),
+        }
+    }
+    slopes.sort_by(|a, b| b.slope.total_cmp(&a.slope).then_with(|| a.country.cmp(&b.country)));
+    Ok((slopes, excluded))
+}
+
+pub(crate) fn export_trends_csv(slopes: &[TrendSlope], output_file: &str, run: &RunMetadata) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_writer(output::stamped_writer(output_file, Some(run))?);
+    for slope in slopes {
+        writer.serialize(slope)?;
+    }
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+// The `top_n` countries with the steepest trends (either direction)
+pub(crate) fn top_movers(slopes: &[TrendSlope], top_n: usize) -> Vec<&TrendSlope> {
+    let mut movers: Vec<&TrendSlope> = slopes.iter().collect();
+    movers.sort_by(|a, b| b.slope.abs().total_cmp(&a.slope.abs()).then_with(|| a.country.cmp(&b.country)));
+    movers.truncate(top_n);
+    movers
+}
+
+// One line per mover over the years, labelled with its slope in the legend
+#[cfg(feature = "plots")]
+pub(crate) fn plot_top_movers(
+    records: &[LifeExpectancyRecord],
+    feature: &str,
+    movers: &[&TrendSlope],
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let meta = field_meta(feature)?;
+    let pivot = country_year_pivot(records, meta);
+    let series: Vec<(&TrendSlope, &BTreeMap<u16, f64>)> =
+        movers.iter().filter_map(|mover| Some((*mover, pivot.get(&mover.country)?))).collect();
+
+    let years = series.iter().flat_map(|(_, values)| values.keys().copied());
+    let (first, last) = years.fold((u16::MAX, u16::MIN), |(lo, hi), year| (lo.min(year), hi.max(year)));
+    if first > last {
+        return Err(AnalysisError::empty(format!("{} of the top movers", meta.name)).into());
+    }
+    let values = series.iter().flat_map(|(_, values)| values.values().copied());
+    let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let pad = ((high - low) * 0.05).max(1e-9);
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1280, 720)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("Largest {} trends", meta.name);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 36))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(first as f64..last as f64, (low - pad)..(high + pad))?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Year")
+        .y_desc(axis_label(meta))
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .y_label_formatter(&|y| format_tick(*y, TickStyle::Si))
+        .draw()?;
+
+    for (i, (mover, values)) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(values.iter().map(|(&year, &value)| (year as f64, value)), color.stroke_width(2)))?
+            .label(format!("{} ({:+.2}/yr)", mover.country, mover.slope))
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerRight)
+        .background_style(WHITE.mix(0.9))
+        .border_style(BLACK)
+        .draw()?;
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        let x_axis = AxisJson::new("Year", (first as f64, last as f64));
+        let mut spec = ChartSpecJson::new("line", caption, x_axis, AxisJson::new(axis_label(meta), (low - pad, high + pad)));
+        for (i, (mover, values)) in series.iter().enumerate() {
+            let (r, g, b) = Palette99::pick(i).rgb();
+            let points = values.iter().map(|(&year, &value)| (year as f64, value)).collect();
+            spec = spec.series(SeriesJson::new(format!("{} ({:+.2}/yr)", mover.country, mover.slope), Some(RGBColor(r, g, b)), points));
+        }
+        spec
+    })?;
+    Ok(())
+}
+
+// Lag, pooled correlation and number of (x, y) pairs
+pub(crate) type LaggedCorrelation = (i32, f64, usize);
+
+// Pooled correlation of x at year t with y at year t + lag, for every lag in
+// `lags`: pairs are formed within each country and only where both values are
+// present, then correlated across all countries at once. The correlation is NaN
+// with fewer than two pairs or when either side is constant. A positive lag
+// asks whether x leads y.
+pub(crate) fn lagged_correlation(
+    records: &[LifeExpectancyRecord],
+    x_field: &str,
+    y_field: &str,
+    lags: RangeInclusive<i32>,
+) -> Result<Vec<LaggedCorrelation>, Box<dyn Error>> {
+    let (x_meta, y_meta) = (field_meta(x_field)?, field_meta(y_field)?);
+    if records.is_empty() {
+        return Err(AnalysisError::empty(format!("{} vs lagged {}", x_meta.name, y_meta.name)).into());
+    }
+    let (x_pivot, y_pivot) = (country_year_pivot(records, x_meta), country_year_pivot(records, y_meta));
+
+    Ok(lags
+        .map(|lag| {
+            let paired = PairedColumns::from_rows(x_pivot.iter().flat_map(|(country, x_years)| {
+                let y_years = y_pivot.get(country);
+                x_years.iter().map(move |(&year, &x)| {
+                    let later = u16::try_from(year as i32 + lag).ok();
+                    let y = later.and_then(|later| y_years?.get(&later).copied());
+                    (Some(x), y, country.as_str())
+                })
+            }));
+            let pairs = paired.len();
+            let correlation = if pairs < 2 {
+                None
+            } else {
+                calculate_correlation(&Array1::from(paired.x).view(), &Array1::from(paired.y).view())
+            };
+            (lag, correlation.unwrap_or(f64::NAN), pairs)
+        })
+        .collect())
+}
+
+// Correlation against lag, with the pair count above each point; NaN lags are left out
+#[cfg(feature = "plots")]
+pub(crate) fn plot_lagged_correlation(
+    correlations: &[LaggedCorrelation],
+    x_field: &str,
+    y_field: &str,
+    output_file: &str,
+    run: &RunMetadata,
+) -> Result<(), Box<dyn Error>> {
+    let points: Vec<(i32, f64, usize)> = correlations.iter().copied().filter(|(_, r, _)| r.is_finite()).collect();
+    let (Some(first), Some(last)) = (correlations.first(), correlations.last()) else {
+        return Err(AnalysisError::empty(format!("{} vs lagged {}", x_field, y_field)).into());
+    };
+    if points.is_empty() {
+        return Err(AnalysisError::empty(format!("{} vs lagged {}", x_field, y_field)).into());
+    }
+
+    let target = output::AtomicTarget::new(output_file)?;
+    let root = BitMapBackend::new(target.tmp_path(), (1024, 640)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let caption = format!("Correlation of {} with lagged {}", x_field, y_field);
+    let x_desc = format!("Lag in years ({} leads for positive lags)", x_field);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(&caption, ("sans-serif", 28))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(first.0 as f64 - 0.5..last.0 as f64 + 0.5, -1.0..1.0)?;
+
+    chart
+        .configure_mesh()
+        .x_desc(&x_desc)
+        .y_desc("Pooled correlation")
+        .axis_desc_style(("sans-serif", 20))
+        .label_style(("sans-serif", 15))
+        .x_labels((last.0 - first.0 + 1) as usize)
+        .x_label_formatter(&|x| format!("{:+.0}", x))
+        .draw()?;
+
+    let (start, end) = (first.0 as f64 - 0.5, last.0 as f64 + 0.5);
+    chart.draw_series(std::iter::once(PathElement::new(vec![(start, 0.0), (end, 0.0)], BLACK.mix(0.4))))?;
+    let line: Vec<(f64, f64)> = points.iter().map(|&(lag, r, _)| (lag as f64, r)).collect();
+    chart.draw_series(LineSeries::new(line.clone(), BLUE.stroke_width(2)))?;
+    chart.draw_series(line.iter().map(|&point| Circle::new(point, 5, BLUE.filled())))?;
+    for &(lag, r, pairs) in &points {
+        let (x, y) = chart.backend_coord(&(lag as f64, r));
+        root.draw(&Text::new(format!("n={}", pairs), (x - 18, y - 24), ("sans-serif", 13)))?;
+    }
+
+    metadata::draw_footer(&root, run)?;
+    root.present()?;
+    target.commit()?;
+    chart_json::write_sidecar(output_file, run, || {
+        ChartSpecJson::new("line", caption, AxisJson::new(x_desc, (start, end)), AxisJson::new("Pooled correlation", (-1.0, 1.0)))
+            .series(SeriesJson::new("Pooled correlation", Some(BLUE), line))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_correlation_peaks_at_the_true_lag() {
+        // Schooling is exactly GDP two years earlier, per country
+        let gdp = |country: usize, year: u16| ((year as usize * 7 + country * 3) % 11) as f64 + country as f64 * 20.0;
+        let records: Vec<LifeExpectancyRecord> = (0..3)
+            .flat_map(|country| {
+                (2000..=2010).map(move |year| LifeExpectancyRecord {
+                    country: format!("C{}", country),
+                    year,
+                    gdp: Some(gdp(country, year)),
+                    schooling: Some(gdp(country, year - 2)),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let lags = lagged_correlation(&records, "GDP", "Schooling", -3..=3).unwrap();
+        assert_eq!(lags.iter().map(|&(lag, _, _)| lag).collect::<Vec<_>>(), (-3..=3).collect::<Vec<_>>());
+        let (_, at_two, pairs) = lags[5];
+        assert!((at_two - 1.0).abs() < 1e-12);
+        // Nine of each country's eleven years have a value two years later
+        assert_eq!(pairs, 27);
+        assert!(lags.iter().filter(|&&(lag, _, _)| lag != 2).all(|&(_, r, _)| r < 0.99));
+        assert_eq!(lagged_correlation(&records[..1], "GDP", "Schooling", 20..=20).unwrap()[0].2, 0);
+    }
+
+    #[test]
+    fn movers_are_ranked_by_the_size_of_their_slope() {
+        let record = |country: &str, year: u16, value: Option<f64>| LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            life_expectancy: value,
+            ..Default::default()
+        };
+        let records = [
+            record("A", 2000, Some(60.0)),
+            record("A", 2001, Some(61.0)),
+            record("A", 2002, Some(62.0)),
+            record("B", 2000, Some(70.0)),
+            record("B", 2001, Some(67.0)),
+            record("B", 2002, Some(64.0)),
+            record("C", 2000, Some(50.0)),
+            record("C", 2001, None),
+        ];
+        let (slopes, excluded) = country_trends(&records, "Life expectancy", 3).unwrap();
+        let slopes_by_country: Vec<(&str, f64, usize)> = slopes.iter().map(|s| (s.country.as_str(), s.slope, s.years)).collect();
+        assert_eq!(slopes_by_country, vec![("A", 1.0, 3), ("B", -3.0, 3)]);
+        assert_eq!(excluded, vec!["C".to_string()]);
+        let movers: Vec<&str> = top_movers(&slopes, 1).iter().map(|s| s.country.as_str()).collect();
+        assert_eq!(movers, vec!["B"]);
+    }
+
+    #[test]
+    fn a_single_year_never_gets_a_slope() {
+        let record = |country: &str, year: u16| LifeExpectancyRecord {
+            country: country.to_string(),
+            year,
+            gdp: Some(year as f64),
+            ..Default::default()
+        };
+        let records = [record("A", 2000), record("A", 2002), record("Niue", 2013)];
+        let (slopes, excluded) = country_trends(&records, "GDP", 1).unwrap();
+        assert_eq!(slopes.iter().map(|slope| (slope.country.as_str(), slope.slope, slope.years)).collect::<Vec<_>>(), vec![("A", 1.0, 2)]);
+        assert_eq!(excluded, vec!["Niue"]);
+        assert!(slopes.iter().all(|slope| slope.slope.is_finite()));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ndarray::Array2;
+use petgraph::Graph;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::country_index::CountryIndex;
+use crate::graph::FeatureCells;
+use crate::output;
+use crate::safe_float::SafeF64;
+
+// Directory where subcommands keep their outputs between invocations
+pub(crate) const WORKSPACE_DIR: &str = ".ds210";
+const MANIFEST_FILE: &str = "manifest.json";
+
+// Input hash plus the cache key each stored artifact was built with
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    input_hash: String,
+    artifacts: HashMap<String, String>,
+}
+
+pub(crate) struct Workspace {
+    dir: PathBuf,
+    manifest: Manifest,
+    no_cache: bool,
+}
+
+// SHA-256 of a file as lowercase hex
+pub(crate) fn file_hash(path: &str) -> Result<String, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let digest = Sha256::digest(&bytes);
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Workspace {
+    // Workspace kept in `dir`, WORKSPACE_DIR unless --workspace names another
+    pub(crate) fn open_in(dir: &Path, input_file: &str, no_cache: bool) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let stored: Manifest = fs::read_to_string(dir.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+
+        // A changed input invalidates everything; a missing input keeps the cache usable
+        let manifest = match file_hash(input_file) {
+            Ok(hash) if hash != stored.input_hash => Manifest {
+                input_hash: hash,
+                artifacts: HashMap::new(),
+            },
+            Ok(_) => stored,
+            Err(_) if !stored.artifacts.is_empty() => {
+                println!("Input {} not found, using cached workspace", input_file);
+                stored
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Workspace {
+            dir: dir.to_path_buf(),
+            manifest,
+            no_cache,
+        })
+    }
+
+    // Load `name` if it was stored with the same key, otherwise build and store it
+    pub(crate) fn cached<T, F>(&mut self, name: &str, key: &str, build: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T, Box<dyn Error>>,
+    {
+        let path = self.dir.join(name);
+        if !self.no_cache && self.manifest.artifacts.get(name).map(String::as_str) == Some(key) {
+            if let Some(value) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|text| serde_json::from_str(&text).ok())
+            {
+                println!("Loaded {} from {}", name, self.dir.display());
+                return Ok(value);
+            }
+        }
+
+        let value = build()?;
+        output::cache_write(&path, serde_json::to_string(&value)?)?;
+        self.manifest.artifacts.insert(name.to_string(), key.to_string());
+        output::cache_write(self.dir.join(MANIFEST_FILE), serde_json::to_string_pretty(&self.manifest)?)?;
+        Ok(value)
+    }
+}
+
+// Graph in a serde-friendly shape: node labels and (source, target, weight) edges
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SerializedGraph {
+    nodes: Vec<String>,
+    edges: Vec<(usize, usize, SafeF64)>,
+}
+
+impl From<&Graph<String, f64>> for SerializedGraph {
+    fn from(graph: &Graph<String, f64>) -> Self {
+        SerializedGraph {
+            nodes: graph.node_indices().map(|node| graph[node].clone()).collect(),
+            edges: graph
+                .edge_indices()
+                .map(|edge| {
+                    let (a, b) = graph.edge_endpoints(edge).unwrap();
+                    (a.index(), b.index(), SafeF64(graph[edge]))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SerializedGraph> for Graph<String, f64> {
+    fn from(serialized: SerializedGraph) -> Self {
+        let mut graph = Graph::<String, f64>::new();
+        let node_indices: Vec<_> = serialized
+            .nodes
+            .into_iter()
+            .map(|country| graph.add_node(country))
+            .collect();
+        for (a, b, weight) in serialized.edges {
+            graph.add_edge(node_indices[a], node_indices[b], weight.0);
+        }
+        graph
+    }
+}
+
+// Feature cells with their countries in a serde-friendly shape; missing cells
+// are written as null, and read_feature_cells keeps no value that is not finite
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SerializedCells {
+    countries: Vec<String>,
+    cells: Vec<Vec<Option<f64>>>,
+}
+
+impl From<FeatureCells> for SerializedCells {
+    fn from((countries, cells): FeatureCells) -> Self {
+        SerializedCells { countries, cells }
+    }
+}
+
+impl From<SerializedCells> for FeatureCells {
+    fn from(serialized: SerializedCells) -> Self {
+        (serialized.countries, serialized.cells)
+    }
+}
+
+// Similarity index in a serde-friendly shape: the countries of a CountryIndex and
+// the similarity matrix over them, row by row
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SerializedMatrix {
+    countries: Vec<String>,
+    rows: Vec<Vec<SafeF64>>,
+}
+
+impl From<(&CountryIndex, &Array2<f64>)> for SerializedMatrix {
+    fn from((index, matrix): (&CountryIndex, &Array2<f64>)) -> Self {
+        let rows = matrix.rows().into_iter().map(|row| row.iter().copied().map(SafeF64).collect()).collect();
+        SerializedMatrix { countries: index.names().to_vec(), rows }
+    }
+}
+
+impl From<SerializedMatrix> for (CountryIndex, Array2<f64>) {
+    fn from(serialized: SerializedMatrix) -> Self {
+        let n = serialized.countries.len();
+        let mut matrix = Array2::zeros((n, n));
+        for (i, row) in serialized.rows.into_iter().enumerate() {
+            for (j, value) in row.into_iter().enumerate() {
+                matrix[(i, j)] = value.0;
+            }
+        }
+        (CountryIndex::new(&serialized.countries), matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph;
+    use crate::test_support::scratch_dir;
+
+    const CSV: &str = "Country,Year,Status,Life expectancy,GDP\nA,2000,Developing,60.5,100\nB,2000,Developed,,2500\n";
+
+    // One graph invocation: the feature cells of columns 3 and 4, from the
+    // workspace when it holds them
+    fn cells(dir: &Path, input: &str, no_cache: bool) -> Result<FeatureCells, Box<dyn Error>> {
+        let mut workspace = Workspace::open_in(&dir.join(".ds210"), input, no_cache)?;
+        let cells: SerializedCells = workspace.cached("features.json", "selection=[3, 4]", || {
+            graph::read_feature_cells(input, &[3, 4]).map(SerializedCells::from)
+        })?;
+        Ok(cells.into())
+    }
+
+    #[test]
+    fn second_invocation_does_not_read_the_deleted_input() {
+        let dir = scratch_dir("workspace-deleted");
+        let input = dir.join("data.csv");
+        fs::write(&input, CSV).unwrap();
+        let input = input.to_str().unwrap();
+
+        let first = cells(&dir, input, false).unwrap();
+        assert_eq!(first, (vec!["A".to_string(), "B".to_string()], vec![vec![Some(60.5), Some(100.0)], vec![None, Some(2500.0)]]));
+        fs::remove_file(input).unwrap();
+        assert_eq!(cells(&dir, input, false).unwrap(), first);
+        // Rebuilding needs the file
+        assert!(cells(&dir, input, true).is_err());
+    }
+
+    #[test]
+    fn changed_input_invalidates_the_cache() {
+        let dir = scratch_dir("workspace-changed");
+        let input = dir.join("data.csv");
+        fs::write(&input, CSV).unwrap();
+        let input = input.to_str().unwrap();
+
+        cells(&dir, input, false).unwrap();
+        fs::write(input, CSV.replace("60.5", "61.5")).unwrap();
+        assert_eq!(cells(&dir, input, false).unwrap().1[0][0], Some(61.5));
+    }
+
+    #[test]
+    fn serialized_matrix_round_trips_with_nan() {
+        let index = CountryIndex::new(["B", "A"]);
+        let matrix = Array2::from_shape_vec((2, 2), vec![1.0, f64::NAN, 0.5, 1.0]).unwrap();
+        let json = serde_json::to_string(&SerializedMatrix::from((&index, &matrix))).unwrap();
+        let (loaded_index, loaded): (CountryIndex, Array2<f64>) = serde_json::from_str::<SerializedMatrix>(&json).unwrap().into();
+        assert_eq!(loaded_index.names(), index.names());
+        assert_eq!(loaded[(1, 0)], 0.5);
+        assert!(loaded[(0, 1)].is_nan());
+    }
+}